@@ -1,10 +1,17 @@
 use clap::{Parser, Subcommand};
+use futures::StreamExt;
 use serde::Serialize;
 use serde_json::json;
 use std::fs;
+use std::io::Write;
 use std::path::PathBuf;
 
 use chat_backend::db;
+use chat_backend::db::at_rest;
+use chat_backend::server::ServerConfig;
+use chat_backend::services::import::{self, ImportData, ImportSummary, Importer};
+use chat_backend::services::transcript::{self, ExportFormat};
+use chat_backend::services::{DataExportService, RetentionService};
 
 #[derive(Parser)]
 #[command(name = "admin_cli")]
@@ -30,10 +37,43 @@ enum Commands {
         #[arg(long, default_value = "50")]
         limit: u32,
     },
+    /// Write a conversation's full transcript, oldest message first
+    ExportConversation {
+        conversation_id: String,
+        /// json, markdown or html
+        #[arg(long, default_value = "json")]
+        format: String,
+        /// File to write instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Import history from a Slack export directory or a generic JSON file
+    ///
+    /// Safe to run again: whatever was imported before is skipped, so an
+    /// interrupted import resumes where it stopped.
+    Import {
+        path: PathBuf,
+        /// slack or json; defaults to slack for a directory, json for a file
+        #[arg(long)]
+        format: Option<String>,
+        /// JSON object mapping source user IDs to existing usernames
+        #[arg(long)]
+        user_map: Option<PathBuf>,
+    },
     /// Server health
     Health,
     /// Server stats
     Stats,
+    /// Master key for encryption at rest
+    Keys {
+        #[command(subcommand)]
+        subcommand: KeysSubcommand,
+    },
+    /// Data retention rules, configured with the server's RETENTION_* variables
+    Retention {
+        #[command(subcommand)]
+        subcommand: RetentionSubcommand,
+    },
 }
 
 #[derive(Subcommand)]
@@ -47,6 +87,26 @@ enum UsersSubcommand {
     Delete { username: String },
 }
 
+#[derive(Subcommand)]
+enum KeysSubcommand {
+    /// Print a new random master key
+    Generate,
+    /// Show which master keys wrap the data keys
+    Status,
+    /// Re-wrap every data key with a new master key; messages are not rewritten
+    Rotate {
+        /// File holding the new base64 master key
+        #[arg(long)]
+        new_key_file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum RetentionSubcommand {
+    /// Show how many rows each rule would delete now, without deleting them
+    DryRun,
+}
+
 #[derive(Debug, Serialize)]
 struct UserView {
     id: String,
@@ -77,6 +137,11 @@ async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
     let pool = db::init_db(&args.db_path).await?;
 
+    // Needed to open content sealed at rest
+    if let Some(key) = at_rest::MasterKey::from_env().map_err(anyhow::Error::msg)? {
+        at_rest::install(key).map_err(anyhow::Error::msg)?;
+    }
+
     match args.command {
         Commands::Users { subcommand } => match subcommand {
             UsersSubcommand::List { deleted } => {
@@ -128,19 +193,88 @@ async fn main() -> anyhow::Result<()> {
             conversation_id,
             limit,
         } => {
-            // Fetch messages in conversation
-            let messages: Vec<chat_backend::models::Message> = sqlx::query_as(
-                "SELECT id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, status, is_anonymized FROM messages WHERE conversation_id = ? ORDER BY created_at DESC LIMIT ?"
-            )
-                .bind(&conversation_id)
-                .bind(limit)
-                .fetch_all(&pool)
-                .await
-                .map_err(|e| anyhow::anyhow!("Failed to fetch messages: {}", e))?;
+            // Fetch messages in conversation, opening content sealed at rest
+            let messages =
+                db::queries::get_messages_by_conversation(&pool, &conversation_id, limit, 0)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to fetch messages: {}", e))?;
 
             let output = json!(messages);
             println!("{}", serde_json::to_string_pretty(&output)?);
         }
+        Commands::ExportConversation {
+            conversation_id,
+            format,
+            output,
+        } => {
+            let Some(format) = ExportFormat::parse(&format) else {
+                eprintln!("Unknown format '{}': use json, markdown or html", format);
+                std::process::exit(1);
+            };
+            let Some(conversation) = db::queries::get_conversation_by_id(&pool, &conversation_id)
+                .await
+                .map_err(anyhow::Error::msg)?
+            else {
+                eprintln!("Conversation '{}' not found", conversation_id);
+                std::process::exit(1);
+            };
+
+            let mut out: Box<dyn Write> = match &output {
+                Some(path) => Box::new(std::io::BufWriter::new(fs::File::create(path)?)),
+                None => Box::new(std::io::stdout().lock()),
+            };
+            let mut chunks = Box::pin(transcript::stream_transcript(
+                pool.clone(),
+                conversation,
+                format,
+                "Conversation export".to_string(),
+            ));
+            while let Some(chunk) = chunks.next().await {
+                out.write_all(chunk.map_err(anyhow::Error::msg)?.as_bytes())?;
+            }
+            out.flush()?;
+            if let Some(path) = output {
+                eprintln!("Wrote {}", path.display());
+            }
+        }
+        Commands::Import {
+            path,
+            format,
+            user_map,
+        } => {
+            let format = format.unwrap_or_else(|| {
+                if path.is_dir() { "slack" } else { "json" }.to_string()
+            });
+            let mut summary = ImportSummary::default();
+            let data = match format.as_str() {
+                "slack" => {
+                    let (data, skipped) = import::slack::load(&path).map_err(anyhow::Error::msg)?;
+                    summary.skipped = skipped;
+                    data
+                }
+                "json" => ImportData::from_json_file(&path).map_err(anyhow::Error::msg)?,
+                other => {
+                    eprintln!("Unknown format '{}': use slack or json", other);
+                    std::process::exit(1);
+                }
+            };
+            let user_map = match user_map {
+                Some(file) => serde_json::from_str(&fs::read_to_string(&file)?)
+                    .map_err(|e| anyhow::anyhow!("Invalid user map: {}", e))?,
+                None => Default::default(),
+            };
+
+            let result = Importer::new(pool.clone())
+                .with_user_map(user_map)
+                .import(&data, &mut summary)
+                .await;
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+            if let Err(e) = result {
+                eprintln!("Import stopped: {}", e);
+                eprintln!("Run the same command again to resume");
+                std::process::exit(1);
+            }
+        }
         Commands::Health => {
             // Simple health check: database connection and table counts
             let user_count: (i64,) =
@@ -194,6 +328,75 @@ async fn main() -> anyhow::Result<()> {
             });
             println!("{}", serde_json::to_string_pretty(&stats)?);
         }
+        Commands::Keys { subcommand } => match subcommand {
+            KeysSubcommand::Generate => {
+                println!("{}", at_rest::MasterKey::generate().to_base64());
+            }
+            KeysSubcommand::Status => {
+                let data_keys = at_rest::data_key_counts(&pool)
+                    .await
+                    .map_err(anyhow::Error::msg)?;
+                let sealed_messages: (i64,) =
+                    sqlx::query_as("SELECT COUNT(*) FROM messages WHERE is_sealed")
+                        .fetch_one(&pool)
+                        .await
+                        .map_err(|e| anyhow::anyhow!("Failed to query messages: {}", e))?;
+
+                let status = json!({
+                    "configured_master_key_id": at_rest::master_key().map(|k| k.id()),
+                    "data_keys": data_keys
+                        .into_iter()
+                        .map(|(master_key_id, count)| json!({
+                            "master_key_id": master_key_id,
+                            "count": count,
+                        }))
+                        .collect::<Vec<_>>(),
+                    "sealed_messages": sealed_messages.0,
+                });
+                println!("{}", serde_json::to_string_pretty(&status)?);
+            }
+            KeysSubcommand::Rotate { new_key_file } => {
+                let Some(current) = at_rest::master_key() else {
+                    eprintln!(
+                        "Set {} or {} to the current master key",
+                        at_rest::MASTER_KEY_ENV,
+                        at_rest::MASTER_KEY_FILE_ENV
+                    );
+                    std::process::exit(1);
+                };
+                let new = at_rest::MasterKey::from_file(&new_key_file).map_err(anyhow::Error::msg)?;
+                let rotated = at_rest::rotate_master_key(&pool, current, &new)
+                    .await
+                    .map_err(anyhow::Error::msg)?;
+
+                let output = json!({
+                    "rotated": rotated,
+                    "master_key_id": new.id(),
+                });
+                println!("{}", serde_json::to_string_pretty(&output)?);
+                eprintln!("Configure the server with the new master key before restarting it");
+            }
+        },
+        Commands::Retention { subcommand } => match subcommand {
+            RetentionSubcommand::DryRun => {
+                let config = ServerConfig::default();
+                let exports = DataExportService::new(
+                    pool.clone(),
+                    config.data_export,
+                    config.jwt_secret.as_bytes(),
+                );
+                let preview = RetentionService::new(pool.clone(), config.retention, exports)
+                    .preview()
+                    .await
+                    .map_err(anyhow::Error::msg)?;
+
+                let output = json!({
+                    "timestamp": chrono::Utc::now().timestamp_millis(),
+                    "rules": preview,
+                });
+                println!("{}", serde_json::to_string_pretty(&output)?);
+            }
+        },
     }
     Ok(())
 }
@@ -0,0 +1,466 @@
+//! Application-level encryption at rest
+//!
+//! Envelope encryption: a master key, read from `CHAT_MASTER_KEY` (base64) or
+//! the file named by `CHAT_MASTER_KEY_FILE`, wraps a random data key per
+//! conversation plus one for auth logs. Message content and auth log details
+//! are sealed with AES-256-GCM under their data key before they are written,
+//! and opened again by the queries that read them.
+//!
+//! Rotating the master key only re-wraps the data keys in `data_keys`;
+//! sealed rows are left as they are.
+//!
+//! Sealed messages stay searchable through a blind index: each distinct word
+//! is stored as an HMAC under a key derived from the conversation's data key,
+//! so equal words match without the words themselves being stored. Unlike the
+//! substring match on plaintext rows, sealed messages match whole words only.
+//!
+//! Without a master key rows are written in plaintext, and rows written
+//! before one was configured stay readable after.
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Environment variable holding the base64 master key
+pub const MASTER_KEY_ENV: &str = "CHAT_MASTER_KEY";
+
+/// Environment variable naming a file that holds the base64 master key
+pub const MASTER_KEY_FILE_ENV: &str = "CHAT_MASTER_KEY_FILE";
+
+/// Data key scope of auth log details
+pub const AUTH_LOGS_SCOPE: &str = "auth_logs";
+
+const NONCE_LEN: usize = 12;
+
+static MASTER_KEY: OnceLock<MasterKey> = OnceLock::new();
+
+/// Key that wraps the data keys
+pub struct MasterKey {
+    key: [u8; 32],
+    id: String,
+}
+
+impl MasterKey {
+    pub fn from_bytes(key: [u8; 32]) -> Self {
+        // Identifies which master key wrapped a data key, without revealing it
+        let digest = Sha256::digest(key);
+        let id = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        Self { key, id }
+    }
+
+    pub fn generate() -> Self {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        Self::from_bytes(key)
+    }
+
+    pub fn from_base64(value: &str) -> Result<Self, String> {
+        let bytes = BASE64
+            .decode(value.trim())
+            .map_err(|_| "Master key is not valid base64".to_string())?;
+        let key: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| "Master key must be 32 bytes".to_string())?;
+        Ok(Self::from_bytes(key))
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let value = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read master key file {}: {}", path.display(), e))?;
+        Self::from_base64(&value)
+    }
+
+    /// Master key configured in the environment, if any
+    pub fn from_env() -> Result<Option<Self>, String> {
+        if let Ok(value) = std::env::var(MASTER_KEY_ENV) {
+            return Self::from_base64(&value).map(Some);
+        }
+        match std::env::var(MASTER_KEY_FILE_ENV) {
+            Ok(path) => Self::from_file(Path::new(&path)).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    pub fn to_base64(&self) -> String {
+        BASE64.encode(self.key)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn wrap(&self, scope: &str, data_key: &[u8; 32]) -> Result<String, String> {
+        seal_bytes(&self.key, scope.as_bytes(), data_key)
+    }
+
+    fn unwrap(&self, scope: &str, wrapped: &str) -> Result<[u8; 32], String> {
+        open_bytes(&self.key, scope.as_bytes(), wrapped)?
+            .try_into()
+            .map_err(|_| format!("Data key for {} is malformed", scope))
+    }
+}
+
+/// Turn on encryption at rest for this process
+///
+/// Called once at startup; rows written from then on are sealed.
+pub fn install(key: MasterKey) -> Result<(), String> {
+    let id = key.id.clone();
+    MASTER_KEY.set(key).or_else(|existing| {
+        if existing.id == id {
+            Ok(())
+        } else {
+            Err("A different master key is already installed".to_string())
+        }
+    })
+}
+
+/// The installed master key, if encryption at rest is on
+pub fn master_key() -> Option<&'static MasterKey> {
+    MASTER_KEY.get()
+}
+
+/// Data key scope of a conversation's messages
+pub fn conversation_scope(conversation_id: &str) -> String {
+    format!("conversation:{}", conversation_id)
+}
+
+/// A value as it is written to the database
+pub struct Sealed {
+    pub value: String,
+    pub is_sealed: bool,
+}
+
+/// Seal `value` under the data key for `scope`, creating the key if needed
+///
+/// Returns the value unchanged when no master key is installed.
+pub async fn seal(pool: &SqlitePool, scope: &str, value: &str) -> Result<Sealed, String> {
+    let Some(master) = master_key() else {
+        return Ok(Sealed {
+            value: value.to_string(),
+            is_sealed: false,
+        });
+    };
+    let data_key = data_key(pool, master, scope, true)
+        .await?
+        .expect("data key is created when missing");
+    Ok(Sealed {
+        value: seal_bytes(&data_key, scope.as_bytes(), value.as_bytes())?,
+        is_sealed: true,
+    })
+}
+
+/// Seal a message's content and compute its blind index tokens
+pub async fn seal_message(
+    pool: &SqlitePool,
+    conversation_id: &str,
+    content: &str,
+) -> Result<(Sealed, Vec<String>), String> {
+    let Some(master) = master_key() else {
+        return Ok((
+            Sealed {
+                value: content.to_string(),
+                is_sealed: false,
+            },
+            Vec::new(),
+        ));
+    };
+    let scope = conversation_scope(conversation_id);
+    let data_key = data_key(pool, master, &scope, true)
+        .await?
+        .expect("data key is created when missing");
+    let sealed = Sealed {
+        value: seal_bytes(&data_key, scope.as_bytes(), content.as_bytes())?,
+        is_sealed: true,
+    };
+    Ok((sealed, blind_tokens(&data_key, content)))
+}
+
+/// Opens sealed values, fetching each scope's data key once
+pub struct Opener<'a> {
+    pool: &'a SqlitePool,
+    keys: HashMap<String, [u8; 32]>,
+}
+
+impl<'a> Opener<'a> {
+    pub fn new(pool: &'a SqlitePool) -> Self {
+        Self {
+            pool,
+            keys: HashMap::new(),
+        }
+    }
+
+    pub async fn open(&mut self, scope: &str, sealed: &str) -> Result<String, String> {
+        if let Some(key) = self.keys.get(scope) {
+            return open_with(key, scope, sealed);
+        }
+        let master = master_key().ok_or_else(|| {
+            format!(
+                "Sealed data found but no master key is configured (set {} or {})",
+                MASTER_KEY_ENV, MASTER_KEY_FILE_ENV
+            )
+        })?;
+        let key = data_key(self.pool, master, scope, false)
+            .await?
+            .ok_or_else(|| format!("No data key for {}", scope))?;
+        self.keys.insert(scope.to_string(), key);
+        open_with(&key, scope, sealed)
+    }
+}
+
+/// Blind index tokens to look up for a search, or `None` when there is
+/// nothing to look up: encryption is off, the conversation has no sealed
+/// messages, or the query has no words
+pub async fn search_tokens(
+    pool: &SqlitePool,
+    conversation_id: &str,
+    query: &str,
+) -> Result<Option<Vec<String>>, String> {
+    let Some(master) = master_key() else {
+        return Ok(None);
+    };
+    let scope = conversation_scope(conversation_id);
+    let Some(data_key) = data_key(pool, master, &scope, false).await? else {
+        return Ok(None);
+    };
+    let tokens = blind_tokens(&data_key, query);
+    Ok((!tokens.is_empty()).then_some(tokens))
+}
+
+/// Re-wrap every data key from `current` to `new`
+///
+/// Keys already wrapped by `new` are skipped, so an interrupted rotation can
+/// be run again. Returns how many keys were re-wrapped.
+pub async fn rotate_master_key(
+    pool: &SqlitePool,
+    current: &MasterKey,
+    new: &MasterKey,
+) -> Result<u64, String> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| format!("Failed to begin transaction: {}", e))?;
+    let rows: Vec<(String, String, String)> =
+        sqlx::query_as("SELECT scope, wrapped_key, master_key_id FROM data_keys")
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(|e| format!("Failed to read data keys: {}", e))?;
+
+    let now = chrono::Utc::now().timestamp_millis();
+    let mut rotated = 0;
+    for (scope, wrapped, master_key_id) in rows {
+        if master_key_id == new.id {
+            continue;
+        }
+        if master_key_id != current.id {
+            return Err(format!(
+                "Data key for {} is wrapped by master key {}, not the current key {}",
+                scope, master_key_id, current.id
+            ));
+        }
+        let data_key = current.unwrap(&scope, &wrapped)?;
+        sqlx::query(
+            "UPDATE data_keys SET wrapped_key = ?, master_key_id = ?, rotated_at = ? WHERE scope = ?",
+        )
+        .bind(new.wrap(&scope, &data_key)?)
+        .bind(&new.id)
+        .bind(now)
+        .bind(&scope)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| format!("Failed to update data key: {}", e))?;
+        rotated += 1;
+    }
+
+    tx.commit()
+        .await
+        .map_err(|e| format!("Failed to commit rotation: {}", e))?;
+    Ok(rotated)
+}
+
+/// How many data keys each master key wraps
+pub async fn data_key_counts(pool: &SqlitePool) -> Result<Vec<(String, i64)>, String> {
+    sqlx::query_as(
+        "SELECT master_key_id, COUNT(*) FROM data_keys GROUP BY master_key_id ORDER BY master_key_id",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to count data keys: {}", e))
+}
+
+/// Unwrapped data key for `scope`, created if there is none and `create` is set
+async fn data_key(
+    pool: &SqlitePool,
+    master: &MasterKey,
+    scope: &str,
+    create: bool,
+) -> Result<Option<[u8; 32]>, String> {
+    let existing = stored_data_key(pool, master, scope).await?;
+    if existing.is_some() || !create {
+        return Ok(existing);
+    }
+
+    let mut data_key = [0u8; 32];
+    OsRng.fill_bytes(&mut data_key);
+    // A concurrent writer may have created it first; its key wins
+    sqlx::query(
+        "INSERT OR IGNORE INTO data_keys (scope, wrapped_key, master_key_id, created_at)
+         VALUES (?, ?, ?, ?)",
+    )
+    .bind(scope)
+    .bind(master.wrap(scope, &data_key)?)
+    .bind(&master.id)
+    .bind(chrono::Utc::now().timestamp_millis())
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to create data key: {}", e))?;
+
+    stored_data_key(pool, master, scope).await
+}
+
+/// Unwrapped data key for `scope` as stored in `data_keys`
+async fn stored_data_key(
+    pool: &SqlitePool,
+    master: &MasterKey,
+    scope: &str,
+) -> Result<Option<[u8; 32]>, String> {
+    let row: Option<(String, String)> =
+        sqlx::query_as("SELECT wrapped_key, master_key_id FROM data_keys WHERE scope = ?")
+            .bind(scope)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| format!("Failed to read data key: {}", e))?;
+    let Some((wrapped, master_key_id)) = row else {
+        return Ok(None);
+    };
+    if master_key_id != master.id {
+        return Err(format!(
+            "Data key for {} is wrapped by master key {}, but {} is configured",
+            scope, master_key_id, master.id
+        ));
+    }
+    master.unwrap(scope, &wrapped).map(Some)
+}
+
+fn open_with(data_key: &[u8; 32], scope: &str, sealed: &str) -> Result<String, String> {
+    String::from_utf8(open_bytes(data_key, scope.as_bytes(), sealed)?)
+        .map_err(|_| format!("Sealed value in {} is not UTF-8", scope))
+}
+
+/// AES-256-GCM with a random nonce; `aad` binds the value to its scope
+fn seal_bytes(key: &[u8; 32], aad: &[u8], plaintext: &[u8]) -> Result<String, String> {
+    let cipher = Aes256Gcm::new(key.into());
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| "Encryption failed".to_string())?;
+    Ok(BASE64.encode([nonce.as_slice(), &ciphertext].concat()))
+}
+
+fn open_bytes(key: &[u8; 32], aad: &[u8], sealed: &str) -> Result<Vec<u8>, String> {
+    let bytes = BASE64
+        .decode(sealed)
+        .map_err(|_| "Sealed value is not valid base64".to_string())?;
+    if bytes.len() < NONCE_LEN {
+        return Err("Sealed value is too short".to_string());
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    Aes256Gcm::new(key.into())
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| "Sealed value failed authentication".to_string())
+}
+
+/// Keyed hashes of the distinct lowercase words of `text`
+fn blind_tokens(data_key: &[u8; 32], text: &str) -> Vec<String> {
+    let mut index_key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, data_key)
+        .expand(b"chat-app search index", &mut index_key)
+        .expect("32 bytes is a valid HKDF-SHA256 length");
+
+    let words: BTreeSet<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    words
+        .iter()
+        .map(|word| {
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&index_key)
+                .expect("HMAC accepts any key length");
+            mac.update(word.as_bytes());
+            BASE64.encode(mac.finalize().into_bytes())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sealed_values_are_bound_to_their_scope() {
+        let key = [3u8; 32];
+        let sealed = seal_bytes(&key, b"conversation:a", b"hello").unwrap();
+        assert_eq!(
+            open_bytes(&key, b"conversation:a", &sealed).unwrap(),
+            b"hello"
+        );
+        assert!(open_bytes(&key, b"conversation:b", &sealed).is_err());
+        assert!(open_bytes(&[4u8; 32], b"conversation:a", &sealed).is_err());
+
+        // Nonces are random, so equal values seal differently
+        assert_ne!(
+            sealed,
+            seal_bytes(&key, b"conversation:a", b"hello").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_master_key_wraps_and_identifies() {
+        let master = MasterKey::generate();
+        let restored = MasterKey::from_base64(&master.to_base64()).unwrap();
+        assert_eq!(restored.id(), master.id());
+        assert_ne!(MasterKey::generate().id(), master.id());
+        assert!(MasterKey::from_base64("c2hvcnQ=").is_err());
+
+        let wrapped = master.wrap("auth_logs", &[9u8; 32]).unwrap();
+        assert_eq!(restored.unwrap("auth_logs", &wrapped).unwrap(), [9u8; 32]);
+        assert!(MasterKey::generate().unwrap("auth_logs", &wrapped).is_err());
+    }
+
+    #[test]
+    fn test_blind_tokens_match_whole_words() {
+        let key = [5u8; 32];
+        let message = blind_tokens(&key, "Lunch at noon? Noon works!");
+        assert_eq!(message.len(), 4);
+        for query in ["noon", "LUNCH works"] {
+            let query = blind_tokens(&key, query);
+            assert!(query.iter().all(|token| message.contains(token)));
+        }
+        assert!(!message.contains(&blind_tokens(&key, "lun")[0]));
+
+        // Another conversation's key gives unrelated tokens
+        assert!(!message.contains(&blind_tokens(&[6u8; 32], "noon")[0]));
+    }
+}
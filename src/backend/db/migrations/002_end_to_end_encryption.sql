@@ -0,0 +1,56 @@
+-- End-to-end encrypted conversations and the key bundles used to set them up
+
+ALTER TABLE conversations ADD COLUMN is_encrypted BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Messages are rebuilt so encrypted content may be longer than plaintext, which a CHECK constraint change needs
+CREATE TABLE messages_new (
+  id TEXT PRIMARY KEY,
+  conversation_id TEXT NOT NULL,
+  sender_id TEXT NOT NULL,
+  recipient_id TEXT NOT NULL,
+  content TEXT NOT NULL,
+  created_at INTEGER NOT NULL DEFAULT (cast(strftime('%s', 'now') as integer) * 1000),
+  delivered_at INTEGER,
+  status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'sent', 'delivered', 'failed')),
+  is_anonymized BOOLEAN NOT NULL DEFAULT FALSE,
+  is_encrypted BOOLEAN NOT NULL DEFAULT FALSE,
+  FOREIGN KEY (conversation_id) REFERENCES conversations(id),
+  FOREIGN KEY (sender_id) REFERENCES users(id),
+  FOREIGN KEY (recipient_id) REFERENCES users(id),
+  CHECK (length(content) >= 1 AND length(content) <= CASE WHEN is_encrypted THEN 16384 ELSE 5000 END)
+);
+
+INSERT INTO messages_new (id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, status, is_anonymized)
+SELECT id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, status, is_anonymized FROM messages;
+
+DROP TABLE messages;
+
+ALTER TABLE messages_new RENAME TO messages;
+
+CREATE INDEX IF NOT EXISTS idx_messages_conversation_id ON messages(conversation_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_messages_sender_id ON messages(sender_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_messages_status ON messages(status) WHERE status IN ('pending', 'failed');
+CREATE INDEX IF NOT EXISTS idx_messages_delivered_at ON messages(delivered_at) WHERE delivered_at IS NULL;
+
+-- End-to-end encryption key bundles (public keys only)
+CREATE TABLE IF NOT EXISTS identity_keys (
+  user_id TEXT PRIMARY KEY,
+  identity_key TEXT NOT NULL,
+  signing_key TEXT NOT NULL,
+  signed_prekey_id INTEGER NOT NULL,
+  signed_prekey TEXT NOT NULL,
+  signed_prekey_signature TEXT NOT NULL,
+  updated_at INTEGER NOT NULL DEFAULT (cast(strftime('%s', 'now') as integer) * 1000),
+  FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+-- One-time prekeys, each handed out once and then deleted
+CREATE TABLE IF NOT EXISTS one_time_prekeys (
+  user_id TEXT NOT NULL,
+  key_id INTEGER NOT NULL,
+  public_key TEXT NOT NULL,
+  PRIMARY KEY (user_id, key_id),
+  FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+INSERT OR IGNORE INTO schema_metadata (version, description) VALUES (2, 'End-to-end encryption: encrypted conversations and prekey bundles');
@@ -0,0 +1,57 @@
+-- Encryption at rest: sealed message content and auth log details
+
+ALTER TABLE auth_logs ADD COLUMN is_sealed BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Messages are rebuilt so sealed content is exempt from the length CHECK
+CREATE TABLE messages_new (
+  id TEXT PRIMARY KEY,
+  conversation_id TEXT NOT NULL,
+  sender_id TEXT NOT NULL,
+  recipient_id TEXT NOT NULL,
+  content TEXT NOT NULL,
+  created_at INTEGER NOT NULL DEFAULT (cast(strftime('%s', 'now') as integer) * 1000),
+  delivered_at INTEGER,
+  status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'sent', 'delivered', 'failed')),
+  is_anonymized BOOLEAN NOT NULL DEFAULT FALSE,
+  is_encrypted BOOLEAN NOT NULL DEFAULT FALSE,
+  -- Content is sealed with the conversation's data key (see data_keys)
+  is_sealed BOOLEAN NOT NULL DEFAULT FALSE,
+  FOREIGN KEY (conversation_id) REFERENCES conversations(id),
+  FOREIGN KEY (sender_id) REFERENCES users(id),
+  FOREIGN KEY (recipient_id) REFERENCES users(id),
+  CHECK (is_sealed OR (length(content) >= 1 AND length(content) <= CASE WHEN is_encrypted THEN 16384 ELSE 5000 END))
+);
+
+INSERT INTO messages_new (id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, status, is_anonymized, is_encrypted)
+SELECT id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, status, is_anonymized, is_encrypted FROM messages;
+
+DROP TABLE messages;
+
+ALTER TABLE messages_new RENAME TO messages;
+
+CREATE INDEX IF NOT EXISTS idx_messages_conversation_id ON messages(conversation_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_messages_sender_id ON messages(sender_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_messages_status ON messages(status) WHERE status IN ('pending', 'failed');
+CREATE INDEX IF NOT EXISTS idx_messages_delivered_at ON messages(delivered_at) WHERE delivered_at IS NULL;
+
+-- Data keys for encryption at rest, each wrapped by the master key.
+-- Scope is 'conversation:<id>' or 'auth_logs'.
+CREATE TABLE IF NOT EXISTS data_keys (
+  scope TEXT PRIMARY KEY,
+  wrapped_key TEXT NOT NULL,
+  master_key_id TEXT NOT NULL,
+  created_at INTEGER NOT NULL DEFAULT (cast(strftime('%s', 'now') as integer) * 1000),
+  rotated_at INTEGER
+);
+
+-- Blind search index of sealed messages: keyed hashes of their words
+CREATE TABLE IF NOT EXISTS message_search_tokens (
+  message_id TEXT NOT NULL,
+  token TEXT NOT NULL,
+  PRIMARY KEY (message_id, token),
+  FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_message_search_tokens_token ON message_search_tokens(token);
+
+INSERT OR IGNORE INTO schema_metadata (version, description) VALUES (3, 'Encryption at rest: sealed content, data keys and blind search tokens');
@@ -0,0 +1,48 @@
+-- Read receipts, system messages and disappearing messages
+
+-- Disappearing messages: seconds a message is kept, counted from when it
+-- is sent or when it is read (NULL keeps messages)
+ALTER TABLE conversations ADD COLUMN ephemeral_ttl INTEGER CHECK (ephemeral_ttl IS NULL OR ephemeral_ttl > 0);
+ALTER TABLE conversations ADD COLUMN ephemeral_start TEXT NOT NULL DEFAULT 'sent' CHECK (ephemeral_start IN ('sent', 'read'));
+
+-- Messages are rebuilt to allow the 'read' status, which a CHECK constraint change needs
+CREATE TABLE messages_new (
+  id TEXT PRIMARY KEY,
+  conversation_id TEXT NOT NULL,
+  sender_id TEXT NOT NULL,
+  recipient_id TEXT NOT NULL,
+  content TEXT NOT NULL,
+  created_at INTEGER NOT NULL DEFAULT (cast(strftime('%s', 'now') as integer) * 1000),
+  delivered_at INTEGER,
+  read_at INTEGER,
+  status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'sent', 'delivered', 'read', 'failed')),
+  is_anonymized BOOLEAN NOT NULL DEFAULT FALSE,
+  is_encrypted BOOLEAN NOT NULL DEFAULT FALSE,
+  -- Content is sealed with the conversation's data key (see data_keys)
+  is_sealed BOOLEAN NOT NULL DEFAULT FALSE,
+  -- Announces a conversation change instead of carrying user content
+  is_system BOOLEAN NOT NULL DEFAULT FALSE,
+  -- The conversation's ephemeral_ttl when sent, kept to start a read timer
+  ephemeral_ttl INTEGER,
+  -- Hidden from reads once passed, then deleted by the reaper
+  expires_at INTEGER,
+  FOREIGN KEY (conversation_id) REFERENCES conversations(id),
+  FOREIGN KEY (sender_id) REFERENCES users(id),
+  FOREIGN KEY (recipient_id) REFERENCES users(id),
+  CHECK (is_sealed OR (length(content) >= 1 AND length(content) <= CASE WHEN is_encrypted THEN 16384 ELSE 5000 END))
+);
+
+INSERT INTO messages_new (id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, status, is_anonymized, is_encrypted, is_sealed)
+SELECT id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, status, is_anonymized, is_encrypted, is_sealed FROM messages;
+
+DROP TABLE messages;
+
+ALTER TABLE messages_new RENAME TO messages;
+
+CREATE INDEX IF NOT EXISTS idx_messages_conversation_id ON messages(conversation_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_messages_sender_id ON messages(sender_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_messages_status ON messages(status) WHERE status IN ('pending', 'failed');
+CREATE INDEX IF NOT EXISTS idx_messages_delivered_at ON messages(delivered_at) WHERE delivered_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_messages_expires_at ON messages(expires_at) WHERE expires_at IS NOT NULL;
+
+INSERT OR IGNORE INTO schema_metadata (version, description) VALUES (4, 'Read receipts, system messages and disappearing messages');
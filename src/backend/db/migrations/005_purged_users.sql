@@ -0,0 +1,6 @@
+-- Users purged by retention. A purged user whose conversations live on with an
+-- active participant keeps a scrubbed row, so their messages still have a
+-- sender, and purged_at keeps retention from purging it again.
+ALTER TABLE users ADD COLUMN purged_at INTEGER;
+
+INSERT OR IGNORE INTO schema_metadata (version, description) VALUES (5, 'Purged users kept as tombstones');
@@ -0,0 +1,17 @@
+-- Personal data exports, built in the background and downloaded through a
+-- signed link until expires_at. The archive itself lives in the export directory.
+CREATE TABLE IF NOT EXISTS data_exports (
+  id TEXT PRIMARY KEY,
+  user_id TEXT NOT NULL,
+  status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'ready', 'failed', 'expired')),
+  created_at INTEGER NOT NULL DEFAULT (cast(strftime('%s', 'now') as integer) * 1000),
+  completed_at INTEGER,
+  expires_at INTEGER,
+  size_bytes INTEGER,
+  error TEXT,
+  FOREIGN KEY (user_id) REFERENCES users(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_data_exports_user_id ON data_exports(user_id, created_at DESC);
+
+INSERT OR IGNORE INTO schema_metadata (version, description) VALUES (6, 'Personal data exports');
@@ -0,0 +1,20 @@
+-- Messages removed from a conversation without a trace in it: reaped after
+-- expiring, purged by retention or dropped with a declined message request.
+-- Transcripts show a marker where they were, so nobody mistakes a conversation
+-- with holes in it for a complete one. Retention keeps a single row per
+-- conversation, since what it removes always comes before everything kept.
+CREATE TABLE IF NOT EXISTS message_gaps (
+  id INTEGER PRIMARY KEY,
+  conversation_id TEXT NOT NULL,
+  reason TEXT NOT NULL CHECK (reason IN ('expired', 'retention', 'declined')),
+  message_count INTEGER NOT NULL CHECK (message_count > 0),
+  -- Creation times of the first and last message removed
+  first_at INTEGER NOT NULL,
+  last_at INTEGER NOT NULL,
+  FOREIGN KEY (conversation_id) REFERENCES conversations(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_message_gaps_conversation_id ON message_gaps(conversation_id, first_at);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_message_gaps_retention ON message_gaps(conversation_id) WHERE reason = 'retention';
+
+INSERT OR IGNORE INTO schema_metadata (version, description) VALUES (7, 'Markers for removed messages');
@@ -0,0 +1,20 @@
+-- First contact from a stranger. While a conversation has a row here, its
+-- messages are held from the recipient, who sees it only in their requests
+-- list, and neither participant sees the other's presence. Accepting deletes
+-- the row, declining drops the held messages and any sent afterwards.
+CREATE TABLE IF NOT EXISTS message_requests (
+  conversation_id TEXT PRIMARY KEY,
+  requester_id TEXT NOT NULL,
+  recipient_id TEXT NOT NULL,
+  status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'declined')),
+  created_at INTEGER NOT NULL DEFAULT (cast(strftime('%s', 'now') as integer) * 1000),
+  FOREIGN KEY (conversation_id) REFERENCES conversations(id),
+  FOREIGN KEY (requester_id) REFERENCES users(id),
+  FOREIGN KEY (recipient_id) REFERENCES users(id),
+  CHECK (requester_id != recipient_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_message_requests_recipient_id ON message_requests(recipient_id, status);
+CREATE INDEX IF NOT EXISTS idx_message_requests_requester_id ON message_requests(requester_id);
+
+INSERT OR IGNORE INTO schema_metadata (version, description) VALUES (9, 'Message requests');
@@ -1,11 +1,13 @@
 //! Database initialization and migration management
 
 use anyhow::Result;
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePool, SqlitePoolOptions};
+use sqlx::Connection;
 use std::path::Path;
 use std::str::FromStr;
 use tracing::info;
 
+pub mod at_rest;
 pub mod queries;
 
 /// Initialize SQLite database and run migrations
@@ -24,8 +26,9 @@ pub async fn init_db(db_path: impl AsRef<Path>) -> Result<SqlitePool> {
     info!("Initializing database: {}", db_url);
 
     // Create connection options with WAL mode for better concurrency.
-    // NOTE: MVP stores SQLite files in plaintext; production deployments should place
-    // the database on an encrypted volume (LUKS/BitLocker) or enable SQLCipher.
+    // Message content and auth log details are sealed by `at_rest` once a master
+    // key is installed; the rest of the file is plaintext, so production
+    // deployments should still use an encrypted volume (LUKS/BitLocker).
     let connect_options = SqliteConnectOptions::from_str(&db_url)?
         .create_if_missing(true)
         .pragma("journal_mode", "WAL")
@@ -46,22 +49,85 @@ pub async fn init_db(db_path: impl AsRef<Path>) -> Result<SqlitePool> {
     Ok(pool)
 }
 
+/// Schema migrations in the order they apply. Each file records its version
+/// in `schema_metadata` as its last statement.
+const MIGRATIONS: &[(i64, &str)] = &[
+    (1, include_str!("migrations/001_initial_schema.sql")),
+    (2, include_str!("migrations/002_end_to_end_encryption.sql")),
+    (3, include_str!("migrations/003_encryption_at_rest.sql")),
+    (4, include_str!("migrations/004_disappearing_messages.sql")),
+    (5, include_str!("migrations/005_purged_users.sql")),
+    (6, include_str!("migrations/006_data_exports.sql")),
+    (7, include_str!("migrations/007_message_gaps.sql")),
+    (8, include_str!("migrations/008_user_blocks.sql")),
+    (9, include_str!("migrations/009_message_requests.sql")),
+];
+
 /// Run all pending migrations
-async fn run_migrations(pool: &SqlitePool) -> Result<()> {
+///
+/// Each migration runs in its own transaction, so a failure leaves the
+/// database at the last version that applied cleanly. Foreign keys are off
+/// while they run so a migration can rebuild a table (SQLite cannot alter a
+/// CHECK constraint) without cascading into the tables that reference it.
+pub async fn run_migrations(pool: &SqlitePool) -> Result<()> {
     info!("Running database migrations...");
 
-    // Read and execute the initial schema
-    let schema_sql = include_str!("migrations/001_initial_schema.sql");
+    let mut conn = pool.acquire().await?;
+    let current = schema_version(&mut conn).await?;
 
-    // Split schema into individual statements
-    for statement in schema_sql.split(';').filter(|s| !s.trim().is_empty()) {
-        sqlx::query(statement).execute(pool).await?;
-    }
+    sqlx::query("PRAGMA foreign_keys = OFF").execute(&mut *conn).await?;
+    let result = apply_migrations(&mut conn, current).await;
+    sqlx::query("PRAGMA foreign_keys = ON").execute(&mut *conn).await?;
+    result?;
 
     info!("Migrations completed");
     Ok(())
 }
 
+async fn apply_migrations(conn: &mut SqliteConnection, current: i64) -> Result<()> {
+    for (version, sql) in MIGRATIONS.iter().filter(|(version, _)| *version > current) {
+        let mut tx = conn.begin().await?;
+
+        // Split the file into individual statements
+        for statement in sql.split(';').filter(|s| !s.trim().is_empty()) {
+            sqlx::query(statement).execute(&mut *tx).await?;
+        }
+
+        let violations = sqlx::query("PRAGMA foreign_key_check")
+            .fetch_all(&mut *tx)
+            .await?;
+        if !violations.is_empty() {
+            anyhow::bail!(
+                "Migration {} left {} foreign key violations",
+                version,
+                violations.len()
+            );
+        }
+
+        tx.commit().await?;
+        info!("Applied migration {}", version);
+    }
+    Ok(())
+}
+
+/// The newest migration applied to the database, or 0 for a new database
+async fn schema_version(conn: &mut SqliteConnection) -> Result<i64> {
+    let (has_metadata,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'schema_metadata'",
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if has_metadata == 0 {
+        return Ok(0);
+    }
+
+    let (version,): (i64,) =
+        sqlx::query_as("SELECT COALESCE(MAX(version), 0) FROM schema_metadata")
+            .fetch_one(&mut *conn)
+            .await?;
+    Ok(version)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -81,4 +147,45 @@ mod tests {
         assert_eq!(result.0, 1, "users table should exist");
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_upgrade_from_initial_schema() -> Result<()> {
+        let pool = SqlitePoolOptions::new().connect("sqlite::memory:").await?;
+
+        // A database created before the second migration existed
+        let (_, initial) = MIGRATIONS[0];
+        for statement in initial.split(';').filter(|s| !s.trim().is_empty()) {
+            sqlx::query(statement).execute(&pool).await?;
+        }
+        for statement in [
+            "INSERT INTO users (id, username, password_hash, password_salt) VALUES ('u1', 'alice', 'h', 's'), ('u2', 'bob', 'h', 's')",
+            "INSERT INTO conversations (id, user1_id, user2_id, message_count) VALUES ('c1', 'u1', 'u2', 1)",
+            "INSERT INTO messages (id, conversation_id, sender_id, recipient_id, content, status) VALUES ('m1', 'c1', 'u1', 'u2', 'hello', 'delivered')",
+        ] {
+            sqlx::query(statement).execute(&pool).await?;
+        }
+        assert_eq!(schema_version(&mut *pool.acquire().await?).await?, 1);
+
+        let (latest, _) = MIGRATIONS[MIGRATIONS.len() - 1];
+        run_migrations(&pool).await?;
+        assert_eq!(schema_version(&mut *pool.acquire().await?).await?, latest);
+
+        let message = queries::find_message_by_id(&pool, "m1")
+            .await
+            .map_err(anyhow::Error::msg)?
+            .expect("message should survive the upgrade");
+        assert_eq!(message.content, "hello");
+        assert_eq!(message.status, "delivered");
+        assert_eq!(message.expires_at, None);
+        let conversation = queries::get_conversation_by_id(&pool, "c1")
+            .await
+            .map_err(anyhow::Error::msg)?
+            .expect("conversation should survive the upgrade");
+        assert!(!conversation.is_encrypted);
+
+        // Running again is a no-op
+        run_migrations(&pool).await?;
+        assert_eq!(schema_version(&mut *pool.acquire().await?).await?, latest);
+        Ok(())
+    }
 }
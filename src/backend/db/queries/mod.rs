@@ -2,8 +2,12 @@
 //!
 //! Provides database operations for user management including insertion, lookup, and updates.
 
-use crate::models::{Conversation, Message, User};
-use sqlx::SqlitePool;
+use crate::db::at_rest;
+use crate::models::{
+    AuthLog, BlockedUser, Conversation, DataExport, ExpiredMessage, IdentityKeys, Message, MessageGap, MessageRequest, OneTimePrekey, User,
+};
+use sqlx::{SqliteConnection, SqlitePool};
+use std::collections::BTreeSet;
 use uuid::Uuid;
 
 /// Auth event types
@@ -37,10 +41,14 @@ pub async fn insert_auth_log(
 ) -> Result<(), String> {
     let id = Uuid::new_v4().to_string();
     let now = chrono::Utc::now().timestamp_millis();
+    let details = match details {
+        Some(details) => Some(at_rest::seal(pool, at_rest::AUTH_LOGS_SCOPE, details).await?),
+        None => None,
+    };
 
     sqlx::query(
-        "INSERT INTO auth_logs (id, ip_address, username, event_type, created_at, user_agent, details)
-         VALUES (?, ?, ?, ?, ?, ?, ?)"
+        "INSERT INTO auth_logs (id, ip_address, username, event_type, created_at, user_agent, details, is_sealed)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
     )
     .bind(id)
     .bind(ip_address)
@@ -48,7 +56,8 @@ pub async fn insert_auth_log(
     .bind(event_type.as_str())
     .bind(now)
     .bind(user_agent)
-    .bind(details)
+    .bind(details.as_ref().map(|d| d.value.as_str()))
+    .bind(details.as_ref().is_some_and(|d| d.is_sealed))
     .execute(pool)
     .await
     .map_err(|e| format!("Failed to insert auth log: {}", e))?;
@@ -78,6 +87,32 @@ pub async fn get_failed_attempts(
     Ok(result as u32)
 }
 
+/// Every auth log entry recorded for a username, oldest first
+pub async fn get_auth_logs_for_username(
+    pool: &SqlitePool,
+    username: &str,
+) -> Result<Vec<AuthLog>, String> {
+    let mut logs = sqlx::query_as::<_, AuthLog>(
+        "SELECT id, ip_address, username, event_type, created_at, user_agent, details, is_sealed
+         FROM auth_logs
+         WHERE username = ?
+         ORDER BY created_at, id",
+    )
+    .bind(username)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to get auth logs: {}", e))?;
+
+    let mut opener = at_rest::Opener::new(pool);
+    for log in logs.iter_mut().filter(|log| log.is_sealed) {
+        if let Some(details) = &log.details {
+            log.details = Some(opener.open(at_rest::AUTH_LOGS_SCOPE, details).await?);
+        }
+        log.is_sealed = false;
+    }
+    Ok(logs)
+}
+
 /// Insert a new user into the database
 ///
 /// Returns the user if successful
@@ -155,12 +190,74 @@ pub async fn update_online_status(
     Ok(())
 }
 
+/// Set user online status with an explicit last-seen time
+///
+/// `last_seen_at` of `None` keeps the stored value, e.g. when clearing a stale
+/// online flag for which no socket activity is known.
+pub async fn update_presence(
+    pool: &SqlitePool,
+    user_id: &str,
+    is_online: bool,
+    last_seen_at: Option<i64>,
+) -> Result<(), String> {
+    let now = chrono::Utc::now().timestamp_millis();
+
+    sqlx::query(
+        "UPDATE users SET is_online = ?, last_seen_at = COALESCE(?, last_seen_at), updated_at = ? WHERE id = ?",
+    )
+    .bind(is_online)
+    .bind(last_seen_at)
+    .bind(now)
+    .bind(user_id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to update presence: {}", e))?;
+
+    Ok(())
+}
+
+/// Get IDs of all users currently flagged online
+pub async fn get_online_user_ids(pool: &SqlitePool) -> Result<Vec<String>, String> {
+    sqlx::query_scalar::<_, String>("SELECT id FROM users WHERE is_online = TRUE")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to get online users: {}", e))
+}
+
+/// Mark every user that is still flagged online as offline
+///
+/// Used on shutdown and at startup, when no live connection exists to do it
+/// individually. `last_seen_at` keeps the last recorded activity. Returns the
+/// number of users updated.
+pub async fn mark_all_users_offline(pool: &SqlitePool) -> Result<u64, String> {
+    let now = chrono::Utc::now().timestamp_millis();
+
+    let result = sqlx::query(
+        "UPDATE users SET is_online = FALSE, updated_at = ? WHERE is_online = TRUE",
+    )
+    .bind(now)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to mark users offline: {}", e))?;
+
+    Ok(result.rows_affected())
+}
+
 /// Update user last seen timestamp
 pub async fn update_last_seen(pool: &SqlitePool, user_id: &str) -> Result<(), String> {
+    update_last_seen_at(pool, user_id, chrono::Utc::now().timestamp_millis()).await
+}
+
+/// Update user last seen timestamp to a known activity time
+pub async fn update_last_seen_at(
+    pool: &SqlitePool,
+    user_id: &str,
+    last_seen_at: i64,
+) -> Result<(), String> {
     let now = chrono::Utc::now().timestamp_millis();
 
     sqlx::query("UPDATE users SET last_seen_at = ?, updated_at = ? WHERE id = ?")
-        .bind(now)
+        .bind(last_seen_at)
         .bind(now)
         .bind(user_id)
         .execute(pool)
@@ -231,7 +328,7 @@ pub async fn search_users_by_prefix(
     .map_err(|e| format!("Failed to search users: {}", e))
 }
 
-/// Search users excluding self
+/// Search users excluding self and anyone blocked either way
 pub async fn search_users_excluding_self(
     pool: &SqlitePool,
     query: &str,
@@ -243,8 +340,13 @@ pub async fn search_users_excluding_self(
     sqlx::query_as::<_, User>(
         "SELECT id, username, password_hash, password_salt, created_at, updated_at, deleted_at, is_online, last_seen_at
          FROM users
-         WHERE username LIKE ? AND id != ? AND deleted_at IS NULL
-         LIMIT ?"
+         WHERE username LIKE ?1 AND id != ?2 AND deleted_at IS NULL
+           AND NOT EXISTS (
+             SELECT 1 FROM user_blocks
+             WHERE (blocker_id = ?2 AND blocked_id = users.id)
+                OR (blocker_id = users.id AND blocked_id = ?2)
+           )
+         LIMIT ?3"
     )
     .bind(search_pattern)
     .bind(current_user_id)
@@ -260,8 +362,8 @@ pub async fn insert_conversation(
     conversation: &Conversation,
 ) -> Result<Conversation, String> {
     sqlx::query(
-        "INSERT INTO conversations (id, user1_id, user2_id, created_at, updated_at, last_message_at, message_count)
-         VALUES (?, ?, ?, ?, ?, ?, ?)"
+        "INSERT INTO conversations (id, user1_id, user2_id, created_at, updated_at, last_message_at, message_count, is_encrypted, ephemeral_ttl, ephemeral_start)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
     )
     .bind(&conversation.id)
     .bind(&conversation.user1_id)
@@ -270,6 +372,9 @@ pub async fn insert_conversation(
     .bind(conversation.updated_at)
     .bind(conversation.last_message_at)
     .bind(conversation.message_count)
+    .bind(conversation.is_encrypted)
+    .bind(conversation.ephemeral_ttl)
+    .bind(&conversation.ephemeral_start)
     .execute(pool)
     .await
     .map_err(|e| format!("Failed to insert conversation: {}", e))?;
@@ -284,7 +389,7 @@ pub async fn get_conversation_by_users(
     user2_id: &str,
 ) -> Result<Option<Conversation>, String> {
     sqlx::query_as::<_, Conversation>(
-        "SELECT id, user1_id, user2_id, created_at, updated_at, last_message_at, message_count
+        "SELECT id, user1_id, user2_id, created_at, updated_at, last_message_at, message_count, is_encrypted, ephemeral_ttl, ephemeral_start
          FROM conversations
          WHERE user1_id = ? AND user2_id = ?",
     )
@@ -301,7 +406,7 @@ pub async fn get_conversation_by_id(
     conversation_id: &str,
 ) -> Result<Option<Conversation>, String> {
     sqlx::query_as::<_, Conversation>(
-        "SELECT id, user1_id, user2_id, created_at, updated_at, last_message_at, message_count
+        "SELECT id, user1_id, user2_id, created_at, updated_at, last_message_at, message_count, is_encrypted, ephemeral_ttl, ephemeral_start
          FROM conversations
          WHERE id = ?",
     )
@@ -312,6 +417,9 @@ pub async fn get_conversation_by_id(
 }
 
 /// Get all conversations for a user (sorted by last_message_at DESC)
+///
+/// Message requests the user has not accepted are left out; their senders
+/// still see them.
 pub async fn get_user_conversations(
     pool: &SqlitePool,
     user_id: &str,
@@ -319,14 +427,17 @@ pub async fn get_user_conversations(
     offset: u32,
 ) -> Result<Vec<Conversation>, String> {
     sqlx::query_as::<_, Conversation>(
-        "SELECT id, user1_id, user2_id, created_at, updated_at, last_message_at, message_count
+        "SELECT id, user1_id, user2_id, created_at, updated_at, last_message_at, message_count, is_encrypted, ephemeral_ttl, ephemeral_start
          FROM conversations
-         WHERE user1_id = ? OR user2_id = ?
+         WHERE (user1_id = ?1 OR user2_id = ?1)
+           AND NOT EXISTS (
+             SELECT 1 FROM message_requests r
+             WHERE r.conversation_id = conversations.id AND r.recipient_id = ?1
+           )
          ORDER BY updated_at DESC
-         LIMIT ? OFFSET ?",
+         LIMIT ?2 OFFSET ?3",
     )
     .bind(user_id)
-    .bind(user_id)
     .bind(limit)
     .bind(offset)
     .fetch_all(pool)
@@ -334,98 +445,236 @@ pub async fn get_user_conversations(
     .map_err(|e| format!("Failed to get user conversations: {}", e))
 }
 
+/// Mark a conversation as end-to-end encrypted
+///
+/// One-way: there is no query to clear the flag.
+pub async fn set_conversation_encrypted(
+    pool: &SqlitePool,
+    conversation_id: &str,
+) -> Result<(), String> {
+    sqlx::query("UPDATE conversations SET is_encrypted = TRUE WHERE id = ?")
+        .bind(conversation_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to mark conversation encrypted: {}", e))?;
+    Ok(())
+}
+
+/// Change a conversation's disappearing-message setting
+///
+/// Applies to messages sent afterwards; existing ones keep their expiry.
+pub async fn set_conversation_ephemeral(
+    pool: &SqlitePool,
+    conversation_id: &str,
+    ephemeral_ttl: Option<i64>,
+    ephemeral_start: &str,
+) -> Result<(), String> {
+    sqlx::query(
+        "UPDATE conversations SET ephemeral_ttl = ?, ephemeral_start = ?, updated_at = ? WHERE id = ?",
+    )
+    .bind(ephemeral_ttl)
+    .bind(ephemeral_start)
+    .bind(chrono::Utc::now().timestamp_millis())
+    .bind(conversation_id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to update disappearing messages: {}", e))?;
+    Ok(())
+}
+
 // ============================================================================
 // Message Queries
 // ============================================================================
 
 /// Insert a new message
+///
+/// Content is sealed at rest when a master key is installed, and its words go
+/// into the blind search index (end-to-end encrypted envelopes have none).
 pub async fn insert_message(pool: &SqlitePool, message: &Message) -> Result<Message, String> {
+    let (content, tokens) =
+        at_rest::seal_message(pool, &message.conversation_id, &message.content).await?;
+    let tokens = if message.is_encrypted || message.is_system {
+        Vec::new()
+    } else {
+        tokens
+    };
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| format!("Failed to begin transaction: {}", e))?;
     sqlx::query(
-        "INSERT INTO messages (id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, status, is_anonymized)
-         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        "INSERT INTO messages (id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, read_at, status, is_anonymized, is_encrypted, is_sealed, is_system, ephemeral_ttl, expires_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
     )
     .bind(&message.id)
     .bind(&message.conversation_id)
     .bind(&message.sender_id)
     .bind(&message.recipient_id)
-    .bind(&message.content)
+    .bind(&content.value)
     .bind(message.created_at)
     .bind(message.delivered_at)
+    .bind(message.read_at)
     .bind(&message.status)
     .bind(message.is_anonymized)
-    .execute(pool)
+    .bind(message.is_encrypted)
+    .bind(content.is_sealed)
+    .bind(message.is_system)
+    .bind(message.ephemeral_ttl)
+    .bind(message.expires_at)
+    .execute(&mut *tx)
     .await
     .map_err(|e| format!("Failed to insert message: {}", e))?;
 
+    for token in tokens {
+        sqlx::query("INSERT INTO message_search_tokens (message_id, token) VALUES (?, ?)")
+            .bind(&message.id)
+            .bind(token)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| format!("Failed to index message: {}", e))?;
+    }
+    tx.commit()
+        .await
+        .map_err(|e| format!("Failed to commit message: {}", e))?;
+
     Ok(message.clone())
 }
 
+/// Open the sealed content of messages read from the database
+async fn open_messages(pool: &SqlitePool, mut messages: Vec<Message>) -> Result<Vec<Message>, String> {
+    let mut opener = at_rest::Opener::new(pool);
+    for message in messages.iter_mut().filter(|m| m.is_sealed) {
+        let scope = at_rest::conversation_scope(&message.conversation_id);
+        message.content = opener.open(&scope, &message.content).await?;
+        message.is_sealed = false;
+    }
+    Ok(messages)
+}
+
 /// Find message by ID
 pub async fn find_message_by_id(
     pool: &SqlitePool,
     message_id: &str,
 ) -> Result<Option<Message>, String> {
-    sqlx::query_as::<_, Message>(
-        "SELECT id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, status, is_anonymized
+    let message = sqlx::query_as::<_, Message>(
+        "SELECT id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, read_at, status, is_anonymized, is_encrypted, is_sealed, is_system, ephemeral_ttl, expires_at
          FROM messages
-         WHERE id = ?"
+         WHERE id = ? AND (expires_at IS NULL OR expires_at > ?)"
     )
     .bind(message_id)
+    .bind(chrono::Utc::now().timestamp_millis())
     .fetch_optional(pool)
     .await
-    .map_err(|e| format!("Failed to find message by id: {}", e))
+    .map_err(|e| format!("Failed to find message by id: {}", e))?;
+
+    match message {
+        Some(message) => Ok(open_messages(pool, vec![message]).await?.pop()),
+        None => Ok(None),
+    }
 }
 
 /// Get messages by conversation (sorted by created_at DESC)
+///
+/// Expired disappearing messages are left out even before the reaper
+/// deletes them, as they are by every other message read.
 pub async fn get_messages_by_conversation(
     pool: &SqlitePool,
     conversation_id: &str,
     limit: u32,
     offset: u32,
 ) -> Result<Vec<Message>, String> {
-    sqlx::query_as::<_, Message>(
-        "SELECT id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, status, is_anonymized
+    let messages = sqlx::query_as::<_, Message>(
+        "SELECT id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, read_at, status, is_anonymized, is_encrypted, is_sealed, is_system, ephemeral_ttl, expires_at
          FROM messages
-         WHERE conversation_id = ?
+         WHERE conversation_id = ? AND (expires_at IS NULL OR expires_at > ?)
          ORDER BY created_at DESC
          LIMIT ? OFFSET ?"
     )
     .bind(conversation_id)
+    .bind(chrono::Utc::now().timestamp_millis())
     .bind(limit)
     .bind(offset)
     .fetch_all(pool)
     .await
-    .map_err(|e| format!("Failed to get messages by conversation: {}", e))
+    .map_err(|e| format!("Failed to get messages by conversation: {}", e))?;
+
+    open_messages(pool, messages).await
+}
+
+/// Get up to `limit` messages of a conversation, oldest first, that come after
+/// `after` (`created_at`, `id`) or from the start if `None`
+///
+/// Paging on the last row seen keeps each page cheap however long the
+/// conversation is.
+pub async fn get_messages_after(
+    pool: &SqlitePool,
+    conversation_id: &str,
+    after: Option<(i64, &str)>,
+    limit: u32,
+) -> Result<Vec<Message>, String> {
+    let (after_created_at, after_id) = match after {
+        Some((created_at, id)) => (Some(created_at), id),
+        None => (None, ""),
+    };
+    let messages = sqlx::query_as::<_, Message>(
+        "SELECT id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, read_at, status, is_anonymized, is_encrypted, is_sealed, is_system, ephemeral_ttl, expires_at
+         FROM messages
+         WHERE conversation_id = ?1 AND (expires_at IS NULL OR expires_at > ?2)
+           AND (?3 IS NULL OR created_at > ?3 OR (created_at = ?3 AND id > ?4))
+         ORDER BY created_at ASC, id ASC
+         LIMIT ?5"
+    )
+    .bind(conversation_id)
+    .bind(chrono::Utc::now().timestamp_millis())
+    .bind(after_created_at)
+    .bind(after_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to get messages by conversation: {}", e))?;
+
+    open_messages(pool, messages).await
 }
 
 /// Get pending messages for a recipient (status = 'pending' or 'failed')
+///
+/// Messages held by a message request are not pending delivery yet.
 pub async fn get_pending_messages(
     pool: &SqlitePool,
     recipient_id: &str,
 ) -> Result<Vec<Message>, String> {
-    sqlx::query_as::<_, Message>(
-        "SELECT id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, status, is_anonymized
+    let messages = sqlx::query_as::<_, Message>(
+        "SELECT id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, read_at, status, is_anonymized, is_encrypted, is_sealed, is_system, ephemeral_ttl, expires_at
          FROM messages
-         WHERE recipient_id = ? AND (status = 'pending' OR status = 'failed')
+         WHERE recipient_id = ? AND (status = 'pending' OR status = 'failed') AND (expires_at IS NULL OR expires_at > ?)
+           AND NOT EXISTS (SELECT 1 FROM message_requests r WHERE r.conversation_id = messages.conversation_id)
          ORDER BY created_at ASC"
     )
     .bind(recipient_id)
+    .bind(chrono::Utc::now().timestamp_millis())
     .fetch_all(pool)
     .await
-    .map_err(|e| format!("Failed to get pending messages: {}", e))
+    .map_err(|e| format!("Failed to get pending messages: {}", e))?;
+
+    open_messages(pool, messages).await
 }
 
 /// Get all pending messages (status = 'pending' or 'failed') for queue initialization
 pub async fn get_all_pending_messages(pool: &SqlitePool) -> Result<Vec<Message>, String> {
-    sqlx::query_as::<_, Message>(
-        "SELECT id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, status, is_anonymized
+    let messages = sqlx::query_as::<_, Message>(
+        "SELECT id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, read_at, status, is_anonymized, is_encrypted, is_sealed, is_system, ephemeral_ttl, expires_at
          FROM messages
-         WHERE status = 'pending' OR status = 'failed'
+         WHERE (status = 'pending' OR status = 'failed') AND (expires_at IS NULL OR expires_at > ?)
+           AND NOT EXISTS (SELECT 1 FROM message_requests r WHERE r.conversation_id = messages.conversation_id)
          ORDER BY created_at ASC"
     )
+    .bind(chrono::Utc::now().timestamp_millis())
     .fetch_all(pool)
     .await
-    .map_err(|e| format!("Failed to get all pending messages: {}", e))
+    .map_err(|e| format!("Failed to get all pending messages: {}", e))?;
+
+    open_messages(pool, messages).await
 }
 
 /// Update message status
@@ -458,6 +707,131 @@ pub async fn mark_message_delivered(pool: &SqlitePool, message_id: &str) -> Resu
     Ok(())
 }
 
+/// Mark message as read (sets read_at and status = 'read')
+///
+/// Starts the disappearing-message timer of a message counted from reading;
+/// a running timer is left alone.
+pub async fn mark_message_read(pool: &SqlitePool, message_id: &str) -> Result<(), String> {
+    let now = chrono::Utc::now().timestamp_millis();
+
+    sqlx::query(
+        "UPDATE messages
+         SET status = 'read', read_at = ?, expires_at = COALESCE(expires_at, ? + ephemeral_ttl * 1000)
+         WHERE id = ?",
+    )
+    .bind(now)
+    .bind(now)
+    .bind(message_id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to mark message read: {}", e))?;
+
+    Ok(())
+}
+
+/// Hard-delete messages whose expiry has passed, with their search index
+/// entries, recording a gap in their place
+pub async fn delete_expired_messages(pool: &SqlitePool) -> Result<Vec<ExpiredMessage>, String> {
+    let now = chrono::Utc::now().timestamp_millis();
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| format!("Failed to begin transaction: {}", e))?;
+
+    sqlx::query(
+        "DELETE FROM message_search_tokens
+         WHERE message_id IN (SELECT id FROM messages WHERE expires_at <= ?)",
+    )
+    .bind(now)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| format!("Failed to delete expired search tokens: {}", e))?;
+
+    sqlx::query(&record_gaps("expires_at <= ?"))
+        .bind("expired")
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| format!("Failed to record expired messages: {}", e))?;
+
+    let expired = sqlx::query_as::<_, ExpiredMessage>(
+        "DELETE FROM messages WHERE expires_at <= ?
+         RETURNING id, conversation_id, sender_id, recipient_id",
+    )
+    .bind(now)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(|e| format!("Failed to delete expired messages: {}", e))?;
+
+    let conversation_ids: BTreeSet<&str> = expired
+        .iter()
+        .map(|message| message.conversation_id.as_str())
+        .collect();
+    refresh_conversation_stats(&mut tx, conversation_ids).await?;
+
+    tx.commit()
+        .await
+        .map_err(|e| format!("Failed to commit expired messages: {}", e))?;
+    Ok(expired)
+}
+
+/// SQL recording a gap per conversation for the messages matching
+/// `condition`, to run before they are deleted
+///
+/// Binds the reason first, then the condition's parameters. Retention gaps
+/// are merged into the conversation's existing one.
+fn record_gaps(condition: &str) -> String {
+    format!(
+        "INSERT INTO message_gaps (conversation_id, reason, message_count, first_at, last_at)
+         SELECT conversation_id, ?, COUNT(*), MIN(created_at), MAX(created_at)
+         FROM messages WHERE {}
+         GROUP BY conversation_id
+         ON CONFLICT (conversation_id) WHERE reason = 'retention' DO UPDATE SET
+           message_count = message_count + excluded.message_count,
+           first_at = MIN(first_at, excluded.first_at),
+           last_at = MAX(last_at, excluded.last_at)",
+        condition
+    )
+}
+
+/// Messages removed from a conversation, oldest first
+pub async fn get_message_gaps(
+    pool: &SqlitePool,
+    conversation_id: &str,
+) -> Result<Vec<MessageGap>, String> {
+    sqlx::query_as::<_, MessageGap>(
+        "SELECT reason, message_count, first_at, last_at
+         FROM message_gaps
+         WHERE conversation_id = ?
+         ORDER BY first_at, id",
+    )
+    .bind(conversation_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to get message gaps: {}", e))
+}
+
+/// Recompute `message_count` and `last_message_at` of conversations that
+/// lost messages
+async fn refresh_conversation_stats<'a>(
+    conn: &mut SqliteConnection,
+    conversation_ids: impl IntoIterator<Item = &'a str>,
+) -> Result<(), String> {
+    for conversation_id in conversation_ids {
+        sqlx::query(
+            "UPDATE conversations SET
+               message_count = (SELECT COUNT(*) FROM messages WHERE conversation_id = conversations.id),
+               last_message_at = (SELECT MAX(created_at) FROM messages WHERE conversation_id = conversations.id)
+             WHERE id = ?",
+        )
+        .bind(conversation_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| format!("Failed to update conversation counters: {}", e))?;
+    }
+    Ok(())
+}
+
 /// Anonymize messages from a deleted user
 pub async fn anonymize_user_messages(pool: &SqlitePool, user_id: &str) -> Result<(), String> {
     sqlx::query("UPDATE messages SET is_anonymized = TRUE WHERE sender_id = ?")
@@ -470,6 +844,10 @@ pub async fn anonymize_user_messages(pool: &SqlitePool, user_id: &str) -> Result
 }
 
 /// Search messages in conversation by content
+///
+/// Plaintext rows match on a substring, sealed rows through the blind index
+/// on whole words (all of them must appear). End-to-end encrypted messages
+/// never match; only the clients can read them. Neither do system messages.
 pub async fn search_messages_in_conversation(
     pool: &SqlitePool,
     conversation_id: &str,
@@ -477,29 +855,748 @@ pub async fn search_messages_in_conversation(
     limit: u32,
 ) -> Result<Vec<Message>, String> {
     let search_pattern = format!("%{}%", search_query);
-
-    sqlx::query_as::<_, Message>(
-        "SELECT id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, status, is_anonymized
+    let tokens = at_rest::search_tokens(pool, conversation_id, search_query)
+        .await?
+        .unwrap_or_default();
+
+    let sealed_match = if tokens.is_empty() {
+        String::new()
+    } else {
+        format!(
+            " OR id IN (SELECT message_id FROM message_search_tokens WHERE token IN ({}) GROUP BY message_id HAVING COUNT(*) = ?)",
+            vec!["?"; tokens.len()].join(", ")
+        )
+    };
+    let sql = format!(
+        "SELECT id, conversation_id, sender_id, recipient_id, content, created_at, delivered_at, read_at, status, is_anonymized, is_encrypted, is_sealed, is_system, ephemeral_ttl, expires_at
          FROM messages
-         WHERE conversation_id = ? AND content LIKE ?
+         WHERE conversation_id = ? AND NOT is_encrypted AND NOT is_system AND (expires_at IS NULL OR expires_at > ?)
+           AND ((NOT is_sealed AND content LIKE ?){})
          ORDER BY created_at DESC
-         LIMIT ?"
-    )
-    .bind(conversation_id)
-    .bind(search_pattern)
-    .bind(limit)
-    .fetch_all(pool)
-    .await
-    .map_err(|e| format!("Failed to search messages: {}", e))
+         LIMIT ?",
+        sealed_match
+    );
+
+    let mut query = sqlx::query_as::<_, Message>(&sql)
+        .bind(conversation_id)
+        .bind(chrono::Utc::now().timestamp_millis())
+        .bind(search_pattern);
+    for token in &tokens {
+        query = query.bind(token);
+    }
+    if !tokens.is_empty() {
+        query = query.bind(tokens.len() as i64);
+    }
+    let messages = query
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to search messages: {}", e))?;
+
+    open_messages(pool, messages).await
 }
 
 /// Soft delete user helper
 pub async fn soft_delete_user(pool: &SqlitePool, user_id: &str) -> Result<(), String> {
     delete_user(pool, user_id).await?;
     anonymize_user_messages(pool, user_id).await?;
+    delete_user_keys(pool, user_id).await?;
+    Ok(())
+}
+
+// ============================================================================
+// Key Bundle Queries
+// ============================================================================
+
+/// Insert or replace a user's identity and signed prekey
+pub async fn upsert_identity_keys(pool: &SqlitePool, keys: &IdentityKeys) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO identity_keys (user_id, identity_key, signing_key, signed_prekey_id, signed_prekey, signed_prekey_signature, updated_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(user_id) DO UPDATE SET
+           identity_key = excluded.identity_key,
+           signing_key = excluded.signing_key,
+           signed_prekey_id = excluded.signed_prekey_id,
+           signed_prekey = excluded.signed_prekey,
+           signed_prekey_signature = excluded.signed_prekey_signature,
+           updated_at = excluded.updated_at"
+    )
+    .bind(&keys.user_id)
+    .bind(&keys.identity_key)
+    .bind(&keys.signing_key)
+    .bind(keys.signed_prekey_id)
+    .bind(&keys.signed_prekey)
+    .bind(&keys.signed_prekey_signature)
+    .bind(keys.updated_at)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to store identity keys: {}", e))?;
+    Ok(())
+}
+
+/// Get a user's identity and signed prekey
+pub async fn get_identity_keys(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Option<IdentityKeys>, String> {
+    sqlx::query_as::<_, IdentityKeys>(
+        "SELECT user_id, identity_key, signing_key, signed_prekey_id, signed_prekey, signed_prekey_signature, updated_at
+         FROM identity_keys
+         WHERE user_id = ?",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Failed to get identity keys: {}", e))
+}
+
+/// Add one-time prekeys; a key ID that is already stored keeps its old key
+pub async fn insert_one_time_prekeys(
+    pool: &SqlitePool,
+    user_id: &str,
+    prekeys: &[OneTimePrekey],
+) -> Result<(), String> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| format!("Failed to begin transaction: {}", e))?;
+    for prekey in prekeys {
+        sqlx::query(
+            "INSERT OR IGNORE INTO one_time_prekeys (user_id, key_id, public_key) VALUES (?, ?, ?)",
+        )
+        .bind(user_id)
+        .bind(prekey.key_id)
+        .bind(&prekey.public_key)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| format!("Failed to store one-time prekey: {}", e))?;
+    }
+    tx.commit()
+        .await
+        .map_err(|e| format!("Failed to store one-time prekeys: {}", e))
+}
+
+/// Remove and return one of a user's one-time prekeys, if any are left
+pub async fn take_one_time_prekey(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Option<OneTimePrekey>, String> {
+    sqlx::query_as::<_, OneTimePrekey>(
+        "DELETE FROM one_time_prekeys
+         WHERE user_id = ? AND key_id = (
+           SELECT key_id FROM one_time_prekeys WHERE user_id = ? ORDER BY key_id LIMIT 1
+         )
+         RETURNING key_id, public_key",
+    )
+    .bind(user_id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Failed to take one-time prekey: {}", e))
+}
+
+/// Count a user's remaining one-time prekeys
+pub async fn count_one_time_prekeys(pool: &SqlitePool, user_id: &str) -> Result<i64, String> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM one_time_prekeys WHERE user_id = ?")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| format!("Failed to count one-time prekeys: {}", e))
+}
+
+/// Delete all of a user's published keys
+pub async fn delete_user_keys(pool: &SqlitePool, user_id: &str) -> Result<(), String> {
+    sqlx::query("DELETE FROM one_time_prekeys WHERE user_id = ?")
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to delete one-time prekeys: {}", e))?;
+    sqlx::query("DELETE FROM identity_keys WHERE user_id = ?")
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to delete identity keys: {}", e))?;
+    Ok(())
+}
+
+// ============================================================================
+// Data Export Queries
+// ============================================================================
+
+/// Record a new export request
+pub async fn insert_data_export(pool: &SqlitePool, export: &DataExport) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO data_exports (id, user_id, status, created_at, completed_at, expires_at, size_bytes, error)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&export.id)
+    .bind(&export.user_id)
+    .bind(&export.status)
+    .bind(export.created_at)
+    .bind(export.completed_at)
+    .bind(export.expires_at)
+    .bind(export.size_bytes)
+    .bind(&export.error)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to insert data export: {}", e))?;
     Ok(())
 }
 
+/// Get an export by ID
+pub async fn get_data_export(pool: &SqlitePool, id: &str) -> Result<Option<DataExport>, String> {
+    sqlx::query_as::<_, DataExport>(
+        "SELECT id, user_id, status, created_at, completed_at, expires_at, size_bytes, error
+         FROM data_exports
+         WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Failed to get data export: {}", e))
+}
+
+/// A user's most recent export that is still being built or can be downloaded
+pub async fn find_active_data_export(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Option<DataExport>, String> {
+    sqlx::query_as::<_, DataExport>(
+        "SELECT id, user_id, status, created_at, completed_at, expires_at, size_bytes, error
+         FROM data_exports
+         WHERE user_id = ? AND (status = 'pending' OR (status = 'ready' AND expires_at > ?))
+         ORDER BY created_at DESC
+         LIMIT 1",
+    )
+    .bind(user_id)
+    .bind(chrono::Utc::now().timestamp_millis())
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Failed to find data export: {}", e))
+}
+
+/// Mark a pending export ready for download until `expires_at`
+///
+/// Returns false if the export is no longer pending, e.g. because its user
+/// deleted their account while it was being built.
+pub async fn complete_data_export(
+    pool: &SqlitePool,
+    id: &str,
+    size_bytes: i64,
+    expires_at: i64,
+) -> Result<bool, String> {
+    sqlx::query(
+        "UPDATE data_exports
+         SET status = 'ready', completed_at = ?, expires_at = ?, size_bytes = ?
+         WHERE id = ? AND status = 'pending'",
+    )
+    .bind(chrono::Utc::now().timestamp_millis())
+    .bind(expires_at)
+    .bind(size_bytes)
+    .bind(id)
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected() > 0)
+    .map_err(|e| format!("Failed to complete data export: {}", e))
+}
+
+/// Mark pending exports failed: one by ID, or all of them when `id` is `None`
+pub async fn fail_data_exports(
+    pool: &SqlitePool,
+    id: Option<&str>,
+    error: &str,
+) -> Result<u64, String> {
+    sqlx::query(
+        "UPDATE data_exports
+         SET status = 'failed', completed_at = ?, error = ?
+         WHERE status = 'pending' AND (?3 IS NULL OR id = ?3)",
+    )
+    .bind(chrono::Utc::now().timestamp_millis())
+    .bind(error)
+    .bind(id)
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected())
+    .map_err(|e| format!("Failed to fail data exports: {}", e))
+}
+
+/// Mark ready exports past their expiry expired, returning their IDs
+pub async fn expire_data_exports(pool: &SqlitePool) -> Result<Vec<String>, String> {
+    sqlx::query_scalar(
+        "UPDATE data_exports SET status = 'expired'
+         WHERE status = 'ready' AND expires_at <= ?
+         RETURNING id",
+    )
+    .bind(chrono::Utc::now().timestamp_millis())
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to expire data exports: {}", e))
+}
+
+/// Expire every pending or ready export of a user, returning the IDs of all
+/// of their exports
+pub async fn expire_user_data_exports(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Vec<String>, String> {
+    sqlx::query_scalar(
+        "UPDATE data_exports
+         SET status = CASE WHEN status IN ('pending', 'ready') THEN 'expired' ELSE status END
+         WHERE user_id = ?
+         RETURNING id",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to expire data exports: {}", e))
+}
+
+// ============================================================================
+// Block Queries
+// ============================================================================
+
+/// Block `blocked_id` for `blocker_id`
+///
+/// Returns false if the block already existed.
+pub async fn insert_block(
+    pool: &SqlitePool,
+    blocker_id: &str,
+    blocked_id: &str,
+) -> Result<bool, String> {
+    let result = sqlx::query(
+        "INSERT OR IGNORE INTO user_blocks (blocker_id, blocked_id, created_at) VALUES (?, ?, ?)",
+    )
+    .bind(blocker_id)
+    .bind(blocked_id)
+    .bind(chrono::Utc::now().timestamp_millis())
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to block user: {}", e))?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Remove a block; returns false if there was none
+pub async fn delete_block(
+    pool: &SqlitePool,
+    blocker_id: &str,
+    blocked_id: &str,
+) -> Result<bool, String> {
+    let result = sqlx::query("DELETE FROM user_blocks WHERE blocker_id = ? AND blocked_id = ?")
+        .bind(blocker_id)
+        .bind(blocked_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to unblock user: {}", e))?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Users blocked by `blocker_id`, most recently blocked first
+pub async fn get_blocked_users(
+    pool: &SqlitePool,
+    blocker_id: &str,
+) -> Result<Vec<BlockedUser>, String> {
+    sqlx::query_as::<_, BlockedUser>(
+        "SELECT users.id AS user_id, users.username, user_blocks.created_at AS blocked_at
+         FROM user_blocks
+         JOIN users ON users.id = user_blocks.blocked_id
+         WHERE user_blocks.blocker_id = ?
+         ORDER BY user_blocks.created_at DESC",
+    )
+    .bind(blocker_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to get blocked users: {}", e))
+}
+
+/// Whether `blocker_id` has blocked `blocked_id`
+pub async fn is_blocked(
+    pool: &SqlitePool,
+    blocker_id: &str,
+    blocked_id: &str,
+) -> Result<bool, String> {
+    sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM user_blocks WHERE blocker_id = ? AND blocked_id = ?)",
+    )
+    .bind(blocker_id)
+    .bind(blocked_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| format!("Failed to check block: {}", e))
+}
+
+/// Whether either user has blocked the other
+pub async fn is_blocked_either_way(
+    pool: &SqlitePool,
+    user_a: &str,
+    user_b: &str,
+) -> Result<bool, String> {
+    sqlx::query_scalar(
+        "SELECT EXISTS (
+           SELECT 1 FROM user_blocks
+           WHERE (blocker_id = ?1 AND blocked_id = ?2) OR (blocker_id = ?2 AND blocked_id = ?1)
+         )",
+    )
+    .bind(user_a)
+    .bind(user_b)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| format!("Failed to check block: {}", e))
+}
+
+/// Users `user_id` has blocked or been blocked by
+pub async fn get_block_partner_ids(pool: &SqlitePool, user_id: &str) -> Result<Vec<String>, String> {
+    sqlx::query_scalar(
+        "SELECT blocked_id FROM user_blocks WHERE blocker_id = ?1
+         UNION
+         SELECT blocker_id FROM user_blocks WHERE blocked_id = ?1",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to get blocks: {}", e))
+}
+
+// ============================================================================
+// Message Request Queries
+// ============================================================================
+
+const MESSAGE_REQUEST_COLUMNS: &str =
+    "r.conversation_id, r.requester_id, users.username AS requester_username, r.recipient_id,
+     r.status, r.created_at,
+     (SELECT COUNT(*) FROM messages WHERE messages.conversation_id = r.conversation_id) AS message_count,
+     (SELECT MAX(created_at) FROM messages WHERE messages.conversation_id = r.conversation_id) AS last_message_at";
+
+/// Hold a new conversation as a message request from `requester_id`
+pub async fn insert_message_request(
+    pool: &SqlitePool,
+    conversation_id: &str,
+    requester_id: &str,
+    recipient_id: &str,
+) -> Result<(), String> {
+    sqlx::query(
+        "INSERT OR IGNORE INTO message_requests (conversation_id, requester_id, recipient_id, created_at)
+         VALUES (?, ?, ?, ?)",
+    )
+    .bind(conversation_id)
+    .bind(requester_id)
+    .bind(recipient_id)
+    .bind(chrono::Utc::now().timestamp_millis())
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to create message request: {}", e))?;
+    Ok(())
+}
+
+/// The message request holding a conversation, if any
+pub async fn get_message_request(
+    pool: &SqlitePool,
+    conversation_id: &str,
+) -> Result<Option<MessageRequest>, String> {
+    sqlx::query_as::<_, MessageRequest>(&format!(
+        "SELECT {} FROM message_requests r
+         JOIN users ON users.id = r.requester_id
+         WHERE r.conversation_id = ?",
+        MESSAGE_REQUEST_COLUMNS
+    ))
+    .bind(conversation_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Failed to get message request: {}", e))
+}
+
+/// Pending requests to `recipient_id`, newest first, leaving out blocked senders
+pub async fn get_message_requests(
+    pool: &SqlitePool,
+    recipient_id: &str,
+) -> Result<Vec<MessageRequest>, String> {
+    sqlx::query_as::<_, MessageRequest>(&format!(
+        "SELECT {} FROM message_requests r
+         JOIN users ON users.id = r.requester_id
+         WHERE r.recipient_id = ?1 AND r.status = 'pending' AND users.deleted_at IS NULL
+           AND NOT EXISTS (
+             SELECT 1 FROM user_blocks WHERE blocker_id = ?1 AND blocked_id = r.requester_id
+           )
+         ORDER BY r.created_at DESC",
+        MESSAGE_REQUEST_COLUMNS
+    ))
+    .bind(recipient_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to get message requests: {}", e))
+}
+
+/// Accept a request; returns false if the conversation had none
+pub async fn delete_message_request(pool: &SqlitePool, conversation_id: &str) -> Result<bool, String> {
+    let result = sqlx::query("DELETE FROM message_requests WHERE conversation_id = ?")
+        .bind(conversation_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to accept message request: {}", e))?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Decline a request, deleting the messages it held with their search index
+/// entries and recording a gap in their place
+pub async fn decline_message_request(pool: &SqlitePool, conversation_id: &str) -> Result<(), String> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| format!("Failed to begin transaction: {}", e))?;
+
+    sqlx::query(&record_gaps("conversation_id = ?"))
+        .bind("declined")
+        .bind(conversation_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| format!("Failed to record declined messages: {}", e))?;
+
+    let statements = [
+        "UPDATE message_requests SET status = 'declined' WHERE conversation_id = ?",
+        "DELETE FROM message_search_tokens
+         WHERE message_id IN (SELECT id FROM messages WHERE conversation_id = ?)",
+        "DELETE FROM messages WHERE conversation_id = ?",
+    ];
+    for statement in statements {
+        sqlx::query(statement)
+            .bind(conversation_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| format!("Failed to decline message request: {}", e))?;
+    }
+
+    tx.commit()
+        .await
+        .map_err(|e| format!("Failed to commit declined request: {}", e))
+}
+
+/// Users `user_id` has a message request with, in either direction
+pub async fn get_message_request_partner_ids(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<Vec<String>, String> {
+    sqlx::query_scalar(
+        "SELECT recipient_id FROM message_requests WHERE requester_id = ?1
+         UNION
+         SELECT requester_id FROM message_requests WHERE recipient_id = ?1",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to get message requests: {}", e))
+}
+
+// ============================================================================
+// Retention Queries
+// ============================================================================
+
+/// Count messages created before `cutoff` (epoch milliseconds)
+pub async fn count_messages_before(pool: &SqlitePool, cutoff: i64) -> Result<i64, String> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM messages WHERE created_at < ?")
+        .bind(cutoff)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| format!("Failed to count old messages: {}", e))
+}
+
+/// Delete up to `limit` of the oldest messages created before `cutoff`, with
+/// their search index entries, recording a gap in their place and refreshing
+/// the counters of the conversations they belonged to
+///
+/// Returns the number of messages deleted.
+pub async fn purge_messages_before(
+    pool: &SqlitePool,
+    cutoff: i64,
+    limit: u32,
+) -> Result<u64, String> {
+    const BATCH: &str =
+        "SELECT id FROM messages WHERE created_at < ? ORDER BY created_at, id LIMIT ?";
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| format!("Failed to begin transaction: {}", e))?;
+
+    sqlx::query(&format!(
+        "DELETE FROM message_search_tokens WHERE message_id IN ({})",
+        BATCH
+    ))
+    .bind(cutoff)
+    .bind(limit)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| format!("Failed to delete old search tokens: {}", e))?;
+
+    sqlx::query(&record_gaps(&format!("id IN ({})", BATCH)))
+        .bind("retention")
+        .bind(cutoff)
+        .bind(limit)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| format!("Failed to record old messages: {}", e))?;
+
+    let conversation_ids: Vec<String> = sqlx::query_scalar(&format!(
+        "DELETE FROM messages WHERE id IN ({}) RETURNING conversation_id",
+        BATCH
+    ))
+    .bind(cutoff)
+    .bind(limit)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(|e| format!("Failed to delete old messages: {}", e))?;
+
+    let affected: BTreeSet<&str> = conversation_ids.iter().map(String::as_str).collect();
+    refresh_conversation_stats(&mut tx, affected).await?;
+
+    tx.commit()
+        .await
+        .map_err(|e| format!("Failed to commit old messages: {}", e))?;
+    Ok(conversation_ids.len() as u64)
+}
+
+/// Count auth log entries created before `cutoff`
+pub async fn count_auth_logs_before(pool: &SqlitePool, cutoff: i64) -> Result<i64, String> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM auth_logs WHERE created_at < ?")
+        .bind(cutoff)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| format!("Failed to count old auth logs: {}", e))
+}
+
+/// Delete up to `limit` of the oldest auth log entries created before `cutoff`
+pub async fn purge_auth_logs_before(
+    pool: &SqlitePool,
+    cutoff: i64,
+    limit: u32,
+) -> Result<u64, String> {
+    sqlx::query(
+        "DELETE FROM auth_logs WHERE id IN (
+           SELECT id FROM auth_logs WHERE created_at < ? ORDER BY created_at, id LIMIT ?
+         )",
+    )
+    .bind(cutoff)
+    .bind(limit)
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected())
+    .map_err(|e| format!("Failed to delete old auth logs: {}", e))
+}
+
+/// Count users soft-deleted before `cutoff` and not purged yet
+pub async fn count_users_deleted_before(pool: &SqlitePool, cutoff: i64) -> Result<i64, String> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE deleted_at < ? AND purged_at IS NULL")
+        .bind(cutoff)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| format!("Failed to count deleted users: {}", e))
+}
+
+/// Purge up to `limit` users soft-deleted before `cutoff`
+///
+/// Each user goes in its own transaction, together with their keys, exports
+/// and blocks. A conversation whose other participant is still active, and
+/// that got past a message request, is theirs to keep: it stays with the
+/// departed user's messages anonymized, and the departed user's row stays as
+/// a tombstone without their username or credentials, so those messages keep
+/// a sender. Every other conversation is deleted with its messages. Returns
+/// the number of users purged and the IDs of the exports deleted with them,
+/// whose archives are left for the caller to remove.
+pub async fn purge_users_deleted_before(
+    pool: &SqlitePool,
+    cutoff: i64,
+    limit: u32,
+) -> Result<(u64, Vec<String>), String> {
+    let user_ids: Vec<String> = sqlx::query_scalar(
+        "SELECT id FROM users WHERE deleted_at < ? AND purged_at IS NULL
+         ORDER BY deleted_at, id LIMIT ?",
+    )
+    .bind(cutoff)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to find deleted users: {}", e))?;
+
+    let mut export_ids = Vec::new();
+    for user_id in &user_ids {
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| format!("Failed to begin transaction: {}", e))?;
+        let purge_error = |e: sqlx::Error| format!("Failed to purge user {}: {}", user_id, e);
+
+        // Conversations nobody active is left in, or still at the request stage
+        let abandoned: Vec<String> = sqlx::query_scalar(
+            "SELECT c.id FROM conversations c
+             JOIN users other
+               ON other.id = CASE WHEN c.user1_id = ?1 THEN c.user2_id ELSE c.user1_id END
+             WHERE (c.user1_id = ?1 OR c.user2_id = ?1)
+               AND (other.deleted_at IS NOT NULL
+                    OR EXISTS (SELECT 1 FROM message_requests r WHERE r.conversation_id = c.id))",
+        )
+        .bind(user_id)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(purge_error)?;
+
+        // Children first, in foreign key order
+        for conversation_id in &abandoned {
+            let statements = [
+                "DELETE FROM message_search_tokens WHERE message_id IN (
+                   SELECT id FROM messages WHERE conversation_id = ?1
+                 )",
+                "DELETE FROM messages WHERE conversation_id = ?1",
+                "DELETE FROM data_keys WHERE scope = 'conversation:' || ?1",
+                "DELETE FROM message_gaps WHERE conversation_id = ?1",
+                "DELETE FROM message_requests WHERE conversation_id = ?1",
+                "DELETE FROM conversations WHERE id = ?1",
+            ];
+            for statement in statements {
+                sqlx::query(statement)
+                    .bind(conversation_id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(purge_error)?;
+            }
+        }
+
+        let deleted_exports: Vec<String> =
+            sqlx::query_scalar("DELETE FROM data_exports WHERE user_id = ? RETURNING id")
+                .bind(user_id)
+                .fetch_all(&mut *tx)
+                .await
+                .map_err(purge_error)?;
+
+        let statements = [
+            "UPDATE messages SET is_anonymized = TRUE WHERE sender_id = ?1",
+            "DELETE FROM one_time_prekeys WHERE user_id = ?1",
+            "DELETE FROM user_blocks WHERE blocker_id = ?1 OR blocked_id = ?1",
+            "DELETE FROM identity_keys WHERE user_id = ?1",
+            // The username is freed for someone else; a hyphen never appears
+            // in a chosen one, and '!' matches no password
+            "UPDATE users SET username = 'deleted-' || id, password_hash = '!', password_salt = '',
+               is_online = FALSE, last_seen_at = NULL, purged_at = ?2
+             WHERE id = ?1",
+            // Tombstones, this one included, that no conversation needs any more
+            "DELETE FROM users WHERE purged_at IS NOT NULL
+               AND id NOT IN (SELECT user1_id FROM conversations UNION SELECT user2_id FROM conversations)",
+        ];
+        let now = chrono::Utc::now().timestamp_millis();
+        for statement in statements {
+            sqlx::query(statement)
+                .bind(user_id)
+                .bind(now)
+                .execute(&mut *tx)
+                .await
+                .map_err(purge_error)?;
+        }
+
+        tx.commit()
+            .await
+            .map_err(|e| format!("Failed to commit user purge: {}", e))?;
+        export_ids.extend(deleted_exports);
+    }
+
+    Ok((user_ids.len() as u64, export_ids))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -511,10 +1608,7 @@ mod tests {
             .await?;
 
         // Run migrations
-        let schema_sql = include_str!("../migrations/001_initial_schema.sql");
-        for statement in schema_sql.split(';').filter(|s| !s.trim().is_empty()) {
-            sqlx::query(statement).execute(&pool).await?;
-        }
+        crate::db::run_migrations(&pool).await?;
 
         // Create and insert user
         let user = User::new(
@@ -541,10 +1635,7 @@ mod tests {
             .await?;
 
         // Run migrations
-        let schema_sql = include_str!("../migrations/001_initial_schema.sql");
-        for statement in schema_sql.split(';').filter(|s| !s.trim().is_empty()) {
-            sqlx::query(statement).execute(&pool).await?;
-        }
+        crate::db::run_migrations(&pool).await?;
 
         // Insert a benign user
         let user = User::new(
@@ -567,4 +1658,31 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_mark_all_users_offline() -> Result<(), Box<dyn std::error::Error>> {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await?;
+
+        // Run migrations
+        crate::db::run_migrations(&pool).await?;
+
+        for name in ["alice", "bob", "carol"] {
+            let user = User::new(name.to_string(), "hash".to_string(), "salt".to_string());
+            insert_user(&pool, &user).await?;
+            if name != "carol" {
+                update_online_status(&pool, &user.id, true).await?;
+            }
+        }
+
+        assert_eq!(mark_all_users_offline(&pool).await?, 2);
+
+        let online: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM users WHERE is_online = TRUE")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(online.0, 0);
+
+        Ok(())
+    }
 }
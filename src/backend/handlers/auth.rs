@@ -5,6 +5,7 @@
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 use tracing::{info, warn};
+use utoipa::ToSchema;
 use warp::{reply, Rejection, Reply};
 
 use crate::db::queries;
@@ -14,21 +15,21 @@ use crate::validators;
 use std::sync::Arc;
 
 /// Signup request payload
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct SignupRequest {
     pub username: String,
     pub password: String,
 }
 
 /// Login request payload
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct LoginRequest {
     pub username: String,
     pub password: String,
 }
 
 /// Authentication response (signup and login)
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct AuthResponse {
     pub user_id: String,
     pub username: String,
@@ -37,12 +38,18 @@ pub struct AuthResponse {
 }
 
 /// Error response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ErrorResponse {
     pub error: String,
     pub message: String,
 }
 
+/// Confirmation response for actions without a resource to return
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SuccessResponse {
+    pub message: String,
+}
+
 /// Unified HTTP response that can be either success or error
 pub struct HttpResponse {
     pub status: u16,
@@ -60,6 +67,16 @@ impl Reply for HttpResponse {
 }
 
 /// Handle POST /auth/logout
+#[utoipa::path(
+    post,
+    path = "/auth/logout",
+    tag = "auth",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Logged out; open sockets are closed", body = SuccessResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+    )
+)]
 pub async fn logout_handler(
     user_id: String,
     connection_manager: Arc<ConnectionManager>,
@@ -85,12 +102,26 @@ pub async fn logout_handler(
     connection_manager.disconnect_user(&user_id).await;
 
     Ok(reply::with_status(
-        reply::json(&serde_json::json!({ "message": "Logged out successfully" })),
+        reply::json(&SuccessResponse {
+            message: "Logged out successfully".to_string(),
+        }),
         warp::http::StatusCode::OK,
     ))
 }
 
 /// Handle POST /auth/signup
+#[utoipa::path(
+    post,
+    path = "/auth/signup",
+    tag = "auth",
+    request_body = SignupRequest,
+    responses(
+        (status = 201, description = "Account created", body = AuthResponse),
+        (status = 400, description = "Invalid username or password", body = ErrorResponse),
+        (status = 409, description = "Username already taken", body = ErrorResponse),
+        (status = 429, description = "Too many attempts", body = ErrorResponse),
+    )
+)]
 pub async fn signup_handler(
     req: SignupRequest,
     pool: SqlitePool,
@@ -207,6 +238,17 @@ pub async fn signup_handler(
 }
 
 /// Handle POST /auth/login
+#[utoipa::path(
+    post,
+    path = "/auth/login",
+    tag = "auth",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "Logged in", body = AuthResponse),
+        (status = 401, description = "Wrong username or password", body = ErrorResponse),
+        (status = 429, description = "Too many attempts", body = ErrorResponse),
+    )
+)]
 pub async fn login_handler(
     req: LoginRequest,
     pool: SqlitePool,
@@ -0,0 +1,180 @@
+//! Per-message compression of WebSocket frames
+//!
+//! Connections that negotiated `frame-deflate-v1` get outbound frames at or
+//! above the size threshold deflated; small frames such as typing indicators are
+//! sent as-is. Inbound compressed frames are expanded with a size cap so a
+//! small frame cannot inflate past `max_message_size`.
+
+use chat_shared::protocol::compression::{
+    self, InflateError, PayloadKind, DEFAULT_COMPRESSION_THRESHOLD,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use warp::ws::Message as WsMessage;
+
+/// Compression policy
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    /// Whether clients may negotiate compression at all
+    pub enabled: bool,
+    /// Smallest payload, in bytes, worth compressing
+    pub threshold: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        }
+    }
+}
+
+/// Server-wide compression counters
+#[derive(Default)]
+pub struct CompressionMetrics {
+    frames_compressed: AtomicU64,
+    bytes_before: AtomicU64,
+    bytes_after: AtomicU64,
+    frames_decompressed: AtomicU64,
+}
+
+/// Snapshot of `CompressionMetrics` for `/status`
+#[derive(Debug, Clone, serde::Serialize, utoipa::ToSchema)]
+pub struct CompressionStats {
+    pub frames_compressed: u64,
+    /// Payload bytes of compressed frames before compression
+    pub bytes_before: u64,
+    /// The same frames' bytes on the wire
+    pub bytes_after: u64,
+    pub bytes_saved: u64,
+    pub frames_decompressed: u64,
+}
+
+impl CompressionMetrics {
+    pub fn snapshot(&self) -> CompressionStats {
+        let bytes_before = self.bytes_before.load(Ordering::Relaxed);
+        let bytes_after = self.bytes_after.load(Ordering::Relaxed);
+        CompressionStats {
+            frames_compressed: self.frames_compressed.load(Ordering::Relaxed),
+            bytes_before,
+            bytes_after,
+            bytes_saved: bytes_before.saturating_sub(bytes_after),
+            frames_decompressed: self.frames_decompressed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Compresses one connection's outbound frames
+#[derive(Clone)]
+pub struct FrameCompressor {
+    threshold: usize,
+    metrics: Arc<CompressionMetrics>,
+}
+
+impl FrameCompressor {
+    pub fn new(threshold: usize, metrics: Arc<CompressionMetrics>) -> Self {
+        Self { threshold, metrics }
+    }
+
+    /// Deflate a text or binary frame if it is large enough and actually shrinks
+    pub fn compress(&self, msg: WsMessage) -> WsMessage {
+        let kind = if msg.is_text() {
+            PayloadKind::Text
+        } else if msg.is_binary() {
+            PayloadKind::Binary
+        } else {
+            return msg;
+        };
+
+        let payload = msg.as_bytes();
+        if payload.len() < self.threshold {
+            return msg;
+        }
+
+        let frame = compression::deflate(payload, kind);
+        if frame.len() >= payload.len() {
+            return msg;
+        }
+
+        self.metrics
+            .frames_compressed
+            .fetch_add(1, Ordering::Relaxed);
+        self.metrics
+            .bytes_before
+            .fetch_add(payload.len() as u64, Ordering::Relaxed);
+        self.metrics
+            .bytes_after
+            .fetch_add(frame.len() as u64, Ordering::Relaxed);
+        WsMessage::binary(frame)
+    }
+}
+
+/// Expand a compressed inbound frame into the frame it carries, producing at
+/// most `limit` bytes; other frames are returned unchanged
+pub fn inflate_frame(
+    msg: &WsMessage,
+    limit: usize,
+    metrics: &CompressionMetrics,
+) -> Result<WsMessage, InflateError> {
+    if !msg.is_binary() || !compression::is_compressed(msg.as_bytes()) {
+        return Ok(msg.clone());
+    }
+
+    let (payload, kind) = compression::inflate(msg.as_bytes(), limit)?;
+    metrics.frames_decompressed.fetch_add(1, Ordering::Relaxed);
+    match kind {
+        PayloadKind::Text => String::from_utf8(payload)
+            .map(WsMessage::text)
+            .map_err(|e| InflateError::Invalid(e.to_string())),
+        PayloadKind::Binary => Ok(WsMessage::binary(payload)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compressor(threshold: usize) -> (FrameCompressor, Arc<CompressionMetrics>) {
+        let metrics = Arc::new(CompressionMetrics::default());
+        (FrameCompressor::new(threshold, metrics.clone()), metrics)
+    }
+
+    #[test]
+    fn test_small_frames_are_not_compressed() {
+        let (compressor, metrics) = compressor(64);
+        let typing = WsMessage::text(r#"{"type":"typing","data":{"isTyping":true}}"#);
+        assert!(compressor.compress(typing).is_text());
+        assert_eq!(metrics.snapshot().frames_compressed, 0);
+    }
+
+    #[test]
+    fn test_large_frames_round_trip_and_report_savings() {
+        let (compressor, metrics) = compressor(64);
+        let history = format!(r#"{{"type":"history","data":"{}"}}"#, "hello ".repeat(200));
+
+        let compressed = compressor.compress(WsMessage::text(history.clone()));
+        assert!(compressed.is_binary());
+
+        let stats = metrics.snapshot();
+        assert_eq!(stats.frames_compressed, 1);
+        assert_eq!(stats.bytes_before, history.len() as u64);
+        assert!(stats.bytes_saved > 0);
+
+        let restored = inflate_frame(&compressed, 10 * 1024, &metrics).unwrap();
+        assert_eq!(restored.to_str().unwrap(), history);
+        assert_eq!(metrics.snapshot().frames_decompressed, 1);
+    }
+
+    #[test]
+    fn test_inflate_applies_limit_to_decompressed_size() {
+        let (compressor, metrics) = compressor(0);
+        let big = compressor.compress(WsMessage::text("x".repeat(64 * 1024)));
+        assert!(big.as_bytes().len() < 1024);
+
+        assert_eq!(
+            inflate_frame(&big, 10 * 1024, &metrics),
+            Err(InflateError::TooLarge { limit: 10 * 1024 })
+        );
+    }
+}
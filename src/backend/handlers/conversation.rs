@@ -1,23 +1,35 @@
 //! Conversation management endpoints
 //!
-//! Handles conversation creation, retrieval, and participant management
+//! Handles conversation creation, retrieval, and participant management,
+//! including message requests: a new conversation is held from the other user
+//! until they accept it
 
 use crate::db::queries;
 use crate::handlers::auth::ErrorResponse;
-use crate::services::{ConversationService, MessageService};
+use crate::handlers::messages::{MessageHandler, SendError};
+use crate::handlers::websocket::ClientConnection;
+use crate::services::ephemeral::SetTtlError;
+use crate::services::transcript::{self, ExportFormat};
+use crate::models::MessageRequest;
+use crate::services::{
+    ConversationService, EphemeralService, MessageRequestService, MessageService,
+};
+use chat_shared::protocol::{MessageDto, TextMessageData};
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
 use warp::{reply, Rejection, Reply};
 
 /// Start conversation request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct StartConversationRequest {
     pub other_user_id: String,
 }
 
 /// Conversation response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ConversationResponse {
     pub conversation_id: String,
     pub participant_id: String,
@@ -26,11 +38,47 @@ pub struct ConversationResponse {
     pub created_at: i64,
     pub last_message_at: Option<i64>,
     pub message_count: i32,
+    /// Messages are end-to-end encrypted; plaintext sends are refused
+    pub encrypted: bool,
+    /// Seconds new messages are kept before they disappear, if set
+    pub ephemeral_ttl: Option<i64>,
+    /// `sent` or `read`: when the disappearing-message timer starts
+    pub ephemeral_start: String,
+    /// A message request in this conversation has not been accepted yet;
+    /// messages are held and presence is hidden until it is
+    pub request_pending: bool,
+}
+
+/// A message request, as listed to its recipient
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MessageRequestResponse {
+    pub conversation_id: String,
+    pub requester_id: String,
+    pub requester_username: String,
+    pub created_at: i64,
+    /// Messages held until the request is accepted
+    pub message_count: i64,
+    pub last_message_at: Option<i64>,
+}
+
+impl From<MessageRequest> for MessageRequestResponse {
+    fn from(request: MessageRequest) -> Self {
+        MessageRequestResponse {
+            conversation_id: request.conversation_id,
+            requester_id: request.requester_id,
+            requester_username: request.requester_username,
+            created_at: request.created_at,
+            message_count: request.message_count,
+            last_message_at: request.last_message_at,
+        }
+    }
 }
 
 /// Conversations list query parameters
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ConversationsQuery {
+    /// Page size (default 20, capped at 50)
     #[serde(default = "default_limit")]
     pub limit: u32,
     #[serde(default)]
@@ -41,9 +89,24 @@ fn default_limit() -> u32 {
     20
 }
 
+/// Conversation export query parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    /// `json` (default), `markdown` or `html`
+    #[serde(default = "default_export_format")]
+    pub format: String,
+}
+
+fn default_export_format() -> String {
+    "json".to_string()
+}
+
 /// Messages query parameters
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct MessagesQuery {
+    /// Page size (default 50, capped at 100)
     #[serde(default = "default_messages_limit")]
     pub limit: u32,
     #[serde(default)]
@@ -55,15 +118,17 @@ fn default_messages_limit() -> u32 {
 }
 
 /// Search query parameters
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct SearchMessagesQuery {
+    /// Keyword to match in message content
     pub q: String,
     #[serde(default = "default_messages_limit")]
     pub limit: u32,
 }
 
 /// Message response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct MessageResponse {
     pub id: String,
     pub sender_id: String,
@@ -73,11 +138,81 @@ pub struct MessageResponse {
     pub created_at: i64,
     pub delivered_at: Option<i64>,
     pub status: String,
+    /// `content` is an end-to-end encrypted envelope
+    pub encrypted: bool,
+    /// Written by the server to announce a conversation change
+    pub system: bool,
+    /// When the message disappears (milliseconds), for disappearing messages
+    pub expires_at: Option<i64>,
+}
+
+/// Send message request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SendMessageRequest {
+    pub content: String,
+    /// `content` is an end-to-end encrypted envelope (up to 16384 bytes)
+    #[serde(default)]
+    pub encrypted: bool,
+}
+
+/// Send message response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SendMessageResponse {
+    /// Same fields as `MessageResponse`
+    #[schema(value_type = MessageResponse)]
+    pub message: MessageDto,
+    /// `delivered` if the recipient has it, otherwise `sent`
+    pub status: String,
+}
+
+/// Disappearing messages request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct EphemeralRequest {
+    /// Seconds new messages are kept (5 to 2419200), or null to keep them
+    pub ephemeral_ttl: Option<i64>,
+    /// `sent` (default) or `read`: when the timer starts
+    #[serde(default = "default_ephemeral_start")]
+    pub ephemeral_start: String,
+}
+
+fn default_ephemeral_start() -> String {
+    "sent".to_string()
 }
 
+/// Disappearing messages response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EphemeralResponse {
+    pub conversation_id: String,
+    pub ephemeral_ttl: Option<i64>,
+    pub ephemeral_start: String,
+    /// System message announcing the change; absent if nothing changed
+    pub system_message: Option<MessageResponse>,
+}
+
+/// Maximum accepted `Idempotency-Key` length
+pub const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 128;
+
 /// Handle POST /conversations/start
 ///
-/// Creates or retrieves existing conversation between current user and other user
+/// Creates or retrieves existing conversation between current user and other user.
+/// A new conversation is a message request to the other user. Starting one
+/// with someone whose request the caller holds returns it still pending; only
+/// POST /conversations/{id}/accept accepts it.
+#[utoipa::path(
+    post,
+    path = "/conversations/start",
+    tag = "conversations",
+    security(("bearer" = [])),
+    request_body = StartConversationRequest,
+    responses(
+        (status = 200, description = "Existing conversation", body = ConversationResponse),
+        (status = 201, description = "Conversation created", body = ConversationResponse),
+        (status = 400, description = "Cannot start a conversation with yourself", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 404, description = "Other user not found", body = ErrorResponse),
+        (status = 410, description = "Other user deleted their account", body = ErrorResponse),
+    )
+)]
 pub async fn start_conversation(
     user_id: String,
     request: StartConversationRequest,
@@ -155,6 +290,37 @@ pub async fn start_conversation(
         conversation.user1_id.clone()
     };
 
+    let request_pending = if was_created {
+        queries::insert_message_request(&pool, &conversation.id, &user_id, &participant_id)
+            .await
+            .map(|_| true)
+    } else {
+        queries::get_message_request(&pool, &conversation.id)
+            .await
+            .map(|request| request.is_some())
+    };
+    let request_pending = match request_pending {
+        Ok(pending) => pending,
+        Err(e) => {
+            warn!("Failed to update message request: {}", e);
+            return Ok(reply::with_status(
+                reply::json(&ErrorResponse {
+                    error: "DATABASE_ERROR".to_string(),
+                    message: "Failed to create conversation".to_string(),
+                }),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ));
+        }
+    };
+
+    // Presence stays hidden between users who have blocked each other, and
+    // until a message request is accepted
+    let participant_is_online = other_user.is_online
+        && !request_pending
+        && !queries::is_blocked_either_way(&pool, &user_id, &participant_id)
+            .await
+            .unwrap_or(true);
+
     let status_code = if was_created {
         warp::http::StatusCode::CREATED
     } else {
@@ -166,10 +332,14 @@ pub async fn start_conversation(
             conversation_id: conversation.id,
             participant_id: participant_id.clone(),
             participant_username: other_user.username,
-            participant_is_online: other_user.is_online,
+            participant_is_online,
             created_at: conversation.created_at,
             last_message_at: conversation.last_message_at,
             message_count: conversation.message_count,
+            encrypted: conversation.is_encrypted,
+            ephemeral_ttl: conversation.ephemeral_ttl,
+            ephemeral_start: conversation.ephemeral_start,
+            request_pending,
         }),
         status_code,
     ))
@@ -178,6 +348,17 @@ pub async fn start_conversation(
 /// Handle GET /conversations?limit=20&offset=0
 ///
 /// Returns list of conversations for the current user
+#[utoipa::path(
+    get,
+    path = "/conversations",
+    tag = "conversations",
+    security(("bearer" = [])),
+    params(ConversationsQuery),
+    responses(
+        (status = 200, description = "Conversations, most recent first", body = [ConversationResponse]),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+    )
+)]
 pub async fn get_conversations(
     user_id: String,
     query: ConversationsQuery,
@@ -205,6 +386,37 @@ pub async fn get_conversations(
         }
     };
 
+    // Presence stays hidden between users who have blocked each other, and
+    // until a message request is accepted. The caller's own unaccepted
+    // requests are the only ones listed.
+    let hidden = match queries::get_block_partner_ids(&pool, &user_id).await {
+        Ok(ids) => ids,
+        Err(e) => {
+            warn!("Failed to get blocked users: {}", e);
+            return Ok(reply::with_status(
+                reply::json(&ErrorResponse {
+                    error: "DATABASE_ERROR".to_string(),
+                    message: "Failed to retrieve conversations".to_string(),
+                }),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ));
+        }
+    };
+
+    let pending = match queries::get_message_request_partner_ids(&pool, &user_id).await {
+        Ok(ids) => ids,
+        Err(e) => {
+            warn!("Failed to get message requests: {}", e);
+            return Ok(reply::with_status(
+                reply::json(&ErrorResponse {
+                    error: "DATABASE_ERROR".to_string(),
+                    message: "Failed to retrieve conversations".to_string(),
+                }),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ));
+        }
+    };
+
     // Enrich with participant info
     let mut responses = Vec::new();
     for conv in conversations {
@@ -229,10 +441,16 @@ pub async fn get_conversations(
             conversation_id: conv.id,
             participant_id: participant.id,
             participant_username: participant.username,
-            participant_is_online: participant.is_online,
+            participant_is_online: participant.is_online
+                && !hidden.contains(participant_id)
+                && !pending.contains(participant_id),
             created_at: conv.created_at,
             last_message_at: conv.last_message_at,
             message_count: conv.message_count,
+            encrypted: conv.is_encrypted,
+            ephemeral_ttl: conv.ephemeral_ttl,
+            ephemeral_start: conv.ephemeral_start,
+            request_pending: pending.contains(participant_id),
         });
     }
 
@@ -242,9 +460,140 @@ pub async fn get_conversations(
     ))
 }
 
+/// Handle GET /conversations/requests
+///
+/// Lists message requests waiting for the caller, newest first. Their
+/// messages can be read with GET /conversations/{id}/messages before deciding.
+#[utoipa::path(
+    get,
+    path = "/conversations/requests",
+    tag = "conversations",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Pending message requests", body = [MessageRequestResponse]),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+    )
+)]
+pub async fn list_message_requests(
+    user_id: String,
+    requests: MessageRequestService,
+) -> Result<impl Reply, Rejection> {
+    match requests.list(&user_id).await {
+        Ok(pending) => {
+            let pending: Vec<MessageRequestResponse> =
+                pending.into_iter().map(MessageRequestResponse::from).collect();
+            Ok(reply::with_status(
+                reply::json(&pending),
+                warp::http::StatusCode::OK,
+            ))
+        }
+        Err(e) => {
+            warn!("Failed to list message requests: {}", e);
+            Ok(reply::with_status(
+                reply::json(&ErrorResponse {
+                    error: "DATABASE_ERROR".to_string(),
+                    message: "Failed to retrieve message requests".to_string(),
+                }),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
+}
+
+/// Handle POST /conversations/{id}/accept
+///
+/// Moves the conversation into the caller's list and delivers its held messages
+#[utoipa::path(
+    post,
+    path = "/conversations/{id}/accept",
+    tag = "conversations",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "Conversation ID")),
+    responses(
+        (status = 200, description = "Request accepted", body = MessageRequestResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 404, description = "No message request to the caller", body = ErrorResponse),
+    )
+)]
+pub async fn accept_message_request(
+    user_id: String,
+    conversation_id: String,
+    requests: MessageRequestService,
+) -> Result<impl Reply, Rejection> {
+    let result = requests.accept(&user_id, &conversation_id).await;
+    message_request_reply(result, "accept")
+}
+
+/// Handle POST /conversations/{id}/decline
+///
+/// Deletes the held messages; later ones from the sender are dropped. The
+/// sender is not told. To block them too, use PUT /user/blocks/{id}.
+#[utoipa::path(
+    post,
+    path = "/conversations/{id}/decline",
+    tag = "conversations",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "Conversation ID")),
+    responses(
+        (status = 200, description = "Request declined", body = MessageRequestResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 404, description = "No message request to the caller", body = ErrorResponse),
+    )
+)]
+pub async fn decline_message_request(
+    user_id: String,
+    conversation_id: String,
+    requests: MessageRequestService,
+) -> Result<impl Reply, Rejection> {
+    let result = requests.decline(&user_id, &conversation_id).await;
+    message_request_reply(result, "decline")
+}
+
+fn message_request_reply(
+    result: Result<Option<MessageRequest>, String>,
+    action: &str,
+) -> Result<reply::WithStatus<reply::Json>, Rejection> {
+    match result {
+        Ok(Some(request)) => Ok(reply::with_status(
+            reply::json(&MessageRequestResponse::from(request)),
+            warp::http::StatusCode::OK,
+        )),
+        Ok(None) => Ok(reply::with_status(
+            reply::json(&ErrorResponse {
+                error: "REQUEST_NOT_FOUND".to_string(),
+                message: "No message request for you in this conversation".to_string(),
+            }),
+            warp::http::StatusCode::NOT_FOUND,
+        )),
+        Err(e) => {
+            warn!("Failed to {} message request: {}", action, e);
+            Ok(reply::with_status(
+                reply::json(&ErrorResponse {
+                    error: "DATABASE_ERROR".to_string(),
+                    message: format!("Failed to {} message request", action),
+                }),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
+}
+
 /// Handle GET /conversations/{id}/messages?limit=50&offset=0
 ///
 /// Returns paginated messages for a conversation
+#[utoipa::path(
+    get,
+    path = "/conversations/{id}/messages",
+    tag = "conversations",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "Conversation ID"), MessagesQuery),
+    responses(
+        (status = 200, description = "One page of messages", body = [MessageResponse]),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Not a participant", body = ErrorResponse),
+        (status = 404, description = "Conversation not found", body = ErrorResponse),
+    )
+)]
 pub async fn get_conversation_messages(
     user_id: String,
     conversation_id: String,
@@ -330,6 +679,9 @@ pub async fn get_conversation_messages(
             created_at: msg.created_at,
             delivered_at: msg.delivered_at,
             status: msg.status,
+            encrypted: msg.is_encrypted,
+            system: msg.is_system,
+            expires_at: msg.expires_at,
         });
     }
 
@@ -342,6 +694,20 @@ pub async fn get_conversation_messages(
 /// Handle GET /conversations/{id}/search?q=keyword
 ///
 /// Searches messages within a conversation by keyword
+#[utoipa::path(
+    get,
+    path = "/conversations/{id}/search",
+    tag = "conversations",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "Conversation ID"), SearchMessagesQuery),
+    responses(
+        (status = 200, description = "Matching messages", body = [MessageResponse]),
+        (status = 400, description = "Empty keyword", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Not a participant", body = ErrorResponse),
+        (status = 404, description = "Conversation not found", body = ErrorResponse),
+    )
+)]
 pub async fn search_messages(
     user_id: String,
     conversation_id: String,
@@ -427,6 +793,9 @@ pub async fn search_messages(
             created_at: msg.created_at,
             delivered_at: msg.delivered_at,
             status: msg.status,
+            encrypted: msg.is_encrypted,
+            system: msg.is_system,
+            expires_at: msg.expires_at,
         });
     }
 
@@ -435,3 +804,448 @@ pub async fn search_messages(
         warp::http::StatusCode::OK,
     ))
 }
+
+/// Handle POST /conversations/{id}/messages
+///
+/// Sends a message to the other participant through the same path as a WebSocket
+/// `message` frame. The `Idempotency-Key` header becomes the message ID, so a retry
+/// returns the stored message (200) instead of sending it twice (201).
+#[utoipa::path(
+    post,
+    path = "/conversations/{id}/messages",
+    tag = "conversations",
+    security(("bearer" = [])),
+    params(
+        ("id" = String, Path, description = "Conversation ID"),
+        ("Idempotency-Key" = String, Header, description = "Client-chosen message ID, at most 128 characters"),
+    ),
+    request_body = SendMessageRequest,
+    responses(
+        (status = 200, description = "Retry of an already stored message", body = SendMessageResponse),
+        (status = 201, description = "Message stored and sent", body = SendMessageResponse),
+        (status = 400, description = "Missing key or invalid content", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Not a participant", body = ErrorResponse),
+        (status = 404, description = "Conversation not found", body = ErrorResponse),
+        (status = 409, description = "Key reused for a different message", body = ErrorResponse),
+        (status = 410, description = "Recipient deleted their account", body = ErrorResponse),
+    )
+)]
+pub async fn send_conversation_message(
+    user_id: String,
+    conversation_id: String,
+    idempotency_key: Option<String>,
+    request: SendMessageRequest,
+    handler: MessageHandler,
+    pool: SqlitePool,
+) -> Result<impl Reply, Rejection> {
+    let error = |status, code: &str, message: &str| -> Result<_, Rejection> {
+        Ok(reply::with_status(
+            reply::json(&ErrorResponse {
+                error: code.to_string(),
+                message: message.to_string(),
+            }),
+            status,
+        ))
+    };
+
+    let message_id = match idempotency_key.as_deref().map(str::trim) {
+        Some(key) if !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH => {
+            key.to_string()
+        }
+        _ => {
+            return error(
+                warp::http::StatusCode::BAD_REQUEST,
+                "INVALID_IDEMPOTENCY_KEY",
+                "An Idempotency-Key header of 1-128 characters is required",
+            );
+        }
+    };
+
+    // Verify conversation exists and user is participant
+    let conversation = match queries::get_conversation_by_id(&pool, &conversation_id).await {
+        Ok(Some(conv)) => conv,
+        Ok(None) => {
+            return error(
+                warp::http::StatusCode::NOT_FOUND,
+                "CONVERSATION_NOT_FOUND",
+                "The specified conversation does not exist",
+            );
+        }
+        Err(e) => {
+            warn!("Failed to get conversation: {}", e);
+            return error(
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "DATABASE_ERROR",
+                "Failed to retrieve conversation",
+            );
+        }
+    };
+
+    let recipient_id = if conversation.user1_id == user_id {
+        conversation.user2_id.clone()
+    } else if conversation.user2_id == user_id {
+        conversation.user1_id.clone()
+    } else {
+        return error(
+            warp::http::StatusCode::FORBIDDEN,
+            "FORBIDDEN",
+            "You are not a participant in this conversation",
+        );
+    };
+
+    let sender = match queries::find_user_by_id(&pool, &user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return error(
+                warp::http::StatusCode::UNAUTHORIZED,
+                "UNAUTHORIZED",
+                "User not found",
+            );
+        }
+        Err(e) => {
+            warn!("Failed to find sender: {}", e);
+            return error(
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "DATABASE_ERROR",
+                "Failed to verify sender",
+            );
+        }
+    };
+
+    let data = TextMessageData {
+        sender_id: None,
+        sender_username: None,
+        recipient_id,
+        content: request.content,
+        conversation_id: Some(conversation_id.clone()),
+        status: None,
+        encrypted: request.encrypted,
+        system: false,
+        expires_at: None,
+    };
+    let connection = ClientConnection::new(sender.id.clone(), sender.username.clone());
+
+    let sent = match handler
+        .send_text_message(&message_id, &connection, &data, None)
+        .await
+    {
+        Ok(sent) => sent,
+        Err(SendError::Invalid(reason)) => {
+            return error(
+                warp::http::StatusCode::BAD_REQUEST,
+                "INVALID_MESSAGE",
+                &reason,
+            );
+        }
+        Err(SendError::RecipientNotFound | SendError::RecipientDeleted) => {
+            return error(
+                warp::http::StatusCode::GONE,
+                "USER_DELETED",
+                "The other participant no longer exists",
+            );
+        }
+        Err(SendError::DuplicateId) => {
+            return error(
+                warp::http::StatusCode::CONFLICT,
+                "IDEMPOTENCY_KEY_CONFLICT",
+                "The Idempotency-Key was already used for another message",
+            );
+        }
+        Err(SendError::Internal(e)) => {
+            warn!("Failed to send message: {}", e);
+            return error(
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "DATABASE_ERROR",
+                "Failed to send message",
+            );
+        }
+    };
+
+    if sent.message.conversation_id != conversation_id {
+        return error(
+            warp::http::StatusCode::CONFLICT,
+            "IDEMPOTENCY_KEY_CONFLICT",
+            "The Idempotency-Key was already used for another message",
+        );
+    }
+
+    let status_code = if sent.was_created {
+        warp::http::StatusCode::CREATED
+    } else {
+        warp::http::StatusCode::OK
+    };
+
+    // Reflect the delivery recorded after the insert
+    let message = match queries::find_message_by_id(&pool, &sent.message.id).await {
+        Ok(Some(stored)) => stored,
+        _ => sent.message,
+    };
+    Ok(reply::with_status(
+        reply::json(&SendMessageResponse {
+            message: MessageDto {
+                id: message.id,
+                sender_id: message.sender_id,
+                sender_username: sender.username,
+                recipient_id: message.recipient_id,
+                content: message.content,
+                created_at: message.created_at as u64,
+                delivered_at: message.delivered_at.map(|t| t as u64),
+                status: message.status,
+                encrypted: message.is_encrypted,
+                system: message.is_system,
+                expires_at: message.expires_at.map(|t| t as u64),
+            },
+            status: sent.delivery_status.to_string(),
+        }),
+        status_code,
+    ))
+}
+
+/// Handle PUT /conversations/{id}/ephemeral
+///
+/// Turns disappearing messages on, off or changes the timer. Either participant
+/// may do so unless one has blocked the other or a message request is still
+/// open; the change applies to messages sent afterwards and is announced to
+/// both with a system message.
+#[utoipa::path(
+    put,
+    path = "/conversations/{id}/ephemeral",
+    tag = "conversations",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "Conversation ID")),
+    request_body = EphemeralRequest,
+    responses(
+        (status = 200, description = "Setting applied", body = EphemeralResponse),
+        (status = 400, description = "TTL out of range or unknown timer start", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Not a participant, either has blocked the other, or a message request is open", body = ErrorResponse),
+        (status = 404, description = "Conversation not found", body = ErrorResponse),
+    )
+)]
+pub async fn set_ephemeral(
+    user_id: String,
+    conversation_id: String,
+    request: EphemeralRequest,
+    service: EphemeralService,
+    pool: SqlitePool,
+) -> Result<impl Reply, Rejection> {
+    let error = |status, code: &str, message: &str| -> Result<_, Rejection> {
+        Ok(reply::with_status(
+            reply::json(&ErrorResponse {
+                error: code.to_string(),
+                message: message.to_string(),
+            }),
+            status,
+        ))
+    };
+
+    if let Err(reason) = EphemeralService::validate(request.ephemeral_ttl, &request.ephemeral_start)
+    {
+        return error(
+            warp::http::StatusCode::BAD_REQUEST,
+            "INVALID_EPHEMERAL_SETTING",
+            &reason,
+        );
+    }
+
+    let conversation = match queries::get_conversation_by_id(&pool, &conversation_id).await {
+        Ok(Some(conv)) => conv,
+        Ok(None) => {
+            return error(
+                warp::http::StatusCode::NOT_FOUND,
+                "CONVERSATION_NOT_FOUND",
+                "The specified conversation does not exist",
+            );
+        }
+        Err(e) => {
+            warn!("Failed to get conversation: {}", e);
+            return error(
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "DATABASE_ERROR",
+                "Failed to retrieve conversation",
+            );
+        }
+    };
+
+    if conversation.user1_id != user_id && conversation.user2_id != user_id {
+        return error(
+            warp::http::StatusCode::FORBIDDEN,
+            "FORBIDDEN",
+            "You are not a participant in this conversation",
+        );
+    }
+
+    let user = match queries::find_user_by_id(&pool, &user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return error(
+                warp::http::StatusCode::UNAUTHORIZED,
+                "UNAUTHORIZED",
+                "User not found",
+            );
+        }
+        Err(e) => {
+            warn!("Failed to find user: {}", e);
+            return error(
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "DATABASE_ERROR",
+                "Failed to verify user",
+            );
+        }
+    };
+
+    let announcement = match service
+        .set_ttl(
+            &conversation,
+            &user,
+            request.ephemeral_ttl,
+            &request.ephemeral_start,
+        )
+        .await
+    {
+        Ok(announcement) => announcement,
+        Err(SetTtlError::Blocked) => {
+            return error(
+                warp::http::StatusCode::FORBIDDEN,
+                "BLOCKED",
+                "Disappearing messages can't be changed while either of you has blocked the other",
+            );
+        }
+        Err(SetTtlError::RequestPending) => {
+            return error(
+                warp::http::StatusCode::FORBIDDEN,
+                "REQUEST_PENDING",
+                "Disappearing messages can be changed once the message request is accepted",
+            );
+        }
+        Err(SetTtlError::Internal(e)) => {
+            warn!("Failed to change disappearing messages: {}", e);
+            return error(
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "DATABASE_ERROR",
+                "Failed to change disappearing messages",
+            );
+        }
+    };
+
+    let (ephemeral_ttl, ephemeral_start) = if announcement.is_some() {
+        (request.ephemeral_ttl, request.ephemeral_start)
+    } else {
+        (conversation.ephemeral_ttl, conversation.ephemeral_start)
+    };
+    Ok(reply::with_status(
+        reply::json(&EphemeralResponse {
+            conversation_id,
+            ephemeral_ttl,
+            ephemeral_start,
+            system_message: announcement.map(|msg| MessageResponse {
+                id: msg.id,
+                sender_id: msg.sender_id,
+                sender_username: user.username,
+                recipient_id: msg.recipient_id,
+                content: msg.content,
+                created_at: msg.created_at,
+                delivered_at: msg.delivered_at,
+                status: msg.status,
+                encrypted: msg.is_encrypted,
+                system: msg.is_system,
+                expires_at: msg.expires_at,
+            }),
+        }),
+        warp::http::StatusCode::OK,
+    ))
+}
+
+/// Handle GET /conversations/{id}/export?format=json|markdown|html
+///
+/// Streams the whole conversation, oldest message first, as a file download.
+/// Senders who deleted their account appear as "Deleted user".
+#[utoipa::path(
+    get,
+    path = "/conversations/{id}/export",
+    tag = "conversations",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "Conversation ID"), ExportQuery),
+    responses(
+        (status = 200, description = "The transcript", content_type = ["application/json", "text/markdown", "text/html"], body = String),
+        (status = 400, description = "Unknown format", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Not a participant", body = ErrorResponse),
+        (status = 404, description = "Conversation not found", body = ErrorResponse),
+    )
+)]
+pub async fn export_conversation(
+    user_id: String,
+    conversation_id: String,
+    query: ExportQuery,
+    pool: SqlitePool,
+) -> Result<warp::reply::Response, Rejection> {
+    let error = |status, code: &str, message: &str| -> Result<_, Rejection> {
+        Ok(reply::with_status(
+            reply::json(&ErrorResponse {
+                error: code.to_string(),
+                message: message.to_string(),
+            }),
+            status,
+        )
+        .into_response())
+    };
+
+    let Some(format) = ExportFormat::parse(&query.format) else {
+        return error(
+            warp::http::StatusCode::BAD_REQUEST,
+            "INVALID_FORMAT",
+            "Format must be json, markdown or html",
+        );
+    };
+
+    let conversation = match queries::get_conversation_by_id(&pool, &conversation_id).await {
+        Ok(Some(conv)) => conv,
+        Ok(None) => {
+            return error(
+                warp::http::StatusCode::NOT_FOUND,
+                "CONVERSATION_NOT_FOUND",
+                "The specified conversation does not exist",
+            );
+        }
+        Err(e) => {
+            warn!("Failed to get conversation: {}", e);
+            return error(
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "DATABASE_ERROR",
+                "Failed to retrieve conversation",
+            );
+        }
+    };
+
+    if conversation.user1_id != user_id && conversation.user2_id != user_id {
+        return error(
+            warp::http::StatusCode::FORBIDDEN,
+            "FORBIDDEN",
+            "You are not a participant in this conversation",
+        );
+    }
+
+    let file_name = format!("conversation-{}.{}", conversation.id, format.extension());
+    let body = transcript::stream_transcript(
+        pool,
+        conversation,
+        format,
+        "Conversation export".to_string(),
+    )
+    .inspect(|chunk| {
+        if let Err(e) = chunk {
+            warn!("Conversation export stopped: {}", e);
+        }
+    });
+
+    Ok(warp::http::Response::builder()
+        .header(warp::http::header::CONTENT_TYPE, format.content_type())
+        .header(
+            warp::http::header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", file_name),
+        )
+        .body(warp::hyper::Body::wrap_stream(body))
+        .unwrap_or_default())
+}
@@ -2,10 +2,16 @@
 //!
 //! Parses incoming WebSocket frames and routes messages to appropriate handlers.
 //! Validates message format, extracts message types, and dispatches to service layer.
+//! Text frames carry JSON envelopes and binary frames carry MessagePack envelopes;
+//! both are decoded to the same JSON value and checked against the protocol
+//! schema for their type before being deserialized.
 
+use crate::handlers::schema::{FieldError, FrameValidator};
 use crate::handlers::websocket::{ErrorResponse, MessageValidator};
-use chat_shared::protocol::MessageEnvelope;
-use serde_json::json;
+use chat_shared::protocol::{
+    ClientFrame, MessageEnvelope, TextMessageData, TypingData, WireEncoding,
+};
+use serde_json::{json, Value};
 use warp::ws::Message as WsMessage;
 
 /// Message dispatcher routes incoming WebSocket messages to appropriate handlers
@@ -25,6 +31,8 @@ pub enum DispatchResult {
         msg_type: String,
         envelope: MessageEnvelope,
     },
+    /// Client asked to replay events missed since `last_seq`
+    Resume { last_seq: u64 },
     /// Error occurred during parsing or dispatching
     Error { error_msg: WsMessage },
     /// Connection should be closed
@@ -34,7 +42,6 @@ pub enum DispatchResult {
 impl MessageDispatcher {
     /// Parse and validate incoming WebSocket message frame
     pub fn parse_message(msg: &WsMessage) -> DispatchResult {
-        // Only process text messages
         if msg.is_text() {
             if let Ok(text) = msg.to_str() {
                 return Self::parse_text_frame(text);
@@ -42,9 +49,7 @@ impl MessageDispatcher {
         }
 
         if msg.is_binary() {
-            return DispatchResult::Error {
-                error_msg: WsMessage::text("Binary frames not supported"),
-            };
+            return Self::parse_binary_frame(msg.as_bytes());
         }
 
         if msg.is_close() {
@@ -61,6 +66,8 @@ impl MessageDispatcher {
                     id: uuid::Uuid::new_v4().to_string(),
                     msg_type: "ping".to_string(),
                     timestamp: chrono::Utc::now().timestamp_millis() as u64,
+                    traceparent: None,
+                    seq: None,
                     data: json!({}),
                 },
             };
@@ -73,6 +80,8 @@ impl MessageDispatcher {
                     id: uuid::Uuid::new_v4().to_string(),
                     msg_type: "pong".to_string(),
                     timestamp: chrono::Utc::now().timestamp_millis() as u64,
+                    traceparent: None,
+                    seq: None,
                     data: json!({}),
                 },
             };
@@ -83,18 +92,101 @@ impl MessageDispatcher {
         }
     }
 
+    /// Read the envelope's `traceparent` ahead of full parsing so that the parse
+    /// step itself is recorded under the client's trace
+    pub fn peek_traceparent(msg: &WsMessage) -> Option<String> {
+        #[derive(serde::Deserialize)]
+        struct TraceOnly {
+            traceparent: Option<String>,
+        }
+
+        let encoding = if msg.is_binary() {
+            WireEncoding::MessagePack
+        } else {
+            WireEncoding::Json
+        };
+        encoding
+            .decode_as::<TraceOnly>(msg.as_bytes())
+            .ok()?
+            .traceparent
+    }
+
+    /// Re-encode an outbound JSON envelope frame for a connection's negotiated
+    /// encoding; frames that are not envelopes pass through unchanged
+    pub fn encode_frame(msg: WsMessage, encoding: WireEncoding) -> WsMessage {
+        if !encoding.is_binary() || !msg.is_text() {
+            return msg;
+        }
+
+        let encoded = serde_json::from_slice::<MessageEnvelope>(msg.as_bytes())
+            .map_err(|e| e.to_string())
+            .and_then(|envelope| encoding.encode(&envelope));
+        match encoded {
+            Ok(bytes) => WsMessage::binary(bytes),
+            Err(_) => msg,
+        }
+    }
+
     /// Parse text frame into message envelope
     fn parse_text_frame(text: &str) -> DispatchResult {
-        // Parse JSON
-        let envelope: MessageEnvelope = match serde_json::from_str(text) {
-            Ok(env) => env,
-            Err(_) => {
-                return DispatchResult::Error {
-                    error_msg: ErrorResponse::invalid_json(),
-                };
-            }
-        };
+        match serde_json::from_str(text) {
+            Ok(frame) => Self::dispatch_frame(frame),
+            Err(_) => DispatchResult::Error {
+                error_msg: ErrorResponse::invalid_json(),
+            },
+        }
+    }
 
+    /// Parse binary frame into message envelope
+    fn parse_binary_frame(bytes: &[u8]) -> DispatchResult {
+        match WireEncoding::MessagePack.decode_as(bytes) {
+            Ok(frame) => Self::dispatch_frame(frame),
+            Err(_) => DispatchResult::Error {
+                error_msg: ErrorResponse::invalid_msgpack(),
+            },
+        }
+    }
+
+    /// Check a decoded frame against the protocol schema, then dispatch it
+    fn dispatch_frame(frame: Value) -> DispatchResult {
+        if let Err(errors) = FrameValidator::global().validate(&frame) {
+            return DispatchResult::Error {
+                error_msg: Self::schema_error(&frame, &errors),
+            };
+        }
+
+        match serde_json::from_value(frame) {
+            Ok(envelope) => Self::dispatch_envelope(envelope),
+            Err(e) => DispatchResult::Error {
+                error_msg: ErrorResponse::invalid_frame(&[FieldError {
+                    path: String::new(),
+                    keyword: "type".to_string(),
+                    message: e.to_string(),
+                }]),
+            },
+        }
+    }
+
+    /// Error frame for schema violations; oversized content keeps its dedicated code
+    fn schema_error(frame: &Value, errors: &[FieldError]) -> WsMessage {
+        let oversized = errors
+            .iter()
+            .any(|e| e.path == "/data/content" && e.keyword == "maxLength");
+        let encrypted = frame
+            .pointer("/data/encrypted")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        match frame.pointer("/data/content").and_then(Value::as_str) {
+            Some(content) if oversized => ErrorResponse::invalid_message_length(
+                content.len(),
+                MessageValidator::max_content_length(encrypted),
+            ),
+            _ => ErrorResponse::invalid_frame(errors),
+        }
+    }
+
+    /// Validate a decoded envelope and route it by type
+    fn dispatch_envelope(envelope: MessageEnvelope) -> DispatchResult {
         // Validate envelope structure
         if let Err(e) = MessageValidator::validate_envelope(&envelope) {
             return DispatchResult::Error {
@@ -102,47 +194,47 @@ impl MessageDispatcher {
             };
         }
 
-        // Dispatch based on message type
-        match envelope.msg_type.as_str() {
-            "message" => Self::dispatch_text_message(&envelope),
-            "typing" => Self::dispatch_typing(&envelope),
-            "heartbeat" => DispatchResult::Success {
+        let frame = match ClientFrame::from_envelope(&envelope) {
+            Ok(frame) => frame,
+            Err(e) => {
+                return DispatchResult::Error {
+                    error_msg: ErrorResponse::server_error(&e),
+                }
+            }
+        };
+
+        match frame {
+            ClientFrame::Message(data) => Self::dispatch_text_message(&data, envelope),
+            ClientFrame::Typing(data) => Self::dispatch_typing(&data, envelope),
+            ClientFrame::Resume(resume) => DispatchResult::Resume {
+                last_seq: resume.last_seq,
+            },
+            ClientFrame::Heartbeat(_) => DispatchResult::Success {
                 msg_type: "heartbeat".to_string(),
                 envelope,
             },
-            "ack" | "presence" | "error" => {
-                // These are typically server-sent, but could be received
+            // These are typically server-sent, but could be received
+            ClientFrame::Ack(_) | ClientFrame::Presence(_) | ClientFrame::Error(_) => {
                 DispatchResult::Success {
                     msg_type: envelope.msg_type.clone(),
                     envelope,
                 }
             }
-            _ => DispatchResult::Error {
-                error_msg: ErrorResponse::server_error(&format!(
-                    "Unknown message type: {}",
-                    envelope.msg_type
-                )),
-            },
         }
     }
 
     /// Dispatch text message with validation
-    fn dispatch_text_message(envelope: &MessageEnvelope) -> DispatchResult {
-        // Extract required fields from data
-        let data = &envelope.data;
-
-        let recipient_id = data
-            .get("recipientId")
-            .and_then(|v| v.as_str())
-            .unwrap_or("");
-
-        let content = data.get("content").and_then(|v| v.as_str()).unwrap_or("");
-
-        // Validate message data
-        if let Err(e) = MessageValidator::validate_text_message(content, recipient_id) {
+    fn dispatch_text_message(data: &TextMessageData, envelope: MessageEnvelope) -> DispatchResult {
+        // Length is checked in bytes here; the schema limit counts characters
+        if let Err(e) =
+            MessageValidator::validate_text_message(&data.content, &data.recipient_id, data.encrypted)
+        {
             return DispatchResult::Error {
                 error_msg: if e.contains("character") {
-                    ErrorResponse::invalid_message_length(content.len(), 5000)
+                    ErrorResponse::invalid_message_length(
+                        data.content.len(),
+                        MessageValidator::max_content_length(data.encrypted),
+                    )
                 } else {
                     ErrorResponse::server_error(&e)
                 },
@@ -152,21 +244,13 @@ impl MessageDispatcher {
         DispatchResult::RequiresAck {
             message_id: envelope.id.clone(),
             msg_type: "message".to_string(),
-            envelope: envelope.clone(),
+            envelope,
         }
     }
 
     /// Dispatch typing indicator with validation
-    fn dispatch_typing(envelope: &MessageEnvelope) -> DispatchResult {
-        let data = &envelope.data;
-
-        let recipient_id = data
-            .get("recipientId")
-            .and_then(|v| v.as_str())
-            .unwrap_or("");
-
-        // Validate typing data
-        if let Err(e) = MessageValidator::validate_typing(recipient_id) {
+    fn dispatch_typing(data: &TypingData, envelope: MessageEnvelope) -> DispatchResult {
+        if let Err(e) = MessageValidator::validate_typing(&data.recipient_id) {
             return DispatchResult::Error {
                 error_msg: ErrorResponse::server_error(&e),
             };
@@ -174,7 +258,7 @@ impl MessageDispatcher {
 
         DispatchResult::Success {
             msg_type: "typing".to_string(),
-            envelope: envelope.clone(),
+            envelope,
         }
     }
 }
@@ -234,6 +318,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_dispatcher_parse_resume() {
+        let json = json!({
+            "id": "resume-1",
+            "type": "resume",
+            "timestamp": chrono::Utc::now().timestamp_millis(),
+            "data": { "lastSeq": 42 }
+        });
+
+        let result = MessageDispatcher::parse_message(&WsMessage::text(json.to_string()));
+        assert!(matches!(result, DispatchResult::Resume { last_seq: 42 }));
+
+        let invalid = json!({
+            "id": "resume-2",
+            "type": "resume",
+            "timestamp": chrono::Utc::now().timestamp_millis(),
+            "data": { "lastSeq": "latest" }
+        });
+        let result = MessageDispatcher::parse_message(&WsMessage::text(invalid.to_string()));
+        assert!(matches!(result, DispatchResult::Error { .. }));
+    }
+
     #[test]
     fn test_dispatcher_invalid_json() {
         let msg = WsMessage::text("not valid json".to_string());
@@ -292,6 +398,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_dispatcher_reports_schema_violations() {
+        let json = json!({
+            "id": "msg-123",
+            "type": "message",
+            "timestamp": chrono::Utc::now().timestamp_millis(),
+            "data": {
+                "recipient_id": "user-456",
+                "content": 42
+            }
+        });
+
+        let result = MessageDispatcher::parse_message(&WsMessage::text(json.to_string()));
+        let DispatchResult::Error { error_msg } = result else {
+            panic!("Expected Error");
+        };
+        let error: MessageEnvelope = serde_json::from_str(error_msg.to_str().unwrap()).unwrap();
+        assert_eq!(error.data["code"], "INVALID_FRAME");
+
+        let mut paths: Vec<&str> = error.data["details"]["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|e| e["path"].as_str())
+            .collect();
+        paths.sort();
+        assert_eq!(paths, ["/data/content", "/data/recipientId"]);
+    }
+
     #[test]
     fn test_dispatcher_empty_message() {
         let json = json!({
@@ -333,6 +468,62 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_dispatcher_msgpack_frame_matches_json() {
+        let envelope = MessageEnvelope::with_id(
+            "msg-123",
+            "message",
+            json!({ "recipientId": "user-456", "content": "Hello" }),
+        );
+        let text = WsMessage::text(serde_json::to_string(&envelope).unwrap());
+        let binary =
+            WsMessage::binary(WireEncoding::MessagePack.encode(&envelope).unwrap());
+
+        for msg in [text, binary] {
+            match MessageDispatcher::parse_message(&msg) {
+                DispatchResult::RequiresAck {
+                    message_id,
+                    envelope: parsed,
+                    ..
+                } => {
+                    assert_eq!(message_id, "msg-123");
+                    assert_eq!(parsed.data, envelope.data);
+                }
+                _ => panic!("Expected RequiresAck"),
+            }
+        }
+
+        // Validation applies to binary frames too
+        let empty = MessageEnvelope::new("message", json!({ "recipientId": "user-456", "content": "" }));
+        let msg = WsMessage::binary(WireEncoding::MessagePack.encode(&empty).unwrap());
+        assert!(matches!(
+            MessageDispatcher::parse_message(&msg),
+            DispatchResult::Error { .. }
+        ));
+    }
+
+    #[test]
+    fn test_encode_frame_for_msgpack_connection() {
+        let envelope = MessageEnvelope::new("presence", json!({ "userId": "u1" })).with_traceparent(
+            Some(chat_shared::protocol::TraceParent::generate().to_string()),
+        );
+        let text = WsMessage::text(serde_json::to_string(&envelope).unwrap());
+
+        let encoded = MessageDispatcher::encode_frame(text.clone(), WireEncoding::MessagePack);
+        assert!(encoded.is_binary());
+        let decoded = WireEncoding::MessagePack.decode(encoded.as_bytes()).unwrap();
+        assert_eq!(decoded.id, envelope.id);
+        assert!(envelope.traceparent.is_some());
+        assert_eq!(
+            MessageDispatcher::peek_traceparent(&encoded),
+            envelope.traceparent
+        );
+
+        // JSON connections and non-envelope frames are left alone
+        assert!(MessageDispatcher::encode_frame(text, WireEncoding::Json).is_text());
+        assert!(MessageDispatcher::encode_frame(WsMessage::ping(Vec::new()), WireEncoding::MessagePack).is_ping());
+    }
+
     #[test]
     fn test_dispatcher_binary_frame() {
         let msg = WsMessage::binary(vec![1, 2, 3]);
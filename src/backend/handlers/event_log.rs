@@ -0,0 +1,254 @@
+//! Per-user event log for session resumption
+//!
+//! Every envelope the server pushes to a user is stamped with a per-user `seq`
+//! and kept in a bounded log. A reconnecting client sends `resume { lastSeq }`
+//! and gets the events it missed replayed, or `resync_required` when the gap
+//! has already been evicted.
+//!
+//! Sequence numbers start at the server's start time in milliseconds, so they
+//! keep increasing across restarts and a `lastSeq` issued by a previous process
+//! is always older than anything this process can replay.
+
+use chat_shared::protocol::MessageEnvelope;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// Log sizing
+#[derive(Debug, Clone)]
+pub struct EventLogConfig {
+    /// Maximum events kept per user
+    pub capacity: usize,
+    /// Events older than this are evicted
+    pub max_age: Duration,
+}
+
+impl Default for EventLogConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 500,
+            max_age: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Outcome of a resume request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Replay {
+    /// Serialized events newer than the client's `lastSeq`, oldest first
+    Events { events: Vec<String>, last_seq: u64 },
+    /// The gap can no longer be replayed; the client must refetch its state
+    ResyncRequired { last_seq: u64 },
+}
+
+struct LoggedEvent {
+    seq: u64,
+    recorded_at: Instant,
+    payload: String,
+}
+
+struct UserLog {
+    /// Highest sequence number assigned so far
+    last_seq: u64,
+    /// Highest sequence number no longer retained
+    floor: u64,
+    events: VecDeque<LoggedEvent>,
+}
+
+impl UserLog {
+    fn new(base_seq: u64) -> Self {
+        Self {
+            last_seq: base_seq,
+            floor: base_seq,
+            events: VecDeque::new(),
+        }
+    }
+
+    fn evict(&mut self, config: &EventLogConfig, now: Instant) {
+        while let Some(front) = self.events.front() {
+            let expired = now.duration_since(front.recorded_at) > config.max_age;
+            if self.events.len() <= config.capacity && !expired {
+                break;
+            }
+            self.floor = front.seq;
+            self.events.pop_front();
+        }
+    }
+}
+
+/// Bounded, in-memory event log keyed by user ID
+pub struct EventLog {
+    config: EventLogConfig,
+    base_seq: u64,
+    logs: Mutex<HashMap<String, UserLog>>,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::new(EventLogConfig::default())
+    }
+}
+
+impl EventLog {
+    pub fn new(config: EventLogConfig) -> Self {
+        Self {
+            config,
+            base_seq: chrono::Utc::now().timestamp_millis() as u64,
+            logs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Stamp `envelope` with the user's next `seq`, log it, and return the JSON frame
+    pub async fn record(
+        &self,
+        user_id: &str,
+        mut envelope: MessageEnvelope,
+    ) -> Result<String, String> {
+        let mut logs = self.logs.lock().await;
+        let log = logs
+            .entry(user_id.to_string())
+            .or_insert_with(|| UserLog::new(self.base_seq));
+
+        let seq = log.last_seq + 1;
+        envelope.seq = Some(seq);
+        let payload = serde_json::to_string(&envelope)
+            .map_err(|e| format!("Failed to serialize event: {}", e))?;
+
+        log.last_seq = seq;
+        log.events.push_back(LoggedEvent {
+            seq,
+            recorded_at: Instant::now(),
+            payload: payload.clone(),
+        });
+        log.evict(&self.config, Instant::now());
+
+        Ok(payload)
+    }
+
+    /// Events recorded for `user_id` after `last_seq`
+    pub async fn replay(&self, user_id: &str, last_seq: u64) -> Replay {
+        let mut logs = self.logs.lock().await;
+        let Some(log) = logs.get_mut(user_id) else {
+            // Nothing recorded by this process; older seqs came from a previous one
+            return if last_seq == self.base_seq {
+                Replay::Events {
+                    events: Vec::new(),
+                    last_seq,
+                }
+            } else {
+                Replay::ResyncRequired {
+                    last_seq: self.base_seq,
+                }
+            };
+        };
+
+        log.evict(&self.config, Instant::now());
+        if last_seq < log.floor || last_seq > log.last_seq {
+            return Replay::ResyncRequired {
+                last_seq: log.last_seq,
+            };
+        }
+
+        Replay::Events {
+            events: log
+                .events
+                .iter()
+                .filter(|e| e.seq > last_seq)
+                .map(|e| e.payload.clone())
+                .collect(),
+            last_seq: log.last_seq,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(n: u64) -> MessageEnvelope {
+        MessageEnvelope::new("presence", json!({ "n": n }))
+    }
+
+    fn seq_of(payload: &str) -> u64 {
+        serde_json::from_str::<MessageEnvelope>(payload)
+            .unwrap()
+            .seq
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_seq_is_monotonic_per_user() {
+        let log = EventLog::default();
+        let a1 = seq_of(&log.record("alice", event(1)).await.unwrap());
+        let a2 = seq_of(&log.record("alice", event(2)).await.unwrap());
+        let b1 = seq_of(&log.record("bob", event(1)).await.unwrap());
+
+        assert_eq!(a2, a1 + 1);
+        assert_eq!(b1, a1);
+    }
+
+    #[tokio::test]
+    async fn test_replay_returns_missed_events() {
+        let log = EventLog::default();
+        let first = seq_of(&log.record("alice", event(1)).await.unwrap());
+        log.record("alice", event(2)).await.unwrap();
+        log.record("alice", event(3)).await.unwrap();
+
+        match log.replay("alice", first).await {
+            Replay::Events { events, last_seq } => {
+                assert_eq!(events.len(), 2);
+                assert_eq!(seq_of(&events[0]), first + 1);
+                assert_eq!(last_seq, first + 2);
+            }
+            other => panic!("unexpected replay: {:?}", other),
+        }
+
+        // Already up to date
+        assert_eq!(
+            log.replay("alice", first + 2).await,
+            Replay::Events {
+                events: Vec::new(),
+                last_seq: first + 2
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_evicted_gap_requires_resync() {
+        let log = EventLog::new(EventLogConfig {
+            capacity: 2,
+            max_age: Duration::from_secs(60),
+        });
+        let first = seq_of(&log.record("alice", event(1)).await.unwrap());
+        for n in 2..=4 {
+            log.record("alice", event(n)).await.unwrap();
+        }
+
+        assert!(matches!(
+            log.replay("alice", first).await,
+            Replay::ResyncRequired { .. }
+        ));
+        assert!(matches!(
+            log.replay("alice", first + 2).await,
+            Replay::Events { ref events, .. } if events.len() == 1
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_seq_requires_resync() {
+        let log = EventLog::default();
+        // From a previous server process
+        assert!(matches!(
+            log.replay("alice", 42).await,
+            Replay::ResyncRequired { .. }
+        ));
+
+        let last = seq_of(&log.record("alice", event(1)).await.unwrap());
+        // Ahead of anything this server issued
+        assert!(matches!(
+            log.replay("alice", last + 10).await,
+            Replay::ResyncRequired { .. }
+        ));
+    }
+}
@@ -5,8 +5,26 @@
 
 use crate::services::auth_service::TokenClaims;
 use crate::services::AuthService;
+use chat_shared::protocol::compression::{COMPRESSION_QUERY_PARAM, FRAME_DEFLATE_V1};
+use chat_shared::protocol::{
+    WireEncoding, ENCODING_QUERY_PARAM, PROTOCOL_QUERY_PARAM, PROTOCOL_VERSION,
+};
 use warp::http::StatusCode;
 
+/// Protocol version assumed for clients that do not declare one
+const LEGACY_PROTOCOL_VERSION: u16 = 1;
+
+/// Why no protocol version could be agreed on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionMismatch {
+    /// Every version the client offered is older than the server accepts
+    ClientTooOld { min_supported: u16 },
+    /// Every version the client offered is newer than the server speaks
+    ClientTooNew { max_supported: u16 },
+    /// The `protocol` parameter could not be parsed
+    Malformed(String),
+}
+
 /// Extract JWT token from WebSocket upgrade request query string
 pub fn extract_token_from_query(query: &str) -> Result<String, String> {
     // Parse query string for ?token=<jwt>
@@ -24,6 +42,53 @@ pub fn extract_token_from_query(query: &str) -> Result<String, String> {
     Err("Token parameter not found in query string".to_string())
 }
 
+/// Parse the client's declared protocol versions from the upgrade query string
+///
+/// Returns `None` when the client did not declare any (legacy clients).
+pub fn extract_protocol_versions(query: &str) -> Option<Result<Vec<u16>, String>> {
+    let prefix = format!("{}=", PROTOCOL_QUERY_PARAM);
+    let value = query.split('&').find_map(|p| p.strip_prefix(prefix.as_str()))?;
+
+    let decoded = percent_decode(value);
+    let versions: Result<Vec<u16>, _> = decoded
+        .split(',')
+        .map(|v| v.trim().parse::<u16>())
+        .collect();
+    Some(match versions {
+        Ok(versions) if !versions.is_empty() => Ok(versions),
+        _ => Err(format!("Invalid protocol versions: {}", decoded)),
+    })
+}
+
+/// Parse the wire encoding requested in the upgrade query string
+///
+/// Defaults to JSON when the client does not ask for one.
+pub fn extract_encoding(query: &str) -> Result<WireEncoding, String> {
+    let prefix = format!("{}=", ENCODING_QUERY_PARAM);
+    let Some(value) = query.split('&').find_map(|p| p.strip_prefix(prefix.as_str())) else {
+        return Ok(WireEncoding::Json);
+    };
+
+    let decoded = percent_decode(value);
+    WireEncoding::from_name(&decoded).ok_or_else(|| format!("Unsupported encoding: {}", decoded))
+}
+
+/// Whether the compression schemes offered in the upgrade query string
+/// include `frame-deflate-v1`
+///
+/// Schemes are comma-separated; ones this server does not know are ignored.
+pub fn extract_compression(query: &str) -> bool {
+    let prefix = format!("{}=", COMPRESSION_QUERY_PARAM);
+    query
+        .split('&')
+        .find_map(|p| p.strip_prefix(prefix.as_str()))
+        .is_some_and(|value| {
+            percent_decode(value)
+                .split(',')
+                .any(|scheme| scheme.trim().eq_ignore_ascii_case(FRAME_DEFLATE_V1))
+        })
+}
+
 /// Basic percent-decoding for URL-encoded tokens
 fn percent_decode(s: &str) -> String {
     let mut result = String::new();
@@ -59,12 +124,48 @@ fn percent_decode(s: &str) -> String {
 /// WebSocket handshake handler
 pub struct HandshakeValidator {
     auth_service: AuthService,
+    min_protocol_version: u16,
 }
 
 impl HandshakeValidator {
     pub fn new(jwt_secret: String) -> Self {
         Self {
             auth_service: AuthService::new(jwt_secret),
+            min_protocol_version: LEGACY_PROTOCOL_VERSION,
+        }
+    }
+
+    /// Refuse clients that cannot speak at least `version`
+    pub fn with_min_protocol_version(mut self, version: u16) -> Self {
+        self.min_protocol_version = version.min(PROTOCOL_VERSION);
+        self
+    }
+
+    /// Pick the newest protocol version both sides support
+    pub fn negotiate_version(&self, query: &str) -> Result<u16, VersionMismatch> {
+        let offered = match extract_protocol_versions(query) {
+            None => vec![LEGACY_PROTOCOL_VERSION],
+            Some(Ok(versions)) => versions,
+            Some(Err(e)) => return Err(VersionMismatch::Malformed(e)),
+        };
+
+        if let Some(version) = offered
+            .iter()
+            .copied()
+            .filter(|v| (self.min_protocol_version..=PROTOCOL_VERSION).contains(v))
+            .max()
+        {
+            return Ok(version);
+        }
+
+        if offered.iter().all(|v| *v < self.min_protocol_version) {
+            Err(VersionMismatch::ClientTooOld {
+                min_supported: self.min_protocol_version,
+            })
+        } else {
+            Err(VersionMismatch::ClientTooNew {
+                max_supported: PROTOCOL_VERSION,
+            })
         }
     }
 
@@ -72,9 +173,13 @@ impl HandshakeValidator {
     pub fn validate_upgrade(&self, query: &str) -> Result<TokenClaims, (StatusCode, String)> {
         // Extract token from query string
         let token = extract_token_from_query(query).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        self.validate_token(&token)
+    }
 
+    /// Verify a session JWT and check the claims a realtime connection relies on
+    pub fn validate_token(&self, token: &str) -> Result<TokenClaims, (StatusCode, String)> {
         // Verify token with auth service
-        let claims = self.auth_service.verify_token(&token).map_err(|e| {
+        let claims = self.auth_service.verify_token(token).map_err(|e| {
             if e.contains("expired") || e.contains("Expiration") {
                 (StatusCode::UNAUTHORIZED, "Token has expired".to_string())
             } else {
@@ -225,6 +330,70 @@ mod tests {
         assert_eq!(status, StatusCode::UNAUTHORIZED);
     }
 
+    #[test]
+    fn test_negotiate_version_picks_highest_common() {
+        let validator = HandshakeValidator::new("test_secret".to_string());
+        assert_eq!(
+            validator.negotiate_version("token=t&protocol=1,2"),
+            Ok(PROTOCOL_VERSION)
+        );
+        assert_eq!(validator.negotiate_version("token=t&protocol=1"), Ok(1));
+        assert_eq!(
+            validator.negotiate_version("token=t&protocol=1%2C2,99"),
+            Ok(PROTOCOL_VERSION)
+        );
+        // Legacy clients send nothing
+        assert_eq!(
+            validator.negotiate_version("token=t"),
+            Ok(LEGACY_PROTOCOL_VERSION)
+        );
+    }
+
+    #[test]
+    fn test_negotiate_version_mismatch() {
+        let validator = HandshakeValidator::new("test_secret".to_string())
+            .with_min_protocol_version(PROTOCOL_VERSION);
+        assert_eq!(
+            validator.negotiate_version("token=t"),
+            Err(VersionMismatch::ClientTooOld {
+                min_supported: PROTOCOL_VERSION
+            })
+        );
+        assert_eq!(
+            validator.negotiate_version("token=t&protocol=99"),
+            Err(VersionMismatch::ClientTooNew {
+                max_supported: PROTOCOL_VERSION
+            })
+        );
+        assert!(matches!(
+            validator.negotiate_version("token=t&protocol=two"),
+            Err(VersionMismatch::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_extract_encoding() {
+        assert_eq!(extract_encoding("token=t"), Ok(WireEncoding::Json));
+        assert_eq!(
+            extract_encoding("token=t&encoding=msgpack"),
+            Ok(WireEncoding::MessagePack)
+        );
+        assert_eq!(extract_encoding("encoding=json&token=t"), Ok(WireEncoding::Json));
+        assert!(extract_encoding("token=t&encoding=xml").is_err());
+    }
+
+    #[test]
+    fn test_extract_compression() {
+        assert!(!extract_compression("token=t"));
+        assert!(extract_compression("token=t&compression=frame-deflate-v1"));
+        assert!(extract_compression(
+            "token=t&compression=frame-deflate-v2%2Cframe-deflate-v1"
+        ));
+        // Neither RFC 7692 nor unknown schemes are ours
+        assert!(!extract_compression("token=t&compression=permessage-deflate"));
+        assert!(!extract_compression("token=t&compression=gzip"));
+    }
+
     #[test]
     fn test_handshake_validator_audience_mismatch() {
         // This test would require creating a token with wrong audience,
@@ -56,13 +56,19 @@ impl HeartbeatState {
     }
 
     /// Check if PONG response is overdue
+    ///
+    /// Only a response received after the latest PING counts; an older PONG
+    /// does not prove the connection is still alive.
     pub fn is_pong_overdue(&self, pong_timeout: u64) -> bool {
-        if let Some(last_ping) = self.last_ping_sent {
-            let elapsed = Instant::now().duration_since(last_ping).as_secs();
-            elapsed > pong_timeout
-        } else {
-            false
+        let Some(last_ping) = self.last_ping_sent else {
+            return false;
+        };
+
+        if matches!(self.last_pong_received, Some(pong) if pong >= last_ping) {
+            return false;
         }
+
+        Instant::now().duration_since(last_ping) > Duration::from_secs(pong_timeout)
     }
 
     /// Record PONG receipt
@@ -101,6 +107,14 @@ impl HeartbeatManager {
         state.record_pong();
     }
 
+    /// Record inbound traffic as proof of liveness
+    ///
+    /// Any frame from the client (PONG, application `heartbeat`, or a regular
+    /// message) shows the connection is not half-open.
+    pub async fn record_activity(&self) {
+        self.handle_pong().await;
+    }
+
     /// Check if connection is still healthy
     pub async fn is_healthy(&self) -> bool {
         let state = self.state.read().await;
@@ -274,6 +288,18 @@ mod tests {
         assert!(!manager.is_healthy().await);
     }
 
+    #[test]
+    fn test_stale_pong_does_not_satisfy_new_ping() {
+        let mut state = HeartbeatState::new();
+        state.last_ping_sent = Some(Instant::now() - Duration::from_secs(2));
+        state.last_pong_received = Some(Instant::now() - Duration::from_secs(3));
+
+        assert!(state.is_pong_overdue(1));
+
+        state.record_pong();
+        assert!(!state.is_pong_overdue(1));
+    }
+
     #[tokio::test]
     async fn test_heartbeat_manager_pong_before_timeout() {
         let config = HeartbeatConfig {
@@ -0,0 +1,547 @@
+//! End-to-end encryption key endpoints
+//!
+//! Clients publish an X25519 identity key, an Ed25519 signing key, a signed
+//! prekey and a batch of one-time prekeys. Anyone signed in can fetch another
+//! user's bundle to start an encrypted session; each fetch hands out one
+//! one-time prekey, so fetches are rate limited per requester and target and
+//! refused between users who have blocked each other. Only public keys reach
+//! the server, and signatures are checked by the clients that use them.
+
+use crate::db::queries;
+use crate::handlers::auth::ErrorResponse;
+use crate::middleware::rate_limit::RateLimiter;
+use crate::models::{IdentityKeys, OneTimePrekey};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use tracing::warn;
+use utoipa::ToSchema;
+use warp::{reject, reply, Rejection, Reply};
+
+/// Most one-time prekeys accepted in one upload
+pub const MAX_PREKEYS_PER_UPLOAD: usize = 100;
+
+/// Most one-time prekeys stored per user
+pub const MAX_STORED_PREKEYS: i64 = 200;
+
+/// Signed prekey; `signature` is the Ed25519 signature of `public_key`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SignedPrekey {
+    pub key_id: u32,
+    /// Base64 X25519 public key
+    pub public_key: String,
+    /// Base64 Ed25519 signature by the signing key
+    pub signature: String,
+}
+
+/// One-time prekey
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PublicPrekey {
+    pub key_id: u32,
+    /// Base64 X25519 public key
+    pub public_key: String,
+}
+
+/// Upload keys request payload
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UploadKeysRequest {
+    /// Base64 X25519 identity key
+    pub identity_key: String,
+    /// Base64 Ed25519 key that signs the prekey
+    pub signing_key: String,
+    pub signed_prekey: SignedPrekey,
+    /// Added to the stored one-time prekeys
+    #[serde(default)]
+    pub one_time_prekeys: Vec<PublicPrekey>,
+}
+
+/// The caller's published key state
+#[derive(Debug, Serialize, ToSchema)]
+pub struct KeyStatusResponse {
+    pub has_keys: bool,
+    pub signed_prekey_id: Option<u32>,
+    /// One-time prekeys not yet handed out
+    pub one_time_prekeys: i64,
+}
+
+/// Another user's keys, for starting an encrypted session
+#[derive(Debug, Serialize, ToSchema)]
+pub struct KeyBundleResponse {
+    pub user_id: String,
+    pub identity_key: String,
+    pub signing_key: String,
+    pub signed_prekey: SignedPrekey,
+    /// Absent once the user has run out; the session then starts without one
+    pub one_time_prekey: Option<PublicPrekey>,
+}
+
+fn error(
+    status: warp::http::StatusCode,
+    code: &str,
+    message: &str,
+) -> Result<reply::WithStatus<reply::Json>, Rejection> {
+    Ok(reply::with_status(
+        reply::json(&ErrorResponse {
+            error: code.to_string(),
+            message: message.to_string(),
+        }),
+        status,
+    ))
+}
+
+/// Check that `value` is base64 of exactly `len` bytes
+fn check_base64(value: &str, len: usize, field: &str) -> Result<(), String> {
+    match BASE64.decode(value) {
+        Ok(bytes) if bytes.len() == len => Ok(()),
+        _ => Err(format!("{} must be {} bytes of base64", field, len)),
+    }
+}
+
+fn validate_upload(request: &UploadKeysRequest) -> Result<(), String> {
+    check_base64(&request.identity_key, 32, "identity_key")?;
+    check_base64(&request.signing_key, 32, "signing_key")?;
+    check_base64(
+        &request.signed_prekey.public_key,
+        32,
+        "signed_prekey.public_key",
+    )?;
+    check_base64(
+        &request.signed_prekey.signature,
+        64,
+        "signed_prekey.signature",
+    )?;
+    if request.one_time_prekeys.len() > MAX_PREKEYS_PER_UPLOAD {
+        return Err(format!(
+            "At most {} one-time prekeys can be uploaded at once",
+            MAX_PREKEYS_PER_UPLOAD
+        ));
+    }
+    for prekey in &request.one_time_prekeys {
+        check_base64(&prekey.public_key, 32, "one_time_prekeys.public_key")?;
+    }
+    Ok(())
+}
+
+async fn key_status(pool: &SqlitePool, user_id: &str) -> Result<KeyStatusResponse, String> {
+    let keys = queries::get_identity_keys(pool, user_id).await?;
+    let one_time_prekeys = queries::count_one_time_prekeys(pool, user_id).await?;
+    Ok(KeyStatusResponse {
+        has_keys: keys.is_some(),
+        signed_prekey_id: keys.map(|k| k.signed_prekey_id as u32),
+        one_time_prekeys,
+    })
+}
+
+/// Handle PUT /keys
+///
+/// Replaces the identity and signed prekey and adds the one-time prekeys.
+/// Changing the identity key is allowed (e.g. after reinstalling); peers see a
+/// new safety number.
+#[utoipa::path(
+    put,
+    path = "/keys",
+    tag = "keys",
+    security(("bearer" = [])),
+    request_body = UploadKeysRequest,
+    responses(
+        (status = 200, description = "Keys stored", body = KeyStatusResponse),
+        (status = 400, description = "Malformed key or too many prekeys", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+    )
+)]
+pub async fn upload_keys(
+    user_id: String,
+    request: UploadKeysRequest,
+    pool: SqlitePool,
+) -> Result<impl Reply, Rejection> {
+    if let Err(reason) = validate_upload(&request) {
+        return error(warp::http::StatusCode::BAD_REQUEST, "INVALID_KEYS", &reason);
+    }
+
+    // One-time prekeys of a replaced identity can no longer be used, so a
+    // new identity starts from an empty pool
+    let stored = async {
+        let existing = queries::get_identity_keys(&pool, &user_id).await?;
+        if existing.is_some_and(|keys| keys.identity_key != request.identity_key) {
+            queries::delete_user_keys(&pool, &user_id).await?;
+        }
+        queries::count_one_time_prekeys(&pool, &user_id).await
+    }
+    .await;
+    let stored = match stored {
+        Ok(count) => count,
+        Err(e) => {
+            warn!("Failed to count prekeys: {}", e);
+            return error(
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "DATABASE_ERROR",
+                "Failed to store keys",
+            );
+        }
+    };
+    if stored + request.one_time_prekeys.len() as i64 > MAX_STORED_PREKEYS {
+        return error(
+            warp::http::StatusCode::BAD_REQUEST,
+            "INVALID_KEYS",
+            &format!(
+                "At most {} one-time prekeys can be stored",
+                MAX_STORED_PREKEYS
+            ),
+        );
+    }
+
+    let keys = IdentityKeys {
+        user_id: user_id.clone(),
+        identity_key: request.identity_key,
+        signing_key: request.signing_key,
+        signed_prekey_id: request.signed_prekey.key_id as i64,
+        signed_prekey: request.signed_prekey.public_key,
+        signed_prekey_signature: request.signed_prekey.signature,
+        updated_at: chrono::Utc::now().timestamp_millis(),
+    };
+    let prekeys: Vec<OneTimePrekey> = request
+        .one_time_prekeys
+        .into_iter()
+        .map(|p| OneTimePrekey {
+            key_id: p.key_id as i64,
+            public_key: p.public_key,
+        })
+        .collect();
+
+    let result = async {
+        queries::upsert_identity_keys(&pool, &keys).await?;
+        queries::insert_one_time_prekeys(&pool, &user_id, &prekeys).await?;
+        key_status(&pool, &user_id).await
+    }
+    .await;
+
+    match result {
+        Ok(status) => Ok(reply::with_status(
+            reply::json(&status),
+            warp::http::StatusCode::OK,
+        )),
+        Err(e) => {
+            warn!("Failed to store keys: {}", e);
+            error(
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "DATABASE_ERROR",
+                "Failed to store keys",
+            )
+        }
+    }
+}
+
+/// Handle GET /keys
+///
+/// Lets a client tell whether it needs to publish keys or top up prekeys
+#[utoipa::path(
+    get,
+    path = "/keys",
+    tag = "keys",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Published key state of the caller", body = KeyStatusResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+    )
+)]
+pub async fn get_key_status(user_id: String, pool: SqlitePool) -> Result<impl Reply, Rejection> {
+    match key_status(&pool, &user_id).await {
+        Ok(status) => Ok(reply::with_status(
+            reply::json(&status),
+            warp::http::StatusCode::OK,
+        )),
+        Err(e) => {
+            warn!("Failed to get key status: {}", e);
+            error(
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "DATABASE_ERROR",
+                "Failed to get key status",
+            )
+        }
+    }
+}
+
+/// Handle GET /keys/{user_id}
+///
+/// Returns the user's bundle and consumes one of their one-time prekeys. Once
+/// they have run out the bundle comes without one. Users who have blocked
+/// each other get the same 404 as for a user without keys.
+#[utoipa::path(
+    get,
+    path = "/keys/{user_id}",
+    tag = "keys",
+    security(("bearer" = [])),
+    params(("user_id" = String, Path, description = "User whose keys to fetch")),
+    responses(
+        (status = 200, description = "Key bundle", body = KeyBundleResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 404, description = "The user has not published keys", body = ErrorResponse),
+        (status = 429, description = "Too many fetches of this user's bundle", body = ErrorResponse),
+    )
+)]
+pub async fn get_key_bundle(
+    user_id: String,
+    target_user_id: String,
+    pool: SqlitePool,
+    rate_limiter: &RateLimiter,
+) -> Result<impl Reply, Rejection> {
+    let blocked = match queries::is_blocked_either_way(&pool, &user_id, &target_user_id).await {
+        Ok(blocked) => blocked,
+        Err(e) => {
+            warn!("Failed to check blocks: {}", e);
+            return error(
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "DATABASE_ERROR",
+                "Failed to get keys",
+            );
+        }
+    };
+
+    rate_limiter
+        .check_and_record(&format!("{}:{}", user_id, target_user_id))
+        .await
+        .map_err(reject::custom)?;
+
+    let keys = match queries::get_identity_keys(&pool, &target_user_id).await {
+        Ok(Some(keys)) if !blocked => keys,
+        Ok(_) => {
+            return error(
+                warp::http::StatusCode::NOT_FOUND,
+                "KEYS_NOT_FOUND",
+                "The user has not set up end-to-end encryption",
+            );
+        }
+        Err(e) => {
+            warn!("Failed to get identity keys: {}", e);
+            return error(
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "DATABASE_ERROR",
+                "Failed to get keys",
+            );
+        }
+    };
+
+    let one_time_prekey = match queries::take_one_time_prekey(&pool, &target_user_id).await {
+        Ok(prekey) => prekey,
+        Err(e) => {
+            warn!("Failed to take one-time prekey: {}", e);
+            return error(
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "DATABASE_ERROR",
+                "Failed to get keys",
+            );
+        }
+    };
+
+    Ok(reply::with_status(
+        reply::json(&KeyBundleResponse {
+            user_id: keys.user_id,
+            identity_key: keys.identity_key,
+            signing_key: keys.signing_key,
+            signed_prekey: SignedPrekey {
+                key_id: keys.signed_prekey_id as u32,
+                public_key: keys.signed_prekey,
+                signature: keys.signed_prekey_signature,
+            },
+            one_time_prekey: one_time_prekey.map(|p| PublicPrekey {
+                key_id: p.key_id as u32,
+                public_key: p.public_key,
+            }),
+        }),
+        warp::http::StatusCode::OK,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::User;
+    use serde_json::json;
+
+    async fn setup() -> (SqlitePool, User) {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::db::run_migrations(&pool).await.unwrap();
+        let user = User::new("alice".to_string(), "hash".to_string(), "salt".to_string());
+        queries::insert_user(&pool, &user).await.unwrap();
+        (pool, user)
+    }
+
+    fn upload(prekeys: u32) -> UploadKeysRequest {
+        let key = BASE64.encode([7u8; 32]);
+        UploadKeysRequest {
+            identity_key: key.clone(),
+            signing_key: key.clone(),
+            signed_prekey: SignedPrekey {
+                key_id: 1,
+                public_key: key.clone(),
+                signature: BASE64.encode([9u8; 64]),
+            },
+            one_time_prekeys: (1..=prekeys)
+                .map(|key_id| PublicPrekey {
+                    key_id,
+                    public_key: key.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    async fn body(reply: impl Reply) -> (u16, serde_json::Value) {
+        let response = reply.into_response();
+        let status = response.status().as_u16();
+        let bytes = warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_bundle_hands_out_each_prekey_once() {
+        let (pool, user) = setup().await;
+        let limiter = RateLimiter::key_bundles();
+
+        let (status, _) = body(
+            get_key_bundle("bob".to_string(), user.id.clone(), pool.clone(), &limiter)
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, 404);
+
+        let (status, json) = body(
+            upload_keys(user.id.clone(), upload(2), pool.clone())
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(json["one_time_prekeys"], 2);
+
+        let mut handed_out = Vec::new();
+        for _ in 0..3 {
+            let (status, json) = body(
+                get_key_bundle("bob".to_string(), user.id.clone(), pool.clone(), &limiter)
+                    .await
+                    .unwrap(),
+            )
+            .await;
+            assert_eq!(status, 200);
+            assert_eq!(json["signed_prekey"]["key_id"], 1);
+            handed_out.push(json["one_time_prekey"]["key_id"].clone());
+        }
+        assert_eq!(handed_out, [json!(1), json!(2), json!(null)]);
+
+        let (_, json) = body(get_key_status(user.id.clone(), pool.clone()).await.unwrap()).await;
+        assert_eq!(json["has_keys"], true);
+        assert_eq!(json["one_time_prekeys"], 0);
+
+        // Prekeys of a replaced identity are dropped
+        upload_keys(user.id.clone(), upload(3), pool.clone())
+            .await
+            .unwrap();
+        let mut request = upload(1);
+        request.identity_key = BASE64.encode([8u8; 32]);
+        let (status, json) = body(upload_keys(user.id, request, pool).await.unwrap()).await;
+        assert_eq!(status, 200);
+        assert_eq!(json["one_time_prekeys"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_bundle_refused_between_blocked_users() {
+        let (pool, user) = setup().await;
+        let limiter = RateLimiter::key_bundles();
+        let bob = User::new("bob".to_string(), "hash".to_string(), "salt".to_string());
+        queries::insert_user(&pool, &bob).await.unwrap();
+        upload_keys(user.id.clone(), upload(1), pool.clone())
+            .await
+            .unwrap();
+        queries::insert_block(&pool, &user.id, &bob.id)
+            .await
+            .unwrap();
+
+        let (status, json) = body(
+            get_key_bundle(bob.id.clone(), user.id.clone(), pool.clone(), &limiter)
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, 404);
+        assert_eq!(json["error"], "KEYS_NOT_FOUND");
+
+        // The prekey was not handed out
+        let (_, json) = body(get_key_status(user.id, pool).await.unwrap()).await;
+        assert_eq!(json["one_time_prekeys"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_bundle_fetches_are_rate_limited_per_target() {
+        let (pool, user) = setup().await;
+        let limiter = RateLimiter::new(2, 60);
+        upload_keys(user.id.clone(), upload(5), pool.clone())
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            let (status, _) = body(
+                get_key_bundle("bob".to_string(), user.id.clone(), pool.clone(), &limiter)
+                    .await
+                    .unwrap(),
+            )
+            .await;
+            assert_eq!(status, 200);
+        }
+        let rejection = match get_key_bundle(
+            "bob".to_string(),
+            user.id.clone(),
+            pool.clone(),
+            &limiter,
+        )
+        .await
+        {
+            Ok(_) => panic!("third fetch should be rate limited"),
+            Err(rejection) => rejection,
+        };
+        assert!(rejection
+            .find::<crate::middleware::rate_limit::RateLimitExceeded>()
+            .is_some());
+
+        // Other requesters are counted separately
+        let (status, _) = body(
+            get_key_bundle("carol".to_string(), user.id.clone(), pool.clone(), &limiter)
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, 200);
+
+        let (_, json) = body(get_key_status(user.id, pool).await.unwrap()).await;
+        assert_eq!(json["one_time_prekeys"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_upload_rejects_malformed_keys() {
+        let (pool, user) = setup().await;
+
+        let mut request = upload(0);
+        request.signed_prekey.signature = BASE64.encode([9u8; 32]);
+        let (status, json) = body(
+            upload_keys(user.id.clone(), request, pool.clone())
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, 400);
+        assert_eq!(json["error"], "INVALID_KEYS");
+
+        let (status, _) = body(
+            upload_keys(user.id, upload(MAX_PREKEYS_PER_UPLOAD as u32 + 1), pool)
+                .await
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, 400);
+    }
+}
@@ -1,18 +1,60 @@
-//! WebSocket message handlers
+//! Message handlers
 //!
-//! Handles incoming text messages from WebSocket connections, validates them,
-//! stores them in the database, and routes them to online recipients or queues
-//! them for offline delivery.
+//! Handles text messages sent over WebSocket or REST, validates them, stores
+//! them in the database, and routes them to online recipients or queues them
+//! for offline delivery.
 
 use crate::db::queries;
-use crate::handlers::websocket::{ClientConnection, ConnectionManager, ErrorResponse};
+use crate::handlers::outbound::SendPriority;
+use crate::handlers::websocket::{
+    ClientConnection, ConnectionManager, ErrorResponse, MessageValidator,
+};
+use crate::models::Message;
 use crate::services::{message_queue::MessageQueueService, message_service::MessageService};
-use chat_shared::protocol::{MessageEnvelope, TextMessageData};
-use serde_json::json;
+use crate::telemetry;
+use chat_shared::protocol::{
+    AckData, DeliveryStatusUpdatedEvent, MessageEnvelope, ServerFrame,
+    SyncDeliveryStatusCompletedEvent, TextMessageData, TypingData,
+};
 use sqlx::SqlitePool;
 use std::sync::Arc;
+use tracing::{info_span, Instrument, Span};
 use warp::ws::Message as WsMessage;
 
+/// A message accepted by [`MessageHandler::send_text_message`]
+#[derive(Debug, Clone)]
+pub struct SentMessage {
+    pub message: Message,
+    pub conversation_id: String,
+    /// `delivered` if the recipient has it, otherwise `sent`
+    pub delivery_status: &'static str,
+    /// False when the ID had already been stored (an idempotent retry)
+    pub was_created: bool,
+}
+
+/// Why a message could not be sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendError {
+    Invalid(String),
+    RecipientNotFound,
+    RecipientDeleted,
+    /// The message ID belongs to another sender's message
+    DuplicateId,
+    Internal(String),
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Invalid(reason) | SendError::Internal(reason) => f.write_str(reason),
+            SendError::RecipientNotFound | SendError::RecipientDeleted => {
+                f.write_str("Recipient not found")
+            }
+            SendError::DuplicateId => f.write_str("Message ID is already in use"),
+        }
+    }
+}
+
 /// Message handler for processing incoming messages
 pub struct MessageHandler {
     pool: SqlitePool,
@@ -40,11 +82,8 @@ impl MessageHandler {
     ///
     /// 1. Validates message envelope and content
     /// 2. Verifies sender is authenticated
-    /// 3. Stores message in database
-    /// 4. Checks if recipient is online
-    /// 5. If online: broadcasts to recipient
-    /// 6. If offline: queues for retry
-    /// 7. Sends acknowledgement to sender
+    /// 3. Stores, delivers or queues it via [`MessageHandler::send_text_message`]
+    /// 4. Sends acknowledgement to sender
     pub async fn handle_message(
         &self,
         envelope: &MessageEnvelope,
@@ -54,92 +93,307 @@ impl MessageHandler {
         let data: TextMessageData = serde_json::from_value(envelope.data.clone())
             .map_err(|e| format!("Invalid message data: {}", e))?;
 
+        let sent = match self
+            .send_text_message(
+                &envelope.id,
+                sender,
+                &data,
+                envelope.traceparent.as_deref(),
+            )
+            .await
+        {
+            Ok(sent) => sent,
+            Err(SendError::RecipientDeleted) => {
+                return Ok(vec![ErrorResponse::recipient_not_found(&data.recipient_id)]);
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+
+        // Send acknowledgement to sender
+        let ack = async {
+            let ack = self
+                .build_ack_envelope(
+                    &envelope.id,
+                    &sent.conversation_id,
+                    &sent.message.id,
+                    sent.delivery_status,
+                )
+                .with_traceparent(telemetry::outbound_traceparent(
+                    &Span::current(),
+                    envelope.traceparent.as_deref(),
+                ));
+            self.connection_manager
+                .record_event(&sender.user_id, ack)
+                .await
+        }
+        .instrument(info_span!("message.ack", status = sent.delivery_status))
+        .await?;
+
+        Ok(vec![ack])
+    }
+
+    /// Validate, store and deliver a text message
+    ///
+    /// Shared by the WebSocket `message` frame and `POST /conversations/{id}/messages`.
+    /// `message_id` is the idempotency key: resending it returns the stored message
+    /// without delivering it again.
+    ///
+    /// 1. Validates content and recipient
+    /// 2. Resolves the conversation, refusing plaintext once it is end-to-end encrypted;
+    ///    a new conversation starts as a message request
+    /// 3. Stores message in database
+    /// 4. While a message request is pending: holds it
+    /// 5. If the recipient is online: delivers it live
+    /// 6. Otherwise: queues for retry
+    pub async fn send_text_message(
+        &self,
+        message_id: &str,
+        sender: &ClientConnection,
+        data: &TextMessageData,
+        traceparent: Option<&str>,
+    ) -> Result<SentMessage, SendError> {
+        MessageValidator::validate_text_message(&data.content, &data.recipient_id, data.encrypted)
+            .map_err(SendError::Invalid)?;
+
         // Validate recipient exists
         let recipient = queries::find_user_by_id(&self.pool, &data.recipient_id)
             .await
-            .map_err(|e| format!("Database error: {}", e))?
-            .ok_or_else(|| "Recipient not found".to_string())?;
+            .map_err(|e| SendError::Internal(format!("Database error: {}", e)))?
+            .ok_or(SendError::RecipientNotFound)?;
 
         if recipient.is_deleted() {
-            return Ok(vec![ErrorResponse::recipient_not_found(&data.recipient_id)]);
+            return Err(SendError::RecipientDeleted);
+        }
+
+        if queries::is_blocked(&self.pool, &sender.user_id, &data.recipient_id)
+            .await
+            .map_err(SendError::Internal)?
+        {
+            return Err(SendError::Invalid(
+                "You have blocked this user; unblock them to send messages".to_string(),
+            ));
         }
 
         // Get or create conversation
-        let conversation_id = if let Some(conv_id) = &data.conversation_id {
-            conv_id.clone()
+        let conversation = if let Some(conv_id) = &data.conversation_id {
+            queries::get_conversation_by_id(&self.pool, conv_id)
+                .await
+                .map_err(|e| SendError::Internal(format!("Database error: {}", e)))?
         } else {
-            // Look up or create conversation between sender and recipient
-            let (conversation, _) = self
+            // Look up or create conversation between sender and recipient;
+            // a new one is a message request until the recipient accepts it
+            let (conversation, was_created) = self
                 .create_or_get_conversation(sender.user_id.clone(), data.recipient_id.clone())
-                .await?;
-            conversation.id
+                .await
+                .map_err(SendError::Internal)?;
+            if was_created {
+                queries::insert_message_request(
+                    &self.pool,
+                    &conversation.id,
+                    &sender.user_id,
+                    &data.recipient_id,
+                )
+                .await
+                .map_err(SendError::Internal)?;
+            }
+            Some(conversation)
+        };
+        let conversation_id = match &conversation {
+            Some(conversation) => conversation.id.clone(),
+            None => data.conversation_id.clone().unwrap_or_default(),
+        };
+
+        // Once a conversation is end-to-end encrypted it stays that way
+        let enable_encryption = match &conversation {
+            Some(conversation) if conversation.is_encrypted && !data.encrypted => {
+                return Err(SendError::Invalid(
+                    "This conversation is end-to-end encrypted; plaintext messages are refused"
+                        .to_string(),
+                ));
+            }
+            Some(conversation) => data.encrypted && !conversation.is_encrypted,
+            None => false,
+        };
+
+        let request = match &conversation {
+            Some(conversation) => queries::get_message_request(&self.pool, &conversation.id)
+                .await
+                .map_err(SendError::Internal)?,
+            None => None,
         };
+        if request
+            .as_ref()
+            .is_some_and(|request| request.recipient_id == sender.user_id)
+        {
+            return Err(SendError::Invalid(
+                "Accept this message request before replying".to_string(),
+            ));
+        }
+
+        // A blocked sender, or one whose message request was declined, is not
+        // told: the message is acknowledged like one to an offline recipient,
+        // then dropped without being stored
+        if request
+            .as_ref()
+            .is_some_and(|request| request.status == "declined")
+            || queries::is_blocked(&self.pool, &data.recipient_id, &sender.user_id)
+                .await
+                .map_err(SendError::Internal)?
+        {
+            let mut message = Message::new(
+                conversation_id.clone(),
+                sender.user_id.clone(),
+                data.recipient_id.clone(),
+                data.content.clone(),
+            );
+            message.id = message_id.to_string();
+            message.is_encrypted = data.encrypted;
+            if let Some(conversation) = conversation.filter(|c| c.ephemeral_start == "sent") {
+                message.ephemeral_ttl = conversation.ephemeral_ttl;
+                message.expires_at = conversation
+                    .ephemeral_ttl
+                    .map(|ttl| message.created_at + ttl * 1000);
+            }
+            return Ok(SentMessage {
+                message,
+                conversation_id,
+                delivery_status: "sent",
+                was_created: true,
+            });
+        }
 
         // Send message using message service (with idempotency)
         let (message, was_created) = self
             .message_service
             .send_message_with_id(
-                envelope.id.clone(),
+                message_id.to_string(),
                 conversation_id.clone(),
                 sender.user_id.clone(),
                 data.recipient_id.clone(),
                 data.content.clone(),
+                data.encrypted,
             )
-            .await?;
+            .instrument(info_span!("message.persist", conversation_id = %conversation_id))
+            .await
+            .map_err(SendError::Internal)?;
 
-        let mut responses = Vec::new();
+        if was_created && enable_encryption {
+            queries::set_conversation_encrypted(&self.pool, &conversation_id)
+                .await
+                .map_err(SendError::Internal)?;
+        }
+
+        if !was_created && message.sender_id != sender.user_id {
+            // Someone else's message already uses this ID
+            return Err(SendError::DuplicateId);
+        }
+
+        let mut delivered = false;
 
-        // If message was just created (not a duplicate), deliver it
-        if was_created {
+        // If message was just created (not a duplicate), deliver it, unless a
+        // message request holds it until accepted
+        if was_created && request.is_none() {
             // Check if recipient is online
             if self
                 .connection_manager
                 .is_user_online(&data.recipient_id)
                 .await
             {
-                // Deliver to recipient immediately
-                let delivery_message = self.build_message_envelope(
-                    &message.id,
-                    &sender.user_id,
-                    &sender.username,
-                    &data.recipient_id,
-                    &data.content,
-                    &conversation_id,
-                    "delivered",
-                );
-
-                self.connection_manager
-                    .send_to_user(
-                        &data.recipient_id,
-                        WsMessage::text(serde_json::to_string(&delivery_message).unwrap()),
-                    )
-                    .await;
+                let deliver_span = info_span!("message.deliver", recipient_id = %data.recipient_id);
+                delivered = async {
+                    // Deliver to recipient immediately
+                    let delivery_message = self
+                        .build_message_envelope(&message, &sender.username, "delivered")
+                        .with_traceparent(telemetry::outbound_traceparent(
+                            &Span::current(),
+                            traceparent,
+                        ));
+
+                    let sent = self
+                        .connection_manager
+                        .send_event(&data.recipient_id, delivery_message, SendPriority::Essential)
+                        .await?;
+                    if sent == 0 {
+                        // Every connection was full or evicted; leave it to the queue
+                        return Ok::<_, String>(false);
+                    }
 
-                // Update message status to 'delivered'
-                self.message_service.mark_delivered(&message.id).await?;
-            } else {
-                // Recipient offline - queue for retry
+                    // Update message status to 'delivered'
+                    self.message_service.mark_delivered(&message.id).await?;
+                    Ok(true)
+                }
+                .instrument(deliver_span)
+                .await
+                .map_err(SendError::Internal)?;
+            }
+
+            if !delivered {
+                // Recipient offline or unreachable - queue for retry
                 self.message_queue
                     .queue_message(message.id.clone(), data.recipient_id.clone())
+                    .instrument(info_span!("message.queue", recipient_id = %data.recipient_id))
                     .await;
             }
         }
 
-        // Send acknowledgement to sender
-        let ack_status = if was_created
-            && self
-                .connection_manager
-                .is_user_online(&data.recipient_id)
-                .await
+        let delivery_status = if delivered
+            || (!was_created && matches!(message.status.as_str(), "delivered" | "read"))
         {
             "delivered"
         } else {
             "sent"
         };
-        let ack = self.build_ack_envelope(&envelope.id, &conversation_id, &message.id, ack_status);
-        responses.push(WsMessage::text(serde_json::to_string(&ack).unwrap()));
 
-        Ok(responses)
+        Ok(SentMessage {
+            message,
+            conversation_id,
+            delivery_status,
+            was_created,
+        })
+    }
+
+    /// Forward a typing indicator to the recipient's connections
+    ///
+    /// Only relayed between users who already share a conversation, so typing
+    /// cannot be used to probe arbitrary user IDs, and never between users who
+    /// have blocked each other or across a message request.
+    pub async fn relay_typing(
+        &self,
+        envelope: &MessageEnvelope,
+        sender: &ClientConnection,
+    ) -> Result<(), String> {
+        let data: TypingData = serde_json::from_value(envelope.data.clone())
+            .map_err(|e| format!("Invalid typing data: {}", e))?;
+
+        let (u1, u2) = if sender.user_id < data.recipient_id {
+            (&sender.user_id, &data.recipient_id)
+        } else {
+            (&data.recipient_id, &sender.user_id)
+        };
+        let Some(conversation) = queries::get_conversation_by_users(&self.pool, u1, u2).await?
+        else {
+            return Ok(());
+        };
+        if queries::get_message_request(&self.pool, &conversation.id)
+            .await?
+            .is_some()
+            || queries::is_blocked_either_way(&self.pool, &sender.user_id, &data.recipient_id)
+                .await?
+        {
+            return Ok(());
+        }
+
+        let event = ServerFrame::Typing(TypingData {
+            sender_id: Some(sender.user_id.clone()),
+            sender_username: Some(sender.username.clone()),
+            recipient_id: data.recipient_id.clone(),
+            is_typing: data.is_typing,
+        })
+        .into_envelope();
+        self.connection_manager
+            .send_event(&data.recipient_id, event, SendPriority::Droppable)
+            .await?;
+        Ok(())
     }
 
     /// Create or get conversation between two users
@@ -168,33 +422,17 @@ impl MessageHandler {
     }
 
     /// Build message envelope for delivery
-    #[allow(clippy::too_many_arguments)]
     fn build_message_envelope(
         &self,
-        message_id: &str,
-        sender_id: &str,
+        message: &Message,
         sender_username: &str,
-        recipient_id: &str,
-        content: &str,
-        conversation_id: &str,
         status: &str,
     ) -> MessageEnvelope {
-        MessageEnvelope {
-            id: message_id.to_string(),
-            msg_type: "message".to_string(),
-            timestamp: chrono::Utc::now().timestamp_millis() as u64,
-            data: json!({
-                "senderId": sender_id,
-                "senderUsername": sender_username,
-                "recipientId": recipient_id,
-                "content": content,
-                "conversationId": conversation_id,
-                "status": status,
-            }),
-        }
+        ServerFrame::Message(message.to_text_data(sender_username, status))
+            .into_envelope_with_id(&message.id)
     }
 
-     /// Build acknowledgement envelope
+    /// Build acknowledgement envelope
     fn build_ack_envelope(
         &self,
         original_message_id: &str,
@@ -202,18 +440,14 @@ impl MessageHandler {
         stored_message_id: &str,
         status: &str,
     ) -> MessageEnvelope {
-        MessageEnvelope {
-            id: uuid::Uuid::new_v4().to_string(),
-            msg_type: "ack".to_string(),
-            timestamp: chrono::Utc::now().timestamp_millis() as u64,
-            data: json!({
-                "status": status,
-                "conversationId": conversation_id,
-                "messageId": stored_message_id,
-                "originalMessageId": original_message_id,
-                "serverTimestamp": chrono::Utc::now().timestamp_millis(),
-            }),
-        }
+        ServerFrame::Ack(AckData {
+            status: status.to_string(),
+            conversation_id: Some(conversation_id.to_string()),
+            message_id: Some(stored_message_id.to_string()),
+            original_message_id: Some(original_message_id.to_string()),
+            server_timestamp: Some(chrono::Utc::now().timestamp_millis() as u64),
+        })
+        .into_envelope()
     }
 
     /// Sync delivery status updates from client
@@ -268,15 +502,9 @@ impl MessageHandler {
                 let now = chrono::Utc::now().timestamp_millis();
                 match update.status.as_str() {
                     "read" => {
-                        sqlx::query(
-                            "UPDATE messages SET status = ?, read_at = ? WHERE id = ?"
-                        )
-                        .bind("read")
-                        .bind(now)
-                        .bind(&update.message_id)
-                        .execute(&self.pool)
-                        .await
-                        .ok();
+                        queries::mark_message_read(&self.pool, &update.message_id)
+                            .await
+                            .ok();
                     }
                     "delivered" => {
                         sqlx::query(
@@ -303,39 +531,32 @@ impl MessageHandler {
 
                 // Broadcast updated status to conversation participants
                 let conv_id = current.conversation_id.clone();
-                let event = MessageEnvelope {
-                    id: uuid::Uuid::new_v4().to_string(),
-                    msg_type: "deliveryStatusUpdated".to_string(),
-                    timestamp: chrono::Utc::now().timestamp_millis() as u64,
-                    data: json!({
-                        "messageId": update.message_id,
-                        "status": update.status,
-                        "timestamp": chrono::Utc::now().timestamp_millis(),
-                        "conversationId": conv_id,
-                    }),
-                };
+                let event = ServerFrame::DeliveryStatusUpdated(DeliveryStatusUpdatedEvent {
+                    message_id: update.message_id.clone(),
+                    status: update.status.clone(),
+                    timestamp: chrono::Utc::now().timestamp_millis(),
+                    conversation_id: Some(conv_id),
+                })
+                .into_envelope();
 
                 // Send to both sender and recipient
-                let event_json = serde_json::to_string(&event).unwrap();
-                self.connection_manager
-                    .send_to_user(&current.sender_id, WsMessage::text(event_json.clone()))
-                    .await;
-                self.connection_manager
-                    .send_to_user(&current.recipient_id, WsMessage::text(event_json))
+                let _ = self
+                    .connection_manager
+                    .broadcast_event(
+                        [&current.sender_id, &current.recipient_id],
+                        event,
+                        SendPriority::Essential,
+                    )
                     .await;
             }
         }
 
         // Send completion event back to sender
-        let completion = MessageEnvelope {
-            id: uuid::Uuid::new_v4().to_string(),
-            msg_type: "syncDeliveryStatusCompleted".to_string(),
-            timestamp: chrono::Utc::now().timestamp_millis() as u64,
-            data: json!({
-                "syncedCount": synced_count,
-                "timestamp": chrono::Utc::now().timestamp_millis(),
-            }),
-        };
+        let completion = ServerFrame::SyncDeliveryStatusCompleted(SyncDeliveryStatusCompletedEvent {
+            synced_count,
+            timestamp: chrono::Utc::now().timestamp_millis(),
+        })
+        .into_envelope();
         responses.push(WsMessage::text(serde_json::to_string(&completion).unwrap()));
 
         Ok(responses)
@@ -345,10 +566,11 @@ impl MessageHandler {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::json;
     use crate::handlers::websocket::ConnectionManager;
     use crate::models::User;
     use crate::services::MessageQueueService;
-    use tokio::sync::mpsc;
+    use crate::handlers::outbound::{OutboundQueue, OutboundQueueConfig};
 
     async fn setup_test_db() -> SqlitePool {
         let pool = sqlx::sqlite::SqlitePoolOptions::new()
@@ -356,10 +578,7 @@ mod tests {
             .await
             .unwrap();
 
-        let schema_sql = include_str!("../../backend/db/migrations/001_initial_schema.sql");
-        for statement in schema_sql.split(';').filter(|s| !s.trim().is_empty()) {
-            sqlx::query(statement).execute(&pool).await.unwrap();
-        }
+        crate::db::run_migrations(&pool).await.unwrap();
 
         pool
     }
@@ -390,8 +609,10 @@ mod tests {
             id: uuid::Uuid::new_v4().to_string(),
             msg_type: "message".to_string(),
             timestamp: chrono::Utc::now().timestamp_millis() as u64,
+            traceparent: None,
+            seq: None,
             data: json!({
-                "recipient_id": user2.id,
+                "recipientId": user2.id,
                 "content": "Hello, Bob!",
             }),
         };
@@ -427,7 +648,7 @@ mod tests {
 
         // Register recipient as online
         let recipient_conn = ClientConnection::new(user2.id.clone(), user2.username.clone());
-        let (tx, _rx) = mpsc::unbounded_channel();
+        let (tx, _rx) = OutboundQueue::new(&OutboundQueueConfig::default());
         conn_mgr.register(recipient_conn, tx).await;
 
         // Create sender connection
@@ -438,8 +659,10 @@ mod tests {
             id: uuid::Uuid::new_v4().to_string(),
             msg_type: "message".to_string(),
             timestamp: chrono::Utc::now().timestamp_millis() as u64,
+            traceparent: None,
+            seq: None,
             data: json!({
-                "recipient_id": user2.id,
+                "recipientId": user2.id,
                 "content": "Hello, Bob!",
             }),
         };
@@ -451,6 +674,44 @@ mod tests {
         assert_eq!(responses.len(), 1);
     }
 
+    #[tokio::test]
+    async fn test_relay_typing_needs_a_conversation() {
+        let pool = setup_test_db().await;
+        let conn_mgr = Arc::new(ConnectionManager::new());
+        let queue = MessageQueueService::new(pool.clone(), conn_mgr.clone());
+        let handler = MessageHandler::new(pool.clone(), conn_mgr.clone(), queue);
+
+        let user1 = User::new("alice".to_string(), "hash1".to_string(), "salt1".to_string());
+        let user2 = User::new("bob".to_string(), "hash2".to_string(), "salt2".to_string());
+        queries::insert_user(&pool, &user1).await.unwrap();
+        queries::insert_user(&pool, &user2).await.unwrap();
+
+        let recipient_conn = ClientConnection::new(user2.id.clone(), user2.username.clone());
+        let (tx, mut rx) = OutboundQueue::new(&OutboundQueueConfig::default());
+        conn_mgr.register(recipient_conn, tx).await;
+        let sender = ClientConnection::new(user1.id.clone(), user1.username.clone());
+
+        let typing = MessageEnvelope::new(
+            "typing",
+            json!({ "recipientId": user2.id, "isTyping": true }),
+        );
+        handler.relay_typing(&typing, &sender).await.unwrap();
+        assert!(rx.try_recv().is_err());
+
+        handler
+            .create_or_get_conversation(user1.id.clone(), user2.id.clone())
+            .await
+            .unwrap();
+        handler.relay_typing(&typing, &sender).await.unwrap();
+
+        let frame = rx.try_recv().unwrap();
+        let frame: serde_json::Value = serde_json::from_str(frame.to_str().unwrap()).unwrap();
+        assert_eq!(frame["type"], "typing");
+        assert_eq!(frame["data"]["senderId"], user1.id);
+        assert_eq!(frame["data"]["senderUsername"], "alice");
+        assert_eq!(frame["data"]["isTyping"], true);
+    }
+
     #[tokio::test]
     async fn test_handle_message_idempotency() {
         let pool = setup_test_db().await;
@@ -477,8 +738,10 @@ mod tests {
             id: message_id.clone(),
             msg_type: "message".to_string(),
             timestamp: chrono::Utc::now().timestamp_millis() as u64,
+            traceparent: None,
+            seq: None,
             data: json!({
-                "recipient_id": user2.id,
+                "recipientId": user2.id,
                 "content": "Hello, Bob!",
             }),
         };
@@ -492,4 +755,121 @@ mod tests {
         // Should still get acknowledgement
         assert!(!responses2.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_send_text_message_rejects_foreign_message_id() {
+        let pool = setup_test_db().await;
+        let conn_mgr = Arc::new(ConnectionManager::new());
+        let queue = MessageQueueService::new(pool.clone(), conn_mgr.clone());
+        let handler = MessageHandler::new(pool.clone(), conn_mgr.clone(), queue);
+
+        let user1 = User::new("alice".to_string(), "hash1".to_string(), "salt1".to_string());
+        let user2 = User::new("bob".to_string(), "hash2".to_string(), "salt2".to_string());
+        queries::insert_user(&pool, &user1).await.unwrap();
+        queries::insert_user(&pool, &user2).await.unwrap();
+
+        let alice = ClientConnection::new(user1.id.clone(), user1.username.clone());
+        let bob = ClientConnection::new(user2.id.clone(), user2.username.clone());
+        let to = |recipient_id: &str| TextMessageData {
+            sender_id: None,
+            sender_username: None,
+            recipient_id: recipient_id.to_string(),
+            content: "Hello".to_string(),
+            conversation_id: None,
+            status: None,
+            encrypted: false,
+            system: false,
+            expires_at: None,
+        };
+
+        let sent = handler
+            .send_text_message("shared-id", &alice, &to(&user2.id), None)
+            .await
+            .unwrap();
+        assert!(sent.was_created);
+        assert_eq!(sent.delivery_status, "sent");
+        // Bob accepts Alice's message request
+        assert!(queries::delete_message_request(&pool, &sent.conversation_id)
+            .await
+            .unwrap());
+
+        // Bob cannot replay Alice's message by reusing its ID
+        assert_eq!(
+            handler
+                .send_text_message("shared-id", &bob, &to(&user1.id), None)
+                .await
+                .unwrap_err(),
+            SendError::DuplicateId
+        );
+
+        let replay = handler
+            .send_text_message("shared-id", &alice, &to(&user2.id), None)
+            .await
+            .unwrap();
+        assert!(!replay.was_created);
+        assert_eq!(replay.message.id, "shared-id");
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_conversation_refuses_plaintext() {
+        let pool = setup_test_db().await;
+        let conn_mgr = Arc::new(ConnectionManager::new());
+        let queue = MessageQueueService::new(pool.clone(), conn_mgr.clone());
+        let handler = MessageHandler::new(pool.clone(), conn_mgr.clone(), queue);
+
+        let user1 = User::new("alice".to_string(), "hash1".to_string(), "salt1".to_string());
+        let user2 = User::new("bob".to_string(), "hash2".to_string(), "salt2".to_string());
+        queries::insert_user(&pool, &user1).await.unwrap();
+        queries::insert_user(&pool, &user2).await.unwrap();
+
+        let alice = ClientConnection::new(user1.id.clone(), user1.username.clone());
+        let message = |content: &str, encrypted: bool| TextMessageData {
+            sender_id: None,
+            sender_username: None,
+            recipient_id: user2.id.clone(),
+            content: content.to_string(),
+            conversation_id: None,
+            status: None,
+            encrypted,
+            system: false,
+            expires_at: None,
+        };
+
+        handler
+            .send_text_message("m1", &alice, &message("findable", false), None)
+            .await
+            .unwrap();
+        // Ciphertext may exceed the plaintext limit
+        let envelope = "x".repeat(6000);
+        let sent = handler
+            .send_text_message("m2", &alice, &message(&envelope, true), None)
+            .await
+            .unwrap();
+        assert!(sent.message.is_encrypted);
+
+        let conversation = queries::get_conversation_by_id(&pool, &sent.message.conversation_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(conversation.is_encrypted);
+
+        // No downgrade once the first encrypted message is stored
+        assert!(matches!(
+            handler
+                .send_text_message("m3", &alice, &message("plaintext", false), None)
+                .await,
+            Err(SendError::Invalid(_))
+        ));
+
+        // Search only sees plaintext
+        let found = queries::search_messages_in_conversation(&pool, &conversation.id, "x", 10)
+            .await
+            .unwrap();
+        assert!(found.is_empty());
+        let found =
+            queries::search_messages_in_conversation(&pool, &conversation.id, "findable", 10)
+                .await
+                .unwrap();
+        assert_eq!(found.len(), 1);
+    }
 }
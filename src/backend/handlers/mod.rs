@@ -2,15 +2,22 @@
 
 pub mod auth;
 pub mod auth_with_rate_limit;
+pub mod compression;
 pub mod conversation;
 pub mod dispatcher;
+pub mod event_log;
 pub mod handshake;
 pub mod heartbeat;
+pub mod keys;
 pub mod messages;
+pub mod openapi;
+pub mod outbound;
 pub mod parser;
 pub mod refresh;
 pub mod router;
+pub mod schema;
 pub mod server;
+pub mod sse;
 pub mod user;
 pub mod websocket;
 
@@ -0,0 +1,185 @@
+//! OpenAPI 3 description of the REST API
+//!
+//! The document is assembled from the `#[utoipa::path]` annotations on each
+//! handler and the `ToSchema` derives on their request and response types, so
+//! it changes with the code. Served at `GET /openapi.json`.
+
+use crate::handlers::compression::CompressionStats;
+use crate::handlers::outbound::QueueStats;
+use crate::handlers::{auth, conversation, keys, server, user};
+use crate::services::retention::RetentionStats;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Chat API", description = "REST endpoints of the chat server. WebSocket frames are described at /protocol/schema."),
+    paths(
+        server::health,
+        server::status,
+        server::protocol_schema,
+        server::openapi,
+        crate::server::handle_websocket_upgrade,
+        crate::server::handle_event_stream,
+        auth::signup_handler,
+        auth::login_handler,
+        auth::logout_handler,
+        user::get_current_user,
+        user::delete_account,
+        user::change_password,
+        user::search_users,
+        user::request_export,
+        user::get_export,
+        user::download_export,
+        user::list_blocked_users,
+        user::block_user,
+        user::unblock_user,
+        conversation::get_conversations,
+        conversation::start_conversation,
+        conversation::list_message_requests,
+        conversation::accept_message_request,
+        conversation::decline_message_request,
+        conversation::get_conversation_messages,
+        conversation::send_conversation_message,
+        conversation::search_messages,
+        conversation::set_ephemeral,
+        conversation::export_conversation,
+        keys::upload_keys,
+        keys::get_key_status,
+        keys::get_key_bundle,
+    ),
+    components(schemas(
+        auth::SignupRequest,
+        auth::LoginRequest,
+        auth::AuthResponse,
+        auth::ErrorResponse,
+        auth::SuccessResponse,
+        user::UserProfileResponse,
+        user::UserSearchResult,
+        user::DeleteAccountRequest,
+        user::ChangePasswordRequest,
+        user::DataExportResponse,
+        user::BlockedUserResponse,
+        conversation::StartConversationRequest,
+        conversation::ConversationResponse,
+        conversation::MessageRequestResponse,
+        conversation::MessageResponse,
+        conversation::SendMessageRequest,
+        conversation::SendMessageResponse,
+        conversation::EphemeralRequest,
+        conversation::EphemeralResponse,
+        keys::SignedPrekey,
+        keys::PublicPrekey,
+        keys::UploadKeysRequest,
+        keys::KeyStatusResponse,
+        keys::KeyBundleResponse,
+        server::HealthResponse,
+        server::StatusResponse,
+        server::DatabaseStatus,
+        server::StatusMetrics,
+        server::SendQueueMetrics,
+        server::ConnectionQueueMetrics,
+        CompressionStats,
+        QueueStats,
+        RetentionStats,
+    )),
+    modifiers(&BearerAuth),
+    tags(
+        (name = "server", description = "Health, diagnostics and API descriptions"),
+        (name = "realtime", description = "WebSocket and Server-Sent Events connections"),
+        (name = "auth", description = "Signup, login and logout"),
+        (name = "user", description = "Account management and user search"),
+        (name = "conversations", description = "Conversations and message history"),
+        (name = "keys", description = "Public keys for end-to-end encryption"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Registers the `bearer` scheme referenced by authenticated paths
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    /// `METHOD /path` of every route registered in `create_routes`, read from
+    /// the `// METHOD /path` comment each route carries
+    fn registered_routes() -> BTreeSet<(String, String)> {
+        let source = include_str!("../server.rs");
+        let start = source.find("pub fn create_routes(").unwrap();
+        let end = start + source[start..].find("\n}\n").unwrap();
+        let body = &source[start..end];
+
+        let routes: BTreeSet<(String, String)> = body
+            .lines()
+            .filter_map(|line| {
+                let mut words = line.trim().strip_prefix("// ")?.split_whitespace();
+                let method = words.next()?;
+                let path = words.next()?;
+                let is_method = ["GET", "POST", "PUT", "PATCH", "DELETE"].contains(&method);
+                (is_method && path.starts_with('/')).then(|| {
+                    let path = path.split('?').next().unwrap_or(path);
+                    (method.to_lowercase(), path.to_string())
+                })
+            })
+            .collect();
+
+        // One comment per route, so a new route without one fails here
+        assert_eq!(
+            routes.len(),
+            body.matches(".and_then(").count(),
+            "every route in create_routes needs a `// METHOD /path` comment"
+        );
+        routes
+    }
+
+    #[test]
+    fn test_every_route_is_documented() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let paths = spec["paths"].as_object().unwrap();
+
+        let routes = registered_routes();
+        for (method, path) in &routes {
+            assert!(
+                paths.get(path).and_then(|p| p.get(method)).is_some(),
+                "{} {} is routed but missing from the OpenAPI document",
+                method.to_uppercase(),
+                path
+            );
+        }
+
+        let documented: usize = paths.values().map(|p| p.as_object().unwrap().len()).sum();
+        assert_eq!(documented, routes.len(), "OpenAPI documents a route that does not exist");
+    }
+
+    #[test]
+    fn test_document_is_complete() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+        assert_eq!(spec["components"]["securitySchemes"]["bearer"]["scheme"], "bearer");
+
+        // Every referenced schema is registered
+        let schemas = spec["components"]["schemas"].as_object().unwrap();
+        let text = spec.to_string();
+        for reference in text.split("\"#/components/schemas/").skip(1) {
+            let name = reference.split('"').next().unwrap();
+            assert!(schemas.contains_key(name), "unregistered schema {}", name);
+        }
+    }
+}
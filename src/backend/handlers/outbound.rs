@@ -0,0 +1,232 @@
+//! Bounded per-connection send queues
+//!
+//! Every socket drains a bounded channel. When a client stops reading, the queue
+//! fills up: droppable frames (typing, presence) are discarded first once the
+//! queue passes its high-water mark, and if an essential frame (a chat message,
+//! an ack) still does not fit, the connection is evicted and closed with
+//! `CLOSE_SLOW_CONSUMER` so the client reconnects and resyncs.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::Notify;
+use warp::ws::Message as WsMessage;
+
+/// Delivery priority of an outbound frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendPriority {
+    /// Ephemeral state (typing, presence); may be dropped under pressure
+    Droppable,
+    /// Must reach the client or the connection is evicted
+    Essential,
+}
+
+/// Queue sizing and overflow policy
+#[derive(Debug, Clone)]
+pub struct OutboundQueueConfig {
+    /// Maximum frames buffered per connection
+    pub capacity: usize,
+    /// Depth at which droppable frames start being discarded
+    pub droppable_high_water: usize,
+}
+
+impl Default for OutboundQueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 256,
+            droppable_high_water: 192,
+        }
+    }
+}
+
+/// Result of pushing a frame onto a connection's queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushOutcome {
+    Queued,
+    /// Droppable frame discarded because the queue is above its high-water mark
+    Dropped,
+    /// Essential frame did not fit; the connection has been evicted
+    Evicted,
+    /// The socket task has already gone away
+    Closed,
+}
+
+/// Point-in-time queue metrics for one connection
+#[derive(Debug, Clone, serde::Serialize, utoipa::ToSchema)]
+pub struct QueueStats {
+    pub depth: usize,
+    pub capacity: usize,
+    pub dropped: u64,
+    pub evicted: bool,
+}
+
+/// Sending half of a connection's bounded queue
+#[derive(Clone)]
+pub struct OutboundQueue {
+    sender: mpsc::Sender<WsMessage>,
+    config: OutboundQueueConfig,
+    dropped: Arc<AtomicU64>,
+    evicted: Arc<AtomicBool>,
+    eviction: Arc<Notify>,
+}
+
+impl OutboundQueue {
+    /// Create a queue and the receiver the socket's forwarder drains
+    pub fn new(config: &OutboundQueueConfig) -> (Self, mpsc::Receiver<WsMessage>) {
+        let capacity = config.capacity.max(1);
+        let (sender, receiver) = mpsc::channel(capacity);
+        let queue = Self {
+            sender,
+            config: OutboundQueueConfig {
+                capacity,
+                droppable_high_water: config.droppable_high_water.min(capacity),
+            },
+            dropped: Arc::new(AtomicU64::new(0)),
+            evicted: Arc::new(AtomicBool::new(false)),
+            eviction: Arc::new(Notify::new()),
+        };
+        (queue, receiver)
+    }
+
+    /// Queue a frame without waiting, applying the overflow policy
+    pub fn push(&self, message: WsMessage, priority: SendPriority) -> PushOutcome {
+        if self.is_evicted() {
+            return PushOutcome::Closed;
+        }
+
+        if priority == SendPriority::Droppable && self.depth() >= self.config.droppable_high_water {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return PushOutcome::Dropped;
+        }
+
+        match self.sender.try_send(message) {
+            Ok(()) => PushOutcome::Queued,
+            Err(TrySendError::Closed(_)) => PushOutcome::Closed,
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                if priority == SendPriority::Droppable {
+                    PushOutcome::Dropped
+                } else {
+                    self.evict();
+                    PushOutcome::Evicted
+                }
+            }
+        }
+    }
+
+    /// Mark the connection as a slow consumer and wake its socket task
+    pub fn evict(&self) {
+        if !self.evicted.swap(true, Ordering::SeqCst) {
+            self.eviction.notify_one();
+        }
+    }
+
+    /// Resolve once the connection has been evicted
+    pub async fn evicted(&self) {
+        if self.is_evicted() {
+            return;
+        }
+        self.eviction.notified().await;
+    }
+
+    pub fn is_evicted(&self) -> bool {
+        self.evicted.load(Ordering::SeqCst)
+    }
+
+    /// Frames currently waiting to be written to the socket
+    pub fn depth(&self) -> usize {
+        self.config.capacity - self.sender.capacity()
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        QueueStats {
+            depth: self.depth(),
+            capacity: self.config.capacity,
+            dropped: self.dropped.load(Ordering::Relaxed),
+            evicted: self.is_evicted(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_config() -> OutboundQueueConfig {
+        OutboundQueueConfig {
+            capacity: 4,
+            droppable_high_water: 2,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_droppable_frames_dropped_above_high_water() {
+        let (queue, _rx) = OutboundQueue::new(&small_config());
+
+        assert_eq!(
+            queue.push(WsMessage::text("m1"), SendPriority::Essential),
+            PushOutcome::Queued
+        );
+        assert_eq!(
+            queue.push(WsMessage::text("t1"), SendPriority::Droppable),
+            PushOutcome::Queued
+        );
+        assert_eq!(
+            queue.push(WsMessage::text("t2"), SendPriority::Droppable),
+            PushOutcome::Dropped
+        );
+
+        // Essential frames still fit
+        assert_eq!(
+            queue.push(WsMessage::text("m2"), SendPriority::Essential),
+            PushOutcome::Queued
+        );
+
+        let stats = queue.stats();
+        assert_eq!(stats.depth, 3);
+        assert_eq!(stats.capacity, 4);
+        assert_eq!(stats.dropped, 1);
+        assert!(!stats.evicted);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_evicts_on_essential_frame() {
+        let (queue, mut rx) = OutboundQueue::new(&small_config());
+        for i in 0..4 {
+            let outcome = queue.push(WsMessage::text(format!("m{}", i)), SendPriority::Essential);
+            assert_eq!(outcome, PushOutcome::Queued);
+        }
+
+        assert_eq!(
+            queue.push(WsMessage::text("m4"), SendPriority::Essential),
+            PushOutcome::Evicted
+        );
+        assert!(queue.is_evicted());
+        tokio::time::timeout(std::time::Duration::from_millis(100), queue.evicted())
+            .await
+            .expect("eviction should be signalled");
+
+        // Nothing more is accepted once evicted
+        rx.recv().await.unwrap();
+        assert_eq!(
+            queue.push(WsMessage::text("m5"), SendPriority::Essential),
+            PushOutcome::Closed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_draining_frees_capacity() {
+        let (queue, mut rx) = OutboundQueue::new(&small_config());
+        queue.push(WsMessage::text("m1"), SendPriority::Essential);
+        queue.push(WsMessage::text("m2"), SendPriority::Essential);
+        assert_eq!(queue.depth(), 2);
+
+        rx.recv().await.unwrap();
+        rx.recv().await.unwrap();
+        assert_eq!(queue.depth(), 0);
+        assert_eq!(
+            queue.push(WsMessage::text("t"), SendPriority::Droppable),
+            PushOutcome::Queued
+        );
+    }
+}
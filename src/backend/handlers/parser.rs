@@ -141,7 +141,12 @@ impl FrameParser {
             .unwrap_or("")
             .to_string();
 
-        MessageValidator::validate_text_message(&content, &recipient_id)
+        let encrypted = data
+            .get("encrypted")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        MessageValidator::validate_text_message(&content, &recipient_id, encrypted)
             .map(|_| (recipient_id, content))
             .map_err(|e| e.to_string())
     }
@@ -307,6 +312,8 @@ mod tests {
             id: "msg-123".to_string(),
             msg_type: "message".to_string(),
             timestamp: 1234567890,
+            traceparent: None,
+            seq: None,
             data: json!({}),
         };
 
@@ -319,6 +326,8 @@ mod tests {
             id: "msg-123".to_string(),
             msg_type: "message".to_string(),
             timestamp: 1234567890,
+            traceparent: None,
+            seq: None,
             data: json!({
                 "recipientId": "user-456",
                 "content": "Hello"
@@ -338,6 +347,8 @@ mod tests {
             id: "msg-123".to_string(),
             msg_type: "message".to_string(),
             timestamp: 1234567890,
+            traceparent: None,
+            seq: None,
             data: json!({
                 "recipientId": "",
                 "content": ""
@@ -354,6 +365,8 @@ mod tests {
             id: "typing-123".to_string(),
             msg_type: "typing".to_string(),
             timestamp: 1234567890,
+            traceparent: None,
+            seq: None,
             data: json!({
                 "recipientId": "user-456",
                 "isTyping": true
@@ -371,6 +384,8 @@ mod tests {
             id: "typing-123".to_string(),
             msg_type: "typing".to_string(),
             timestamp: 1234567890,
+            traceparent: None,
+            seq: None,
             data: json!({
                 "recipientId": "",
                 "isTyping": true
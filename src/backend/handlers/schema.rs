@@ -0,0 +1,160 @@
+//! Inbound frame validation against the protocol JSON Schemas
+//!
+//! Schemas come from `chat_shared::protocol::schema` and are compiled once per
+//! frame type. Every inbound envelope is checked against the schema for its
+//! `type` before it is deserialized, and each violation is reported with the
+//! JSON pointer of the offending field.
+
+use chat_shared::protocol::schema::{inbound_frame_schema, INBOUND_TYPES};
+use jsonschema::{error::ValidationErrorKind, Draft, JSONSchema};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// One schema violation in an inbound frame
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+    /// JSON pointer to the field, e.g. `/data/recipientId`
+    pub path: String,
+    /// Schema keyword that failed, e.g. `required` or `maxLength`
+    pub keyword: String,
+    pub message: String,
+}
+
+/// Compiled schemas for every inbound frame type
+pub struct FrameValidator {
+    schemas: HashMap<&'static str, JSONSchema>,
+}
+
+impl FrameValidator {
+    /// Shared validator, compiled on first use
+    pub fn global() -> &'static FrameValidator {
+        static VALIDATOR: OnceLock<FrameValidator> = OnceLock::new();
+        VALIDATOR.get_or_init(FrameValidator::new)
+    }
+
+    fn new() -> Self {
+        let schemas = INBOUND_TYPES
+            .iter()
+            .filter_map(|msg_type| {
+                let schema = inbound_frame_schema(msg_type)?;
+                let compiled = JSONSchema::options()
+                    .with_draft(Draft::Draft7)
+                    .compile(&schema)
+                    .unwrap_or_else(|e| panic!("invalid schema for {}: {}", msg_type, e));
+                Some((*msg_type, compiled))
+            })
+            .collect();
+        Self { schemas }
+    }
+
+    /// Validate a decoded frame against the schema for its `type`
+    pub fn validate(&self, frame: &Value) -> Result<(), Vec<FieldError>> {
+        let Some(msg_type) = frame.get("type").and_then(Value::as_str) else {
+            return Err(vec![FieldError {
+                path: "/type".to_string(),
+                keyword: "required".to_string(),
+                message: "Frame must have a string \"type\"".to_string(),
+            }]);
+        };
+        let Some(schema) = self.schemas.get(msg_type) else {
+            return Err(vec![FieldError {
+                path: "/type".to_string(),
+                keyword: "enum".to_string(),
+                message: format!("Unknown message type: {}", msg_type),
+            }]);
+        };
+
+        schema.validate(frame).map_err(|errors| {
+            errors
+                .map(|e| {
+                    let mut path = e.instance_path.to_string();
+                    // Point at the missing field itself rather than its parent
+                    if let ValidationErrorKind::Required { property } = &e.kind {
+                        if let Some(name) = property.as_str() {
+                            path = format!("{}/{}", path, name);
+                        }
+                    }
+                    let schema_path = e.schema_path.to_string();
+                    FieldError {
+                        path,
+                        keyword: schema_path.rsplit('/').next().unwrap_or_default().to_string(),
+                        message: e.to_string(),
+                    }
+                })
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn frame(msg_type: &str, data: Value) -> Value {
+        json!({
+            "id": "frame-1",
+            "type": msg_type,
+            "timestamp": 1702657890000u64,
+            "data": data,
+        })
+    }
+
+    #[test]
+    fn test_valid_frames_pass() {
+        let validator = FrameValidator::global();
+        let frames = [
+            frame("message", json!({ "recipientId": "u2", "content": "Hi" })),
+            frame("typing", json!({ "recipientId": "u2", "isTyping": true })),
+            frame("resume", json!({ "lastSeq": 42 })),
+            frame("heartbeat", json!({})),
+        ];
+        for f in &frames {
+            assert_eq!(validator.validate(f), Ok(()), "{}", f);
+        }
+    }
+
+    #[test]
+    fn test_errors_point_at_fields() {
+        let validator = FrameValidator::global();
+
+        let errors = validator
+            .validate(&frame("message", json!({ "recipient_id": "u2", "content": "Hi" })))
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].path, "/data/recipientId");
+        assert_eq!(errors[0].keyword, "required");
+
+        let errors = validator
+            .validate(&frame("typing", json!({ "recipientId": "u2", "isTyping": "yes" })))
+            .unwrap_err();
+        assert_eq!(errors[0].path, "/data/isTyping");
+        assert_eq!(errors[0].keyword, "type");
+
+        // The schema bound admits ciphertext; plaintext is held to 5000 after parsing
+        let long = "a".repeat(chat_shared::protocol::MAX_ENCRYPTED_CONTENT_LENGTH + 1);
+        let errors = validator
+            .validate(&frame("message", json!({ "recipientId": "u2", "content": long })))
+            .unwrap_err();
+        assert_eq!(errors[0].path, "/data/content");
+        assert_eq!(errors[0].keyword, "maxLength");
+    }
+
+    #[test]
+    fn test_unknown_or_missing_type() {
+        let validator = FrameValidator::global();
+
+        let errors = validator.validate(&frame("welcome", json!({}))).unwrap_err();
+        assert_eq!(errors[0].path, "/type");
+
+        let errors = validator.validate(&json!({ "id": "x" })).unwrap_err();
+        assert_eq!(errors[0].path, "/type");
+
+        let mut missing_id = frame("heartbeat", json!({}));
+        missing_id.as_object_mut().unwrap().remove("id");
+        let errors = validator.validate(&missing_id).unwrap_err();
+        assert_eq!(errors[0].path, "/id");
+    }
+}
@@ -1,20 +1,25 @@
-//! Server-level HTTP handlers (health and status endpoints)
+//! Server-level HTTP handlers (health, status, protocol schema and OpenAPI endpoints)
 
+use crate::handlers::compression::CompressionStats;
+use crate::handlers::outbound::QueueStats;
+use crate::handlers::openapi::ApiDoc;
 use crate::handlers::{rejection, ApiError};
 use crate::server::ServerState;
+use crate::services::retention::RetentionStats;
 use serde::Serialize;
 use tracing::{info, warn};
+use utoipa::{OpenApi, ToSchema};
 use warp::{reply, Rejection, Reply};
 
-#[derive(Serialize)]
-struct HealthResponse {
+#[derive(Serialize, ToSchema)]
+pub struct HealthResponse {
     status: &'static str,
     timestamp: i64,
     uptime_seconds: u64,
 }
 
-#[derive(Serialize)]
-struct StatusResponse {
+#[derive(Serialize, ToSchema)]
+pub struct StatusResponse {
     status: &'static str,
     version: &'static str,
     timestamp: i64,
@@ -23,20 +28,45 @@ struct StatusResponse {
     metrics: StatusMetrics,
 }
 
-#[derive(Serialize)]
-struct DatabaseStatus {
+#[derive(Serialize, ToSchema)]
+pub struct DatabaseStatus {
     status: &'static str,
     engine: &'static str,
 }
 
-#[derive(Serialize)]
-struct StatusMetrics {
+#[derive(Serialize, ToSchema)]
+pub struct StatusMetrics {
     total_users: i64,
     total_messages: i64,
     online_connections: usize,
+    send_queues: SendQueueMetrics,
+    compression: CompressionStats,
+    /// Rows deleted by retention rules since startup
+    retention: RetentionStats,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SendQueueMetrics {
+    total_depth: usize,
+    max_depth: usize,
+    total_dropped: u64,
+    connections: Vec<ConnectionQueueMetrics>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ConnectionQueueMetrics {
+    connection_id: String,
+    #[serde(flatten)]
+    stats: QueueStats,
 }
 
 /// GET /health - lightweight readiness check
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "server",
+    responses((status = 200, description = "Server is up", body = HealthResponse))
+)]
 pub async fn health(state: ServerState) -> Result<impl Reply, Rejection> {
     let uptime = state.start_time.elapsed().as_secs();
     let response = HealthResponse {
@@ -55,7 +85,38 @@ pub async fn health(state: ServerState) -> Result<impl Reply, Rejection> {
     Ok(reply::json(&response))
 }
 
+/// GET /protocol/schema - JSON Schemas for every WebSocket frame type
+#[utoipa::path(
+    get,
+    path = "/protocol/schema",
+    tag = "server",
+    responses((status = 200, description = "Draft-07 schema per inbound and outbound frame type", body = Object))
+)]
+pub async fn protocol_schema() -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&chat_shared::protocol::schema::protocol_schema()))
+}
+
+/// GET /openapi.json - OpenAPI 3 description of the REST API
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "server",
+    responses((status = 200, description = "This document", body = Object))
+)]
+pub async fn openapi() -> Result<impl Reply, Rejection> {
+    Ok(reply::json(&ApiDoc::openapi()))
+}
+
 /// GET /status - richer diagnostics with database connectivity and simple metrics
+#[utoipa::path(
+    get,
+    path = "/status",
+    tag = "server",
+    responses(
+        (status = 200, description = "Server, database and connection metrics", body = StatusResponse),
+        (status = 500, description = "Database unreachable", body = ErrorResponse),
+    )
+)]
 pub async fn status(state: ServerState) -> Result<impl Reply, Rejection> {
     let uptime = state.start_time.elapsed().as_secs();
     let timestamp = chrono::Utc::now().timestamp_millis();
@@ -96,6 +157,7 @@ pub async fn status(state: ServerState) -> Result<impl Reply, Rejection> {
         })?;
 
     let online_connections = state.connection_manager.get_online_users().await.len();
+    let send_queues = send_queue_metrics(&state).await;
 
     let response = StatusResponse {
         status: "running",
@@ -110,6 +172,9 @@ pub async fn status(state: ServerState) -> Result<impl Reply, Rejection> {
             total_users,
             total_messages,
             online_connections,
+            send_queues,
+            compression: state.compression_metrics.snapshot(),
+            retention: state.retention_service.metrics().snapshot(),
         },
     };
 
@@ -124,3 +189,26 @@ pub async fn status(state: ServerState) -> Result<impl Reply, Rejection> {
 
     Ok(reply::json(&response))
 }
+
+/// Per-connection outbound queue depth, capacity and drop counts
+async fn send_queue_metrics(state: &ServerState) -> SendQueueMetrics {
+    let mut connections: Vec<ConnectionQueueMetrics> = state
+        .connection_manager
+        .queue_stats()
+        .await
+        .into_iter()
+        .map(|(connection_id, stats)| ConnectionQueueMetrics {
+            connection_id,
+            stats,
+        })
+        .collect();
+    // Most backed-up connections first
+    connections.sort_by_key(|c| std::cmp::Reverse(c.stats.depth));
+
+    SendQueueMetrics {
+        total_depth: connections.iter().map(|c| c.stats.depth).sum(),
+        max_depth: connections.iter().map(|c| c.stats.depth).max().unwrap_or(0),
+        total_dropped: connections.iter().map(|c| c.stats.dropped).sum(),
+        connections,
+    }
+}
@@ -0,0 +1,155 @@
+//! Server-Sent Events transport
+//!
+//! `GET /events` is a receive-only fallback for networks that block WebSocket
+//! upgrades. The stream drains the same bounded outbound queue a socket does, so
+//! it is registered with `ConnectionManager`, counts for presence and gets live
+//! delivery; sending goes through the REST endpoints. Each event's `id` is the
+//! envelope's `seq`, so a reconnecting client resumes with `Last-Event-ID`.
+
+use crate::handlers::outbound::OutboundQueue;
+use futures::Stream;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use tokio::sync::mpsc;
+use warp::sse::Event;
+use warp::ws::Message as WsMessage;
+
+/// Header an `EventSource` sends with the last event ID it saw when reconnecting
+pub const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
+/// What an outbound queue frame becomes on an event stream
+#[derive(Debug)]
+pub enum SseFrame {
+    Event(Event),
+    /// Transport-level frame (ping, binary) with no SSE equivalent
+    Skip,
+    /// The connection was closed server-side; end the stream
+    Close,
+}
+
+#[derive(Deserialize)]
+struct Sequenced {
+    seq: Option<u64>,
+}
+
+/// Convert a queued frame into an SSE event, tagging it with the envelope's `seq`
+pub fn to_sse_frame(msg: &WsMessage) -> SseFrame {
+    if msg.is_close() {
+        return SseFrame::Close;
+    }
+    let Ok(text) = msg.to_str() else {
+        return SseFrame::Skip;
+    };
+
+    let event = Event::default().data(text);
+    match serde_json::from_str::<Sequenced>(text).ok().and_then(|s| s.seq) {
+        Some(seq) => SseFrame::Event(event.id(seq.to_string())),
+        None => SseFrame::Event(event),
+    }
+}
+
+/// Parse a `Last-Event-ID` value back into the `seq` to resume from
+pub fn parse_last_event_id(value: Option<&str>) -> Option<u64> {
+    value.and_then(|v| v.trim().parse().ok())
+}
+
+/// Token from an `Authorization: Bearer <token>` header value
+pub fn bearer_token(header: Option<&str>) -> Option<&str> {
+    header
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+}
+
+/// Stream `initial` frames, then the connection's queue until it closes or is evicted
+///
+/// `guard` is dropped with the stream, which happens when the client goes away.
+pub fn event_stream<G>(
+    initial: Vec<WsMessage>,
+    rx: mpsc::Receiver<WsMessage>,
+    queue: OutboundQueue,
+    guard: G,
+) -> impl Stream<Item = Result<Event, Infallible>>
+where
+    G: Send + 'static,
+{
+    let state = (VecDeque::from(initial), rx, queue, guard);
+    futures::stream::unfold(state, |(mut initial, mut rx, queue, guard)| async move {
+        loop {
+            let msg = match initial.pop_front() {
+                Some(msg) => msg,
+                None => tokio::select! {
+                    msg = rx.recv() => msg?,
+                    _ = queue.evicted() => return None,
+                },
+            };
+            match to_sse_frame(&msg) {
+                SseFrame::Event(event) => return Some((Ok(event), (initial, rx, queue, guard))),
+                SseFrame::Skip => continue,
+                SseFrame::Close => return None,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::outbound::{OutboundQueueConfig, SendPriority};
+    use futures::StreamExt;
+
+    #[test]
+    fn test_sequenced_envelopes_carry_event_id() {
+        let frame = WsMessage::text(r#"{"id":"e1","type":"presence","seq":42,"data":{}}"#);
+        match to_sse_frame(&frame) {
+            SseFrame::Event(event) => {
+                let wire = event.to_string();
+                assert!(wire.contains("id:42\n"));
+                assert!(wire.contains(r#"data:{"id":"e1""#));
+            }
+            other => panic!("expected an event, got {:?}", other),
+        }
+
+        assert!(matches!(
+            to_sse_frame(&WsMessage::ping(Vec::new())),
+            SseFrame::Skip
+        ));
+        assert!(matches!(
+            to_sse_frame(&WsMessage::close_with(4000u16, "restart")),
+            SseFrame::Close
+        ));
+    }
+
+    #[test]
+    fn test_header_parsing() {
+        assert_eq!(parse_last_event_id(Some(" 17 ")), Some(17));
+        assert_eq!(parse_last_event_id(Some("abc")), None);
+        assert_eq!(bearer_token(Some("Bearer abc")), Some("abc"));
+        assert_eq!(bearer_token(Some("Basic abc")), None);
+        assert_eq!(bearer_token(None), None);
+    }
+
+    #[tokio::test]
+    async fn test_stream_ends_on_close_and_drops_guard() {
+        let (queue, rx) = OutboundQueue::new(&OutboundQueueConfig::default());
+        let (guard_tx, guard_rx) = tokio::sync::oneshot::channel::<()>();
+
+        queue.push(WsMessage::text(r#"{"type":"live"}"#), SendPriority::Essential);
+        queue.push(WsMessage::close_with(1000u16, "bye"), SendPriority::Essential);
+
+        let stream = event_stream(
+            vec![WsMessage::text(r#"{"type":"welcome"}"#)],
+            rx,
+            queue.clone(),
+            guard_tx,
+        );
+        let events: Vec<String> = stream.map(|e| e.unwrap().to_string()).collect().await;
+
+        assert_eq!(events.len(), 2);
+        assert!(events[0].contains("welcome"));
+        assert!(events[1].contains("live"));
+        // The guard went away with the stream
+        assert!(guard_rx.await.is_err());
+    }
+}
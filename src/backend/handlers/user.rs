@@ -1,18 +1,22 @@
 //! User profile endpoints
 //!
-//! Handles GET /user/me and other user-related endpoints
+//! Handles GET /user/me and other user-related endpoints, including personal
+//! data exports and blocking other users
 
 use crate::db::queries;
-use crate::handlers::auth::ErrorResponse;
-use crate::services::{AuthService, UserService};
+use crate::handlers::auth::{ErrorResponse, SuccessResponse};
+use crate::models::{BlockedUser, DataExport};
+use crate::services::data_export::DownloadError;
+use crate::services::{AuthService, DataExportService, PresenceService, UserService};
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 use std::sync::Arc;
 use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
 use warp::{reply, Rejection, Reply};
 
 /// User profile response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct UserProfileResponse {
     pub user_id: String,
     pub username: String,
@@ -22,7 +26,7 @@ pub struct UserProfileResponse {
 }
 
 /// User search result item
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct UserSearchResult {
     pub user_id: String,
     pub username: String,
@@ -30,9 +34,12 @@ pub struct UserSearchResult {
 }
 
 /// User search query parameters
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct SearchQuery {
+    /// Username prefix, case-insensitive
     pub q: String,
+    /// Maximum results (default 10, capped at 50)
     #[serde(default = "default_limit")]
     pub limit: u32,
 }
@@ -41,20 +48,49 @@ fn default_limit() -> u32 {
     10
 }
 
+/// A user the caller has blocked
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BlockedUserResponse {
+    pub user_id: String,
+    pub username: String,
+    pub blocked_at: i64,
+}
+
+impl From<BlockedUser> for BlockedUserResponse {
+    fn from(blocked: BlockedUser) -> Self {
+        BlockedUserResponse {
+            user_id: blocked.user_id,
+            username: blocked.username,
+            blocked_at: blocked.blocked_at,
+        }
+    }
+}
+
 /// Delete account request payload
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct DeleteAccountRequest {
     pub password: String,
 }
 
 /// Change password request payload
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct ChangePasswordRequest {
     pub current_password: String,
     pub new_password: String,
 }
 
 /// Handle GET /user/me
+#[utoipa::path(
+    get,
+    path = "/user/me",
+    tag = "user",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Profile of the authenticated user", body = UserProfileResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 404, description = "User not found or deleted", body = ErrorResponse),
+    )
+)]
 pub async fn get_current_user(user_id: String, pool: SqlitePool) -> Result<impl Reply, Rejection> {
     // Fetch user from database
     let user = match queries::find_user_by_id(&pool, &user_id).await {
@@ -109,6 +145,18 @@ pub async fn get_current_user(user_id: String, pool: SqlitePool) -> Result<impl
 /// Searches for users by username prefix (case-insensitive)
 /// Excludes current user and deleted users
 /// Returns up to `limit` results (max 50, default 10)
+#[utoipa::path(
+    get,
+    path = "/users/search",
+    tag = "user",
+    security(("bearer" = [])),
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "Matching users", body = [UserSearchResult]),
+        (status = 400, description = "Empty query", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+    )
+)]
 pub async fn search_users(
     user_id: String,
     query: SearchQuery,
@@ -160,10 +208,23 @@ pub async fn search_users(
 }
 
 /// Handle DELETE /user/me
+#[utoipa::path(
+    delete,
+    path = "/user/me",
+    tag = "user",
+    security(("bearer" = [])),
+    request_body = DeleteAccountRequest,
+    responses(
+        (status = 204, description = "Account deleted"),
+        (status = 401, description = "Wrong password or invalid token", body = ErrorResponse),
+        (status = 404, description = "User not found", body = ErrorResponse),
+    )
+)]
 pub async fn delete_account(
     user_id: String,
     request: DeleteAccountRequest,
     pool: SqlitePool,
+    exports: DataExportService,
 ) -> Result<impl Reply, Rejection> {
     // 1. Fetch user to get password hash
     let user = match queries::find_user_by_id(&pool, &user_id).await {
@@ -227,7 +288,12 @@ pub async fn delete_account(
         ));
     }
 
-    // 4. Return success (No Content)
+    // 4. Delete any data export archives; the account is gone either way
+    if let Err(e) = exports.remove_archives(&user_id).await {
+        warn!("Failed to remove data exports of deleted user: {}", e);
+    }
+
+    // 5. Return success (No Content)
     Ok(reply::with_status(
         reply::json(&serde_json::json!({})), // warp reply needs body even for 204? Usually empty.
         warp::http::StatusCode::NO_CONTENT,
@@ -235,6 +301,18 @@ pub async fn delete_account(
 }
 
 /// Handle POST /user/change-password
+#[utoipa::path(
+    post,
+    path = "/user/change-password",
+    tag = "user",
+    security(("bearer" = [])),
+    request_body = ChangePasswordRequest,
+    responses(
+        (status = 200, description = "Password changed", body = SuccessResponse),
+        (status = 400, description = "New password fails validation", body = ErrorResponse),
+        (status = 401, description = "Wrong current password or invalid token", body = ErrorResponse),
+    )
+)]
 pub async fn change_password(
     user_id: String,
     request: ChangePasswordRequest,
@@ -317,7 +395,399 @@ pub async fn change_password(
     }
 
     Ok(reply::with_status(
-        reply::json(&serde_json::json!({ "message": "Password changed successfully" })),
+        reply::json(&SuccessResponse {
+            message: "Password changed successfully".to_string(),
+        }),
         warp::http::StatusCode::OK,
     ))
 }
+
+/// Personal data export status
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DataExportResponse {
+    pub export_id: String,
+    /// `pending`, `ready`, `failed` or `expired`
+    pub status: String,
+    pub created_at: i64,
+    pub completed_at: Option<i64>,
+    /// When the download link stops working
+    pub expires_at: Option<i64>,
+    pub size_bytes: Option<i64>,
+    /// Path of the archive, valid without a token until `expires_at`; set once `ready`
+    pub download_url: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Download link query parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadQuery {
+    /// Signature from `download_url`
+    pub token: String,
+}
+
+fn export_response(export: DataExport, exports: &DataExportService) -> DataExportResponse {
+    DataExportResponse {
+        download_url: exports.download_url(&export),
+        export_id: export.id,
+        status: export.status,
+        created_at: export.created_at,
+        completed_at: export.completed_at,
+        expires_at: export.expires_at,
+        size_bytes: export.size_bytes,
+        error: export.error,
+    }
+}
+
+/// Handle POST /user/export
+///
+/// Starts building an archive of the caller's data in the background. While an
+/// export is pending or downloadable, the same export is returned instead.
+#[utoipa::path(
+    post,
+    path = "/user/export",
+    tag = "user",
+    security(("bearer" = [])),
+    responses(
+        (status = 202, description = "Export started; poll `GET /user/export/{id}`", body = DataExportResponse),
+        (status = 200, description = "An export is already pending or ready", body = DataExportResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+    )
+)]
+pub async fn request_export(
+    user_id: String,
+    exports: DataExportService,
+) -> Result<impl Reply, Rejection> {
+    match exports.request(&user_id).await {
+        Ok((export, created)) => {
+            let status = if created {
+                warp::http::StatusCode::ACCEPTED
+            } else {
+                warp::http::StatusCode::OK
+            };
+            Ok(reply::with_status(
+                reply::json(&export_response(export, &exports)),
+                status,
+            ))
+        }
+        Err(e) => {
+            warn!("Failed to start data export: {}", e);
+            Ok(reply::with_status(
+                reply::json(&ErrorResponse {
+                    error: "DATABASE_ERROR".to_string(),
+                    message: "Failed to start data export".to_string(),
+                }),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
+}
+
+/// Handle GET /user/export/{id}
+#[utoipa::path(
+    get,
+    path = "/user/export/{id}",
+    tag = "user",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "Export ID")),
+    responses(
+        (status = 200, description = "Export status, with a download link once ready", body = DataExportResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 404, description = "No such export of the caller", body = ErrorResponse),
+    )
+)]
+pub async fn get_export(
+    user_id: String,
+    export_id: String,
+    exports: DataExportService,
+) -> Result<impl Reply, Rejection> {
+    match exports.get(&user_id, &export_id).await {
+        Ok(Some(export)) => Ok(reply::with_status(
+            reply::json(&export_response(export, &exports)),
+            warp::http::StatusCode::OK,
+        )),
+        Ok(None) => Ok(reply::with_status(
+            reply::json(&ErrorResponse {
+                error: "EXPORT_NOT_FOUND".to_string(),
+                message: "Data export not found".to_string(),
+            }),
+            warp::http::StatusCode::NOT_FOUND,
+        )),
+        Err(e) => {
+            warn!("Failed to get data export: {}", e);
+            Ok(reply::with_status(
+                reply::json(&ErrorResponse {
+                    error: "DATABASE_ERROR".to_string(),
+                    message: "Failed to get data export".to_string(),
+                }),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
+}
+
+/// Handle GET /exports/{id}/download?token=<token>
+///
+/// The signed token is the credential, so the link works from a browser.
+#[utoipa::path(
+    get,
+    path = "/exports/{id}/download",
+    tag = "user",
+    params(("id" = String, Path, description = "Export ID"), DownloadQuery),
+    responses(
+        (status = 200, description = "The archive", content_type = "application/gzip", body = Vec<u8>),
+        (status = 404, description = "Unknown export, not ready, or bad token", body = ErrorResponse),
+        (status = 410, description = "The link has expired", body = ErrorResponse),
+    )
+)]
+pub async fn download_export(
+    export_id: String,
+    query: DownloadQuery,
+    exports: DataExportService,
+) -> Result<warp::reply::Response, Rejection> {
+    let (error, message, status) = match exports.open_download(&export_id, &query.token).await {
+        Ok((bytes, file_name)) => {
+            let response = warp::http::Response::builder()
+                .header(warp::http::header::CONTENT_TYPE, "application/gzip")
+                .header(
+                    warp::http::header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}\"", file_name),
+                )
+                .body(bytes.into())
+                .unwrap_or_default();
+            return Ok(response);
+        }
+        Err(DownloadError::NotFound) => (
+            "EXPORT_NOT_FOUND",
+            "Data export not found",
+            warp::http::StatusCode::NOT_FOUND,
+        ),
+        Err(DownloadError::Expired) => (
+            "EXPORT_EXPIRED",
+            "The download link has expired; request a new export",
+            warp::http::StatusCode::GONE,
+        ),
+        Err(DownloadError::Internal(e)) => {
+            warn!("Failed to read data export: {}", e);
+            (
+                "SERVER_ERROR",
+                "Failed to read data export",
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        }
+    };
+
+    Ok(reply::with_status(
+        reply::json(&ErrorResponse {
+            error: error.to_string(),
+            message: message.to_string(),
+        }),
+        status,
+    )
+    .into_response())
+}
+
+/// Handle PUT /user/blocks/{id}
+///
+/// Blocked users disappear from each other's search and presence, and the
+/// blocked user's messages are silently dropped.
+#[utoipa::path(
+    put,
+    path = "/user/blocks/{id}",
+    tag = "user",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "User to block")),
+    responses(
+        (status = 201, description = "User blocked", body = BlockedUserResponse),
+        (status = 200, description = "User was already blocked", body = BlockedUserResponse),
+        (status = 400, description = "Attempt to block yourself", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 404, description = "No such user", body = ErrorResponse),
+    )
+)]
+pub async fn block_user(
+    user_id: String,
+    target_id: String,
+    user_service: Arc<UserService>,
+    presence: PresenceService,
+    pool: SqlitePool,
+) -> Result<impl Reply, Rejection> {
+    let error = |error: &str, message: &str, status| {
+        Ok(reply::with_status(
+            reply::json(&ErrorResponse {
+                error: error.to_string(),
+                message: message.to_string(),
+            }),
+            status,
+        ))
+    };
+
+    if target_id == user_id {
+        return error(
+            "INVALID_USER",
+            "You cannot block yourself",
+            warp::http::StatusCode::BAD_REQUEST,
+        );
+    }
+
+    match queries::find_user_by_id(&pool, &target_id).await {
+        Ok(Some(user)) if !user.is_deleted() => {}
+        Ok(_) => {
+            return error(
+                "USER_NOT_FOUND",
+                "User not found",
+                warp::http::StatusCode::NOT_FOUND,
+            );
+        }
+        Err(e) => {
+            warn!("Failed to look up user to block: {}", e);
+            return error(
+                "DATABASE_ERROR",
+                "Failed to block user",
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            );
+        }
+    }
+
+    let created = match user_service.block_user(&user_id, &target_id).await {
+        Ok(created) => created,
+        Err(e) => {
+            warn!("Failed to block user: {}", e);
+            return error(
+                "DATABASE_ERROR",
+                "Failed to block user",
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            );
+        }
+    };
+
+    if created {
+        // Each now sees the other as offline for as long as the block lasts
+        for (user, viewer) in [(&target_id, &user_id), (&user_id, &target_id)] {
+            if let Err(e) = presence.hide_from(user, viewer).await {
+                warn!("Failed to hide presence after block: {}", e);
+            }
+        }
+    }
+
+    let blocked = match user_service.blocked_users(&user_id).await {
+        Ok(blocked) => blocked.into_iter().find(|b| b.user_id == target_id),
+        Err(e) => {
+            warn!("Failed to load blocked users: {}", e);
+            None
+        }
+    };
+    let Some(blocked) = blocked else {
+        return error(
+            "DATABASE_ERROR",
+            "Failed to block user",
+            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        );
+    };
+
+    let status = if created {
+        warp::http::StatusCode::CREATED
+    } else {
+        warp::http::StatusCode::OK
+    };
+    Ok(reply::with_status(
+        reply::json(&BlockedUserResponse::from(blocked)),
+        status,
+    ))
+}
+
+/// Handle DELETE /user/blocks/{id}
+#[utoipa::path(
+    delete,
+    path = "/user/blocks/{id}",
+    tag = "user",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "User to unblock")),
+    responses(
+        (status = 204, description = "User unblocked"),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 404, description = "User was not blocked", body = ErrorResponse),
+    )
+)]
+pub async fn unblock_user(
+    user_id: String,
+    target_id: String,
+    user_service: Arc<UserService>,
+    presence: PresenceService,
+    pool: SqlitePool,
+) -> Result<impl Reply, Rejection> {
+    match user_service.unblock_user(&user_id, &target_id).await {
+        Ok(true) => {
+            // A block the other way keeps presence hidden
+            match queries::is_blocked(&pool, &target_id, &user_id).await {
+                Ok(false) => {
+                    for (user, viewer) in [(&target_id, &user_id), (&user_id, &target_id)] {
+                        if let Err(e) = presence.reveal_to(user, viewer).await {
+                            warn!("Failed to restore presence after unblock: {}", e);
+                        }
+                    }
+                }
+                Ok(true) => {}
+                Err(e) => warn!("Failed to check block after unblock: {}", e),
+            }
+            Ok(reply::with_status(
+                reply::json(&serde_json::json!({})),
+                warp::http::StatusCode::NO_CONTENT,
+            ))
+        }
+        Ok(false) => Ok(reply::with_status(
+            reply::json(&ErrorResponse {
+                error: "NOT_BLOCKED".to_string(),
+                message: "User is not blocked".to_string(),
+            }),
+            warp::http::StatusCode::NOT_FOUND,
+        )),
+        Err(e) => {
+            warn!("Failed to unblock user: {}", e);
+            Ok(reply::with_status(
+                reply::json(&ErrorResponse {
+                    error: "DATABASE_ERROR".to_string(),
+                    message: "Failed to unblock user".to_string(),
+                }),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
+}
+
+/// Handle GET /user/blocks
+#[utoipa::path(
+    get,
+    path = "/user/blocks",
+    tag = "user",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Users the caller has blocked, most recent first", body = [BlockedUserResponse]),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+    )
+)]
+pub async fn list_blocked_users(
+    user_id: String,
+    user_service: Arc<UserService>,
+) -> Result<impl Reply, Rejection> {
+    match user_service.blocked_users(&user_id).await {
+        Ok(blocked) => {
+            let blocked: Vec<BlockedUserResponse> =
+                blocked.into_iter().map(BlockedUserResponse::from).collect();
+            Ok(reply::with_status(
+                reply::json(&blocked),
+                warp::http::StatusCode::OK,
+            ))
+        }
+        Err(e) => {
+            warn!("Failed to list blocked users: {}", e);
+            Ok(reply::with_status(
+                reply::json(&ErrorResponse {
+                    error: "DATABASE_ERROR".to_string(),
+                    message: "Failed to list blocked users".to_string(),
+                }),
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        }
+    }
+}
@@ -3,11 +3,17 @@
 //! Manages WebSocket connections, message routing, and real-time delivery.
 //! Handles authentication, message validation, and client-server communication.
 
-use chat_shared::protocol::MessageEnvelope;
+use crate::handlers::event_log::{EventLog, Replay};
+use crate::handlers::outbound::{OutboundQueue, PushOutcome, QueueStats, SendPriority};
+use crate::handlers::schema::FieldError;
+use chat_shared::protocol::{
+    ErrorData, MessageEnvelope, ServerFrame, ShutdownData, MAX_CONTENT_LENGTH,
+    MAX_ENCRYPTED_CONTENT_LENGTH,
+};
 use serde_json::json;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::Arc;
-use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::RwLock;
 use warp::ws::Message as WsMessage;
 
@@ -41,12 +47,16 @@ impl ClientConnection {
 pub struct ConnectionManager {
     /// Map of user_id -> active connections
     connections: Arc<RwLock<HashMap<String, Vec<ManagedConnection>>>>,
+    /// Sequenced events per user, replayed on resume
+    event_log: EventLog,
 }
 
 #[derive(Clone)]
 pub struct ManagedConnection {
     pub client: ClientConnection,
-    pub sender: UnboundedSender<WsMessage>,
+    pub sender: OutboundQueue,
+    /// Time of the last inbound frame (ms since epoch)
+    pub last_activity: Arc<AtomicI64>,
 }
 
 impl Default for ConnectionManager {
@@ -57,8 +67,13 @@ impl Default for ConnectionManager {
 
 impl ConnectionManager {
     pub fn new() -> Self {
+        Self::with_event_log(EventLog::default())
+    }
+
+    pub fn with_event_log(event_log: EventLog) -> Self {
         Self {
             connections: Arc::new(RwLock::new(HashMap::new())),
+            event_log,
         }
     }
 
@@ -66,7 +81,7 @@ impl ConnectionManager {
     pub async fn register(
         &self,
         client: ClientConnection,
-        sender: UnboundedSender<WsMessage>,
+        sender: OutboundQueue,
     ) -> ConnectionId {
         let mut conns = self.connections.write().await;
         let connection_id = client.connection_id.clone();
@@ -74,7 +89,11 @@ impl ConnectionManager {
         conns
             .entry(client.user_id.clone())
             .or_insert_with(Vec::new)
-            .push(ManagedConnection { client, sender });
+            .push(ManagedConnection {
+                last_activity: Arc::new(AtomicI64::new(client.connected_at as i64)),
+                client,
+                sender,
+            });
 
         connection_id
     }
@@ -120,31 +139,181 @@ impl ConnectionManager {
         conns.keys().cloned().collect()
     }
 
+    /// Record inbound activity on a connection
+    pub async fn touch(&self, user_id: &str, connection_id: &str) {
+        let conns = self.connections.read().await;
+        if let Some(conn) = conns
+            .get(user_id)
+            .and_then(|entries| entries.iter().find(|c| c.client.connection_id == connection_id))
+        {
+            conn.last_activity
+                .store(chrono::Utc::now().timestamp_millis(), Ordering::Relaxed);
+        }
+    }
+
+    /// Most recent inbound activity across a user's connections
+    pub async fn last_activity(&self, user_id: &str) -> Option<i64> {
+        let conns = self.connections.read().await;
+        conns
+            .get(user_id)?
+            .iter()
+            .map(|c| c.last_activity.load(Ordering::Relaxed))
+            .max()
+    }
+
+    /// Most recent inbound activity for every online user
+    pub async fn online_activity(&self) -> HashMap<String, i64> {
+        let conns = self.connections.read().await;
+        conns
+            .iter()
+            .filter_map(|(user_id, entries)| {
+                entries
+                    .iter()
+                    .map(|c| c.last_activity.load(Ordering::Relaxed))
+                    .max()
+                    .map(|ts| (user_id.clone(), ts))
+            })
+            .collect()
+    }
+
     /// Send a WebSocket message to all active connections for a user.
     /// Returns number of connections the message was sent to.
     pub async fn send_to_user(&self, user_id: &str, message: WsMessage) -> usize {
+        self.send_to_user_with_priority(user_id, message, SendPriority::Essential)
+            .await
+    }
+
+    /// Send with an explicit priority; see [`OutboundQueue::push`] for the
+    /// overflow policy. Returns number of connections the message was queued on.
+    pub async fn send_to_user_with_priority(
+        &self,
+        user_id: &str,
+        message: WsMessage,
+        priority: SendPriority,
+    ) -> usize {
         let conns = self.connections.read().await;
-        if let Some(entries) = conns.get(user_id) {
-            let mut delivered = 0;
-            for conn in entries {
-                if conn.sender.send(message.clone()).is_ok() {
-                    delivered += 1;
-                }
+        let Some(entries) = conns.get(user_id) else {
+            return 0;
+        };
+
+        let mut delivered = 0;
+        for conn in entries {
+            match conn.sender.push(message.clone(), priority) {
+                PushOutcome::Queued => delivered += 1,
+                PushOutcome::Evicted => tracing::warn!(
+                    "Evicting slow consumer: user {} connection {}",
+                    user_id,
+                    conn.client.connection_id
+                ),
+                PushOutcome::Dropped | PushOutcome::Closed => {}
             }
-            delivered
-        } else {
-            0
         }
+        delivered
+    }
+
+    /// Stamp `envelope` with the user's next `seq` and log it for replay
+    ///
+    /// Returns the frame to send; use this for replies written straight to
+    /// the originating socket.
+    pub async fn record_event(
+        &self,
+        user_id: &str,
+        envelope: MessageEnvelope,
+    ) -> Result<WsMessage, String> {
+        self.event_log
+            .record(user_id, envelope)
+            .await
+            .map(WsMessage::text)
+    }
+
+    /// Record `envelope` for the user and send it to all their connections
+    pub async fn send_event(
+        &self,
+        user_id: &str,
+        envelope: MessageEnvelope,
+        priority: SendPriority,
+    ) -> Result<usize, String> {
+        let message = self.record_event(user_id, envelope).await?;
+        Ok(self
+            .send_to_user_with_priority(user_id, message, priority)
+            .await)
+    }
+
+    /// Record and send the same event to several users, each with their own `seq`
+    pub async fn broadcast_event<I>(
+        &self,
+        user_ids: I,
+        envelope: MessageEnvelope,
+        priority: SendPriority,
+    ) -> Result<(), String>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        for uid in user_ids {
+            self.send_event(uid.as_ref(), envelope.clone(), priority)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Events the user missed since `last_seq`
+    pub async fn replay_events(&self, user_id: &str, last_seq: u64) -> Replay {
+        self.event_log.replay(user_id, last_seq).await
+    }
+
+    /// Outbound queue metrics keyed by connection ID
+    pub async fn queue_stats(&self) -> HashMap<ConnectionId, QueueStats> {
+        let conns = self.connections.read().await;
+        conns
+            .values()
+            .flatten()
+            .map(|c| (c.client.connection_id.clone(), c.sender.stats()))
+            .collect()
+    }
+
+    /// Total number of open connections across all users
+    pub async fn connection_count(&self) -> usize {
+        let conns = self.connections.read().await;
+        conns.values().map(Vec::len).sum()
+    }
+
+    /// Ask every connection to close, e.g. on server shutdown
+    ///
+    /// Each client first receives a `shutdown` envelope with the suggested
+    /// reconnect delay, followed by a close frame with `code` and `reason`.
+    /// Connections stay registered until their socket task unregisters them.
+    /// Returns the number of connections notified.
+    pub async fn close_all(&self, code: u16, reason: &str, retry_after_ms: u64) -> usize {
+        let notice = ServerFrame::Shutdown(ShutdownData {
+            reason: reason.to_string(),
+            retry_after_ms,
+        })
+        .into_envelope();
+        let notice = WsMessage::text(serde_json::to_string(&notice).unwrap_or_default());
+        let close = WsMessage::close_with(code, reason.to_string());
+
+        let conns = self.connections.read().await;
+        let mut notified = 0;
+        for conn in conns.values().flatten() {
+            let _ = conn.sender.push(notice.clone(), SendPriority::Essential);
+            if conn.sender.push(close.clone(), SendPriority::Essential) == PushOutcome::Queued {
+                notified += 1;
+            }
+        }
+        notified
     }
 
     /// Broadcast a message to multiple user IDs.
-    pub async fn broadcast_to_users<I>(&self, user_ids: I, message: WsMessage)
+    pub async fn broadcast_to_users<I>(&self, user_ids: I, message: WsMessage, priority: SendPriority)
     where
         I: IntoIterator,
         I::Item: AsRef<str>,
     {
         for uid in user_ids {
-            let _ = self.send_to_user(uid.as_ref(), message.clone()).await;
+            let _ = self
+                .send_to_user_with_priority(uid.as_ref(), message.clone(), priority)
+                .await;
         }
     }
 }
@@ -162,7 +331,7 @@ impl MessageValidator {
 
         // Check message type is valid
         match envelope.msg_type.as_str() {
-            "message" | "typing" | "presence" | "ack" | "error" | "heartbeat" => {}
+            "message" | "typing" | "presence" | "ack" | "error" | "heartbeat" | "resume" => {}
             _ => return Err(format!("Invalid message type: {}", envelope.msg_type)),
         }
 
@@ -177,11 +346,26 @@ impl MessageValidator {
         Ok(())
     }
 
+    /// Longest content accepted for a plaintext or end-to-end encrypted message
+    pub fn max_content_length(encrypted: bool) -> usize {
+        if encrypted {
+            MAX_ENCRYPTED_CONTENT_LENGTH
+        } else {
+            MAX_CONTENT_LENGTH
+        }
+    }
+
     /// Validate text message data
-    pub fn validate_text_message(content: &str, recipient_id: &str) -> Result<(), String> {
-        if content.is_empty() || content.len() > 5000 {
+    pub fn validate_text_message(
+        content: &str,
+        recipient_id: &str,
+        encrypted: bool,
+    ) -> Result<(), String> {
+        let max = Self::max_content_length(encrypted);
+        if content.is_empty() || content.len() > max {
             return Err(format!(
-                "Message content must be 1-5000 characters, got {}",
+                "Message content must be 1-{} characters, got {}",
+                max,
                 content.len()
             ));
         }
@@ -206,88 +390,89 @@ impl MessageValidator {
 pub struct ErrorResponse;
 
 impl ErrorResponse {
-    pub fn invalid_message_length(sent_length: usize, max_length: usize) -> WsMessage {
-        let error = json!({
-            "id": uuid::Uuid::new_v4().to_string(),
-            "type": "error",
-            "timestamp": chrono::Utc::now().timestamp_millis() as u64,
-            "data": {
-                "code": "INVALID_MESSAGE_LENGTH",
-                "message": format!("Message content exceeds {} character limit", max_length),
-                "details": {
-                    "sentLength": sent_length,
-                    "maxLength": max_length
-                }
-            }
-        });
+    /// Build an `error` frame
+    fn frame(code: &str, message: impl Into<String>, details: Option<serde_json::Value>) -> WsMessage {
+        let envelope = ServerFrame::Error(ErrorData {
+            code: code.to_string(),
+            message: message.into(),
+            details,
+        })
+        .into_envelope();
 
-        WsMessage::text(error.to_string())
+        WsMessage::text(serde_json::to_string(&envelope).unwrap_or_default())
     }
 
-    pub fn recipient_not_found(recipient_id: &str) -> WsMessage {
-        let error = json!({
-            "id": uuid::Uuid::new_v4().to_string(),
-            "type": "error",
-            "timestamp": chrono::Utc::now().timestamp_millis() as u64,
-            "data": {
-                "code": "RECIPIENT_NOT_FOUND",
-                "message": "Recipient user not found",
-                "details": {
-                    "recipientId": recipient_id
-                }
-            }
-        });
+    pub fn invalid_message_length(sent_length: usize, max_length: usize) -> WsMessage {
+        Self::frame(
+            "INVALID_MESSAGE_LENGTH",
+            format!("Message content exceeds {} character limit", max_length),
+            Some(json!({
+                "sentLength": sent_length,
+                "maxLength": max_length
+            })),
+        )
+    }
 
-        WsMessage::text(error.to_string())
+    pub fn recipient_not_found(recipient_id: &str) -> WsMessage {
+        Self::frame(
+            "RECIPIENT_NOT_FOUND",
+            "Recipient user not found",
+            Some(json!({ "recipientId": recipient_id })),
+        )
     }
 
     pub fn unauthorized(reason: &str) -> WsMessage {
-        let error = json!({
-            "id": uuid::Uuid::new_v4().to_string(),
-            "type": "error",
-            "timestamp": chrono::Utc::now().timestamp_millis() as u64,
-            "data": {
-                "code": "UNAUTHORIZED",
-                "message": reason,
-            }
-        });
-
-        WsMessage::text(error.to_string())
+        Self::frame("UNAUTHORIZED", reason, None)
     }
 
     pub fn invalid_json() -> WsMessage {
-        let error = json!({
-            "id": uuid::Uuid::new_v4().to_string(),
-            "type": "error",
-            "timestamp": chrono::Utc::now().timestamp_millis() as u64,
-            "data": {
-                "code": "INVALID_JSON",
-                "message": "Message is not valid JSON",
-            }
-        });
+        Self::frame("INVALID_JSON", "Message is not valid JSON", None)
+    }
+
+    pub fn invalid_msgpack() -> WsMessage {
+        Self::frame(
+            "INVALID_MSGPACK",
+            "Binary frame is not a valid MessagePack envelope",
+            None,
+        )
+    }
 
-        WsMessage::text(error.to_string())
+    /// The frame does not match the protocol schema for its type
+    pub fn invalid_frame(errors: &[FieldError]) -> WsMessage {
+        Self::frame(
+            "INVALID_FRAME",
+            "Frame does not match the protocol schema",
+            Some(json!({ "errors": errors })),
+        )
     }
 
     pub fn server_error(reason: &str) -> WsMessage {
-        let error = json!({
-            "id": uuid::Uuid::new_v4().to_string(),
-            "type": "error",
-            "timestamp": chrono::Utc::now().timestamp_millis() as u64,
-            "data": {
-                "code": "SERVER_ERROR",
-                "message": reason,
-            }
-        });
+        Self::frame("SERVER_ERROR", reason, None)
+    }
 
-        WsMessage::text(error.to_string())
+    /// The client's protocol is too old; it must be updated to connect
+    pub fn update_required(min_version: u16) -> WsMessage {
+        Self::frame(
+            "UPDATE_REQUIRED",
+            "This version of the app is no longer supported. Please update to continue.",
+            Some(json!({ "minProtocolVersion": min_version })),
+        )
+    }
+
+    /// The client only speaks protocol versions newer than the server
+    pub fn unsupported_protocol(max_version: u16) -> WsMessage {
+        Self::frame(
+            "UNSUPPORTED_PROTOCOL",
+            "The server does not support this client's protocol version yet.",
+            Some(json!({ "maxProtocolVersion": max_version })),
+        )
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio::sync::mpsc;
+    use crate::handlers::outbound::OutboundQueueConfig;
 
     #[test]
     fn test_client_connection_new() {
@@ -304,7 +489,7 @@ mod tests {
         let client = ClientConnection::new("user123".to_string(), "alice".to_string());
         let connection_id = client.connection_id.clone();
 
-        let (tx, _rx) = mpsc::unbounded_channel();
+        let (tx, _rx) = OutboundQueue::new(&OutboundQueueConfig::default());
         let registered_id = manager.register(client, tx).await;
         assert_eq!(registered_id, connection_id);
 
@@ -314,13 +499,65 @@ mod tests {
         assert_eq!(conns[0].user_id, "user123");
     }
 
+    #[tokio::test]
+    async fn test_close_all_sends_notice_then_close_frame() {
+        let manager = ConnectionManager::new();
+        let (tx1, mut rx1) = OutboundQueue::new(&OutboundQueueConfig::default());
+        let (tx2, mut rx2) = OutboundQueue::new(&OutboundQueueConfig::default());
+        manager
+            .register(ClientConnection::new("u1".to_string(), "alice".to_string()), tx1)
+            .await;
+        manager
+            .register(ClientConnection::new("u2".to_string(), "bob".to_string()), tx2)
+            .await;
+        assert_eq!(manager.connection_count().await, 2);
+
+        let notified = manager.close_all(1012, "server restarting, reconnect", 2000).await;
+        assert_eq!(notified, 2);
+
+        for rx in [&mut rx1, &mut rx2] {
+            let notice = rx.recv().await.unwrap();
+            let envelope: MessageEnvelope =
+                serde_json::from_str(notice.to_str().unwrap()).unwrap();
+            assert_eq!(envelope.msg_type, "shutdown");
+            assert_eq!(envelope.data["retryAfterMs"], 2000);
+
+            let close = rx.recv().await.unwrap();
+            assert!(close.is_close());
+            assert_eq!(close.close_frame().unwrap().0, 1012);
+        }
+
+        // Connections are removed by their socket tasks, not by close_all
+        assert_eq!(manager.connection_count().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_touch_updates_last_activity() {
+        let manager = ConnectionManager::new();
+        let client = ClientConnection::new("user123".to_string(), "alice".to_string());
+        let connection_id = client.connection_id.clone();
+        let connected_at = client.connected_at as i64;
+
+        let (tx, _rx) = OutboundQueue::new(&OutboundQueueConfig::default());
+        manager.register(client, tx).await;
+        assert_eq!(manager.last_activity("user123").await, Some(connected_at));
+
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        manager.touch("user123", &connection_id).await;
+
+        let touched = manager.last_activity("user123").await.unwrap();
+        assert!(touched > connected_at);
+        assert_eq!(manager.online_activity().await.get("user123"), Some(&touched));
+        assert_eq!(manager.last_activity("nobody").await, None);
+    }
+
     #[tokio::test]
     async fn test_connection_manager_unregister() {
         let manager = ConnectionManager::new();
         let client = ClientConnection::new("user123".to_string(), "alice".to_string());
         let connection_id = client.connection_id.clone();
 
-        let (tx, _rx) = mpsc::unbounded_channel();
+        let (tx, _rx) = OutboundQueue::new(&OutboundQueueConfig::default());
         manager.register(client, tx).await;
         assert!(manager.is_user_online("user123").await);
 
@@ -335,8 +572,8 @@ mod tests {
         let client2 = ClientConnection::new("user123".to_string(), "alice".to_string());
         let conn2_id = client2.connection_id.clone();
 
-        let (tx1, _rx1) = mpsc::unbounded_channel();
-        let (tx2, _rx2) = mpsc::unbounded_channel();
+        let (tx1, _rx1) = OutboundQueue::new(&OutboundQueueConfig::default());
+        let (tx2, _rx2) = OutboundQueue::new(&OutboundQueueConfig::default());
 
         manager.register(client1, tx1).await;
         manager.register(client2, tx2).await;
@@ -358,6 +595,8 @@ mod tests {
             id: "msg-123".to_string(),
             msg_type: "message".to_string(),
             timestamp: chrono::Utc::now().timestamp_millis() as u64,
+            traceparent: None,
+            seq: None,
             data: serde_json::json!({}),
         };
 
@@ -370,6 +609,8 @@ mod tests {
             id: "".to_string(),
             msg_type: "message".to_string(),
             timestamp: chrono::Utc::now().timestamp_millis() as u64,
+            traceparent: None,
+            seq: None,
             data: serde_json::json!({}),
         };
 
@@ -382,6 +623,8 @@ mod tests {
             id: "msg-123".to_string(),
             msg_type: "invalid_type".to_string(),
             timestamp: chrono::Utc::now().timestamp_millis() as u64,
+            traceparent: None,
+            seq: None,
             data: serde_json::json!({}),
         };
 
@@ -390,23 +633,29 @@ mod tests {
 
     #[test]
     fn test_message_validator_text_message_valid() {
-        assert!(MessageValidator::validate_text_message("Hello", "recipient-456").is_ok());
+        assert!(MessageValidator::validate_text_message("Hello", "recipient-456", false).is_ok());
     }
 
     #[test]
     fn test_message_validator_text_message_empty() {
-        assert!(MessageValidator::validate_text_message("", "recipient-456").is_err());
+        assert!(MessageValidator::validate_text_message("", "recipient-456", false).is_err());
     }
 
     #[test]
     fn test_message_validator_text_message_too_long() {
         let long_content = "a".repeat(5001);
-        assert!(MessageValidator::validate_text_message(&long_content, "recipient-456").is_err());
+        assert!(
+            MessageValidator::validate_text_message(&long_content, "recipient-456", false).is_err()
+        );
+        // Ciphertext envelopes get a larger allowance
+        assert!(
+            MessageValidator::validate_text_message(&long_content, "recipient-456", true).is_ok()
+        );
     }
 
     #[test]
     fn test_message_validator_text_message_no_recipient() {
-        assert!(MessageValidator::validate_text_message("Hello", "").is_err());
+        assert!(MessageValidator::validate_text_message("Hello", "", false).is_err());
     }
 
     #[test]
@@ -3,9 +3,10 @@
 //! This is the main entry point for the chat server. It initializes the database,
 //! sets up WebSocket listeners, and starts the HTTP API.
 
-use chat_backend::{db, init_tracing, server};
+use chat_backend::{db, init_tracing, server, shutdown_tracing};
 use clap::Parser;
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(name = "chat-server")]
@@ -22,6 +23,10 @@ struct Args {
     /// Log level
     #[arg(short, long, default_value = "info")]
     log_level: String,
+
+    /// Seconds allowed for draining connections on SIGTERM/SIGINT
+    #[arg(long, env = "SHUTDOWN_TIMEOUT_SECS", default_value = "10")]
+    shutdown_timeout: u64,
 }
 
 #[tokio::main]
@@ -34,12 +39,33 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("Starting chat server on port {}", args.port);
     tracing::info!("Database: {}", args.db_path.display());
 
+    // Seal message content and auth log details at rest when a master key is configured
+    match db::at_rest::MasterKey::from_env().map_err(anyhow::Error::msg)? {
+        Some(key) => {
+            tracing::info!("Encryption at rest enabled (master key {})", key.id());
+            db::at_rest::install(key).map_err(anyhow::Error::msg)?;
+        }
+        None => tracing::warn!(
+            "No master key configured ({} or {}); message content is stored in plaintext",
+            db::at_rest::MASTER_KEY_ENV,
+            db::at_rest::MASTER_KEY_FILE_ENV
+        ),
+    }
+
     // Initialize database
     let pool = db::init_db(&args.db_path).await?;
     tracing::info!("Database initialized");
 
     // Start HTTP server
-    server::start_server(args.port, pool, None).await?;
+    let config = server::ServerConfig {
+        shutdown_timeout: Duration::from_secs(args.shutdown_timeout),
+        ..Default::default()
+    };
+
+    let result = server::start_server(args.port, pool, Some(config)).await;
+
+    // Flush any buffered spans before exiting
+    shutdown_tracing();
 
-    Ok(())
+    result
 }
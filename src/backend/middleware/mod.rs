@@ -2,6 +2,8 @@
 
 pub mod auth;
 pub mod rate_limit;
+pub mod request_id;
 
 pub use auth::{with_auth, Unauthorized};
 pub use rate_limit::RateLimiter;
+pub use request_id::with_request_id;
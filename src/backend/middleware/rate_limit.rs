@@ -50,6 +50,12 @@ impl RateLimiter {
         Self::new(1000, 60)
     }
 
+    /// Convenience constructor for key bundle fetches, keyed by requester and
+    /// target (10 fetches / hour)
+    pub fn key_bundles() -> Self {
+        Self::new(10, 3600)
+    }
+
     /// Create default rate limiter (5 attempts per 15 minutes)
     pub fn default_auth() -> Self {
         Self::new(5, 900)
@@ -0,0 +1,74 @@
+//! Request ID middleware
+//!
+//! Tags every HTTP response with an `X-Request-Id` header. A well-formed ID
+//! supplied by the client is echoed back so it can be correlated with client
+//! logs; otherwise a new UUID is generated.
+
+use warp::{
+    filters::header::headers_cloned,
+    http::header::HeaderMap,
+    reply::{self, WithHeader},
+    Filter, Rejection, Reply,
+};
+
+/// Header carrying the request ID in both directions
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest client-supplied request ID that is echoed back
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Extract the request ID for the current request, generating one if needed
+pub fn request_id() -> impl Filter<Extract = (String,), Error = std::convert::Infallible> + Clone {
+    headers_cloned().map(|headers: HeaderMap| {
+        client_request_id(&headers).unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+    })
+}
+
+/// Wrap `filter` so that its reply carries an `X-Request-Id` header
+pub fn with_request_id<F, R>(
+    filter: F,
+) -> impl Filter<Extract = (WithHeader<R>,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    request_id().and(filter).map(|id: String, inner: R| {
+        tracing::debug!(request_id = %id, "Tagged response with request ID");
+        reply::with_header(inner, REQUEST_ID_HEADER, id)
+    })
+}
+
+/// Accept a client-supplied ID only if it is short and header-safe
+fn client_request_id(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(REQUEST_ID_HEADER)?.to_str().ok()?.trim();
+
+    let valid = !value.is_empty()
+        && value.len() <= MAX_REQUEST_ID_LEN
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+
+    valid.then(|| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::http::HeaderValue;
+
+    #[test]
+    fn test_client_request_id_validation() {
+        let mut headers = HeaderMap::new();
+        assert!(client_request_id(&headers).is_none());
+
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("abc-123_x.y"));
+        assert_eq!(client_request_id(&headers).as_deref(), Some("abc-123_x.y"));
+
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("has spaces"));
+        assert!(client_request_id(&headers).is_none());
+
+        let too_long = "a".repeat(MAX_REQUEST_ID_LEN + 1);
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_str(&too_long).unwrap());
+        assert!(client_request_id(&headers).is_none());
+    }
+}
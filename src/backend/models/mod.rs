@@ -1,5 +1,6 @@
 //! Domain models for the chat application
 
+use chat_shared::protocol::{TextMessageData, MAX_CONTENT_LENGTH, MAX_ENCRYPTED_CONTENT_LENGTH};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -54,6 +55,12 @@ pub struct Conversation {
     pub updated_at: i64,
     pub last_message_at: Option<i64>,
     pub message_count: i32,
+    /// Set by the first end-to-end encrypted message; plaintext is refused after
+    pub is_encrypted: bool,
+    /// Seconds new messages are kept, or `None` to keep them
+    pub ephemeral_ttl: Option<i64>,
+    /// `sent` or `read`: when the disappearing-message timer starts
+    pub ephemeral_start: String,
 }
 
 impl Conversation {
@@ -67,6 +74,9 @@ impl Conversation {
             updated_at: now,
             last_message_at: None,
             message_count: 0,
+            is_encrypted: false,
+            ephemeral_ttl: None,
+            ephemeral_start: "sent".to_string(),
         }
     }
 
@@ -95,6 +105,19 @@ pub struct Message {
     pub read_at: Option<i64>,
     pub status: String,
     pub is_anonymized: bool,
+    /// `content` is an end-to-end encrypted envelope the server cannot read
+    pub is_encrypted: bool,
+    /// `content` is sealed at rest (see `db::at_rest`); queries open it
+    /// before returning messages
+    #[serde(skip)]
+    #[sqlx(default)]
+    pub is_sealed: bool,
+    /// Announces a conversation change; written by the server, not a user
+    pub is_system: bool,
+    /// Conversation's `ephemeral_ttl` when sent, in seconds
+    pub ephemeral_ttl: Option<i64>,
+    /// When the message disappears; for read timers, unset until it is read
+    pub expires_at: Option<i64>,
 }
 
 impl Message {
@@ -116,6 +139,40 @@ impl Message {
             read_at: None,
             status: "pending".to_string(),
             is_anonymized: false,
+            is_encrypted: false,
+            is_sealed: false,
+            is_system: false,
+            ephemeral_ttl: None,
+            expires_at: None,
+        }
+    }
+
+    /// Apply the conversation's disappearing-message setting
+    ///
+    /// A timer counted from sending starts now; one counted from reading
+    /// starts when the message is marked read.
+    pub fn start_expiry(&mut self, conversation: &Conversation) {
+        self.ephemeral_ttl = conversation.ephemeral_ttl;
+        self.expires_at = match conversation.ephemeral_ttl {
+            Some(ttl) if conversation.ephemeral_start == "sent" => {
+                Some(self.created_at + ttl * 1000)
+            }
+            _ => None,
+        };
+    }
+
+    /// Payload of the `message` frame that delivers this message
+    pub fn to_text_data(&self, sender_username: &str, status: &str) -> TextMessageData {
+        TextMessageData {
+            sender_id: Some(self.sender_id.clone()),
+            sender_username: Some(sender_username.to_string()),
+            recipient_id: self.recipient_id.clone(),
+            content: self.content.clone(),
+            conversation_id: Some(self.conversation_id.clone()),
+            status: Some(status.to_string()),
+            encrypted: self.is_encrypted,
+            system: self.is_system,
+            expires_at: self.expires_at.map(|t| t as u64),
         }
     }
 
@@ -125,8 +182,13 @@ impl Message {
         if len < 1 {
             return Err("Message content cannot be empty".to_string());
         }
-        if len > 5000 {
-            return Err("Message content exceeds 5000 character limit".to_string());
+        let max = if self.is_encrypted {
+            MAX_ENCRYPTED_CONTENT_LENGTH
+        } else {
+            MAX_CONTENT_LENGTH
+        };
+        if len > max {
+            return Err(format!("Message content exceeds {} character limit", max));
         }
         if self.sender_id == self.recipient_id {
             return Err("Cannot send message to yourself".to_string());
@@ -154,3 +216,116 @@ impl Message {
         self.status == "failed"
     }
 }
+
+/// A message deleted by the disappearing-messages reaper
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ExpiredMessage {
+    pub id: String,
+    pub conversation_id: String,
+    pub sender_id: String,
+    pub recipient_id: String,
+}
+
+/// Messages removed from a conversation by expiry, retention or a declined
+/// message request
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct MessageGap {
+    /// `expired`, `retention` or `declined`
+    pub reason: String,
+    pub message_count: i64,
+    /// Creation times of the first and last message removed
+    pub first_at: i64,
+    pub last_at: i64,
+}
+
+/// Auth log entry, with `details` opened if it was sealed at rest
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AuthLog {
+    pub id: String,
+    pub ip_address: String,
+    pub username: Option<String>,
+    pub event_type: String,
+    pub created_at: i64,
+    pub user_agent: Option<String>,
+    pub details: Option<String>,
+    #[serde(skip)]
+    pub is_sealed: bool,
+}
+
+/// A user's request for an archive of their personal data
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DataExport {
+    pub id: String,
+    pub user_id: String,
+    /// `pending`, `ready`, `failed` or `expired`
+    pub status: String,
+    pub created_at: i64,
+    pub completed_at: Option<i64>,
+    /// When the download link stops working and the archive is deleted
+    pub expires_at: Option<i64>,
+    pub size_bytes: Option<i64>,
+    pub error: Option<String>,
+}
+
+impl DataExport {
+    pub fn new(user_id: String) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id,
+            status: "pending".to_string(),
+            created_at: chrono::Utc::now().timestamp_millis(),
+            completed_at: None,
+            expires_at: None,
+            size_bytes: None,
+            error: None,
+        }
+    }
+}
+
+/// A blocked user, as listed to the user who blocked them
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct BlockedUser {
+    pub user_id: String,
+    pub username: String,
+    pub blocked_at: i64,
+}
+
+/// A stranger's first contact, as listed to its recipient
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct MessageRequest {
+    pub conversation_id: String,
+    pub requester_id: String,
+    pub requester_username: String,
+    pub recipient_id: String,
+    /// `pending` or `declined`
+    pub status: String,
+    pub created_at: i64,
+    /// Messages held until the request is accepted
+    pub message_count: i64,
+    pub last_message_at: Option<i64>,
+}
+
+/// A user's published end-to-end encryption keys (public halves only)
+///
+/// Keys and signatures are base64. The server never sees private keys and does
+/// not verify signatures; clients check them before trusting a bundle.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct IdentityKeys {
+    pub user_id: String,
+    /// X25519 identity key
+    pub identity_key: String,
+    /// Ed25519 key the signed prekey signature is made with
+    pub signing_key: String,
+    pub signed_prekey_id: i64,
+    /// X25519 medium-term prekey
+    pub signed_prekey: String,
+    pub signed_prekey_signature: String,
+    pub updated_at: i64,
+}
+
+/// Single-use X25519 prekey
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct OneTimePrekey {
+    pub key_id: i64,
+    pub public_key: String,
+}
@@ -4,32 +4,65 @@
 //! Routes:
 //! - GET /health - server health check
 //! - GET /socket - WebSocket upgrade endpoint (requires JWT authentication)
+//! - GET /events - Server-Sent Events fallback for clients that cannot upgrade
+//! - GET /protocol/schema - JSON Schemas for the WebSocket protocol
+//! - GET /openapi.json - OpenAPI 3 description of the REST endpoints
 //! - POST /auth/signup - user registration
 //! - POST /auth/login - user authentication
-//! - GET /conversations/* - conversation management (stubs for Phase 3+)
+//! - GET /conversations/* - conversation listing, history and search
+//! - GET /conversations/requests - message requests, answered with POST /conversations/{id}/accept|decline
+//! - POST /conversations/{id}/messages - send a message (requires `Idempotency-Key`)
+//! - PUT /conversations/{id}/ephemeral - disappearing messages setting
+//! - GET /conversations/{id}/export - transcript as JSON, Markdown or HTML
+//! - POST /user/export - personal data export, downloaded from /exports/{id}/download
+//! - GET /user/blocks - blocked users, changed with PUT/DELETE /user/blocks/{id}
 
 use anyhow::Error;
 use futures::{SinkExt, StreamExt};
 use sqlx::SqlitePool;
+use std::future::Future;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
-use tokio::sync::mpsc;
-use tracing::{info, warn};
+use std::time::{Duration, Instant};
+use tracing::{info, info_span, warn, Instrument};
 use warp::cors::Cors;
 use warp::filters::ws::{WebSocket, Ws};
-use warp::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use warp::http::header::{HeaderName, AUTHORIZATION, CONTENT_TYPE};
 use warp::http::StatusCode;
 use warp::{Filter, Rejection, Reply};
 
 use crate::handlers::dispatcher::{DispatchResult, MessageDispatcher};
-use crate::handlers::handshake::HandshakeValidator;
+use crate::handlers::event_log::{EventLog, EventLogConfig, Replay};
+use crate::handlers::compression::{self as frame_compression, CompressionConfig, CompressionMetrics, FrameCompressor};
+use crate::handlers::handshake::{
+    extract_compression, extract_encoding, extract_token_from_query, HandshakeValidator,
+    VersionMismatch,
+};
+use crate::handlers::heartbeat::{HeartbeatConfig, HeartbeatManager, HeartbeatScheduler};
 use crate::handlers::messages::MessageHandler;
+use crate::handlers::outbound::{OutboundQueue, OutboundQueueConfig, PushOutcome, SendPriority};
 use crate::services::auth_service::TokenClaims;
-use crate::services::{MessageQueueService, PresenceService};
-
-use crate::handlers::{self, auth, conversation, server as server_handlers, user, websocket};
-use crate::middleware::{auth as auth_middleware, rate_limit};
+use crate::services::data_export::DataExportConfig;
+use crate::services::retention::RetentionConfig;
+use crate::services::{
+    DataExportService, EphemeralService, MessageQueueService, MessageRequestService,
+    PresenceService, RetentionService,
+};
+
+use crate::handlers::{
+    self, auth, conversation, keys, server as server_handlers, sse, user, websocket,
+};
+use crate::middleware::{auth as auth_middleware, rate_limit, request_id};
+use crate::telemetry;
+use chat_shared::protocol::compression::{InflateError, FRAME_DEFLATE_V1};
+
+/// Header carrying the client-chosen message ID for `POST /conversations/{id}/messages`
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+use chat_shared::protocol::{
+    ResumedData, ResyncRequiredData, ServerFrame, WelcomeData, WireEncoding,
+    MIN_PROTOCOL_VERSION,
+};
 
 /// Server configuration
 #[derive(Clone)]
@@ -37,8 +70,36 @@ pub struct ServerConfig {
     pub jwt_secret: String,
     pub max_message_size: usize,
     pub allowed_origins: Vec<String>,
+    /// Upper bound on connection draining after SIGTERM/SIGINT
+    pub shutdown_timeout: Duration,
+    /// Reconnect delay suggested to clients when the server restarts
+    pub reconnect_delay: Duration,
+    /// How often `users.is_online` is checked against live connections
+    pub presence_reconcile_interval: Duration,
+    /// How often expired disappearing messages are deleted
+    pub ephemeral_reap_interval: Duration,
+    /// How long messages, auth logs and soft-deleted users are kept
+    pub retention: RetentionConfig,
+    /// Where personal data exports are written and how long they can be downloaded
+    pub data_export: DataExportConfig,
+    /// WebSocket ping interval and pong timeout
+    pub heartbeat: HeartbeatConfig,
+    /// Per-connection send queue size and overflow policy
+    pub outbound_queue: OutboundQueueConfig,
+    /// How many recent events per user are kept for session resumption
+    pub event_log: EventLogConfig,
+    /// Oldest WebSocket protocol version clients may connect with
+    pub min_protocol_version: u16,
+    /// Per-message compression policy for clients that request it
+    pub compression: CompressionConfig,
 }
 
+/// Optional features advertised to clients in the `welcome` frame
+const SERVER_CAPABILITIES: &[&str] = &["presence", "receipts", "resume", "ephemeral"];
+
+/// How long to wait for the close frame to reach an evicted slow consumer
+const SLOW_CONSUMER_CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
 impl Default for ServerConfig {
     fn default() -> Self {
         let origins = std::env::var("CORS_ALLOWED_ORIGINS")
@@ -57,10 +118,115 @@ impl Default for ServerConfig {
             jwt_secret: std::env::var("JWT_SECRET").unwrap_or_else(|_| "secret".to_string()),
             max_message_size: 10 * 1024, // 10 KB
             allowed_origins: origins,
+            shutdown_timeout: Duration::from_secs(env_u64("SHUTDOWN_TIMEOUT_SECS").unwrap_or(10)),
+            reconnect_delay: Duration::from_millis(env_u64("RECONNECT_DELAY_MS").unwrap_or(2000)),
+            presence_reconcile_interval: Duration::from_secs(
+                env_u64("PRESENCE_RECONCILE_SECS").unwrap_or(60),
+            ),
+            ephemeral_reap_interval: Duration::from_secs(
+                env_u64("EPHEMERAL_REAP_SECS").filter(|secs| *secs > 0).unwrap_or(5),
+            ),
+            retention: retention_config_from_env(),
+            data_export: data_export_config_from_env(),
+            heartbeat: heartbeat_config_from_env(),
+            outbound_queue: outbound_queue_config_from_env(),
+            event_log: event_log_config_from_env(),
+            min_protocol_version: env_u64("MIN_PROTOCOL_VERSION")
+                .and_then(|v| u16::try_from(v).ok())
+                .unwrap_or(MIN_PROTOCOL_VERSION),
+            compression: compression_config_from_env(),
         }
     }
 }
 
+fn compression_config_from_env() -> CompressionConfig {
+    let defaults = CompressionConfig::default();
+    CompressionConfig {
+        enabled: std::env::var("WS_COMPRESSION")
+            .map(|v| !matches!(v.trim().to_ascii_lowercase().as_str(), "off" | "false" | "0"))
+            .unwrap_or(defaults.enabled),
+        threshold: env_u64("WS_COMPRESSION_THRESHOLD")
+            .map(|n| n as usize)
+            .unwrap_or(defaults.threshold),
+    }
+}
+
+/// `0` days keeps that kind of data forever
+fn retention_config_from_env() -> RetentionConfig {
+    let defaults = RetentionConfig::default();
+    let days = |key: &str, default: Option<u64>| match env_u64(key) {
+        Some(0) => None,
+        Some(days) => Some(days),
+        None => default,
+    };
+    RetentionConfig {
+        messages_days: days("RETENTION_MESSAGES_DAYS", defaults.messages_days),
+        auth_logs_days: days("RETENTION_AUTH_LOGS_DAYS", defaults.auth_logs_days),
+        deleted_users_days: days("RETENTION_DELETED_USERS_DAYS", defaults.deleted_users_days),
+        batch_size: env_u64("RETENTION_BATCH_SIZE")
+            .and_then(|n| u32::try_from(n).ok())
+            .filter(|n| *n > 0)
+            .unwrap_or(defaults.batch_size),
+        interval: env_u64("RETENTION_INTERVAL_SECS")
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.interval),
+    }
+}
+
+fn data_export_config_from_env() -> DataExportConfig {
+    let defaults = DataExportConfig::default();
+    DataExportConfig {
+        dir: std::env::var("EXPORT_DIR")
+            .ok()
+            .filter(|dir| !dir.trim().is_empty())
+            .map(std::path::PathBuf::from)
+            .unwrap_or(defaults.dir),
+        link_ttl: env_u64("EXPORT_LINK_TTL_SECS")
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.link_ttl),
+    }
+}
+
+fn outbound_queue_config_from_env() -> OutboundQueueConfig {
+    let defaults = OutboundQueueConfig::default();
+    match env_u64("OUTBOUND_QUEUE_CAPACITY").filter(|n| *n > 0) {
+        // Keep the default ratio: droppable frames go once the queue is 3/4 full
+        Some(capacity) => OutboundQueueConfig {
+            capacity: capacity as usize,
+            droppable_high_water: (capacity as usize * 3 / 4).max(1),
+        },
+        None => defaults,
+    }
+}
+
+fn heartbeat_config_from_env() -> HeartbeatConfig {
+    let defaults = HeartbeatConfig::default();
+    HeartbeatConfig {
+        ping_interval: env_u64("HEARTBEAT_INTERVAL_SECS")
+            .filter(|secs| *secs > 0)
+            .unwrap_or(defaults.ping_interval),
+        pong_timeout: env_u64("HEARTBEAT_TIMEOUT_SECS").unwrap_or(defaults.pong_timeout),
+    }
+}
+
+fn event_log_config_from_env() -> EventLogConfig {
+    let defaults = EventLogConfig::default();
+    EventLogConfig {
+        capacity: env_u64("EVENT_LOG_CAPACITY")
+            .map(|n| n as usize)
+            .unwrap_or(defaults.capacity),
+        max_age: env_u64("EVENT_LOG_RETENTION_SECS")
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.max_age),
+    }
+}
+
+fn env_u64(key: &str) -> Option<u64> {
+    std::env::var(key).ok().and_then(|v| v.trim().parse().ok())
+}
+
 /// Server state shared across routes
 #[derive(Clone)]
 pub struct ServerState {
@@ -68,33 +234,138 @@ pub struct ServerState {
     pub config: ServerConfig,
     pub connection_manager: Arc<websocket::ConnectionManager>,
     pub presence_service: PresenceService,
+    pub ephemeral_service: EphemeralService,
+    /// Purges data past its retention period; also counts what it purged
+    pub retention_service: RetentionService,
+    pub data_export_service: DataExportService,
     pub message_queue: MessageQueueService,
+    pub message_request_service: MessageRequestService,
     pub user_service: Arc<crate::services::UserService>,
     pub global_rate_limiter: Arc<rate_limit::RateLimiter>,
     pub auth_rate_limiter: Arc<rate_limit::RateLimiter>,
+    /// Limits how often one user fetches another's key bundle, since each
+    /// fetch consumes a one-time prekey
+    pub key_bundle_rate_limiter: Arc<rate_limit::RateLimiter>,
     pub start_time: Instant,
+    /// Set once shutdown begins; new WebSocket upgrades are refused
+    pub shutting_down: Arc<AtomicBool>,
+    /// Bytes saved by per-message compression
+    pub compression_metrics: Arc<CompressionMetrics>,
 }
 
 impl ServerState {
     pub fn new(pool: SqlitePool, config: ServerConfig) -> Self {
-        let connection_manager = Arc::new(websocket::ConnectionManager::new());
+        let connection_manager = Arc::new(websocket::ConnectionManager::with_event_log(
+            EventLog::new(config.event_log.clone()),
+        ));
         let pool_for_services = pool.clone();
+        let retention_config = config.retention.clone();
+        let data_export_service = DataExportService::new(
+            pool.clone(),
+            config.data_export.clone(),
+            config.jwt_secret.as_bytes(),
+        );
         let global_rate_limiter = Arc::new(rate_limit::RateLimiter::global());
         let auth_rate_limiter = Arc::new(rate_limit::RateLimiter::auth());
+        let key_bundle_rate_limiter = Arc::new(rate_limit::RateLimiter::key_bundles());
         let user_service = Arc::new(crate::services::UserService::new(pool.clone()));
+        let presence_service =
+            PresenceService::new(pool_for_services.clone(), connection_manager.clone());
+        let message_queue =
+            MessageQueueService::new(pool_for_services.clone(), connection_manager.clone());
+        let message_request_service = MessageRequestService::new(
+            pool_for_services.clone(),
+            message_queue.clone(),
+            presence_service.clone(),
+        );
         Self {
             pool,
             config,
-            presence_service: PresenceService::new(
+            presence_service,
+            ephemeral_service: EphemeralService::new(
                 pool_for_services.clone(),
                 connection_manager.clone(),
             ),
-            message_queue: MessageQueueService::new(pool_for_services, connection_manager.clone()),
+            retention_service: RetentionService::new(
+                pool_for_services.clone(),
+                retention_config,
+                data_export_service.clone(),
+            ),
+            data_export_service,
+            message_queue,
+            message_request_service,
             connection_manager,
             user_service,
             global_rate_limiter,
             auth_rate_limiter,
+            key_bundle_rate_limiter,
             start_time: Instant::now(),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            compression_metrics: Arc::new(CompressionMetrics::default()),
+        }
+    }
+
+    /// Whether the server has started shutting down
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Drain the server before exit
+    ///
+    /// Refuses new upgrades, runs a final offline-delivery pass, tells every
+    /// connected client to reconnect later and waits for their sockets to close,
+    /// all bounded by `shutdown_timeout`. Every user is then marked offline.
+    /// Undelivered messages stay `pending` in the database and are reloaded on
+    /// the next start.
+    pub async fn shutdown(&self) {
+        self.shutdown_with(async {}).await;
+    }
+
+    /// [`shutdown`](Self::shutdown), also waiting for `server` under the same
+    /// deadline
+    ///
+    /// `server` is the HTTP listener's graceful shutdown, which only finishes
+    /// once every response has ended. Event streams end when the drain closes
+    /// their connection, so both run together.
+    async fn shutdown_with(&self, server: impl Future<Output = ()>) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        let deadline = self.config.shutdown_timeout;
+        info!("Draining connections (deadline {:?})", deadline);
+
+        let drain = async {
+            tokio::join!(self.drain_connections(), server);
+        };
+        if tokio::time::timeout(deadline, drain).await.is_err() {
+            warn!(
+                "Shutdown deadline reached with {} connection(s) still open",
+                self.connection_manager.connection_count().await
+            );
+        }
+
+        match crate::db::queries::mark_all_users_offline(&self.pool).await {
+            Ok(count) => info!("Marked {} user(s) offline", count),
+            Err(e) => warn!("Failed to mark users offline: {}", e),
+        }
+    }
+
+    async fn drain_connections(&self) {
+        let remaining = self.message_queue.drain().await;
+        if remaining > 0 {
+            info!("{} queued message(s) left pending for next start", remaining);
+        }
+
+        let closed = self
+            .connection_manager
+            .close_all(
+                chat_shared::protocol::CLOSE_SERVICE_RESTART,
+                "server restarting, reconnect",
+                self.config.reconnect_delay.as_millis() as u64,
+            )
+            .await;
+        info!("Sent restart close frame to {} connection(s)", closed);
+
+        while self.connection_manager.connection_count().await > 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
         }
     }
 }
@@ -113,21 +384,33 @@ pub fn create_routes(
     ));
     let with_auth = auth_middleware::with_auth(auth_service.clone());
 
-    // Health endpoint
+    // GET /health
     let health_route = warp::path!("health")
         .and(warp::get())
         .and(rate_limit_filter.clone())
         .and(state_filter.clone())
         .and_then(server_handlers::health);
 
-    // Status endpoint
+    // GET /status
     let status_route = warp::path!("status")
         .and(warp::get())
         .and(rate_limit_filter.clone())
         .and(state_filter.clone())
         .and_then(server_handlers::status);
 
-    // WebSocket endpoint with JWT authentication
+    // GET /protocol/schema
+    let schema_route = warp::path!("protocol" / "schema")
+        .and(warp::get())
+        .and(rate_limit_filter.clone())
+        .and_then(server_handlers::protocol_schema);
+
+    // GET /openapi.json
+    let openapi_route = warp::path!("openapi.json")
+        .and(warp::get())
+        .and(rate_limit_filter.clone())
+        .and_then(server_handlers::openapi);
+
+    // GET /socket - WebSocket upgrade with JWT authentication
     let websocket_route = warp::path!("socket")
         .and(warp::ws())
         .and(rate_limit_filter.clone())
@@ -135,6 +418,16 @@ pub fn create_routes(
         .and(state_filter.clone())
         .and_then(handle_websocket_upgrade);
 
+    // GET /events - Server-Sent Events fallback for networks that block WebSocket upgrades
+    let events_route = warp::path!("events")
+        .and(warp::get())
+        .and(rate_limit_filter.clone())
+        .and(warp::header::optional::<String>(AUTHORIZATION.as_str()))
+        .and(warp::header::optional::<String>(sse::LAST_EVENT_ID_HEADER))
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(state_filter.clone())
+        .and_then(handle_event_stream);
+
     // Auth routes
     let auth_routes = warp::path("auth").and(
         // POST /auth/signup
@@ -198,11 +491,101 @@ pub fn create_routes(
                     .and(warp::body::json())
                     .and(state_filter.clone())
                     .and_then(handle_change_password),
+            )
+            .or(
+                // POST /user/export
+                warp::post()
+                    .and(warp::path("export"))
+                    .and(warp::path::end())
+                    .and(with_auth.clone())
+                    .and(rate_limit_filter.clone())
+                    .and(state_filter.clone())
+                    .and_then(|user_id, state: ServerState| async move {
+                        user::request_export(user_id, state.data_export_service).await
+                    }),
+            )
+            .or(
+                // GET /user/export/{id}
+                warp::get()
+                    .and(warp::path("export"))
+                    .and(warp::path::param())
+                    .and(warp::path::end())
+                    .and(with_auth.clone())
+                    .and(rate_limit_filter.clone())
+                    .and(state_filter.clone())
+                    .and_then(|export_id: String, user_id, state: ServerState| async move {
+                        user::get_export(user_id, export_id, state.data_export_service).await
+                    }),
+            )
+            .or(
+                // GET /user/blocks
+                warp::get()
+                    .and(warp::path("blocks"))
+                    .and(warp::path::end())
+                    .and(with_auth.clone())
+                    .and(rate_limit_filter.clone())
+                    .and(state_filter.clone())
+                    .and_then(|user_id, state: ServerState| async move {
+                        user::list_blocked_users(user_id, state.user_service).await
+                    }),
+            )
+            .or(
+                // PUT /user/blocks/{id}
+                warp::put()
+                    .and(warp::path("blocks"))
+                    .and(warp::path::param())
+                    .and(warp::path::end())
+                    .and(with_auth.clone())
+                    .and(rate_limit_filter.clone())
+                    .and(state_filter.clone())
+                    .and_then(|target_id: String, user_id, state: ServerState| async move {
+                        user::block_user(
+                            user_id,
+                            target_id,
+                            state.user_service,
+                            state.presence_service,
+                            state.pool,
+                        )
+                        .await
+                    }),
+            )
+            .or(
+                // DELETE /user/blocks/{id}
+                warp::delete()
+                    .and(warp::path("blocks"))
+                    .and(warp::path::param())
+                    .and(warp::path::end())
+                    .and(with_auth.clone())
+                    .and(rate_limit_filter.clone())
+                    .and(state_filter.clone())
+                    .and_then(|target_id: String, user_id, state: ServerState| async move {
+                        user::unblock_user(
+                            user_id,
+                            target_id,
+                            state.user_service,
+                            state.presence_service,
+                            state.pool,
+                        )
+                        .await
+                    }),
             ),
     );
 
-    // User Search route (GET /users/search)
-    // Note: This was separate in handlers/user.rs, likely mapped to /users/search
+    // GET /exports/{id}/download (the signed token stands in for a bearer token)
+    let export_download_route = warp::path("exports").and(
+        warp::get()
+            .and(warp::path::param())
+            .and(warp::path("download"))
+            .and(warp::path::end())
+            .and(rate_limit_filter.clone())
+            .and(warp::query::<user::DownloadQuery>())
+            .and(state_filter.clone())
+            .and_then(|export_id: String, query, state: ServerState| async move {
+                user::download_export(export_id, query, state.data_export_service).await
+            }),
+    );
+
+    // GET /users/search
     let users_routes = warp::path("users").and(
         warp::path("search")
             .and(warp::get())
@@ -215,7 +598,7 @@ pub fn create_routes(
             }),
     );
 
-    // Conversations routes (stubs for Phase 3+)
+    // Conversations routes
     let conversation_routes = warp::path("conversations").and(
         // GET /conversations (list conversations)
         warp::get()
@@ -240,6 +623,55 @@ pub fn create_routes(
                         conversation::start_conversation(user_id, body, state.pool).await
                     }),
             )
+            .or(
+                // GET /conversations/requests (message requests awaiting the caller)
+                warp::get()
+                    .and(warp::path("requests"))
+                    .and(warp::path::end())
+                    .and(with_auth.clone())
+                    .and(rate_limit_filter.clone())
+                    .and(state_filter.clone())
+                    .and_then(|user_id, state: ServerState| async move {
+                        conversation::list_message_requests(user_id, state.message_request_service)
+                            .await
+                    }),
+            )
+            .or(
+                // POST /conversations/{id}/accept (accept a message request)
+                warp::post()
+                    .and(warp::path::param())
+                    .and(warp::path("accept"))
+                    .and(warp::path::end())
+                    .and(with_auth.clone())
+                    .and(rate_limit_filter.clone())
+                    .and(state_filter.clone())
+                    .and_then(|conversation_id: String, user_id, state: ServerState| async move {
+                        conversation::accept_message_request(
+                            user_id,
+                            conversation_id,
+                            state.message_request_service,
+                        )
+                        .await
+                    }),
+            )
+            .or(
+                // POST /conversations/{id}/decline (decline a message request)
+                warp::post()
+                    .and(warp::path::param())
+                    .and(warp::path("decline"))
+                    .and(warp::path::end())
+                    .and(with_auth.clone())
+                    .and(rate_limit_filter.clone())
+                    .and(state_filter.clone())
+                    .and_then(|conversation_id: String, user_id, state: ServerState| async move {
+                        conversation::decline_message_request(
+                            user_id,
+                            conversation_id,
+                            state.message_request_service,
+                        )
+                        .await
+                    }),
+            )
             .or(
                 // GET /conversations/{id}/messages (get conversation messages)
                 warp::get()
@@ -262,6 +694,85 @@ pub fn create_routes(
                         },
                     ),
             )
+            .or(
+                // POST /conversations/{id}/messages (send a message)
+                warp::post()
+                    .and(warp::path::param())
+                    .and(warp::path("messages"))
+                    .and(warp::path::end())
+                    .and(with_auth.clone())
+                    .and(rate_limit_filter.clone())
+                    .and(warp::header::optional::<String>(IDEMPOTENCY_KEY_HEADER))
+                    .and(warp::body::json())
+                    .and(state_filter.clone())
+                    .and_then(
+                        |conversation_id: String,
+                         user_id,
+                         idempotency_key,
+                         body,
+                         state: ServerState| async move {
+                            let handler = MessageHandler::new(
+                                state.pool.clone(),
+                                state.connection_manager.clone(),
+                                state.message_queue.clone(),
+                            );
+                            conversation::send_conversation_message(
+                                user_id,
+                                conversation_id,
+                                idempotency_key,
+                                body,
+                                handler,
+                                state.pool,
+                            )
+                            .await
+                        },
+                    ),
+            )
+            .or(
+                // PUT /conversations/{id}/ephemeral (disappearing messages)
+                warp::put()
+                    .and(warp::path::param())
+                    .and(warp::path("ephemeral"))
+                    .and(warp::path::end())
+                    .and(with_auth.clone())
+                    .and(rate_limit_filter.clone())
+                    .and(warp::body::json())
+                    .and(state_filter.clone())
+                    .and_then(
+                        |conversation_id: String, user_id, body, state: ServerState| async move {
+                            conversation::set_ephemeral(
+                                user_id,
+                                conversation_id,
+                                body,
+                                state.ephemeral_service,
+                                state.pool,
+                            )
+                            .await
+                        },
+                    ),
+            )
+            .or(
+                // GET /conversations/{id}/export?format=json|markdown|html
+                warp::get()
+                    .and(warp::path::param())
+                    .and(warp::path("export"))
+                    .and(warp::path::end())
+                    .and(with_auth.clone())
+                    .and(rate_limit_filter.clone())
+                    .and(warp::query::<conversation::ExportQuery>())
+                    .and(state_filter.clone())
+                    .and_then(
+                        |conversation_id: String, user_id, query, state: ServerState| async move {
+                            conversation::export_conversation(
+                                user_id,
+                                conversation_id,
+                                query,
+                                state.pool,
+                            )
+                            .await
+                        },
+                    ),
+            )
             .or(
                 // GET /conversations/{id}/search?q=keyword
                 warp::get()
@@ -286,14 +797,64 @@ pub fn create_routes(
             ),
     );
 
+    // End-to-end encryption key routes
+    let key_routes = warp::path("keys").and(
+        // PUT /keys (publish identity and prekeys)
+        warp::put()
+            .and(warp::path::end())
+            .and(with_auth.clone())
+            .and(rate_limit_filter.clone())
+            .and(warp::body::json())
+            .and(state_filter.clone())
+            .and_then(|user_id, body, state: ServerState| async move {
+                keys::upload_keys(user_id, body, state.pool).await
+            })
+            .or(
+                // GET /keys (own published key state)
+                warp::get()
+                    .and(warp::path::end())
+                    .and(with_auth.clone())
+                    .and(rate_limit_filter.clone())
+                    .and(state_filter.clone())
+                    .and_then(|user_id, state: ServerState| async move {
+                        keys::get_key_status(user_id, state.pool).await
+                    }),
+            )
+            .or(
+                // GET /keys/{user_id} (fetch a bundle, consuming a one-time prekey)
+                warp::get()
+                    .and(warp::path::param())
+                    .and(warp::path::end())
+                    .and(with_auth.clone())
+                    .and(rate_limit_filter.clone())
+                    .and(state_filter.clone())
+                    .and_then(
+                        |target_user_id: String, user_id, state: ServerState| async move {
+                            keys::get_key_bundle(
+                                user_id,
+                                target_user_id,
+                                state.pool,
+                                &state.key_bundle_rate_limiter,
+                            )
+                            .await
+                        },
+                    ),
+            ),
+    );
+
     // Combine all routes
-    health_route
+    let routes = health_route
         .or(websocket_route)
+        .or(events_route)
         .or(status_route)
+        .or(schema_route)
+        .or(openapi_route)
         .or(auth_routes)
         .or(user_routes)
         .or(users_routes)
+        .or(export_download_route)
         .or(conversation_routes)
+        .or(key_routes)
         .with(cors)
         .with(warp::reply::with::default_header(
             "Strict-Transport-Security",
@@ -316,15 +877,20 @@ pub fn create_routes(
             "X-XSS-Protection",
             "1; mode=block",
         ))
-        .with(warp::log("chat_server"))
-        .recover(handle_rejection)
+        .with(warp::log("chat_server"));
+
+    request_id::with_request_id(routes.recover(handle_rejection))
 }
 
 /// Build CORS policy based on server configuration
 fn build_cors(config: &ServerConfig) -> Cors {
     let mut cors = warp::cors()
-        .allow_headers(vec![CONTENT_TYPE, AUTHORIZATION])
-        .allow_methods(vec!["GET", "POST", "DELETE", "OPTIONS"])
+        .allow_headers(vec![
+            CONTENT_TYPE,
+            AUTHORIZATION,
+            HeaderName::from_static(IDEMPOTENCY_KEY_HEADER),
+        ])
+        .allow_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
         .max_age(86_400);
 
     let allow_any = config.allowed_origins.iter().any(|o| o == "*");
@@ -341,6 +907,17 @@ fn build_cors(config: &ServerConfig) -> Cors {
 }
 
 /// Handle WebSocket upgrade with JWT authentication
+#[utoipa::path(
+    get,
+    path = "/socket",
+    tag = "realtime",
+    params(("token" = String, Query, description = "JWT from /auth/login")),
+    responses(
+        (status = 101, description = "Upgraded; frames follow /protocol/schema"),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 503, description = "Server is shutting down", body = ErrorResponse),
+    )
+)]
 async fn handle_websocket_upgrade(
     ws: Ws,
     query: String,
@@ -349,15 +926,60 @@ async fn handle_websocket_upgrade(
     info!("WebSocket connection request, query: {}", query);
     eprintln!("Calling validator with query: '{}'", query);
 
+    if state.is_shutting_down() {
+        return Err(warp::reject::custom(ServerShuttingDown {
+            retry_after_ms: state.config.reconnect_delay.as_millis() as u64,
+        }));
+    }
+
     // Validate JWT token using handshake validator
-    let validator = HandshakeValidator::new(state.config.jwt_secret.clone());
+    let validator = HandshakeValidator::new(state.config.jwt_secret.clone())
+        .with_min_protocol_version(state.config.min_protocol_version);
     match validator.validate_upgrade(&query) {
         Ok(claims) => {
             info!(
                 "WebSocket authentication successful for user: {}",
                 claims.sub
             );
-            Ok(ws.on_upgrade(move |socket| handle_websocket_connection(socket, state, claims)))
+            let negotiated = match validator.negotiate_version(&query) {
+                Err(VersionMismatch::Malformed(message)) => {
+                    return Err(warp::reject::custom(WebSocketAuthError {
+                        status: StatusCode::BAD_REQUEST,
+                        message,
+                    }));
+                }
+                other => other,
+            };
+            let encoding = match extract_encoding(&query) {
+                Ok(encoding) => encoding,
+                Err(message) => {
+                    return Err(warp::reject::custom(WebSocketAuthError {
+                        status: StatusCode::BAD_REQUEST,
+                        message,
+                    }));
+                }
+            };
+            let compression = extract_compression(&query);
+            let heartbeat_interval = state.config.heartbeat.ping_interval;
+            Ok(warp::reply::with_header(
+                ws.on_upgrade(move |socket| async move {
+                    match negotiated {
+                        Ok(protocol_version) => {
+                            // Both options are confirmed by the welcome frame, which v1 clients never get
+                            let v2 = protocol_version >= 2;
+                            let session = SessionOptions {
+                                protocol_version,
+                                encoding: if v2 { encoding } else { WireEncoding::Json },
+                                compression: v2 && compression && state.config.compression.enabled,
+                            };
+                            handle_websocket_connection(socket, state, claims, session).await
+                        }
+                        Err(mismatch) => refuse_protocol(socket, &claims.sub, mismatch).await,
+                    }
+                }),
+                chat_shared::protocol::HEARTBEAT_INTERVAL_HEADER,
+                heartbeat_interval.to_string(),
+            ))
         }
         Err((status, message)) => {
             warn!("WebSocket authentication failed: {} - {}", status, message);
@@ -368,36 +990,237 @@ async fn handle_websocket_upgrade(
     }
 }
 
-/// Custom rejection type for WebSocket authentication errors
-#[derive(Debug)]
-struct WebSocketAuthError {
-    status: StatusCode,
-    message: String,
+/// `welcome` frame confirming the negotiated protocol and server capabilities
+fn welcome_frame(state: &ServerState, session: &SessionOptions) -> warp::ws::Message {
+    let welcome = WelcomeData {
+        protocol_version: session.protocol_version,
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        capabilities: SERVER_CAPABILITIES
+            .iter()
+            .copied()
+            .chain(state.config.compression.enabled.then_some(FRAME_DEFLATE_V1))
+            .map(str::to_string)
+            .collect(),
+        heartbeat_interval_secs: state.config.heartbeat.ping_interval,
+        encoding: session.encoding,
+        compression: session.compression,
+    };
+    let envelope = ServerFrame::Welcome(welcome).into_envelope();
+    warp::ws::Message::text(serde_json::to_string(&envelope).unwrap_or_default())
 }
 
-impl warp::reject::Reject for WebSocketAuthError {}
-
-/// Handle WebSocket connection after upgrade
-async fn handle_websocket_connection(socket: WebSocket, state: ServerState, claims: TokenClaims) {
-    let user_id = claims.sub.clone();
-    info!("WebSocket connection established for user: {}", user_id);
-
-    // Lookup username from database
-    let username = match crate::db::queries::find_user_by_id(&state.pool, &user_id).await {
-        Ok(Some(user)) => user.username,
-        _ => "unknown".to_string(),
+/// Explain why the client's protocol version was refused, then close
+async fn refuse_protocol(mut socket: WebSocket, user_id: &str, mismatch: VersionMismatch) {
+    let (error, close) = match mismatch {
+        VersionMismatch::ClientTooOld { min_supported } => {
+            warn!(
+                "Refusing outdated client for user {} (requires protocol {})",
+                user_id, min_supported
+            );
+            (
+                websocket::ErrorResponse::update_required(min_supported),
+                warp::ws::Message::close_with(
+                    chat_shared::protocol::CLOSE_UPDATE_REQUIRED,
+                    "update required",
+                ),
+            )
+        }
+        VersionMismatch::ClientTooNew { max_supported } => {
+            warn!(
+                "Refusing client for user {}: protocol newer than {}",
+                user_id, max_supported
+            );
+            (
+                websocket::ErrorResponse::unsupported_protocol(max_supported),
+                warp::ws::Message::close_with(1002u16, "unsupported protocol version"),
+            )
+        }
+        VersionMismatch::Malformed(reason) => (
+            websocket::ErrorResponse::server_error(&reason),
+            warp::ws::Message::close_with(1002u16, "invalid protocol versions"),
+        ),
     };
 
-    // Register connection with connection manager
-    let connection = websocket::ClientConnection::new(user_id.clone(), username);
+    let _ = socket.send(error).await;
+    let _ = socket.send(close).await;
+}
 
-    // Channel used by other parts of the system to push frames to this socket
-    let (tx, mut rx) = mpsc::unbounded_channel::<warp::ws::Message>();
-    let connection_id = state
-        .connection_manager
-        .register(connection.clone(), tx.clone())
-        .await;
-    info!(
+/// Send a PING every interval and signal `dead` once a PONG goes missing
+fn spawn_heartbeat(
+    heartbeat: Arc<HeartbeatManager>,
+    scheduler: HeartbeatScheduler,
+    tx: OutboundQueue,
+    dead: Arc<tokio::sync::Notify>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(scheduler.get_ping_interval());
+        ticker.tick().await; // first tick fires immediately
+        loop {
+            ticker.tick().await;
+            match tx.push(warp::ws::Message::ping(Vec::new()), SendPriority::Droppable) {
+                PushOutcome::Queued => {}
+                // A backed-up queue is handled by eviction, not by the heartbeat
+                PushOutcome::Dropped => continue,
+                PushOutcome::Evicted | PushOutcome::Closed => break,
+            }
+            heartbeat.mark_ping_sent().await;
+
+            tokio::time::sleep(scheduler.get_pong_timeout() + Duration::from_millis(100)).await;
+            if !heartbeat.is_healthy().await {
+                heartbeat.mark_dead().await;
+                dead.notify_one();
+                break;
+            }
+        }
+    })
+}
+
+/// Replay the events a reconnecting client missed, followed by `resumed`,
+/// or a single `resync_required` if the gap is no longer in the log
+async fn resume_frames(state: &ServerState, user_id: &str, last_seq: u64) -> Vec<warp::ws::Message> {
+    let (mut frames, envelope) = match state
+        .connection_manager
+        .replay_events(user_id, last_seq)
+        .await
+    {
+        Replay::Events { events, last_seq } => {
+            info!(
+                "Resuming session for user {}: replaying {} events",
+                user_id,
+                events.len()
+            );
+            let resumed = ResumedData {
+                replayed: events.len(),
+                last_seq,
+            };
+            (
+                events.into_iter().map(warp::ws::Message::text).collect(),
+                ServerFrame::Resumed(resumed).into_envelope(),
+            )
+        }
+        Replay::ResyncRequired { last_seq: current } => {
+            info!(
+                "Resync required for user {}: seq {} no longer available",
+                user_id, last_seq
+            );
+            (
+                Vec::new(),
+                ServerFrame::ResyncRequired(ResyncRequiredData { last_seq: current })
+                    .into_envelope(),
+            )
+        }
+    };
+
+    if let Ok(text) = serde_json::to_string(&envelope) {
+        frames.push(warp::ws::Message::text(text));
+    }
+    frames
+}
+
+/// Options agreed with the client during the upgrade
+#[derive(Debug, Clone, Copy)]
+struct SessionOptions {
+    protocol_version: u16,
+    encoding: WireEncoding,
+    /// Whether outbound frames above the threshold are deflated
+    compression: bool,
+}
+
+/// Write half of a client socket; envelopes are re-encoded for the negotiated
+/// encoding and compressed when the client asked for it
+#[derive(Clone)]
+struct SocketWriter {
+    sink: Arc<tokio::sync::Mutex<futures::stream::SplitSink<WebSocket, warp::ws::Message>>>,
+    encoding: WireEncoding,
+    compressor: Option<FrameCompressor>,
+}
+
+impl SocketWriter {
+    fn new(
+        sink: futures::stream::SplitSink<WebSocket, warp::ws::Message>,
+        encoding: WireEncoding,
+        compressor: Option<FrameCompressor>,
+    ) -> Self {
+        Self {
+            sink: Arc::new(tokio::sync::Mutex::new(sink)),
+            encoding,
+            compressor,
+        }
+    }
+
+    async fn send(&self, msg: warp::ws::Message) -> Result<(), warp::Error> {
+        self.send_all(vec![msg]).await
+    }
+
+    /// Write frames back to back, without interleaving queued frames
+    async fn send_all(&self, frames: Vec<warp::ws::Message>) -> Result<(), warp::Error> {
+        let mut sink = self.sink.lock().await;
+        for frame in frames {
+            let mut frame = MessageDispatcher::encode_frame(frame, self.encoding);
+            if let Some(compressor) = &self.compressor {
+                frame = compressor.compress(frame);
+            }
+            sink.send(frame).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Custom rejection type for WebSocket and event stream authentication errors
+#[derive(Debug)]
+struct WebSocketAuthError {
+    status: StatusCode,
+    message: String,
+}
+
+impl warp::reject::Reject for WebSocketAuthError {}
+
+/// Rejection for upgrades attempted while the server is draining
+#[derive(Debug)]
+struct ServerShuttingDown {
+    retry_after_ms: u64,
+}
+
+impl warp::reject::Reject for ServerShuttingDown {}
+
+/// Handle WebSocket connection after upgrade
+async fn handle_websocket_connection(
+    socket: WebSocket,
+    state: ServerState,
+    claims: TokenClaims,
+    session: SessionOptions,
+) {
+    let user_id = claims.sub.clone();
+    info!(
+        "WebSocket connection established for user: {} (protocol v{}, {}, compression {})",
+        user_id,
+        session.protocol_version,
+        session.encoding,
+        if session.compression { "on" } else { "off" }
+    );
+
+    // Lookup username from database
+    let username = match crate::db::queries::find_user_by_id(&state.pool, &user_id).await {
+        Ok(Some(user)) => user.username,
+        _ => "unknown".to_string(),
+    };
+
+    // Register connection with connection manager
+    let connection = websocket::ClientConnection::new(user_id.clone(), username);
+
+    // Bounded queue used by other parts of the system to push frames to this socket
+    let (tx, mut rx) = OutboundQueue::new(&state.config.outbound_queue);
+
+    // Confirm the negotiated protocol before any other frame can be queued
+    if session.protocol_version >= 2 {
+        tx.push(welcome_frame(&state, &session), SendPriority::Essential);
+    }
+
+    let connection_id = state
+        .connection_manager
+        .register(connection.clone(), tx.clone())
+        .await;
+    info!(
         "Registered connection {} for user {}",
         connection_id, user_id
     );
@@ -414,51 +1237,132 @@ async fn handle_websocket_connection(socket: WebSocket, state: ServerState, clai
     );
 
     let (ws_tx, mut ws_rx) = socket.split();
-    let ws_tx = Arc::new(tokio::sync::Mutex::new(ws_tx));
+    let compressor = session.compression.then(|| {
+        FrameCompressor::new(
+            state.config.compression.threshold,
+            state.compression_metrics.clone(),
+        )
+    });
+    let ws_tx = SocketWriter::new(ws_tx, session.encoding, compressor);
 
     // Forward messages from channel to websocket sink
     let ws_tx_forward = ws_tx.clone();
-    tokio::spawn(async move {
+    let forwarder = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
-            let mut sender = ws_tx_forward.lock().await;
-            if sender.send(msg).await.is_err() {
+            if ws_tx_forward.send(msg).await.is_err() {
                 break;
             }
         }
     });
 
+    // Ping the client periodically; a missed pong marks the connection dead
+    let heartbeat = Arc::new(HeartbeatManager::new(state.config.heartbeat.clone()));
+    let heartbeat_dead = Arc::new(tokio::sync::Notify::new());
+    let heartbeat_task = spawn_heartbeat(
+        heartbeat.clone(),
+        HeartbeatScheduler::new(state.config.heartbeat.clone()),
+        tx.clone(),
+        heartbeat_dead.clone(),
+    );
+
     // Process incoming messages
-    while let Some(result) = ws_rx.next().await {
+    loop {
+        let result = tokio::select! {
+            next = ws_rx.next() => match next {
+                Some(result) => result,
+                None => break,
+            },
+            _ = heartbeat_dead.notified() => {
+                warn!(
+                    "Heartbeat timeout for user {} on connection {}, closing",
+                    user_id, connection_id
+                );
+                let _ = tx.push(
+                    warp::ws::Message::close_with(
+                        chat_shared::protocol::CLOSE_HEARTBEAT_TIMEOUT,
+                        "heartbeat timeout",
+                    ),
+                    SendPriority::Essential,
+                );
+                break;
+            }
+            _ = tx.evicted() => {
+                let stats = tx.stats();
+                warn!(
+                    "Slow consumer: user {} connection {} queue full ({}/{}), closing",
+                    user_id, connection_id, stats.depth, stats.capacity
+                );
+                // Skip the backlog and close right away
+                forwarder.abort();
+                let close = warp::ws::Message::close_with(
+                    chat_shared::protocol::CLOSE_SLOW_CONSUMER,
+                    "slow consumer",
+                );
+                let _ = tokio::time::timeout(SLOW_CONSUMER_CLOSE_TIMEOUT, ws_tx.send(close)).await;
+                break;
+            }
+        };
+        heartbeat.record_activity().await;
+
         match result {
             Ok(msg) => {
                 info!(
                     "Received WebSocket message from user {}: {:?}",
                     user_id, msg
                 );
+                state
+                    .connection_manager
+                    .touch(&user_id, &connection_id)
+                    .await;
+
+                let msg = match enforce_frame_size(
+                    &msg,
+                    state.config.max_message_size,
+                    session.compression.then_some(state.compression_metrics.as_ref()),
+                ) {
+                    Ok(msg) => msg,
+                    Err(error_response) => {
+                        warn!(
+                            "Closing connection for user {} due to oversized frame",
+                            user_id
+                        );
+                        let _ = ws_tx.send(error_response).await;
+                        break;
+                    }
+                };
 
-                if let Err(error_response) = enforce_frame_size(&msg, state.config.max_message_size)
-                {
-                    warn!(
-                        "Closing connection for user {} due to oversized frame",
-                        user_id
-                    );
-                    let mut sender = ws_tx.lock().await;
-                    let _ = sender.send(error_response).await;
-                    break;
-                }
+                // One span per inbound frame, continuing the client's trace if it sent one
+                let span = info_span!(
+                    "message",
+                    user_id = %user_id,
+                    msg_type = tracing::field::Empty
+                );
+                telemetry::set_remote_parent(
+                    &span,
+                    MessageDispatcher::peek_traceparent(&msg).as_deref(),
+                );
 
                 // Parse and dispatch message
-                let dispatch_result = MessageDispatcher::parse_message(&msg);
+                let dispatch_result = span.in_scope(|| {
+                    info_span!("message.parse").in_scope(|| MessageDispatcher::parse_message(&msg))
+                });
 
                 match dispatch_result {
-                    DispatchResult::RequiresAck { envelope, .. } => {
+                    DispatchResult::RequiresAck {
+                        envelope, msg_type, ..
+                    } => {
+                        span.record("msg_type", msg_type.as_str());
+
                         // Handle text message
-                        match message_handler.handle_message(&envelope, &connection).await {
+                        match message_handler
+                            .handle_message(&envelope, &connection)
+                            .instrument(span.clone())
+                            .await
+                        {
                             Ok(responses) => {
                                 // Send all responses
                                 for response in responses {
-                                    let mut sender = ws_tx.lock().await;
-                                    if let Err(e) = sender.send(response).await {
+                                    if let Err(e) = ws_tx.send(response).await {
                                         warn!("Failed to send response: {}", e);
                                     }
                                 }
@@ -466,24 +1370,39 @@ async fn handle_websocket_connection(socket: WebSocket, state: ServerState, clai
                             Err(e) => {
                                 warn!("Message handling error: {}", e);
                                 let error_response = websocket::ErrorResponse::server_error(&e);
-                                let mut sender = ws_tx.lock().await;
-                                if let Err(e) = sender.send(error_response).await {
+                                if let Err(e) = ws_tx.send(error_response).await {
                                     warn!("Failed to send error response: {}", e);
                                 }
                             }
                         }
                     }
-                    DispatchResult::Success { msg_type, .. } => {
-                        // Heartbeat, typing, etc. - just log
+                    DispatchResult::Success { msg_type, envelope } => {
+                        span.record("msg_type", msg_type.as_str());
+                        if msg_type == "typing" {
+                            if let Err(e) = message_handler
+                                .relay_typing(&envelope, &connection)
+                                .instrument(span.clone())
+                                .await
+                            {
+                                warn!("Failed to relay typing from {}: {}", user_id, e);
+                            }
+                        }
+                        // Heartbeat etc. - just log
                         info!("Handled {} message from {}", msg_type, user_id);
                     }
                     DispatchResult::Error { error_msg } => {
                         // Send error response
-                        let mut sender = ws_tx.lock().await;
-                        if let Err(e) = sender.send(error_msg).await {
+                        if let Err(e) = ws_tx.send(error_msg).await {
                             warn!("Failed to send error response: {}", e);
                         }
                     }
+                    DispatchResult::Resume { last_seq } => {
+                        span.record("msg_type", "resume");
+                        let frames = resume_frames(&state, &user_id, last_seq).await;
+                        if let Err(e) = ws_tx.send_all(frames).await {
+                            warn!("Failed to send replayed events: {}", e);
+                        }
+                    }
                     DispatchResult::Close { code, reason } => {
                         info!("Client requested close: {} - {}", code, reason);
                         break;
@@ -497,16 +1416,147 @@ async fn handle_websocket_connection(socket: WebSocket, state: ServerState, clai
         }
     }
 
-    // Unregister connection
+    heartbeat_task.abort();
+
+    release_connection(&state, &user_id, &connection_id).await;
+    info!("WebSocket connection closed for user: {}", user_id);
+}
+
+/// Unregister a closed connection and mark the user offline if it was their last
+async fn release_connection(state: &ServerState, user_id: &str, connection_id: &str) {
+    let last_activity = state.connection_manager.last_activity(user_id).await;
     state
         .connection_manager
-        .unregister(&user_id, &connection_id)
+        .unregister(user_id, connection_id)
         .await;
 
-    if let Err(e) = state.presence_service.mark_offline(&user_id).await {
-        warn!("Failed to mark presence offline: {}", e);
+    // Other sessions may still be open for this user
+    if !state.connection_manager.is_user_online(user_id).await {
+        let last_seen =
+            last_activity.unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+        if let Err(e) = state
+            .presence_service
+            .mark_offline_at(user_id, last_seen)
+            .await
+        {
+            warn!("Failed to mark presence offline: {}", e);
+        }
+    }
+}
+
+/// Handle GET /events
+///
+/// Authenticates with the same JWT as `/socket`, either as a Bearer header or a
+/// `token` query parameter (browsers' `EventSource` cannot set headers), then
+/// registers the stream like a socket. `Last-Event-ID` replays missed events.
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "realtime",
+    params(
+        ("token" = Option<String>, Query, description = "JWT, when no Authorization header is sent"),
+        ("Last-Event-ID" = Option<u64>, Header, description = "Last sequence number received"),
+    ),
+    security((), ("bearer" = [])),
+    responses(
+        (status = 200, description = "`text/event-stream` of server frames", content_type = "text/event-stream", body = String),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 503, description = "Server is shutting down", body = ErrorResponse),
+    )
+)]
+async fn handle_event_stream(
+    authorization: Option<String>,
+    last_event_id: Option<String>,
+    query: String,
+    state: ServerState,
+) -> Result<impl Reply, Rejection> {
+    if state.is_shutting_down() {
+        return Err(warp::reject::custom(ServerShuttingDown {
+            retry_after_ms: state.config.reconnect_delay.as_millis() as u64,
+        }));
+    }
+
+    let token = match sse::bearer_token(authorization.as_deref()) {
+        Some(token) => token.to_string(),
+        None => extract_token_from_query(&query).map_err(|message| {
+            warp::reject::custom(WebSocketAuthError {
+                status: StatusCode::UNAUTHORIZED,
+                message,
+            })
+        })?,
+    };
+    let claims = HandshakeValidator::new(state.config.jwt_secret.clone())
+        .validate_token(&token)
+        .map_err(|(status, message)| {
+            warn!("Event stream authentication failed: {} - {}", status, message);
+            warp::reject::custom(WebSocketAuthError { status, message })
+        })?;
+
+    let user_id = claims.sub;
+    let username = match crate::db::queries::find_user_by_id(&state.pool, &user_id).await {
+        Ok(Some(user)) => user.username,
+        _ => "unknown".to_string(),
+    };
+    info!("Event stream established for user: {}", user_id);
+
+    let (tx, rx) = OutboundQueue::new(&state.config.outbound_queue);
+    let connection_id = state
+        .connection_manager
+        .register(
+            websocket::ClientConnection::new(user_id.clone(), username),
+            tx.clone(),
+        )
+        .await;
+    if let Err(e) = state.presence_service.mark_online(&user_id).await {
+        warn!("Failed to mark presence online: {}", e);
+    }
+
+    // Registered first, so nothing sent during the replay is lost
+    let session = SessionOptions {
+        protocol_version: chat_shared::protocol::PROTOCOL_VERSION,
+        encoding: WireEncoding::Json,
+        compression: false,
+    };
+    let mut initial = vec![welcome_frame(&state, &session)];
+    if let Some(last_seq) = sse::parse_last_event_id(last_event_id.as_deref()) {
+        initial.extend(resume_frames(&state, &user_id, last_seq).await);
+    }
+
+    let heartbeat_interval = state.config.heartbeat.ping_interval;
+    let guard = EventStreamGuard {
+        state,
+        user_id,
+        connection_id,
+    };
+    let stream = sse::event_stream(initial, rx, tx, guard);
+    Ok(warp::reply::with_header(
+        warp::sse::reply(
+            warp::sse::keep_alive()
+                .interval(Duration::from_secs(heartbeat_interval))
+                .stream(stream),
+        ),
+        chat_shared::protocol::HEARTBEAT_INTERVAL_HEADER,
+        heartbeat_interval.to_string(),
+    ))
+}
+
+/// Releases an event stream's connection once the client goes away
+struct EventStreamGuard {
+    state: ServerState,
+    user_id: String,
+    connection_id: String,
+}
+
+impl Drop for EventStreamGuard {
+    fn drop(&mut self) {
+        let state = self.state.clone();
+        let user_id = std::mem::take(&mut self.user_id);
+        let connection_id = std::mem::take(&mut self.connection_id);
+        tokio::spawn(async move {
+            release_connection(&state, &user_id, &connection_id).await;
+            info!("Event stream closed for user: {}", user_id);
+        });
     }
-    info!("WebSocket connection closed for user: {}", user_id);
 }
 
 /// Handle signup request
@@ -576,7 +1626,7 @@ async fn handle_delete_account(
     req: user::DeleteAccountRequest,
     state: ServerState,
 ) -> Result<impl Reply, Rejection> {
-    user::delete_account(user_id, req, state.pool).await
+    user::delete_account(user_id, req, state.pool, state.data_export_service).await
 }
 
 /// Handle POST /user/change-password
@@ -594,10 +1644,36 @@ fn client_ip(remote_addr: Option<SocketAddr>) -> String {
         .unwrap_or_else(|| "unknown".to_string())
 }
 
+/// Check an inbound frame against the size limit
+///
+/// `compression` is set only when the connection negotiated compressed frames;
+/// those are measured after inflating, which stops at the limit. Elsewhere a
+/// compressed-looking frame is just a binary frame.
 fn enforce_frame_size(
     msg: &warp::ws::Message,
     max_message_size: usize,
-) -> Result<(), warp::ws::Message> {
+    compression: Option<&CompressionMetrics>,
+) -> Result<warp::ws::Message, warp::ws::Message> {
+    let Some(metrics) = compression else {
+        return check_frame_size(msg.clone(), max_message_size);
+    };
+    let msg = match frame_compression::inflate_frame(msg, max_message_size, metrics) {
+        Ok(msg) => msg,
+        Err(InflateError::TooLarge { limit }) => {
+            return Err(websocket::ErrorResponse::invalid_message_length(
+                limit + 1,
+                max_message_size,
+            ));
+        }
+        Err(e) => return Err(websocket::ErrorResponse::server_error(&e.to_string())),
+    };
+    check_frame_size(msg, max_message_size)
+}
+
+fn check_frame_size(
+    msg: warp::ws::Message,
+    max_message_size: usize,
+) -> Result<warp::ws::Message, warp::ws::Message> {
     let payload_len = msg.as_bytes().len();
     if payload_len > max_message_size {
         Err(websocket::ErrorResponse::invalid_message_length(
@@ -605,7 +1681,7 @@ fn enforce_frame_size(
             max_message_size,
         ))
     } else {
-        Ok(())
+        Ok(msg)
     }
 }
 
@@ -666,6 +1742,17 @@ async fn handle_rejection(err: Rejection) -> Result<impl Reply, Rejection> {
             warp::reply::json(&body),
             warp::http::StatusCode::TOO_MANY_REQUESTS,
         ));
+    } else if let Some(shutdown) = err.find::<ServerShuttingDown>() {
+        let body = serde_json::json!({
+            "error": "SERVICE_UNAVAILABLE",
+            "message": "Server is restarting; reconnect later",
+            "retryAfterMs": shutdown.retry_after_ms,
+        });
+
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&body),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        ));
     } else {
         (
             warp::http::StatusCode::INTERNAL_SERVER_ERROR,
@@ -691,6 +1778,34 @@ pub async fn start_server(
     let config = config.unwrap_or_default();
     let state = ServerState::new(pool, config);
 
+    // Nobody is connected yet, so any online flag is left over from a crash
+    let stale = state
+        .presence_service
+        .reset_stale_online()
+        .await
+        .map_err(Error::msg)?;
+    if stale > 0 {
+        info!("Cleared {} stale online flag(s)", stale);
+    }
+    state
+        .presence_service
+        .start_reconciler(state.config.presence_reconcile_interval);
+    state
+        .ephemeral_service
+        .start_reaper(state.config.ephemeral_reap_interval);
+    state.retention_service.start_scheduler();
+
+    // Builds in progress died with the previous process
+    let interrupted = state
+        .data_export_service
+        .fail_interrupted()
+        .await
+        .map_err(Error::msg)?;
+    if interrupted > 0 {
+        info!("Marked {} interrupted data export(s) failed", interrupted);
+    }
+    state.data_export_service.start_cleanup();
+
     // Start background workers (offline delivery)
     state
         .message_queue
@@ -699,18 +1814,81 @@ pub async fn start_server(
         .map_err(Error::msg)?;
     state.message_queue.start().await;
 
-    let routes = create_routes(state);
-
     info!("Starting HTTP server on port {}", port);
 
-    warp::serve(routes).run(([0, 0, 0, 0], port)).await;
+    let (_, server) = bind_with_shutdown(state, ([0, 0, 0, 0], port).into(), shutdown_signal());
+    server.await;
+    info!("Server stopped");
 
     Ok(())
 }
 
+/// Serve the routes on `addr` until `signal` resolves, then shut down
+///
+/// The listener stops accepting, and the open connections are drained as in
+/// [`ServerState::shutdown`] while in-flight responses finish, all within
+/// `shutdown_timeout`.
+pub fn bind_with_shutdown(
+    state: ServerState,
+    addr: SocketAddr,
+    signal: impl Future<Output = ()> + Send + 'static,
+) -> (SocketAddr, impl Future<Output = ()>) {
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let (bound, server) = warp::serve(create_routes(state.clone()))
+        .bind_with_graceful_shutdown(addr, async move {
+            let _ = stop_rx.await;
+        });
+
+    let run = async move {
+        let mut server = tokio::spawn(server);
+        signal.await;
+        let _ = stop_tx.send(());
+        state
+            .shutdown_with(async {
+                let _ = (&mut server).await;
+            })
+            .await;
+        // Responses still running after the deadline are cut off
+        server.abort();
+    };
+    (bound, run)
+}
+
+/// Resolve on SIGINT (Ctrl+C) or, on Unix, SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received SIGINT, shutting down"),
+        _ = terminate => info!("Received SIGTERM, shutting down"),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chat_shared::protocol::MessageEnvelope;
     use sqlx::SqlitePool;
     use std::sync::Arc;
     use warp::http::header::CONTENT_TYPE;
@@ -734,6 +1912,44 @@ mod tests {
         assert!(String::from_utf8_lossy(resp.body()).contains("healthy"));
     }
 
+    #[tokio::test]
+    async fn test_protocol_schema_endpoint() {
+        let pool = init_test_pool().await;
+        let routes = create_routes(ServerState::new(pool, ServerConfig::default()));
+
+        let resp = request()
+            .method("GET")
+            .path("/protocol/schema")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["inbound"]["message"]["properties"]["type"]["const"], "message");
+        assert!(body["outbound"]["welcome"].is_object());
+    }
+
+    #[tokio::test]
+    async fn test_openapi_endpoint() {
+        let pool = init_test_pool().await;
+        let routes = create_routes(ServerState::new(pool, ServerConfig::default()));
+
+        let resp = request()
+            .method("GET")
+            .path("/openapi.json")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        let send = &body["paths"]["/conversations/{id}/messages"]["post"];
+        assert_eq!(
+            send["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/SendMessageRequest"
+        );
+        assert!(body["components"]["schemas"]["AuthResponse"].is_object());
+    }
+
     #[tokio::test]
     async fn test_websocket_upgrade_without_token() {
         let pool = init_test_pool().await;
@@ -779,6 +1995,422 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
     }
 
+    #[tokio::test]
+    async fn test_websocket_upgrade_refused_while_shutting_down() {
+        let pool = init_test_pool().await;
+        let state = ServerState::new(pool, ServerConfig::default());
+        let routes = create_routes(state.clone());
+
+        state.shutdown().await;
+        assert!(state.is_shutting_down());
+
+        let resp = request()
+            .method("GET")
+            .path("/socket?token=irrelevant")
+            .header("Upgrade", "websocket")
+            .header("Connection", "Upgrade")
+            .header("Sec-WebSocket-Version", "13")
+            .header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["retryAfterMs"], 2000);
+    }
+
+    /// Start a real server on an ephemeral port with one registered user
+    async fn spawn_heartbeat_server(config: ServerConfig) -> (SocketAddr, ServerState, String) {
+        let (state, token) = state_with_user(config).await;
+        let (addr, server) =
+            warp::serve(create_routes(state.clone())).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        (addr, state, token)
+    }
+
+    /// Server state with one registered user, alice, and her token
+    async fn state_with_user(config: ServerConfig) -> (ServerState, String) {
+        let pool = init_test_pool().await;
+        let user = crate::models::User::new(
+            "alice".to_string(),
+            "hash".to_string(),
+            "salt".to_string(),
+        );
+        crate::db::queries::insert_user(&pool, &user).await.unwrap();
+        let (token, _) = crate::services::auth_service::AuthService::new(config.jwt_secret.clone())
+            .generate_token(user.id.clone())
+            .unwrap();
+
+        (ServerState::new(pool, config), token)
+    }
+
+    fn fast_heartbeat_config() -> ServerConfig {
+        ServerConfig {
+            heartbeat: HeartbeatConfig {
+                ping_interval: 1,
+                pong_timeout: 1,
+            },
+            ..ServerConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_closes_unresponsive_connection() {
+        let (addr, state, token) = spawn_heartbeat_server(fast_heartbeat_config()).await;
+
+        let (socket, response) =
+            tokio_tungstenite::connect_async(format!("ws://{}/socket?token={}", addr, token))
+                .await
+                .unwrap();
+        assert_eq!(
+            response.headers()[chat_shared::protocol::HEARTBEAT_INTERVAL_HEADER],
+            "1"
+        );
+
+        // The socket is never polled, so pings go unanswered
+        let mut registered = false;
+        for _ in 0..100 {
+            let count = state.connection_manager.connection_count().await;
+            registered |= count == 1;
+            if registered && count == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(registered);
+        assert_eq!(state.connection_manager.connection_count().await, 0);
+        drop(socket);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_keeps_responsive_connection() {
+        let (addr, state, token) = spawn_heartbeat_server(fast_heartbeat_config()).await;
+
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async(format!("ws://{}/socket?token={}", addr, token))
+                .await
+                .unwrap();
+
+        // Reading lets the client answer pings automatically
+        let reader = tokio::spawn(async move {
+            let mut pings = 0;
+            while let Some(Ok(frame)) = socket.next().await {
+                if frame.is_ping() {
+                    pings += 1;
+                }
+            }
+            pings
+        });
+
+        tokio::time::sleep(Duration::from_millis(3500)).await;
+        assert_eq!(state.connection_manager.connection_count().await, 1);
+
+        state
+            .connection_manager
+            .close_all(1000, "test done", 0)
+            .await;
+        let pings = tokio::time::timeout(Duration::from_secs(2), reader)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(pings >= 2);
+    }
+
+    #[tokio::test]
+    async fn test_welcome_confirms_negotiated_protocol() {
+        let (addr, _state, token) = spawn_heartbeat_server(ServerConfig::default()).await;
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!(
+            "ws://{}/socket?token={}&protocol=1,2",
+            addr, token
+        ))
+        .await
+        .unwrap();
+
+        let frame = tokio::time::timeout(Duration::from_secs(2), socket.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let envelope: MessageEnvelope = serde_json::from_str(frame.to_text().unwrap()).unwrap();
+        assert_eq!(envelope.msg_type, "welcome");
+
+        let welcome: WelcomeData = serde_json::from_value(envelope.data).unwrap();
+        assert_eq!(welcome.protocol_version, 2);
+        assert!(welcome.capabilities.contains(&"resume".to_string()));
+    }
+
+    async fn next_msgpack_envelope(
+        socket: &mut tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+    ) -> MessageEnvelope {
+        let frame = tokio::time::timeout(Duration::from_secs(2), socket.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        match frame {
+            tokio_tungstenite::tungstenite::Message::Binary(bytes) => {
+                WireEncoding::MessagePack.decode(&bytes).unwrap()
+            }
+            other => panic!("expected a binary frame, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_msgpack_connection_uses_binary_frames() {
+        use tokio_tungstenite::tungstenite::Message as WsFrame;
+
+        let (addr, _state, token) = spawn_heartbeat_server(ServerConfig::default()).await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!(
+            "ws://{}/socket?token={}&protocol=1,2&encoding=msgpack",
+            addr, token
+        ))
+        .await
+        .unwrap();
+
+        let welcome = next_msgpack_envelope(&mut socket).await;
+        assert_eq!(welcome.msg_type, "welcome");
+        let welcome: WelcomeData = serde_json::from_value(welcome.data).unwrap();
+        assert_eq!(welcome.encoding, WireEncoding::MessagePack);
+
+        // Requests go through the same dispatcher when sent as MessagePack
+        let resume = MessageEnvelope::new("resume", serde_json::json!({ "lastSeq": 1 }));
+        socket
+            .send(WsFrame::Binary(
+                WireEncoding::MessagePack.encode(&resume).unwrap(),
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            next_msgpack_envelope(&mut socket).await.msg_type,
+            "resync_required"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_encoding_is_rejected() {
+        let (addr, _state, token) = spawn_heartbeat_server(ServerConfig::default()).await;
+        let result = tokio_tungstenite::connect_async(format!(
+            "ws://{}/socket?token={}&protocol=2&encoding=xml",
+            addr, token
+        ))
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_compressed_connection_deflates_large_frames() {
+        use chat_shared::protocol::compression;
+
+        let config = ServerConfig {
+            compression: CompressionConfig {
+                enabled: true,
+                threshold: 64,
+            },
+            ..ServerConfig::default()
+        };
+        let (addr, state, token) = spawn_heartbeat_server(config).await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!(
+            "ws://{}/socket?token={}&protocol=2&compression=frame-deflate-v1",
+            addr, token
+        ))
+        .await
+        .unwrap();
+
+        let frame = tokio::time::timeout(Duration::from_secs(2), socket.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap()
+            .into_data();
+        assert!(compression::is_compressed(&frame));
+        let (payload, _) = compression::inflate(&frame, 10 * 1024).unwrap();
+        let envelope: MessageEnvelope = serde_json::from_slice(&payload).unwrap();
+        let welcome: WelcomeData = serde_json::from_value(envelope.data).unwrap();
+        assert!(welcome.compression);
+        assert!(welcome
+            .capabilities
+            .contains(&compression::FRAME_DEFLATE_V1.to_string()));
+
+        let stats = state.compression_metrics.snapshot();
+        assert_eq!(stats.frames_compressed, 1);
+        assert_eq!(stats.bytes_before, payload.len() as u64);
+        assert!(stats.bytes_saved > 0);
+    }
+
+    #[tokio::test]
+    async fn test_outdated_client_is_told_to_update() {
+        let config = ServerConfig {
+            min_protocol_version: 2,
+            ..ServerConfig::default()
+        };
+        let (addr, state, token) = spawn_heartbeat_server(config).await;
+
+        // No declared versions: a legacy v1 client
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async(format!("ws://{}/socket?token={}", addr, token))
+                .await
+                .unwrap();
+
+        let mut error_code = None;
+        let mut close_code = None;
+        while let Ok(Some(Ok(frame))) =
+            tokio::time::timeout(Duration::from_secs(2), socket.next()).await
+        {
+            match frame {
+                tokio_tungstenite::tungstenite::Message::Text(text) => {
+                    let envelope: MessageEnvelope = serde_json::from_str(&text).unwrap();
+                    error_code = envelope.data["code"].as_str().map(str::to_string);
+                }
+                tokio_tungstenite::tungstenite::Message::Close(Some(frame)) => {
+                    close_code = Some(u16::from(frame.code));
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        assert_eq!(error_code.as_deref(), Some("UPDATE_REQUIRED"));
+        assert_eq!(close_code, Some(chat_shared::protocol::CLOSE_UPDATE_REQUIRED));
+        assert_eq!(state.connection_manager.connection_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_slow_consumer_is_evicted() {
+        let config = ServerConfig {
+            outbound_queue: OutboundQueueConfig {
+                capacity: 4,
+                droppable_high_water: 2,
+            },
+            ..ServerConfig::default()
+        };
+        let (addr, state, token) = spawn_heartbeat_server(config).await;
+
+        let (mut socket, _) =
+            tokio_tungstenite::connect_async(format!("ws://{}/socket?token={}", addr, token))
+                .await
+                .unwrap();
+
+        let mut user_id = None;
+        for _ in 0..100 {
+            user_id = state.connection_manager.get_online_users().await.pop();
+            if user_id.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let user_id = user_id.unwrap();
+
+        // Large frames fill the socket buffers while the client is not reading
+        let payload = "x".repeat(512 * 1024);
+        for _ in 0..200 {
+            let stats = state.connection_manager.queue_stats().await;
+            if stats.values().any(|s| s.evicted) || stats.is_empty() {
+                break;
+            }
+            state
+                .connection_manager
+                .send_to_user(&user_id, warp::ws::Message::text(payload.clone()))
+                .await;
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        // The client sees the slow-consumer close once it catches up
+        let close_code = tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(Ok(frame)) = socket.next().await {
+                if let tokio_tungstenite::tungstenite::Message::Close(Some(frame)) = frame {
+                    return Some(u16::from(frame.code));
+                }
+            }
+            None
+        })
+        .await
+        .unwrap();
+        assert_eq!(close_code, Some(chat_shared::protocol::CLOSE_SLOW_CONSUMER));
+
+        for _ in 0..100 {
+            if state.connection_manager.connection_count().await == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(state.connection_manager.connection_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_resume_replays_missed_events() {
+        let pool = init_test_pool().await;
+        let state = ServerState::new(pool, ServerConfig::default());
+
+        // Events recorded while the user is offline
+        let first = state
+            .connection_manager
+            .record_event("u1", MessageEnvelope::new("presence", serde_json::json!({})))
+            .await
+            .unwrap();
+        let first: MessageEnvelope = serde_json::from_str(first.to_str().unwrap()).unwrap();
+        let first_seq = first.seq.unwrap();
+        for _ in 0..2 {
+            state
+                .connection_manager
+                .send_event(
+                    "u1",
+                    MessageEnvelope::new("presence", serde_json::json!({})),
+                    SendPriority::Droppable,
+                )
+                .await
+                .unwrap();
+        }
+
+        let frames = resume_frames(&state, "u1", first_seq).await;
+        let envelopes: Vec<MessageEnvelope> = frames
+            .iter()
+            .map(|f| serde_json::from_str(f.to_str().unwrap()).unwrap())
+            .collect();
+        assert_eq!(envelopes.len(), 3);
+        assert_eq!(envelopes[0].seq, Some(first_seq + 1));
+        assert_eq!(envelopes[1].seq, Some(first_seq + 2));
+        assert_eq!(envelopes[2].msg_type, "resumed");
+        assert_eq!(envelopes[2].data["replayed"], 2);
+        assert_eq!(envelopes[2].data["lastSeq"], first_seq + 2);
+
+        // A seq from before this server started cannot be replayed
+        let frames = resume_frames(&state, "u1", 1).await;
+        assert_eq!(frames.len(), 1);
+        let envelope: MessageEnvelope = serde_json::from_str(frames[0].to_str().unwrap()).unwrap();
+        assert_eq!(envelope.msg_type, "resync_required");
+        assert_eq!(envelope.data["lastSeq"], first_seq + 2);
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_send_queue_metrics() {
+        let pool = init_test_pool().await;
+        let state = ServerState::new(pool, ServerConfig::default());
+        let (queue, _rx) = OutboundQueue::new(&state.config.outbound_queue);
+        queue.push(warp::ws::Message::text("m"), SendPriority::Essential);
+        state
+            .connection_manager
+            .register(
+                websocket::ClientConnection::new("u1".to_string(), "alice".to_string()),
+                queue,
+            )
+            .await;
+        let routes = create_routes(state);
+
+        let resp = request().method("GET").path("/status").reply(&routes).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+        let queues = &body["metrics"]["send_queues"];
+        assert_eq!(queues["total_depth"], 1);
+        assert_eq!(queues["connections"][0]["depth"], 1);
+        assert_eq!(queues["connections"][0]["capacity"], 256);
+        assert!(queues["connections"][0].get("user_id").is_none());
+    }
+
     #[tokio::test]
     async fn test_not_found() {
         let pool = init_test_pool().await;
@@ -794,6 +2426,44 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::NOT_FOUND);
     }
 
+    #[tokio::test]
+    async fn test_request_id_added_to_responses() {
+        let pool = init_test_pool().await;
+        let state = ServerState::new(pool, ServerConfig::default());
+        let routes = create_routes(state);
+
+        let ok = request().method("GET").path("/health").reply(&routes).await;
+        let generated = ok.headers().get("x-request-id").unwrap().to_str().unwrap();
+        assert!(uuid::Uuid::parse_str(generated).is_ok());
+
+        // Rejections go through the same wrapper
+        let not_found = request()
+            .method("GET")
+            .path("/nonexistent")
+            .reply(&routes)
+            .await;
+        assert!(not_found.headers().contains_key("x-request-id"));
+    }
+
+    #[tokio::test]
+    async fn test_request_id_echoes_client_value() {
+        let pool = init_test_pool().await;
+        let state = ServerState::new(pool, ServerConfig::default());
+        let routes = create_routes(state);
+
+        let resp = request()
+            .method("GET")
+            .path("/health")
+            .header("X-Request-Id", "client-req-42")
+            .reply(&routes)
+            .await;
+
+        assert_eq!(
+            resp.headers().get("x-request-id").unwrap(),
+            "client-req-42"
+        );
+    }
+
     #[tokio::test]
     async fn test_status_endpoint() {
         let pool = init_test_pool().await;
@@ -938,8 +2608,234 @@ mod tests {
     #[test]
     fn test_enforce_frame_size_rejects_large_frames() {
         let msg = warp::ws::Message::text("123456");
-        let result = enforce_frame_size(&msg, 4);
-        assert!(result.is_err());
+        assert!(enforce_frame_size(&msg, 4, None).is_err());
+        assert!(enforce_frame_size(&msg, 4, Some(&CompressionMetrics::default())).is_err());
+    }
+
+    #[test]
+    fn test_enforce_frame_size_measures_decompressed_frames() {
+        use chat_shared::protocol::compression::{deflate, PayloadKind};
+        let metrics = CompressionMetrics::default();
+
+        let small = warp::ws::Message::binary(deflate(b"{}", PayloadKind::Text));
+        let inflated = enforce_frame_size(&small, 4, Some(&metrics)).unwrap();
+        assert_eq!(inflated.to_str().unwrap(), "{}");
+
+        // A few hundred bytes on the wire, far more once inflated
+        let bomb = warp::ws::Message::binary(deflate(&[b' '; 1 << 20], PayloadKind::Text));
+        assert!(bomb.as_bytes().len() < 10 * 1024);
+        assert!(enforce_frame_size(&bomb, 10 * 1024, Some(&metrics)).is_err());
+        assert_eq!(metrics.snapshot().frames_decompressed, 1);
+
+        // Without negotiated compression the frame is left alone
+        let passed = enforce_frame_size(&bomb, 10 * 1024, None).unwrap();
+        assert_eq!(passed, bomb);
+        assert_eq!(metrics.snapshot().frames_decompressed, 1);
+    }
+
+    /// Read from a raw HTTP response until `needle` shows up
+    async fn read_until(stream: &mut tokio::net::TcpStream, buffer: &mut String, needle: &str) {
+        use tokio::io::AsyncReadExt;
+        let mut chunk = [0u8; 4096];
+        while !buffer.contains(needle) {
+            let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut chunk))
+                .await
+                .expect("timed out waiting for event stream")
+                .unwrap();
+            assert!(n > 0, "event stream closed before {:?}", needle);
+            buffer.push_str(&String::from_utf8_lossy(&chunk[..n]));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_event_stream_registers_and_delivers_events() {
+        use tokio::io::AsyncWriteExt;
+
+        let (addr, state, token) = spawn_heartbeat_server(ServerConfig::default()).await;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                format!(
+                    "GET /events HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {}\r\nAccept: text/event-stream\r\n\r\n",
+                    token
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        let mut received = String::new();
+        read_until(&mut stream, &mut received, "\"welcome\"").await;
+        assert!(received.starts_with("HTTP/1.1 200"));
+        assert!(received.contains("text/event-stream"));
+
+        // Registered like a socket: online and reachable for delivery
+        let user_id = crate::db::queries::find_user_by_username(&state.pool, "alice")
+            .await
+            .unwrap()
+            .unwrap()
+            .id;
+        assert_eq!(state.connection_manager.connection_count().await, 1);
+        assert!(state.connection_manager.is_user_online(&user_id).await);
+        let event = MessageEnvelope::new("presence", serde_json::json!({ "n": 1 }));
+        assert_eq!(
+            state
+                .connection_manager
+                .send_event(&user_id, event, SendPriority::Essential)
+                .await
+                .unwrap(),
+            1
+        );
+        read_until(&mut stream, &mut received, "\"presence\"").await;
+        // Sequenced events carry their seq as the event ID; the welcome has none
+        read_until(&mut stream, &mut received, "id:").await;
+
+        // Closing the stream releases the connection
+        drop(stream);
+        for _ in 0..100 {
+            if state.connection_manager.connection_count().await == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(state.connection_manager.connection_count().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_ends_open_event_streams() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Well past the test's own timeout, so only closing the stream passes
+        let config = ServerConfig {
+            shutdown_timeout: Duration::from_secs(30),
+            ..ServerConfig::default()
+        };
+        let (state, token) = state_with_user(config).await;
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let (addr, server) = bind_with_shutdown(state.clone(), ([127, 0, 0, 1], 0).into(), async {
+            let _ = stop_rx.await;
+        });
+        let server = tokio::spawn(server);
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                format!(
+                    "GET /events HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {}\r\nAccept: text/event-stream\r\n\r\n",
+                    token
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let mut received = String::new();
+        read_until(&mut stream, &mut received, "\"welcome\"").await;
+
+        stop_tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("shutdown waited on the open event stream")
+            .unwrap();
+        assert!(state.is_shutting_down());
+        assert_eq!(state.connection_manager.connection_count().await, 0);
+
+        // The client saw the restart notice and then the end of the stream
+        read_until(&mut stream, &mut received, "\"shutdown\"").await;
+        let mut rest = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut rest))
+            .await
+            .expect("event stream still open after shutdown")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_event_stream_requires_token() {
+        let pool = init_test_pool().await;
+        let routes = create_routes(ServerState::new(pool, ServerConfig::default()));
+
+        let resp = request().method("GET").path("/events").reply(&routes).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = request()
+            .method("GET")
+            .path("/events?token=invalid")
+            .reply(&routes)
+            .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_rest_send_message_is_idempotent_and_delivered_live() {
+        use crate::handlers::outbound::{OutboundQueue, OutboundQueueConfig};
+        use crate::models::{Conversation, User};
+
+        let pool = init_test_pool().await;
+        let config = ServerConfig::default();
+        let alice = User::new("alice".to_string(), "hash".to_string(), "salt".to_string());
+        let bob = User::new("bob".to_string(), "hash".to_string(), "salt".to_string());
+        crate::db::queries::insert_user(&pool, &alice).await.unwrap();
+        crate::db::queries::insert_user(&pool, &bob).await.unwrap();
+        let (u1, u2) = if alice.id < bob.id {
+            (alice.id.clone(), bob.id.clone())
+        } else {
+            (bob.id.clone(), alice.id.clone())
+        };
+        let conversation =
+            crate::db::queries::insert_conversation(&pool, &Conversation::new(u1, u2))
+                .await
+                .unwrap();
+        let (token, _) = crate::services::auth_service::AuthService::new(config.jwt_secret.clone())
+            .generate_token(alice.id.clone())
+            .unwrap();
+
+        let state = ServerState::new(pool, config);
+        let (bob_queue, mut bob_rx) = OutboundQueue::new(&OutboundQueueConfig::default());
+        state
+            .connection_manager
+            .register(
+                websocket::ClientConnection::new(bob.id.clone(), bob.username.clone()),
+                bob_queue,
+            )
+            .await;
+        let routes = create_routes(state);
+
+        let send = |key: Option<&str>| {
+            let mut req = request()
+                .method("POST")
+                .path(&format!("/conversations/{}/messages", conversation.id))
+                .header(AUTHORIZATION, format!("Bearer {}", token))
+                .json(&serde_json::json!({ "content": "Hello over REST" }));
+            if let Some(key) = key {
+                req = req.header(IDEMPOTENCY_KEY_HEADER, key);
+            }
+            req
+        };
+
+        let missing = send(None).reply(&routes).await;
+        assert_eq!(missing.status(), StatusCode::BAD_REQUEST);
+
+        let first = send(Some("rest-msg-1")).reply(&routes).await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        let body: serde_json::Value = serde_json::from_slice(first.body()).unwrap();
+        assert_eq!(body["status"], "delivered");
+        assert_eq!(body["message"]["id"], "rest-msg-1");
+        assert_eq!(body["message"]["sender_username"], "alice");
+        assert_eq!(body["message"]["recipient_id"], bob.id.as_str());
+
+        // The connected recipient got it live
+        let frame = bob_rx.recv().await.unwrap();
+        let envelope: MessageEnvelope = serde_json::from_str(frame.to_str().unwrap()).unwrap();
+        assert_eq!(envelope.msg_type, "message");
+        assert_eq!(envelope.id, "rest-msg-1");
+        assert_eq!(envelope.data["content"], "Hello over REST");
+
+        // A retry returns the stored message without delivering it again
+        let retry = send(Some("rest-msg-1")).reply(&routes).await;
+        assert_eq!(retry.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(retry.body()).unwrap();
+        assert_eq!(body["message"]["id"], "rest-msg-1");
+        assert_eq!(body["status"], "delivered");
+        assert!(bob_rx.try_recv().is_err());
     }
 
     async fn init_test_pool() -> SqlitePool {
@@ -947,13 +2843,9 @@ mod tests {
             .await
             .expect("Failed to create test pool");
 
-        let schema_sql = include_str!("db/migrations/001_initial_schema.sql");
-        for statement in schema_sql.split(';').filter(|s| !s.trim().is_empty()) {
-            sqlx::query(statement)
-                .execute(&pool)
-                .await
-                .expect("Failed to run schema statement");
-        }
+        crate::db::run_migrations(&pool)
+            .await
+            .expect("Failed to run migrations");
 
         pool
     }
@@ -0,0 +1,547 @@
+//! Personal data exports
+//!
+//! `POST /user/export` records a `data_exports` row and builds a `.tar.gz`
+//! archive of everything the server holds about the user in the background:
+//! profile, published keys, every conversation with its messages (as JSON and
+//! as a readable HTML page) and their auth log history. Other participants who
+//! deleted their account appear as `transcript::DELETED_USER`.
+//!
+//! A finished archive is downloaded through a link signed with the server
+//! secret, so it works without a bearer token until the export expires. Expired
+//! archives are deleted by a periodic cleanup, and a user's archives are
+//! deleted with their account.
+
+use crate::db::queries;
+use crate::models::{DataExport, User};
+use crate::services::transcript::{self, Transcript};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::SqlitePool;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Conversations fetched per query while collecting an export
+const PAGE_SIZE: u32 = 100;
+
+/// How often expired archives are looked for
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+const README: &str = "Personal data export
+
+profile.json        Your account and the public encryption keys you published
+conversations.json  Every conversation you are part of, with its messages
+conversations.html  The same conversations, readable in a browser
+auth_log.json       Sign-ups, logins and logouts recorded for your username
+
+Messages sent by people who have since deleted their account are shown as
+\"Deleted user\". End-to-end encrypted messages are included as the envelopes
+the server stored; only your devices hold the keys to read them.
+
+Messages that disappeared after expiring, were removed by the retention policy
+or were dropped with a declined message request are not included. Each
+conversation lists them under \"gaps\", and the HTML page marks where they were.
+
+This server does not store attachments, so there are none to include.
+";
+
+/// Where archives are written and how long their links stay valid
+#[derive(Debug, Clone)]
+pub struct DataExportConfig {
+    pub dir: PathBuf,
+    pub link_ttl: Duration,
+}
+
+impl Default for DataExportConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("exports"),
+            link_ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// Why a download link was refused
+#[derive(Debug, PartialEq, Eq)]
+pub enum DownloadError {
+    /// Unknown export, not finished yet, or a bad token
+    NotFound,
+    /// The link has expired and the archive is gone
+    Expired,
+    Internal(String),
+}
+
+#[derive(Clone)]
+pub struct DataExportService {
+    pool: SqlitePool,
+    config: DataExportConfig,
+    secret: Arc<[u8]>,
+}
+
+impl DataExportService {
+    /// `secret` signs download links; the JWT secret is used in practice
+    pub fn new(pool: SqlitePool, config: DataExportConfig, secret: &[u8]) -> Self {
+        Self {
+            pool,
+            config,
+            secret: Arc::from(secret),
+        }
+    }
+
+    /// Start an export for a user, or return the one already pending or ready
+    ///
+    /// The second value is `true` when a new export was started.
+    pub async fn request(&self, user_id: &str) -> Result<(DataExport, bool), String> {
+        if let Some(existing) = queries::find_active_data_export(&self.pool, user_id).await? {
+            return Ok((existing, false));
+        }
+
+        let export = DataExport::new(user_id.to_string());
+        queries::insert_data_export(&self.pool, &export).await?;
+
+        let service = self.clone();
+        let pending = export.clone();
+        tokio::spawn(async move {
+            if let Err(e) = service.build(&pending).await {
+                warn!(
+                    target: "export",
+                    event = "export.failed",
+                    export_id = %pending.id,
+                    error = %e,
+                    "Data export failed"
+                );
+                let _ = queries::fail_data_exports(&service.pool, Some(&pending.id), &e).await;
+            }
+        });
+
+        Ok((export, true))
+    }
+
+    /// A user's export, or `None` if it does not exist or belongs to someone else
+    pub async fn get(&self, user_id: &str, export_id: &str) -> Result<Option<DataExport>, String> {
+        Ok(queries::get_data_export(&self.pool, export_id)
+            .await?
+            .filter(|export| export.user_id == user_id))
+    }
+
+    /// Collect the user's data and write the archive
+    async fn build(&self, export: &DataExport) -> Result<(), String> {
+        let user = queries::find_user_by_id(&self.pool, &export.user_id)
+            .await?
+            .ok_or("User not found")?;
+        let files = self.collect(&user).await?;
+
+        let path = self.archive_path(&export.id);
+        let root = format!("export-{}", user.username);
+        let size = tokio::task::spawn_blocking(move || write_archive(&path, &root, &files))
+            .await
+            .map_err(|e| format!("Archive task failed: {}", e))??;
+
+        let expires_at = chrono::Utc::now().timestamp_millis()
+            + i64::try_from(self.config.link_ttl.as_millis()).unwrap_or(i64::MAX);
+        if !queries::complete_data_export(&self.pool, &export.id, size as i64, expires_at).await? {
+            // Expired while being built; nobody may download it
+            self.remove_archive_files(std::slice::from_ref(&export.id))
+                .await;
+            return Ok(());
+        }
+
+        info!(
+            target: "export",
+            event = "export.ready",
+            export_id = %export.id,
+            user_id = %user.id,
+            size_bytes = size,
+            "Data export ready"
+        );
+        Ok(())
+    }
+
+    /// The archive's files as (name, contents)
+    async fn collect(&self, user: &User) -> Result<Vec<(&'static str, Vec<u8>)>, String> {
+        let keys = queries::get_identity_keys(&self.pool, &user.id).await?;
+        let one_time_prekeys = queries::count_one_time_prekeys(&self.pool, &user.id).await?;
+        let profile = json!({
+            "exported_at": chrono::Utc::now().timestamp_millis(),
+            "user_id": user.id,
+            "username": user.username,
+            "created_at": user.created_at,
+            "updated_at": user.updated_at,
+            "last_seen_at": user.last_seen_at,
+            "encryption_keys": keys.map(|keys| json!({
+                "identity_key": keys.identity_key,
+                "signing_key": keys.signing_key,
+                "signed_prekey_id": keys.signed_prekey_id,
+                "signed_prekey": keys.signed_prekey,
+                "updated_at": keys.updated_at,
+                "one_time_prekeys": one_time_prekeys,
+            })),
+        });
+
+        let mut transcripts: Vec<Transcript> = Vec::new();
+        let mut offset = 0;
+        loop {
+            let page =
+                queries::get_user_conversations(&self.pool, &user.id, PAGE_SIZE, offset).await?;
+            for conversation in &page {
+                transcripts.push(transcript::load_transcript(&self.pool, conversation).await?);
+            }
+            if (page.len() as u32) < PAGE_SIZE {
+                break;
+            }
+            offset += PAGE_SIZE;
+        }
+        transcripts.sort_by_key(|t| t.created_at);
+
+        let auth_log = queries::get_auth_logs_for_username(&self.pool, &user.username).await?;
+
+        let html =
+            transcript::render_html(&format!("Conversations of {}", user.username), &transcripts);
+        Ok(vec![
+            ("README.txt", README.as_bytes().to_vec()),
+            ("profile.json", to_json(&profile)?),
+            ("conversations.json", to_json(&transcripts)?),
+            ("conversations.html", html.into_bytes()),
+            ("auth_log.json", to_json(&auth_log)?),
+        ])
+    }
+
+    fn archive_path(&self, export_id: &str) -> PathBuf {
+        self.config.dir.join(format!("{}.tar.gz", export_id))
+    }
+
+    /// Signature over an export's ID and expiry
+    fn sign(&self, export_id: &str, expires_at: i64) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.secret)
+            .expect("HMAC accepts keys of any length");
+        mac.update(format!("data-export:{}:{}", export_id, expires_at).as_bytes());
+        mac
+    }
+
+    /// Download path of a ready export; carries its own credential
+    pub fn download_url(&self, export: &DataExport) -> Option<String> {
+        if export.status != "ready" {
+            return None;
+        }
+        let token = URL_SAFE_NO_PAD.encode(
+            self.sign(&export.id, export.expires_at?)
+                .finalize()
+                .into_bytes(),
+        );
+        Some(format!("/exports/{}/download?token={}", export.id, token))
+    }
+
+    /// Check a download link and read the archive
+    ///
+    /// Returns the archive bytes and a file name for it.
+    pub async fn open_download(
+        &self,
+        export_id: &str,
+        token: &str,
+    ) -> Result<(Vec<u8>, String), DownloadError> {
+        let export = queries::get_data_export(&self.pool, export_id)
+            .await
+            .map_err(DownloadError::Internal)?
+            .ok_or(DownloadError::NotFound)?;
+        let expires_at = export.expires_at.ok_or(DownloadError::NotFound)?;
+
+        let signature = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|_| DownloadError::NotFound)?;
+        self.sign(&export.id, expires_at)
+            .verify_slice(&signature)
+            .map_err(|_| DownloadError::NotFound)?;
+
+        if export.status == "expired" || expires_at <= chrono::Utc::now().timestamp_millis() {
+            return Err(DownloadError::Expired);
+        }
+        if export.status != "ready" {
+            return Err(DownloadError::NotFound);
+        }
+
+        let bytes = tokio::fs::read(self.archive_path(&export.id))
+            .await
+            .map_err(|e| DownloadError::Internal(format!("Failed to read archive: {}", e)))?;
+        let file_name = format!(
+            "data-export-{}.tar.gz",
+            chrono::Utc::now().format("%Y-%m-%d")
+        );
+        Ok((bytes, file_name))
+    }
+
+    /// Fail exports left pending by a previous run; their build task is gone
+    pub async fn fail_interrupted(&self) -> Result<u64, String> {
+        queries::fail_data_exports(&self.pool, None, "Interrupted by a server restart").await
+    }
+
+    /// Expire exports past their link lifetime and delete their archives
+    pub async fn purge_expired(&self) -> Result<usize, String> {
+        let expired = queries::expire_data_exports(&self.pool).await?;
+        self.remove_archive_files(&expired).await;
+        Ok(expired.len())
+    }
+
+    /// Expire all of a user's exports and delete their archives, when the
+    /// account is deleted
+    pub async fn remove_archives(&self, user_id: &str) -> Result<usize, String> {
+        let export_ids = queries::expire_user_data_exports(&self.pool, user_id).await?;
+        self.remove_archive_files(&export_ids).await;
+        Ok(export_ids.len())
+    }
+
+    /// Delete the archives of these exports, if they were written
+    pub async fn remove_archive_files(&self, export_ids: &[String]) {
+        for export_id in export_ids {
+            match tokio::fs::remove_file(self.archive_path(export_id)).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Failed to delete export archive {}: {}", export_id, e),
+            }
+        }
+    }
+
+    /// Run `purge_expired` periodically in the background
+    pub fn start_cleanup(&self) {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(CLEANUP_INTERVAL);
+            loop {
+                ticker.tick().await;
+                match service.purge_expired().await {
+                    Ok(0) => {}
+                    Ok(count) => info!("Deleted {} expired data export(s)", count),
+                    Err(e) => warn!("Data export cleanup failed: {}", e),
+                }
+            }
+        });
+    }
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<Vec<u8>, String> {
+    serde_json::to_vec_pretty(value).map_err(|e| format!("Failed to serialize export: {}", e))
+}
+
+/// Write `files` under `root/` into a gzipped tarball, returning its size
+fn write_archive(
+    path: &std::path::Path,
+    root: &str,
+    files: &[(&'static str, Vec<u8>)],
+) -> Result<u64, String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create export directory: {}", e))?;
+    }
+    let file =
+        std::fs::File::create(path).map_err(|e| format!("Failed to create archive: {}", e))?;
+
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    let mtime = chrono::Utc::now().timestamp().max(0) as u64;
+    for (name, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o600);
+        header.set_mtime(mtime);
+        archive
+            .append_data(
+                &mut header,
+                format!("{}/{}", root, name),
+                contents.as_slice(),
+            )
+            .map_err(|e| format!("Failed to write {}: {}", name, e))?;
+    }
+    archive
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .map_err(|e| format!("Failed to finish archive: {}", e))?;
+
+    std::fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .map_err(|e| format!("Failed to stat archive: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::queries::AuthEventType;
+    use crate::models::{Conversation, Message};
+    use flate2::read::GzDecoder;
+    use std::collections::HashMap;
+    use std::io::Read;
+
+    async fn setup_test_db() -> SqlitePool {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+
+        crate::db::run_migrations(&pool).await.unwrap();
+
+        pool
+    }
+
+    fn service(pool: &SqlitePool) -> DataExportService {
+        let dir = std::env::temp_dir().join(format!("chat-exports-{}", uuid::Uuid::new_v4()));
+        DataExportService::new(
+            pool.clone(),
+            DataExportConfig {
+                dir,
+                link_ttl: Duration::from_secs(60),
+            },
+            b"secret",
+        )
+    }
+
+    async fn wait_until_done(
+        service: &DataExportService,
+        user_id: &str,
+        export_id: &str,
+    ) -> DataExport {
+        for _ in 0..100 {
+            let export = service.get(user_id, export_id).await.unwrap().unwrap();
+            if export.status != "pending" {
+                return export;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("export did not finish");
+    }
+
+    fn unpack(bytes: &[u8]) -> HashMap<String, String> {
+        let mut archive = tar::Archive::new(GzDecoder::new(bytes));
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let name = entry.path().unwrap().to_string_lossy().to_string();
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents).unwrap();
+                (name, contents)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_export_archive_contents_and_link() {
+        let pool = setup_test_db().await;
+        let alice = User::new("alice".to_string(), "hash".to_string(), "salt".to_string());
+        let bob = User::new("bob".to_string(), "hash".to_string(), "salt".to_string());
+        queries::insert_user(&pool, &alice).await.unwrap();
+        queries::insert_user(&pool, &bob).await.unwrap();
+        let (user1, user2) = if alice.id < bob.id {
+            (alice.id.clone(), bob.id.clone())
+        } else {
+            (bob.id.clone(), alice.id.clone())
+        };
+        let conversation = queries::insert_conversation(&pool, &Conversation::new(user1, user2))
+            .await
+            .unwrap();
+        for (from, to, text) in [(&alice, &bob, "from alice"), (&bob, &alice, "from bob")] {
+            let message = Message::new(
+                conversation.id.clone(),
+                from.id.clone(),
+                to.id.clone(),
+                text.to_string(),
+            );
+            queries::insert_message(&pool, &message).await.unwrap();
+        }
+        queries::insert_auth_log(
+            &pool,
+            "10.0.0.1",
+            Some("alice"),
+            AuthEventType::LoginSuccess,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        queries::soft_delete_user(&pool, &bob.id).await.unwrap();
+
+        let service = service(&pool);
+        let (export, created) = service.request(&alice.id).await.unwrap();
+        assert!(created);
+        // Asking again returns the same export
+        let (again, created) = service.request(&alice.id).await.unwrap();
+        assert!(!created);
+        assert_eq!(again.id, export.id);
+        // Someone else's export is not visible
+        assert!(service.get(&bob.id, &export.id).await.unwrap().is_none());
+
+        let export = wait_until_done(&service, &alice.id, &export.id).await;
+        assert_eq!(export.status, "ready");
+        let url = service.download_url(&export).unwrap();
+        let token = url.split("token=").nth(1).unwrap();
+
+        let (bytes, _) = service.open_download(&export.id, token).await.unwrap();
+        assert_eq!(bytes.len() as i64, export.size_bytes.unwrap());
+        let files = unpack(&bytes);
+        let file = |name: &str| &files[&format!("export-alice/{}", name)];
+
+        let profile: serde_json::Value = serde_json::from_str(file("profile.json")).unwrap();
+        assert_eq!(profile["username"], "alice");
+        assert!(file("README.txt").contains("attachments"));
+        assert!(file("auth_log.json").contains("10.0.0.1"));
+        let conversations = file("conversations.json");
+        assert!(conversations.contains("from alice") && conversations.contains("from bob"));
+        assert!(conversations.contains(transcript::DELETED_USER));
+        assert!(!conversations.contains(&bob.id));
+        assert!(!conversations.contains("\"bob\""));
+        assert!(file("conversations.html").contains("from bob"));
+
+        // Tampered links are refused
+        assert_eq!(
+            service.open_download(&export.id, "AAAA").await.unwrap_err(),
+            DownloadError::NotFound
+        );
+
+        // Once expired, the link stops working and the archive is deleted
+        sqlx::query("UPDATE data_exports SET expires_at = ? WHERE id = ?")
+            .bind(chrono::Utc::now().timestamp_millis() - 1)
+            .bind(&export.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(service.purge_expired().await.unwrap(), 1);
+        assert!(!service.archive_path(&export.id).exists());
+        let export = service.get(&alice.id, &export.id).await.unwrap().unwrap();
+        assert_eq!(export.status, "expired");
+        assert!(service.download_url(&export).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_account_deletion_removes_archives() {
+        let pool = setup_test_db().await;
+        let alice = User::new("alice".to_string(), "hash".to_string(), "salt".to_string());
+        queries::insert_user(&pool, &alice).await.unwrap();
+
+        let service = service(&pool);
+        let (export, _) = service.request(&alice.id).await.unwrap();
+        let export = wait_until_done(&service, &alice.id, &export.id).await;
+        let url = service.download_url(&export).unwrap();
+        let token = url.split("token=").nth(1).unwrap();
+        assert!(service.archive_path(&export.id).exists());
+
+        queries::soft_delete_user(&pool, &alice.id).await.unwrap();
+        assert_eq!(service.remove_archives(&alice.id).await.unwrap(), 1);
+        assert!(!service.archive_path(&export.id).exists());
+        assert_eq!(
+            service.open_download(&export.id, token).await.unwrap_err(),
+            DownloadError::Expired
+        );
+
+        // An export still being built when the account goes is never offered
+        let pending = DataExport::new(alice.id.clone());
+        queries::insert_data_export(&pool, &pending).await.unwrap();
+        service.remove_archives(&alice.id).await.unwrap();
+        service.build(&pending).await.unwrap();
+        assert!(!service.archive_path(&pending.id).exists());
+        let pending = service.get(&alice.id, &pending.id).await.unwrap().unwrap();
+        assert_eq!(pending.status, "expired");
+    }
+}
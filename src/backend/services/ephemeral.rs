@@ -0,0 +1,527 @@
+//! Disappearing messages
+//!
+//! A conversation's `ephemeral_ttl` gives every message sent afterwards an
+//! `expires_at`, counted from sending or, with `ephemeral_start = 'read'`, from
+//! when the message is marked read. Either participant may change it, unless
+//! one has blocked the other or the conversation is still a message request;
+//! the change is announced in the conversation with a system message.
+//!
+//! Reads skip expired messages as soon as they expire. A periodic reaper then
+//! deletes them, with their search index entries, and tells both participants
+//! with a `message_expired` frame.
+
+use crate::db::queries;
+use crate::handlers::outbound::SendPriority;
+use crate::handlers::websocket::ConnectionManager;
+use crate::models::{Conversation, Message, User};
+use chat_shared::protocol::{MessageExpiredData, ServerFrame};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Shortest accepted `ephemeral_ttl`, in seconds
+pub const MIN_EPHEMERAL_TTL_SECS: i64 = 5;
+
+/// Longest accepted `ephemeral_ttl`, in seconds (four weeks)
+pub const MAX_EPHEMERAL_TTL_SECS: i64 = 28 * 24 * 60 * 60;
+
+/// Accepted `ephemeral_start` values
+pub const EPHEMERAL_STARTS: &[&str] = &["sent", "read"];
+
+/// Why a change to the setting was refused
+#[derive(Debug, PartialEq, Eq)]
+pub enum SetTtlError {
+    /// One participant has blocked the other
+    Blocked,
+    /// The conversation is a message request that has not been accepted
+    RequestPending,
+    Internal(String),
+}
+
+#[derive(Clone)]
+pub struct EphemeralService {
+    pool: SqlitePool,
+    connection_manager: Arc<ConnectionManager>,
+}
+
+impl EphemeralService {
+    pub fn new(pool: SqlitePool, connection_manager: Arc<ConnectionManager>) -> Self {
+        Self {
+            pool,
+            connection_manager,
+        }
+    }
+
+    /// Check a requested setting before applying it
+    pub fn validate(ephemeral_ttl: Option<i64>, ephemeral_start: &str) -> Result<(), String> {
+        if let Some(ttl) = ephemeral_ttl {
+            if !(MIN_EPHEMERAL_TTL_SECS..=MAX_EPHEMERAL_TTL_SECS).contains(&ttl) {
+                return Err(format!(
+                    "ephemeral_ttl must be between {} and {} seconds",
+                    MIN_EPHEMERAL_TTL_SECS, MAX_EPHEMERAL_TTL_SECS
+                ));
+            }
+        }
+        if !EPHEMERAL_STARTS.contains(&ephemeral_start) {
+            return Err("ephemeral_start must be `sent` or `read`".to_string());
+        }
+        Ok(())
+    }
+
+    /// Change a conversation's setting and announce it to both participants
+    ///
+    /// Returns the system message, or `None` if the setting was unchanged.
+    pub async fn set_ttl(
+        &self,
+        conversation: &Conversation,
+        changed_by: &User,
+        ephemeral_ttl: Option<i64>,
+        ephemeral_start: &str,
+    ) -> Result<Option<Message>, SetTtlError> {
+        let recipient_id = if conversation.user1_id == changed_by.id {
+            &conversation.user2_id
+        } else {
+            &conversation.user1_id
+        };
+        // The announcement is a message between the two, refused like one
+        if queries::is_blocked_either_way(&self.pool, &changed_by.id, recipient_id)
+            .await
+            .map_err(SetTtlError::Internal)?
+        {
+            return Err(SetTtlError::Blocked);
+        }
+        // Held messages wait for acceptance, and so does the announcement
+        if queries::get_message_request(&self.pool, &conversation.id)
+            .await
+            .map_err(SetTtlError::Internal)?
+            .is_some()
+        {
+            return Err(SetTtlError::RequestPending);
+        }
+
+        let unchanged = conversation.ephemeral_ttl == ephemeral_ttl
+            && (ephemeral_ttl.is_none() || conversation.ephemeral_start == ephemeral_start);
+        if unchanged {
+            return Ok(None);
+        }
+
+        queries::set_conversation_ephemeral(
+            &self.pool,
+            &conversation.id,
+            ephemeral_ttl,
+            ephemeral_start,
+        )
+        .await
+        .map_err(SetTtlError::Internal)?;
+
+        let mut message = Message::new(
+            conversation.id.clone(),
+            changed_by.id.clone(),
+            recipient_id.clone(),
+            describe_change(&changed_by.username, ephemeral_ttl, ephemeral_start),
+        );
+        message.is_system = true;
+        message.status = "sent".to_string();
+        queries::insert_message(&self.pool, &message)
+            .await
+            .map_err(SetTtlError::Internal)?;
+
+        let envelope = ServerFrame::Message(message.to_text_data(&changed_by.username, "sent"))
+            .into_envelope_with_id(&message.id);
+        self.connection_manager
+            .broadcast_event(
+                [&conversation.user1_id, &conversation.user2_id],
+                envelope,
+                SendPriority::Essential,
+            )
+            .await
+            .map_err(SetTtlError::Internal)?;
+
+        info!(
+            target: "message",
+            event = "conversation.ephemeral",
+            conversation_id = %conversation.id,
+            ephemeral_ttl = ?ephemeral_ttl,
+            ephemeral_start = %ephemeral_start,
+            "Disappearing messages changed"
+        );
+        Ok(Some(message))
+    }
+
+    /// Delete expired messages and tell the participants which ones went
+    ///
+    /// Returns the number deleted.
+    pub async fn reap_expired(&self) -> Result<usize, String> {
+        let expired = queries::delete_expired_messages(&self.pool).await?;
+
+        let mut by_conversation: HashMap<String, (Vec<String>, [String; 2])> = HashMap::new();
+        for message in &expired {
+            by_conversation
+                .entry(message.conversation_id.clone())
+                .or_insert_with(|| {
+                    (
+                        Vec::new(),
+                        [message.sender_id.clone(), message.recipient_id.clone()],
+                    )
+                })
+                .0
+                .push(message.id.clone());
+        }
+
+        for (conversation_id, (message_ids, participants)) in by_conversation {
+            let envelope = ServerFrame::MessageExpired(MessageExpiredData {
+                conversation_id,
+                message_ids,
+            })
+            .into_envelope();
+            self.connection_manager
+                .broadcast_event(&participants, envelope, SendPriority::Essential)
+                .await?;
+        }
+
+        Ok(expired.len())
+    }
+
+    /// Run `reap_expired` every `interval` in the background
+    pub fn start_reaper(&self, interval: Duration) {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match service.reap_expired().await {
+                    Ok(0) => {}
+                    Ok(count) => info!("Deleted {} expired message(s)", count),
+                    Err(e) => warn!("Expired message cleanup failed: {}", e),
+                }
+            }
+        });
+    }
+}
+
+/// System message text for a setting change
+fn describe_change(username: &str, ephemeral_ttl: Option<i64>, ephemeral_start: &str) -> String {
+    match ephemeral_ttl {
+        None => format!("{} turned off disappearing messages", username),
+        Some(ttl) => {
+            let after = if ephemeral_start == "read" {
+                "being read"
+            } else {
+                "being sent"
+            };
+            format!(
+                "{} set messages to disappear {} after {}",
+                username,
+                describe_duration(ttl),
+                after
+            )
+        }
+    }
+}
+
+/// `90` -> "90 seconds", `3600` -> "1 hour", in the largest whole unit
+fn describe_duration(secs: i64) -> String {
+    const UNITS: &[(i64, &str)] = &[
+        (7 * 24 * 60 * 60, "week"),
+        (24 * 60 * 60, "day"),
+        (60 * 60, "hour"),
+        (60, "minute"),
+        (1, "second"),
+    ];
+    let (size, unit) = UNITS
+        .iter()
+        .find(|(size, _)| secs % size == 0)
+        .copied()
+        .unwrap_or((1, "second"));
+    let count = secs / size;
+    if count == 1 {
+        format!("1 {}", unit)
+    } else {
+        format!("{} {}s", count, unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::outbound::{OutboundQueue, OutboundQueueConfig};
+    use crate::handlers::websocket::ClientConnection;
+    use crate::services::MessageService;
+
+    async fn setup_test_db() -> SqlitePool {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+
+        crate::db::run_migrations(&pool).await.unwrap();
+
+        pool
+    }
+
+    async fn setup_conversation(pool: &SqlitePool) -> (User, User, Conversation) {
+        let alice = User::new("alice".to_string(), "hash".to_string(), "salt".to_string());
+        let bob = User::new("bob".to_string(), "hash".to_string(), "salt".to_string());
+        queries::insert_user(pool, &alice).await.unwrap();
+        queries::insert_user(pool, &bob).await.unwrap();
+        let (u1, u2) = if alice.id < bob.id {
+            (alice.id.clone(), bob.id.clone())
+        } else {
+            (bob.id.clone(), alice.id.clone())
+        };
+        let conversation = queries::insert_conversation(pool, &Conversation::new(u1, u2))
+            .await
+            .unwrap();
+        (alice, bob, conversation)
+    }
+
+    #[tokio::test]
+    async fn test_set_ttl_announces_and_applies_to_new_messages() {
+        let pool = setup_test_db().await;
+        let manager = Arc::new(ConnectionManager::new());
+        let service = EphemeralService::new(pool.clone(), manager.clone());
+        let (alice, bob, conversation) = setup_conversation(&pool).await;
+
+        let (tx, mut rx) = OutboundQueue::new(&OutboundQueueConfig::default());
+        manager
+            .register(ClientConnection::new(bob.id.clone(), bob.username.clone()), tx)
+            .await;
+
+        let announcement = service
+            .set_ttl(&conversation, &alice, Some(3600), "sent")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(announcement.is_system);
+        assert_eq!(announcement.expires_at, None);
+        assert_eq!(
+            announcement.content,
+            "alice set messages to disappear 1 hour after being sent"
+        );
+
+        let frame = rx.try_recv().unwrap();
+        let frame: serde_json::Value = serde_json::from_str(frame.to_str().unwrap()).unwrap();
+        assert_eq!(frame["type"], "message");
+        assert_eq!(frame["data"]["system"], true);
+
+        // Setting the same value again says nothing
+        let conversation = queries::get_conversation_by_id(&pool, &conversation.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(conversation.ephemeral_ttl, Some(3600));
+        assert!(service
+            .set_ttl(&conversation, &bob, Some(3600), "sent")
+            .await
+            .unwrap()
+            .is_none());
+
+        let message = MessageService::new(pool.clone())
+            .send_message(
+                conversation.id.clone(),
+                bob.id.clone(),
+                alice.id.clone(),
+                "see you".to_string(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(message.expires_at, Some(message.created_at + 3_600_000));
+    }
+
+    #[tokio::test]
+    async fn test_set_ttl_refused_between_blocked_users() {
+        let pool = setup_test_db().await;
+        let service = EphemeralService::new(pool.clone(), Arc::new(ConnectionManager::new()));
+        let (alice, bob, conversation) = setup_conversation(&pool).await;
+
+        // Whoever blocked whom, neither can change the setting
+        queries::insert_block(&pool, &bob.id, &alice.id)
+            .await
+            .unwrap();
+        for user in [&alice, &bob] {
+            assert_eq!(
+                service
+                    .set_ttl(&conversation, user, Some(60), "sent")
+                    .await
+                    .unwrap_err(),
+                SetTtlError::Blocked
+            );
+        }
+        let unchanged = queries::get_conversation_by_id(&pool, &conversation.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(unchanged.ephemeral_ttl, None);
+        assert!(
+            queries::get_messages_by_conversation(&pool, &conversation.id, 10, 0)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        queries::delete_block(&pool, &bob.id, &alice.id)
+            .await
+            .unwrap();
+        assert!(service
+            .set_ttl(&conversation, &alice, Some(60), "sent")
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_set_ttl_waits_for_message_request() {
+        let pool = setup_test_db().await;
+        let service = EphemeralService::new(pool.clone(), Arc::new(ConnectionManager::new()));
+        let (alice, bob, conversation) = setup_conversation(&pool).await;
+        queries::insert_message_request(&pool, &conversation.id, &alice.id, &bob.id)
+            .await
+            .unwrap();
+
+        for user in [&alice, &bob] {
+            assert_eq!(
+                service
+                    .set_ttl(&conversation, user, Some(60), "sent")
+                    .await
+                    .unwrap_err(),
+                SetTtlError::RequestPending
+            );
+        }
+        assert!(
+            queries::get_messages_by_conversation(&pool, &conversation.id, 10, 0)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        // Once accepted, either may change it
+        queries::delete_message_request(&pool, &conversation.id)
+            .await
+            .unwrap();
+        assert!(service
+            .set_ttl(&conversation, &bob, Some(60), "sent")
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_read_timer_starts_when_read() {
+        let pool = setup_test_db().await;
+        let service = EphemeralService::new(pool.clone(), Arc::new(ConnectionManager::new()));
+        let (alice, bob, conversation) = setup_conversation(&pool).await;
+
+        service
+            .set_ttl(&conversation, &alice, Some(60), "read")
+            .await
+            .unwrap();
+        let message = MessageService::new(pool.clone())
+            .send_message(
+                conversation.id.clone(),
+                alice.id.clone(),
+                bob.id.clone(),
+                "read me".to_string(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(message.expires_at, None);
+
+        queries::mark_message_read(&pool, &message.id).await.unwrap();
+        let read = queries::find_message_by_id(&pool, &message.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(read.status, "read");
+        assert_eq!(read.expires_at, Some(read.read_at.unwrap() + 60_000));
+    }
+
+    #[tokio::test]
+    async fn test_expired_messages_are_hidden_then_reaped() {
+        let pool = setup_test_db().await;
+        let manager = Arc::new(ConnectionManager::new());
+        let service = EphemeralService::new(pool.clone(), manager.clone());
+        let (alice, bob, conversation) = setup_conversation(&pool).await;
+
+        let mut expired = Message::new(
+            conversation.id.clone(),
+            alice.id.clone(),
+            bob.id.clone(),
+            "gone soon".to_string(),
+        );
+        expired.created_at += 1000;
+        expired.expires_at = Some(expired.created_at - 2000);
+        queries::insert_message(&pool, &expired).await.unwrap();
+        let kept = Message::new(
+            conversation.id.clone(),
+            alice.id.clone(),
+            bob.id.clone(),
+            "still here".to_string(),
+        );
+        queries::insert_message(&pool, &kept).await.unwrap();
+
+        // Hidden before the reaper runs
+        let history = queries::get_messages_by_conversation(&pool, &conversation.id, 50, 0)
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].id, kept.id);
+        assert!(queries::find_message_by_id(&pool, &expired.id)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(
+            queries::search_messages_in_conversation(&pool, &conversation.id, "gone", 10)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        let (tx, mut rx) = OutboundQueue::new(&OutboundQueueConfig::default());
+        manager
+            .register(ClientConnection::new(alice.id.clone(), alice.username.clone()), tx)
+            .await;
+
+        assert_eq!(service.reap_expired().await.unwrap(), 1);
+        assert_eq!(service.reap_expired().await.unwrap(), 0);
+
+        let (remaining,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM messages")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 1);
+
+        // The conversation's counters no longer include the expired message
+        let conversation = queries::get_conversation_by_id(&pool, &conversation.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(conversation.message_count, 1);
+        assert_eq!(conversation.last_message_at, Some(kept.created_at));
+
+        let frame = rx.try_recv().unwrap();
+        let frame: serde_json::Value = serde_json::from_str(frame.to_str().unwrap()).unwrap();
+        assert_eq!(frame["type"], "message_expired");
+        assert_eq!(frame["data"]["conversationId"], conversation.id);
+        assert_eq!(frame["data"]["messageIds"], serde_json::json!([expired.id]));
+    }
+
+    #[test]
+    fn test_validate_and_describe() {
+        assert!(EphemeralService::validate(None, "sent").is_ok());
+        assert!(EphemeralService::validate(Some(30), "read").is_ok());
+        assert!(EphemeralService::validate(Some(1), "sent").is_err());
+        assert!(EphemeralService::validate(Some(MAX_EPHEMERAL_TTL_SECS + 1), "sent").is_err());
+        assert!(EphemeralService::validate(Some(30), "opened").is_err());
+
+        assert_eq!(describe_duration(90), "90 seconds");
+        assert_eq!(describe_duration(300), "5 minutes");
+        assert_eq!(describe_duration(86_400), "1 day");
+        assert_eq!(describe_duration(1_209_600), "2 weeks");
+        assert_eq!(
+            describe_change("bob", None, "sent"),
+            "bob turned off disappearing messages"
+        );
+    }
+}
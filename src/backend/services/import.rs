@@ -0,0 +1,684 @@
+//! Importing history from other chat systems
+//!
+//! `admin_cli import` reads either a Slack workspace export (the unzipped
+//! directory) or the generic JSON format below, and writes its users,
+//! one-to-one conversations and messages into the database.
+//!
+//! Every step is idempotent, so an interrupted import is resumed by running it
+//! again: users are matched by username, conversations by their participants,
+//! and messages keep their original IDs (`slack-{channel}-{ts}` for Slack) and
+//! are skipped if already present. Anything that cannot be imported is listed
+//! in the [`ImportSummary`] rather than failing the whole run.
+//!
+//! Generic format, with times in epoch milliseconds:
+//!
+//! ```json
+//! {
+//!   "users": [{ "id": "u1", "username": "alice" }, { "id": "u2", "username": "bob" }],
+//!   "conversations": [{
+//!     "id": "c1",
+//!     "participants": ["u1", "u2"],
+//!     "created_at": 1700000000000,
+//!     "messages": [
+//!       { "id": "m1", "sender": "u1", "content": "Hi", "created_at": 1700000000000 }
+//!     ]
+//!   }]
+//! }
+//! ```
+
+use crate::db::queries;
+use crate::models::{Conversation, Message, User};
+use crate::validators;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::path::Path;
+use uuid::Uuid;
+
+/// Maximum username length, as enforced at signup
+const MAX_USERNAME_LEN: usize = 50;
+
+/// History to import, in the generic format
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ImportData {
+    pub users: Vec<ImportUser>,
+    pub conversations: Vec<ImportConversation>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportUser {
+    /// ID in the source system, referenced by conversations and messages
+    pub id: String,
+    pub username: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportConversation {
+    pub id: String,
+    /// Source user IDs; only conversations between exactly two users are imported
+    pub participants: Vec<String>,
+    /// Defaults to the first message's time
+    #[serde(default)]
+    pub created_at: Option<i64>,
+    #[serde(default)]
+    pub messages: Vec<ImportMessage>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportMessage {
+    /// Kept as the message ID
+    pub id: String,
+    /// Source user ID
+    pub sender: String,
+    pub content: String,
+    pub created_at: i64,
+}
+
+/// Something left out of an import, and why
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportIssue {
+    /// `user`, `conversation` or `message`
+    pub kind: &'static str,
+    /// ID in the source system
+    pub id: String,
+    pub reason: String,
+}
+
+/// What an import run did
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportSummary {
+    /// Source users matched to an account that already existed
+    pub users_mapped: usize,
+    /// Placeholder accounts created for source users
+    pub users_created: usize,
+    pub conversations_created: usize,
+    /// Conversations that already existed between the two users
+    pub conversations_existing: usize,
+    pub messages_imported: usize,
+    /// Messages present from an earlier run
+    pub messages_already_imported: usize,
+    /// Data that clashes with what is already in the database
+    pub conflicts: Vec<ImportIssue>,
+    /// Data that cannot be represented here
+    pub skipped: Vec<ImportIssue>,
+}
+
+impl ImportIssue {
+    fn new(kind: &'static str, id: &str, reason: impl Into<String>) -> Self {
+        Self {
+            kind,
+            id: id.to_string(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl ImportData {
+    /// Read a file in the generic format
+    pub fn from_json_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&text).map_err(|e| format!("Invalid import file: {}", e))
+    }
+}
+
+/// Writes imported history into the database
+pub struct Importer {
+    pool: SqlitePool,
+    /// Source user ID to local username, overriding the source's username
+    user_map: HashMap<String, String>,
+}
+
+impl Importer {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self {
+            pool,
+            user_map: HashMap::new(),
+        }
+    }
+
+    /// Map source users onto existing accounts by local username
+    pub fn with_user_map(mut self, user_map: HashMap<String, String>) -> Self {
+        self.user_map = user_map;
+        self
+    }
+
+    /// Import everything in `data`, adding to `summary`
+    ///
+    /// Only database errors abort the run; it can then be started again.
+    pub async fn import(
+        &self,
+        data: &ImportData,
+        summary: &mut ImportSummary,
+    ) -> Result<(), String> {
+        let users = self.resolve_users(&data.users, summary).await?;
+
+        for conversation in &data.conversations {
+            self.import_conversation(conversation, &users, summary)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Local user ID for each source user that could be mapped or created
+    async fn resolve_users(
+        &self,
+        source_users: &[ImportUser],
+        summary: &mut ImportSummary,
+    ) -> Result<HashMap<String, String>, String> {
+        let mut users = HashMap::new();
+        // Local username to the source user that claimed it
+        let mut claimed: HashMap<String, String> = HashMap::new();
+
+        for source in source_users {
+            let (username, explicit) = match self.user_map.get(&source.id) {
+                Some(username) => (username.clone(), true),
+                None => (placeholder_username(&source.username), false),
+            };
+            if validators::validate_username(&username).is_err() {
+                summary.skipped.push(ImportIssue::new(
+                    "user",
+                    &source.id,
+                    format!("'{}' cannot be made into a valid username", source.username),
+                ));
+                continue;
+            }
+            if let Some(other) = claimed.get(&username) {
+                summary.conflicts.push(ImportIssue::new(
+                    "user",
+                    &source.id,
+                    format!(
+                        "username '{}' is already used by source user {}",
+                        username, other
+                    ),
+                ));
+                continue;
+            }
+
+            match queries::find_user_by_username(&self.pool, &username).await? {
+                Some(user) if user.is_deleted() => {
+                    summary.conflicts.push(ImportIssue::new(
+                        "user",
+                        &source.id,
+                        format!("'{}' belongs to a deleted account", username),
+                    ));
+                    continue;
+                }
+                Some(user) => {
+                    summary.users_mapped += 1;
+                    users.insert(source.id.clone(), user.id);
+                }
+                None if explicit => {
+                    summary.conflicts.push(ImportIssue::new(
+                        "user",
+                        &source.id,
+                        format!("mapped to '{}', which does not exist", username),
+                    ));
+                    continue;
+                }
+                None => {
+                    let user = placeholder_user(username.clone())?;
+                    queries::insert_user(&self.pool, &user).await?;
+                    summary.users_created += 1;
+                    users.insert(source.id.clone(), user.id);
+                }
+            }
+            claimed.insert(username, source.id.clone());
+        }
+        Ok(users)
+    }
+
+    async fn import_conversation(
+        &self,
+        source: &ImportConversation,
+        users: &HashMap<String, String>,
+        summary: &mut ImportSummary,
+    ) -> Result<(), String> {
+        let [a, b] = source.participants.as_slice() else {
+            summary.skipped.push(ImportIssue::new(
+                "conversation",
+                &source.id,
+                format!(
+                    "has {} participants; only one-to-one conversations can be imported",
+                    source.participants.len()
+                ),
+            ));
+            return Ok(());
+        };
+        let (Some(a), Some(b)) = (users.get(a), users.get(b)) else {
+            summary.skipped.push(ImportIssue::new(
+                "conversation",
+                &source.id,
+                "a participant was not imported",
+            ));
+            return Ok(());
+        };
+        if a == b {
+            summary.skipped.push(ImportIssue::new(
+                "conversation",
+                &source.id,
+                "both participants are the same account",
+            ));
+            return Ok(());
+        }
+
+        let mut messages: Vec<&ImportMessage> = source.messages.iter().collect();
+        messages.sort_by_key(|m| m.created_at);
+
+        let (user1, user2) = if a < b { (a, b) } else { (b, a) };
+        let conversation =
+            match queries::get_conversation_by_users(&self.pool, user1, user2).await? {
+                Some(conversation) => {
+                    summary.conversations_existing += 1;
+                    conversation
+                }
+                None => {
+                    let mut conversation = Conversation::new(user1.clone(), user2.clone());
+                    let first = messages.first().map(|m| m.created_at);
+                    let last = messages.last().map(|m| m.created_at);
+                    if let Some(created_at) = source.created_at.or(first) {
+                        conversation.created_at = created_at;
+                    }
+                    conversation.updated_at = last.unwrap_or(conversation.created_at);
+                    conversation.last_message_at = last;
+                    conversation.message_count = messages.len() as i32;
+                    summary.conversations_created += 1;
+                    queries::insert_conversation(&self.pool, &conversation).await?
+                }
+            };
+
+        for source_message in messages {
+            let Some(sender) = users.get(&source_message.sender) else {
+                summary.skipped.push(ImportIssue::new(
+                    "message",
+                    &source_message.id,
+                    "its sender was not imported",
+                ));
+                continue;
+            };
+            if sender != a && sender != b {
+                summary.skipped.push(ImportIssue::new(
+                    "message",
+                    &source_message.id,
+                    "its sender is not a participant",
+                ));
+                continue;
+            }
+
+            if let Some(existing) = self.find_message(&source_message.id).await? {
+                if existing.0 == conversation.id && &existing.1 == sender {
+                    summary.messages_already_imported += 1;
+                } else {
+                    summary.conflicts.push(ImportIssue::new(
+                        "message",
+                        &source_message.id,
+                        "the ID is taken by a different message",
+                    ));
+                }
+                continue;
+            }
+
+            let recipient = if sender == user1 { user2 } else { user1 };
+            let mut message = Message::new(
+                conversation.id.clone(),
+                sender.clone(),
+                recipient.clone(),
+                source_message.content.clone(),
+            );
+            message.id = source_message.id.clone();
+            message.created_at = source_message.created_at;
+            // History is not delivered again
+            message.delivered_at = Some(source_message.created_at);
+            message.read_at = Some(source_message.created_at);
+            message.status = "read".to_string();
+            queries::insert_message(&self.pool, &message).await?;
+            summary.messages_imported += 1;
+        }
+        Ok(())
+    }
+
+    /// Conversation and sender of the message with `id`, including expired ones
+    async fn find_message(&self, id: &str) -> Result<Option<(String, String)>, String> {
+        sqlx::query_as("SELECT conversation_id, sender_id FROM messages WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| format!("Failed to find message: {}", e))
+    }
+}
+
+/// A valid username close to `name`
+fn placeholder_username(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .take(MAX_USERNAME_LEN)
+        .collect()
+}
+
+/// An account nobody can log in to, holding an imported user's messages
+fn placeholder_user(username: String) -> Result<User, String> {
+    // The password is random and never shown, so bcrypt's lowest cost will do
+    let hash = bcrypt::hash(Uuid::new_v4().to_string(), 4)
+        .map_err(|e| format!("Failed to hash password: {}", e))?;
+    Ok(User::new(username, hash.clone(), hash))
+}
+
+/// Slack workspace exports
+pub mod slack {
+    use super::{ImportConversation, ImportData, ImportIssue, ImportMessage, ImportUser};
+    use serde::Deserialize;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    #[derive(Deserialize)]
+    struct SlackUser {
+        id: String,
+        name: String,
+    }
+
+    #[derive(Deserialize)]
+    struct SlackChannel {
+        id: String,
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        created: Option<i64>,
+        #[serde(default)]
+        members: Vec<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct SlackMessage {
+        #[serde(default)]
+        user: Option<String>,
+        #[serde(default)]
+        text: String,
+        ts: String,
+        #[serde(default)]
+        subtype: Option<String>,
+    }
+
+    /// Subtypes that are still something a person wrote
+    const USER_SUBTYPES: &[&str] = &["me_message", "thread_broadcast"];
+
+    /// Read an unzipped Slack export directory
+    ///
+    /// Direct messages (`dms.json`) become conversations. Channels, private
+    /// channels and group DMs are reported as skipped, as are messages that
+    /// are not plain user messages.
+    pub fn load(dir: &Path) -> Result<(ImportData, Vec<ImportIssue>), String> {
+        let users: Vec<SlackUser> = read_json(&dir.join("users.json"))?
+            .ok_or_else(|| format!("{} has no users.json", dir.display()))?;
+        let names: HashMap<&str, &str> = users
+            .iter()
+            .map(|u| (u.id.as_str(), u.name.as_str()))
+            .collect();
+
+        let mut skipped = Vec::new();
+        for file in ["channels.json", "groups.json", "mpims.json"] {
+            for channel in read_json::<Vec<SlackChannel>>(&dir.join(file))?.unwrap_or_default() {
+                skipped.push(ImportIssue::new(
+                    "conversation",
+                    &channel.id,
+                    format!(
+                        "{} is not a direct message; only one-to-one conversations can be imported",
+                        channel.name.as_deref().unwrap_or("channel")
+                    ),
+                ));
+            }
+        }
+
+        let mut conversations = Vec::new();
+        for dm in read_json::<Vec<SlackChannel>>(&dir.join("dms.json"))?.unwrap_or_default() {
+            let mut messages = Vec::new();
+            for message in read_messages(&dir.join(&dm.id))? {
+                let id = format!("slack-{}-{}", dm.id, message.ts);
+                let user_written = message
+                    .subtype
+                    .as_deref()
+                    .is_none_or(|subtype| USER_SUBTYPES.contains(&subtype));
+                let (Some(sender), true) = (message.user, user_written) else {
+                    skipped.push(ImportIssue::new("message", &id, "not a user message"));
+                    continue;
+                };
+                if message.text.trim().is_empty() {
+                    skipped.push(ImportIssue::new("message", &id, "has no text"));
+                    continue;
+                }
+                let Some(created_at) = ts_millis(&message.ts) else {
+                    skipped.push(ImportIssue::new("message", &id, "has an invalid timestamp"));
+                    continue;
+                };
+                messages.push(ImportMessage {
+                    id,
+                    sender,
+                    content: plain_text(&message.text, &names),
+                    created_at,
+                });
+            }
+            conversations.push(ImportConversation {
+                id: dm.id,
+                participants: dm.members,
+                created_at: dm.created.map(|secs| secs * 1000),
+                messages,
+            });
+        }
+
+        let users = users
+            .into_iter()
+            .map(|u| ImportUser {
+                id: u.id,
+                username: u.name,
+            })
+            .collect();
+        Ok((
+            ImportData {
+                users,
+                conversations,
+            },
+            skipped,
+        ))
+    }
+
+    /// Parse a JSON file, or `None` if it does not exist
+    fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<Option<T>, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map(Some)
+                .map_err(|e| format!("Invalid {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Messages of one conversation, stored as one file per day
+    fn read_messages(dir: &Path) -> Result<Vec<SlackMessage>, String> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read {}: {}", dir.display(), e)),
+        };
+        let mut files: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        files.sort();
+
+        let mut messages = Vec::new();
+        for file in files {
+            messages.extend(read_json::<Vec<SlackMessage>>(&file)?.unwrap_or_default());
+        }
+        Ok(messages)
+    }
+
+    /// `1600000000.000200` (seconds and microseconds) in milliseconds
+    fn ts_millis(ts: &str) -> Option<i64> {
+        let (secs, micros) = ts.split_once('.').unwrap_or((ts, "0"));
+        let micros = format!("{:0<6}", micros);
+        Some(secs.parse::<i64>().ok()? * 1000 + micros.get(..3)?.parse::<i64>().ok()?)
+    }
+
+    /// Undo Slack's markup: `<@U123>` mentions, `<url|label>` links and entities
+    fn plain_text(text: &str, names: &HashMap<&str, &str>) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('<') {
+            out.push_str(&rest[..start]);
+            let Some(len) = rest[start..].find('>') else {
+                break;
+            };
+            let inner = &rest[start + 1..start + len];
+            if let Some(id) = inner.strip_prefix('@') {
+                let id = id.split('|').next().unwrap_or(id);
+                out.push('@');
+                out.push_str(names.get(id).copied().unwrap_or(id));
+            } else {
+                match inner.split_once('|') {
+                    Some((url, label)) => {
+                        out.push_str(label);
+                        out.push_str(" (");
+                        out.push_str(url);
+                        out.push(')');
+                    }
+                    None => out.push_str(inner),
+                }
+            }
+            rest = &rest[start + len + 1..];
+        }
+        out.push_str(rest);
+        out.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&amp;", "&")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn setup_test_db() -> SqlitePool {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+
+        crate::db::run_migrations(&pool).await.unwrap();
+
+        pool
+    }
+
+    #[tokio::test]
+    async fn test_generic_import_is_idempotent() {
+        let pool = setup_test_db().await;
+        let alice = User::new("alice".to_string(), "hash".to_string(), "salt".to_string());
+        queries::insert_user(&pool, &alice).await.unwrap();
+
+        let data: ImportData = serde_json::from_value(serde_json::json!({
+            "users": [
+                { "id": "u1", "username": "alice" },
+                { "id": "u2", "username": "bob.smith" },
+                { "id": "u3", "username": "bob_smith" }
+            ],
+            "conversations": [
+                {
+                    "id": "c1",
+                    "participants": ["u1", "u2"],
+                    "messages": [
+                        { "id": "m2", "sender": "u2", "content": "hello", "created_at": 2000 },
+                        { "id": "m1", "sender": "u1", "content": "hi", "created_at": 1000 }
+                    ]
+                },
+                { "id": "c2", "participants": ["u1", "u2", "u3"], "messages": [] }
+            ]
+        }))
+        .unwrap();
+
+        let mut first = ImportSummary::default();
+        Importer::new(pool.clone())
+            .import(&data, &mut first)
+            .await
+            .unwrap();
+        assert_eq!((first.users_mapped, first.users_created), (1, 1));
+        assert_eq!(first.conversations_created, 1);
+        assert_eq!(first.messages_imported, 2);
+        assert_eq!(first.conflicts.len(), 1);
+        assert_eq!(first.conflicts[0].id, "u3");
+        assert_eq!(first.skipped.len(), 1);
+
+        let bob = queries::find_user_by_username(&pool, "bob_smith")
+            .await
+            .unwrap()
+            .unwrap();
+        let (user1, user2) = if alice.id < bob.id {
+            (&alice.id, &bob.id)
+        } else {
+            (&bob.id, &alice.id)
+        };
+        let conversation = queries::get_conversation_by_users(&pool, user1, user2)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(conversation.created_at, 1000);
+        let messages = queries::get_messages_by_conversation(&pool, &conversation.id, 10, 0)
+            .await
+            .unwrap();
+        assert_eq!(messages[0].id, "m2");
+        assert_eq!(messages[0].created_at, 2000);
+        assert_eq!(messages[0].status, "read");
+
+        // Running again finds everything in place
+        let mut second = ImportSummary::default();
+        Importer::new(pool.clone())
+            .import(&data, &mut second)
+            .await
+            .unwrap();
+        assert_eq!((second.users_mapped, second.users_created), (2, 0));
+        assert_eq!(second.conversations_existing, 1);
+        assert_eq!(second.messages_imported, 0);
+        assert_eq!(second.messages_already_imported, 2);
+    }
+
+    #[tokio::test]
+    async fn test_slack_export_direct_messages() {
+        let dir = std::env::temp_dir().join(format!("slack-export-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("D1")).unwrap();
+        let write = |name: &str, value: serde_json::Value| {
+            std::fs::write(dir.join(name), value.to_string()).unwrap();
+        };
+        write(
+            "users.json",
+            serde_json::json!([{ "id": "U1", "name": "ann" }, { "id": "U2", "name": "ben" }]),
+        );
+        write(
+            "channels.json",
+            serde_json::json!([{ "id": "C1", "name": "general", "members": ["U1", "U2"] }]),
+        );
+        write(
+            "dms.json",
+            serde_json::json!([{ "id": "D1", "created": 1600000000, "members": ["U1", "U2"] }]),
+        );
+        write(
+            "D1/2020-09-13.json",
+            serde_json::json!([
+                { "type": "message", "user": "U1", "text": "hey <@U2> see <https://example.com|this> &amp; that", "ts": "1600000001.000200" },
+                { "type": "message", "subtype": "bot_message", "text": "beep", "ts": "1600000002.000000" }
+            ]),
+        );
+
+        let (data, skipped) = slack::load(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(data.users.len(), 2);
+        assert_eq!(data.conversations.len(), 1);
+        let message = &data.conversations[0].messages[0];
+        assert_eq!(message.id, "slack-D1-1600000001.000200");
+        assert_eq!(message.created_at, 1_600_000_001_000);
+        assert_eq!(
+            message.content,
+            "hey @ben see this (https://example.com) & that"
+        );
+        let reasons: Vec<_> = skipped.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(reasons, vec!["C1", "slack-D1-1600000002.000000"]);
+    }
+}
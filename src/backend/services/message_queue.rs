@@ -5,16 +5,15 @@
 //! Retries indefinitely until recipient comes online or is deleted.
 
 use crate::db::queries;
+use crate::handlers::outbound::SendPriority;
 use crate::handlers::websocket::ConnectionManager;
 use crate::services::message_service::{MessageService, MessageStatus};
-use chat_shared::protocol::MessageEnvelope;
-use serde_json::json;
+use chat_shared::protocol::{AckData, ServerFrame};
 use sqlx::SqlitePool;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tokio::time::{sleep, Duration};
-use warp::ws::Message as WsMessage;
 
 /// Retry schedule in seconds
 const RETRY_SCHEDULE: &[u64] = &[0, 1, 3, 7, 15, 30, 60];
@@ -32,7 +31,6 @@ struct QueuedMessage {
 #[derive(Clone)]
 pub struct MessageQueueService {
     pool: SqlitePool,
-    #[allow(dead_code)]
     message_service: MessageService,
     connection_manager: Arc<ConnectionManager>,
     /// Queue of pending messages: recipient_id -> Vec<QueuedMessage>
@@ -128,6 +126,40 @@ impl MessageQueueService {
         *running = false;
     }
 
+    /// Final delivery pass before shutdown
+    ///
+    /// Stops the worker and attempts every queued message whose recipient is
+    /// still connected, ignoring backoff. Anything undelivered stays `pending`
+    /// in the database and is picked up by `load_pending_messages` on the next
+    /// start. Returns the number of messages still queued.
+    pub async fn drain(&self) -> usize {
+        self.stop().await;
+
+        let queued: Vec<(String, Vec<QueuedMessage>)> = {
+            let mut queue = self.queue.write().await;
+            queue.drain().collect()
+        };
+
+        for (recipient_id, messages) in queued {
+            if self.connection_manager.is_user_online(&recipient_id).await {
+                Self::deliver_batch(
+                    &self.pool,
+                    &self.message_service,
+                    self.connection_manager.as_ref(),
+                    self.queue.clone(),
+                    messages,
+                )
+                .await;
+            } else {
+                let mut queue = self.queue.write().await;
+                queue.entry(recipient_id).or_default().extend(messages);
+            }
+        }
+
+        let queue = self.queue.read().await;
+        queue.values().map(Vec::len).sum()
+    }
+
     /// Queue a message for delivery
     pub async fn queue_message(&self, message_id: String, recipient_id: String) {
         let queued_msg = QueuedMessage {
@@ -174,29 +206,16 @@ impl MessageQueueService {
             .await?
             .ok_or_else(|| "Sender not found".to_string())?;
 
-        let envelope = MessageEnvelope {
-            id: message.id.clone(),
-            msg_type: "message".to_string(),
-            timestamp: chrono::Utc::now().timestamp_millis() as u64,
-            data: json!({
-                "senderId": sender.id,
-                "senderUsername": sender.username,
-                "recipientId": message.recipient_id,
-                "content": message.content,
-                "conversationId": message.conversation_id,
-                "status": "delivered",
-            }),
-        };
-
-        let outbound = WsMessage::text(
-            serde_json::to_string(&envelope)
-                .map_err(|e| format!("Failed to serialize message: {}", e))?,
-        );
+        let envelope = ServerFrame::Message(message.to_text_data(&sender.username, "delivered"))
+            .into_envelope_with_id(message.id.clone());
 
         // Attempt to send to recipient
+        if !connection_manager.is_user_online(&recipient.id).await {
+            return Err("Recipient offline".to_string());
+        }
         let delivered = connection_manager
-            .send_to_user(&recipient.id, outbound.clone())
-            .await;
+            .send_event(&recipient.id, envelope, SendPriority::Essential)
+            .await?;
         if delivered == 0 {
             return Err("Recipient offline".to_string());
         }
@@ -204,21 +223,17 @@ impl MessageQueueService {
         // Mark delivered and send ack to sender if connected
         message_service.mark_delivered(&message.id).await?;
 
-        let ack = MessageEnvelope {
-            id: uuid::Uuid::new_v4().to_string(),
-            msg_type: "ack".to_string(),
-            timestamp: chrono::Utc::now().timestamp_millis() as u64,
-            data: json!({
-                "status": "delivered",
-                "messageId": message.id,
-                "conversationId": message.conversation_id,
-                "serverTimestamp": chrono::Utc::now().timestamp_millis(),
-            }),
-        };
-        let ack_msg = WsMessage::text(
-            serde_json::to_string(&ack).map_err(|e| format!("Failed to serialize ack: {}", e))?,
-        );
-        let _ = connection_manager.send_to_user(&sender.id, ack_msg).await;
+        let ack = ServerFrame::Ack(AckData {
+            status: "delivered".to_string(),
+            conversation_id: Some(message.conversation_id.clone()),
+            message_id: Some(message.id.clone()),
+            original_message_id: None,
+            server_timestamp: Some(chrono::Utc::now().timestamp_millis() as u64),
+        })
+        .into_envelope();
+        let _ = connection_manager
+            .send_event(&sender.id, ack, SendPriority::Essential)
+            .await;
 
         Ok(())
     }
@@ -242,8 +257,12 @@ impl MessageQueueService {
             {
                 Ok(_) => {}
                 Err(reason) => {
-                    // Recipient offline handled by caller; if we hit a transient failure, requeue
-                    if reason != "Recipient deleted" && reason != "Recipient not found" {
+                    // Recipient offline handled by caller; if we hit a transient failure, requeue.
+                    // A missing message was deleted or has disappeared, so it is dropped.
+                    if !matches!(
+                        reason.as_str(),
+                        "Recipient deleted" | "Recipient not found" | "Message not found"
+                    ) {
                         Self::requeue_message(queue.clone(), queued_msg).await;
                     }
                 }
@@ -314,10 +333,7 @@ mod tests {
             .await
             .unwrap();
 
-        let schema_sql = include_str!("../../backend/db/migrations/001_initial_schema.sql");
-        for statement in schema_sql.split(';').filter(|s| !s.trim().is_empty()) {
-            sqlx::query(statement).execute(&pool).await.unwrap();
-        }
+        crate::db::run_migrations(&pool).await.unwrap();
 
         pool
     }
@@ -386,4 +402,64 @@ mod tests {
         let stats = queue_service.get_queue_stats().await;
         assert!(stats.contains_key(&user2.id));
     }
+
+    #[tokio::test]
+    async fn test_drain_delivers_to_online_and_keeps_offline() {
+        let pool = setup_test_db().await;
+        let conn_mgr = Arc::new(ConnectionManager::new());
+        let queue_service = MessageQueueService::new(pool.clone(), conn_mgr.clone());
+
+        let alice = User::new("alice".to_string(), "hash1".to_string(), "salt1".to_string());
+        let bob = User::new("bob".to_string(), "hash2".to_string(), "salt2".to_string());
+        queries::insert_user(&pool, &alice).await.unwrap();
+        queries::insert_user(&pool, &bob).await.unwrap();
+
+        let (u1, u2) = if alice.id < bob.id {
+            (alice.id.clone(), bob.id.clone())
+        } else {
+            (bob.id.clone(), alice.id.clone())
+        };
+        let conv = Conversation::new(u1, u2);
+        queries::insert_conversation(&pool, &conv).await.unwrap();
+
+        // Bob is online, Alice is not
+        let (tx, mut rx) = crate::handlers::outbound::OutboundQueue::new(&Default::default());
+        conn_mgr
+            .register(
+                crate::handlers::websocket::ClientConnection::new(bob.id.clone(), "bob".to_string()),
+                tx,
+            )
+            .await;
+
+        let to_bob = crate::models::Message::new(
+            conv.id.clone(),
+            alice.id.clone(),
+            bob.id.clone(),
+            "Hi Bob".to_string(),
+        );
+        let to_alice = crate::models::Message::new(
+            conv.id.clone(),
+            bob.id.clone(),
+            alice.id.clone(),
+            "Hi Alice".to_string(),
+        );
+        queries::insert_message(&pool, &to_bob).await.unwrap();
+        queries::insert_message(&pool, &to_alice).await.unwrap();
+        queue_service
+            .queue_message(to_bob.id.clone(), bob.id.clone())
+            .await;
+        queue_service
+            .queue_message(to_alice.id.clone(), alice.id.clone())
+            .await;
+
+        let remaining = queue_service.drain().await;
+        assert_eq!(remaining, 1);
+
+        let delivered = rx.recv().await.unwrap();
+        assert!(delivered.to_str().unwrap().contains("Hi Bob"));
+
+        let stats = queue_service.get_queue_stats().await;
+        assert_eq!(stats.get(&alice.id), Some(&1));
+        assert!(!stats.contains_key(&bob.id));
+    }
 }
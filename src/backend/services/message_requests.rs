@@ -0,0 +1,261 @@
+//! Message requests
+//!
+//! The first conversation between two users is a message request from whoever
+//! started it. Until the recipient accepts, the conversation is left out of
+//! their conversation list, the sender's messages are stored but not
+//! delivered, and neither sees the other's presence. Declining deletes the
+//! held messages and silently drops any sent afterwards.
+
+use crate::db::queries;
+use crate::models::MessageRequest;
+use crate::services::{MessageQueueService, PresenceService};
+use sqlx::SqlitePool;
+use tracing::warn;
+
+#[derive(Clone)]
+pub struct MessageRequestService {
+    pool: SqlitePool,
+    message_queue: MessageQueueService,
+    presence: PresenceService,
+}
+
+impl MessageRequestService {
+    pub fn new(
+        pool: SqlitePool,
+        message_queue: MessageQueueService,
+        presence: PresenceService,
+    ) -> Self {
+        Self {
+            pool,
+            message_queue,
+            presence,
+        }
+    }
+
+    /// Pending requests to `user_id`, newest first
+    pub async fn list(&self, user_id: &str) -> Result<Vec<MessageRequest>, String> {
+        queries::get_message_requests(&self.pool, user_id).await
+    }
+
+    /// Accept the request holding `conversation_id`, if it was sent to `user_id`
+    ///
+    /// Queues the held messages for delivery and shows each participant the
+    /// other's presence. Declined requests can be accepted too; their held
+    /// messages are already gone.
+    pub async fn accept(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+    ) -> Result<Option<MessageRequest>, String> {
+        let Some(request) = self.request_to(user_id, conversation_id).await? else {
+            return Ok(None);
+        };
+        if !queries::delete_message_request(&self.pool, conversation_id).await? {
+            // Accepted concurrently
+            return Ok(Some(request));
+        }
+
+        for message in queries::get_pending_messages(&self.pool, user_id).await? {
+            if message.conversation_id == conversation_id {
+                self.message_queue
+                    .queue_message(message.id, user_id.to_string())
+                    .await;
+            }
+        }
+
+        if !queries::is_blocked_either_way(&self.pool, user_id, &request.requester_id).await? {
+            let requester_id = request.requester_id.as_str();
+            for (user, viewer) in [(requester_id, user_id), (user_id, requester_id)] {
+                if let Err(e) = self.presence.reveal_to(user, viewer).await {
+                    warn!("Failed to share presence after accepting request: {}", e);
+                }
+            }
+        }
+
+        Ok(Some(request))
+    }
+
+    /// Decline the request holding `conversation_id`, if it was sent to `user_id`
+    ///
+    /// The sender is not told.
+    pub async fn decline(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+    ) -> Result<Option<MessageRequest>, String> {
+        let Some(request) = self.request_to(user_id, conversation_id).await? else {
+            return Ok(None);
+        };
+        queries::decline_message_request(&self.pool, conversation_id).await?;
+        Ok(Some(request))
+    }
+
+    async fn request_to(
+        &self,
+        user_id: &str,
+        conversation_id: &str,
+    ) -> Result<Option<MessageRequest>, String> {
+        Ok(queries::get_message_request(&self.pool, conversation_id)
+            .await?
+            .filter(|request| request.recipient_id == user_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::websocket::ConnectionManager;
+    use crate::models::{Conversation, User};
+    use crate::services::MessageService;
+    use std::sync::Arc;
+
+    async fn setup_test_db() -> SqlitePool {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+
+        crate::db::run_migrations(&pool).await.unwrap();
+
+        pool
+    }
+
+    /// A request from alice to bob holding one message
+    async fn setup_request(pool: &SqlitePool) -> (MessageRequestService, User, User, Conversation) {
+        let connection_manager = Arc::new(ConnectionManager::new());
+        let service = MessageRequestService::new(
+            pool.clone(),
+            MessageQueueService::new(pool.clone(), connection_manager.clone()),
+            PresenceService::new(pool.clone(), connection_manager),
+        );
+
+        let alice = User::new("alice".into(), "hash".into(), "salt".into());
+        let bob = User::new("bob".into(), "hash".into(), "salt".into());
+        queries::insert_user(pool, &alice).await.unwrap();
+        queries::insert_user(pool, &bob).await.unwrap();
+        let (u1, u2) = if alice.id < bob.id {
+            (alice.id.clone(), bob.id.clone())
+        } else {
+            (bob.id.clone(), alice.id.clone())
+        };
+        let conversation = queries::insert_conversation(pool, &Conversation::new(u1, u2))
+            .await
+            .unwrap();
+        queries::insert_message_request(pool, &conversation.id, &alice.id, &bob.id)
+            .await
+            .unwrap();
+        MessageService::new(pool.clone())
+            .send_message_with_id(
+                "held-1".into(),
+                conversation.id.clone(),
+                alice.id.clone(),
+                bob.id.clone(),
+                "hi bob".into(),
+                false,
+            )
+            .await
+            .unwrap();
+
+        (service, alice, bob, conversation)
+    }
+
+    #[tokio::test]
+    async fn test_held_messages_wait_for_acceptance() {
+        let pool = setup_test_db().await;
+        let (service, alice, bob, conversation) = setup_request(&pool).await;
+
+        assert!(queries::get_pending_messages(&pool, &bob.id)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(queries::get_user_conversations(&pool, &bob.id, 20, 0)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            queries::get_user_conversations(&pool, &alice.id, 20, 0)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let requests = service.list(&bob.id).await.unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].requester_username, "alice");
+        assert_eq!(requests[0].message_count, 1);
+        assert!(service.list(&alice.id).await.unwrap().is_empty());
+
+        // Only the recipient can accept
+        assert!(service
+            .accept(&alice.id, &conversation.id)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(service
+            .accept(&bob.id, &conversation.id)
+            .await
+            .unwrap()
+            .is_some());
+        assert!(service
+            .accept(&bob.id, &conversation.id)
+            .await
+            .unwrap()
+            .is_none());
+
+        assert_eq!(
+            queries::get_pending_messages(&pool, &bob.id)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            queries::get_user_conversations(&pool, &bob.id, 20, 0)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(service.list(&bob.id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_declining_drops_held_messages() {
+        let pool = setup_test_db().await;
+        let (service, alice, bob, conversation) = setup_request(&pool).await;
+
+        assert!(service
+            .decline(&alice.id, &conversation.id)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(service
+            .decline(&bob.id, &conversation.id)
+            .await
+            .unwrap()
+            .is_some());
+
+        assert!(queries::find_message_by_id(&pool, "held-1")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(service.list(&bob.id).await.unwrap().is_empty());
+        assert!(queries::get_user_conversations(&pool, &bob.id, 20, 0)
+            .await
+            .unwrap()
+            .is_empty());
+        let request = queries::get_message_request(&pool, &conversation.id)
+            .await
+            .unwrap();
+        assert_eq!(request.unwrap().status, "declined");
+
+        // Alice's copy of the conversation shows what was dropped
+        let gaps = queries::get_message_gaps(&pool, &conversation.id)
+            .await
+            .unwrap();
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].reason, "declined");
+        assert_eq!(gaps[0].message_count, 1);
+    }
+}
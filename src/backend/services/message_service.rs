@@ -4,6 +4,7 @@
 
 use crate::db::queries;
 use crate::models::Message;
+use chat_shared::protocol::{MAX_CONTENT_LENGTH, MAX_ENCRYPTED_CONTENT_LENGTH};
 use sqlx::SqlitePool;
 use tracing::{info, warn};
 
@@ -51,8 +52,30 @@ impl MessageService {
         recipient_id: String,
         content: String,
     ) -> Result<Message, String> {
-        // Validate content length (1-5000 characters)
-        if content.is_empty() || content.len() > 5000 {
+        self.create_message(None, conversation_id, sender_id, recipient_id, content, false)
+            .await
+    }
+
+    /// Validate and store a message, using `message_id` if given or a fresh UUID
+    ///
+    /// `encrypted` content is an opaque end-to-end encrypted envelope and may be
+    /// longer than plaintext.
+    async fn create_message(
+        &self,
+        message_id: Option<String>,
+        conversation_id: String,
+        sender_id: String,
+        recipient_id: String,
+        content: String,
+        encrypted: bool,
+    ) -> Result<Message, String> {
+        // Validate content length (1-5000 characters, more for ciphertext)
+        let max_length = if encrypted {
+            MAX_ENCRYPTED_CONTENT_LENGTH
+        } else {
+            MAX_CONTENT_LENGTH
+        };
+        if content.is_empty() || content.len() > max_length {
             warn!(
                 target: "message",
                 event = "message.send",
@@ -63,7 +86,10 @@ impl MessageService {
                 reason = "invalid_length",
                 content_length = content.len()
             );
-            return Err("Message content must be between 1 and 5000 characters".to_string());
+            return Err(format!(
+                "Message content must be between 1 and {} characters",
+                max_length
+            ));
         }
 
         // Validate UTF-8 (Rust strings are already UTF-8, but check for validity)
@@ -99,12 +125,21 @@ impl MessageService {
         }
 
         // Create message with generated UUID
-        let message = Message::new(
+        let mut message = Message::new(
             conversation_id.clone(),
             sender_id.clone(),
             recipient_id.clone(),
             content,
         );
+        if let Some(id) = message_id {
+            message.id = id;
+        }
+        message.is_encrypted = encrypted;
+        if let Some(conversation) =
+            queries::get_conversation_by_id(&self.pool, &conversation_id).await?
+        {
+            message.start_expiry(&conversation);
+        }
 
         // Insert into database
         let created_message = queries::insert_message(&self.pool, &message).await?;
@@ -132,6 +167,7 @@ impl MessageService {
         sender_id: String,
         recipient_id: String,
         content: String,
+        encrypted: bool,
     ) -> Result<(Message, bool), String> {
         // Check if message already exists (idempotency)
         if let Some(existing) = queries::find_message_by_id(&self.pool, &message_id).await? {
@@ -148,13 +184,25 @@ impl MessageService {
             return Ok((existing, false)); // Not created, already exists
         }
 
-        // Validate and create new message
-        let mut message = self
-            .send_message(conversation_id, sender_id, recipient_id, content)
-            .await?;
-
-        // Update ID to client-provided one
-        message.id = message_id;
+        // Validate and create new message under the client-provided ID
+        let message = match self
+            .create_message(
+                Some(message_id.clone()),
+                conversation_id,
+                sender_id,
+                recipient_id,
+                content,
+                encrypted,
+            )
+            .await
+        {
+            Ok(message) => message,
+            // A concurrent retry with the same ID won the insert
+            Err(e) => match queries::find_message_by_id(&self.pool, &message_id).await? {
+                Some(existing) => return Ok((existing, false)),
+                None => return Err(e),
+            },
+        };
         info!(
             target: "message",
             event = "message.send",
@@ -325,13 +373,7 @@ impl MessageService {
                 
                 match new_status.as_str() {
                     "read" => {
-                        sqlx::query("UPDATE messages SET status = ?, read_at = ? WHERE id = ?")
-                            .bind("read")
-                            .bind(now)
-                            .bind(&message_id)
-                            .execute(&self.pool)
-                            .await
-                            .map_err(|e| format!("Failed to update message: {}", e))?;
+                        queries::mark_message_read(&self.pool, &message_id).await?;
                     }
                     "delivered" => {
                         sqlx::query("UPDATE messages SET status = ?, delivered_at = ? WHERE id = ?")
@@ -377,7 +419,7 @@ impl MessageService {
     ///
     /// Returns true if content is valid, false otherwise
     pub fn validate_content(content: &str) -> bool {
-        !content.is_empty() && content.len() <= 5000 && content.chars().all(|c| c.is_valid())
+        !content.is_empty() && content.len() <= MAX_CONTENT_LENGTH && content.chars().all(|c| c.is_valid())
     }
 }
 
@@ -403,10 +445,7 @@ mod tests {
             .await
             .unwrap();
 
-        let schema_sql = include_str!("../../backend/db/migrations/001_initial_schema.sql");
-        for statement in schema_sql.split(';').filter(|s| !s.trim().is_empty()) {
-            sqlx::query(statement).execute(&pool).await.unwrap();
-        }
+        crate::db::run_migrations(&pool).await.unwrap();
 
         pool
     }
@@ -451,6 +490,45 @@ mod tests {
         assert_eq!(message.sender_id, user1.id);
     }
 
+    #[tokio::test]
+    async fn test_send_message_with_id_is_idempotent() {
+        let pool = setup_test_db().await;
+        let service = MessageService::new(pool.clone());
+
+        let user1 = User::new("alice".to_string(), "hash1".to_string(), "salt1".to_string());
+        let user2 = User::new("bob".to_string(), "hash2".to_string(), "salt2".to_string());
+        queries::insert_user(&pool, &user1).await.unwrap();
+        queries::insert_user(&pool, &user2).await.unwrap();
+        let (user1_id, user2_id) = if user1.id < user2.id {
+            (user1.id.clone(), user2.id.clone())
+        } else {
+            (user2.id.clone(), user1.id.clone())
+        };
+        let conv = crate::models::Conversation::new(user1_id, user2_id);
+        queries::insert_conversation(&pool, &conv).await.unwrap();
+
+        let send = || {
+            service.send_message_with_id(
+                "client-id-1".to_string(),
+                conv.id.clone(),
+                user1.id.clone(),
+                user2.id.clone(),
+                "Hello".to_string(),
+                false,
+            )
+        };
+        let (first, created) = send().await.unwrap();
+        assert!(created);
+        assert_eq!(first.id, "client-id-1");
+
+        // Stored under the client's ID, so a retry finds it
+        let stored = queries::find_message_by_id(&pool, "client-id-1").await.unwrap();
+        assert!(stored.is_some());
+        let (second, created) = send().await.unwrap();
+        assert!(!created);
+        assert_eq!(second.id, first.id);
+    }
+
     #[tokio::test]
     async fn test_validate_content_length() {
         assert!(MessageService::validate_content("Valid message"));
@@ -2,14 +2,24 @@
 
 pub mod auth_service;
 pub mod conversation_service;
+pub mod data_export;
+pub mod ephemeral;
+pub mod import;
 pub mod message_queue;
+pub mod message_requests;
 pub mod message_service;
 pub mod presence;
+pub mod retention;
+pub mod transcript;
 pub mod user_service;
 
 pub use auth_service::AuthService;
 pub use conversation_service::ConversationService;
+pub use data_export::DataExportService;
+pub use ephemeral::EphemeralService;
 pub use message_queue::MessageQueueService;
+pub use message_requests::MessageRequestService;
 pub use message_service::MessageService;
 pub use presence::PresenceService;
+pub use retention::RetentionService;
 pub use user_service::UserService;
@@ -1,14 +1,35 @@
 //! Presence service
 //!
 //! Tracks online/offline state and broadcasts presence updates to conversation participants.
+//! Users who have blocked each other never see each other's presence, nor do
+//! the sender and recipient of a message request until it is accepted.
+//!
+//! `ConnectionManager` is the source of truth for who is online; `users.is_online`
+//! mirrors it. Flags left behind by a crash are cleared by a startup sweep, and a
+//! periodic reconciliation repairs any drift and refreshes `last_seen_at` from
+//! socket activity.
 
 use crate::db::queries;
+use crate::handlers::outbound::SendPriority;
 use crate::handlers::websocket::ConnectionManager;
-use chat_shared::protocol::{MessageEnvelope, PresenceData};
-use serde_json::json;
+use crate::models::User;
+use chat_shared::protocol::{MessageEnvelope, PresenceData, ServerFrame};
 use sqlx::SqlitePool;
+use std::collections::HashSet;
 use std::sync::Arc;
-use warp::ws::Message as WsMessage;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Outcome of a presence consistency check
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PresenceReconciliation {
+    /// Users flagged online in the DB without a live connection
+    pub marked_offline: Vec<String>,
+    /// Users with a live connection not flagged online in the DB
+    pub marked_online: Vec<String>,
+    /// Users whose correction failed; retried on the next pass
+    pub failed: Vec<String>,
+}
 
 #[derive(Clone)]
 pub struct PresenceService {
@@ -32,10 +53,137 @@ impl PresenceService {
 
     /// Mark user offline and broadcast to participants.
     pub async fn mark_offline(&self, user_id: &str) -> Result<(), String> {
-        queries::update_online_status(&self.pool, user_id, false).await?;
+        self.mark_offline_at(user_id, chrono::Utc::now().timestamp_millis())
+            .await
+    }
+
+    /// Mark user offline, recording their last socket activity as `last_seen_at`.
+    pub async fn mark_offline_at(&self, user_id: &str, last_seen_at: i64) -> Result<(), String> {
+        queries::update_presence(&self.pool, user_id, false, Some(last_seen_at)).await?;
         self.broadcast_presence(user_id, false).await
     }
 
+    /// Clear online flags left over from a previous run
+    ///
+    /// Must run before the server accepts connections: at that point nobody can
+    /// be online, so every flag still set is stale. Returns the number cleared.
+    pub async fn reset_stale_online(&self) -> Result<u64, String> {
+        queries::mark_all_users_offline(&self.pool).await
+    }
+
+    /// Bring `users.is_online` in line with the live connections
+    ///
+    /// Also refreshes `last_seen_at` for connected users from their most recent
+    /// inbound frame. Corrections are broadcast to conversation partners. A
+    /// failure for one user is logged and counted without stopping the pass.
+    pub async fn reconcile(&self) -> Result<PresenceReconciliation, String> {
+        let activity = self.connection_manager.online_activity().await;
+        let flagged: HashSet<String> = queries::get_online_user_ids(&self.pool)
+            .await?
+            .into_iter()
+            .collect();
+
+        let mut report = PresenceReconciliation::default();
+
+        for user_id in flagged.iter().filter(|id| !activity.contains_key(*id)) {
+            // Re-check: the user may have connected since the snapshot
+            if self.connection_manager.is_user_online(user_id).await {
+                continue;
+            }
+            match self.correct(user_id, false, None).await {
+                Ok(()) => report.marked_offline.push(user_id.clone()),
+                Err(e) => {
+                    warn!("Failed to mark {} offline: {}", user_id, e);
+                    report.failed.push(user_id.clone());
+                }
+            }
+        }
+
+        for (user_id, last_activity) in &activity {
+            let result = if flagged.contains(user_id) {
+                queries::update_last_seen_at(&self.pool, user_id, *last_activity).await
+            } else {
+                self.correct(user_id, true, Some(*last_activity))
+                    .await
+                    .map(|()| report.marked_online.push(user_id.clone()))
+            };
+            if let Err(e) = result {
+                warn!("Failed to reconcile presence of {}: {}", user_id, e);
+                report.failed.push(user_id.clone());
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Store a corrected online flag and tell conversation partners
+    async fn correct(
+        &self,
+        user_id: &str,
+        is_online: bool,
+        last_seen_at: Option<i64>,
+    ) -> Result<(), String> {
+        queries::update_presence(&self.pool, user_id, is_online, last_seen_at).await?;
+        self.broadcast_presence(user_id, is_online).await
+    }
+
+    /// Run `reconcile` every `interval` in the background
+    pub fn start_reconciler(&self, interval: Duration) {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await; // first tick fires immediately
+            loop {
+                ticker.tick().await;
+                match service.reconcile().await {
+                    Ok(report)
+                        if !report.marked_offline.is_empty()
+                            || !report.marked_online.is_empty()
+                            || !report.failed.is_empty() =>
+                    {
+                        info!(
+                            "Presence reconciled: {} marked offline, {} marked online, {} failed",
+                            report.marked_offline.len(),
+                            report.marked_online.len(),
+                            report.failed.len()
+                        );
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("Presence reconciliation failed: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Show `viewer_id` that `user_id` is offline, as it will look from now on
+    ///
+    /// Called when either blocks the other.
+    pub async fn hide_from(&self, user_id: &str, viewer_id: &str) -> Result<(), String> {
+        self.send_presence(user_id, viewer_id, Some(false)).await
+    }
+
+    /// Show `viewer_id` whether `user_id` is online, after a block is lifted
+    pub async fn reveal_to(&self, user_id: &str, viewer_id: &str) -> Result<(), String> {
+        self.send_presence(user_id, viewer_id, None).await
+    }
+
+    /// Send one user's presence to another; `None` sends their actual state
+    async fn send_presence(
+        &self,
+        user_id: &str,
+        viewer_id: &str,
+        is_online: Option<bool>,
+    ) -> Result<(), String> {
+        let Some(user) = queries::find_user_by_id(&self.pool, user_id).await? else {
+            return Ok(());
+        };
+        let is_online = is_online.unwrap_or(user.is_online);
+        self.connection_manager
+            .send_event(viewer_id, presence_frame(&user, is_online), SendPriority::Droppable)
+            .await?;
+        Ok(())
+    }
+
     /// Broadcast presence update to all users that share a conversation with this user.
     async fn broadcast_presence(&self, user_id: &str, is_online: bool) -> Result<(), String> {
         let user = match queries::find_user_by_id(&self.pool, user_id).await? {
@@ -44,6 +192,11 @@ impl PresenceService {
         };
 
         let conversations = queries::get_user_conversations(&self.pool, user_id, 200, 0).await?;
+        let mut hidden: HashSet<String> = queries::get_block_partner_ids(&self.pool, user_id)
+            .await?
+            .into_iter()
+            .collect();
+        hidden.extend(queries::get_message_request_partner_ids(&self.pool, user_id).await?);
 
         // Collect participant ids (the other user in each conversation)
         let mut recipients = Vec::new();
@@ -53,37 +206,157 @@ impl PresenceService {
             } else {
                 conv.user1_id
             };
-            recipients.push(partner);
+            if !hidden.contains(&partner) {
+                recipients.push(partner);
+            }
         }
 
         if recipients.is_empty() {
             return Ok(());
         }
 
-        let envelope = MessageEnvelope {
-            id: uuid::Uuid::new_v4().to_string(),
-            msg_type: "presence".to_string(),
-            timestamp: chrono::Utc::now().timestamp_millis() as u64,
-            data: json!(PresenceData {
-                user_id: user.id.clone(),
-                username: user.username.clone(),
-                is_online,
-                last_seen_at: user
-                    .last_seen_at
-                    .unwrap_or_else(|| chrono::Utc::now().timestamp_millis())
-                    as u64,
-            }),
-        };
+        self.connection_manager
+            .broadcast_event(
+                recipients,
+                presence_frame(&user, is_online),
+                SendPriority::Droppable,
+            )
+            .await
+    }
+}
+
+fn presence_frame(user: &User, is_online: bool) -> MessageEnvelope {
+    ServerFrame::Presence(PresenceData {
+        user_id: user.id.clone(),
+        username: user.username.clone(),
+        is_online,
+        last_seen_at: user
+            .last_seen_at
+            .unwrap_or_else(|| chrono::Utc::now().timestamp_millis()) as u64,
+    })
+    .into_envelope()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::websocket::ClientConnection;
+    use crate::models::User;
+    use crate::handlers::outbound::{OutboundQueue, OutboundQueueConfig};
+
+    async fn setup_test_db() -> SqlitePool {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+
+        crate::db::run_migrations(&pool).await.unwrap();
+
+        pool
+    }
+
+    async fn insert_user(pool: &SqlitePool, username: &str, online: bool) -> User {
+        let user = User::new(username.to_string(), "hash".to_string(), "salt".to_string());
+        queries::insert_user(pool, &user).await.unwrap();
+        if online {
+            queries::update_presence(pool, &user.id, true, Some(1_000)).await.unwrap();
+        }
+        user
+    }
+
+    #[tokio::test]
+    async fn test_reset_stale_online() {
+        let pool = setup_test_db().await;
+        let service = PresenceService::new(pool.clone(), Arc::new(ConnectionManager::new()));
+        insert_user(&pool, "alice", true).await;
+        insert_user(&pool, "bob", true).await;
+
+        assert_eq!(service.reset_stale_online().await.unwrap(), 2);
+        assert!(queries::get_online_user_ids(&pool).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_fixes_drift_both_ways() {
+        let pool = setup_test_db().await;
+        let manager = Arc::new(ConnectionManager::new());
+        let service = PresenceService::new(pool.clone(), manager.clone());
 
-        let message = WsMessage::text(
-            serde_json::to_string(&envelope)
-                .map_err(|e| format!("Failed to serialize presence: {}", e))?,
+        // Stale: flagged online, no connection
+        let stale = insert_user(&pool, "alice", true).await;
+        // Missing: connected, not flagged
+        let missing = insert_user(&pool, "bob", false).await;
+        // Consistent: connected and flagged
+        let steady = insert_user(&pool, "carol", true).await;
+
+        for user in [&missing, &steady] {
+            let (tx, _rx) = OutboundQueue::new(&OutboundQueueConfig::default());
+            manager
+                .register(ClientConnection::new(user.id.clone(), user.username.clone()), tx)
+                .await;
+        }
+
+        let report = service.reconcile().await.unwrap();
+        assert_eq!(report.marked_offline, vec![stale.id.clone()]);
+        assert_eq!(report.marked_online, vec![missing.id.clone()]);
+
+        let mut online = queries::get_online_user_ids(&pool).await.unwrap();
+        online.sort();
+        let mut expected = vec![missing.id.clone(), steady.id.clone()];
+        expected.sort();
+        assert_eq!(online, expected);
+
+        // last_seen_at comes from socket activity, not the stale seed value
+        let steady_row = queries::find_user_by_id(&pool, &steady.id).await.unwrap().unwrap();
+        assert_eq!(
+            steady_row.last_seen_at,
+            manager.last_activity(&steady.id).await
         );
+        let stale_row = queries::find_user_by_id(&pool, &stale.id).await.unwrap().unwrap();
+        assert_eq!(stale_row.last_seen_at, Some(1_000));
 
-        self.connection_manager
-            .broadcast_to_users(recipients, message)
-            .await;
+        // Second pass is a no-op
+        assert_eq!(
+            service.reconcile().await.unwrap(),
+            PresenceReconciliation::default()
+        );
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn test_reconcile_continues_past_a_failed_user() {
+        let pool = setup_test_db().await;
+        let service = PresenceService::new(pool.clone(), Arc::new(ConnectionManager::new()));
+        let broken = insert_user(&pool, "alice", true).await;
+        let stale = insert_user(&pool, "bob", true).await;
+
+        // Any update to alice's row fails
+        sqlx::query(&format!(
+            "CREATE TRIGGER fail_alice BEFORE UPDATE ON users WHEN NEW.id = '{}' \
+             BEGIN SELECT RAISE(ABORT, 'disk on fire'); END",
+            broken.id
+        ))
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let report = service.reconcile().await.unwrap();
+        assert_eq!(report.failed, vec![broken.id.clone()]);
+        assert_eq!(report.marked_offline, vec![stale.id.clone()]);
+        assert_eq!(
+            queries::get_online_user_ids(&pool).await.unwrap(),
+            vec![broken.id.clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mark_offline_at_records_activity_time() {
+        let pool = setup_test_db().await;
+        let service = PresenceService::new(pool.clone(), Arc::new(ConnectionManager::new()));
+        let user = insert_user(&pool, "alice", true).await;
+
+        service.mark_offline_at(&user.id, 42_000).await.unwrap();
+
+        let row = queries::find_user_by_id(&pool, &user.id).await.unwrap().unwrap();
+        assert!(!row.is_online);
+        assert_eq!(row.last_seen_at, Some(42_000));
     }
 }
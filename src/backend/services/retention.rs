@@ -0,0 +1,538 @@
+//! Data retention
+//!
+//! Old rows are purged by a background scheduler according to
+//! `RetentionConfig`: messages past a maximum age, auth logs past a maximum age
+//! and users soft-deleted longer ago than a grace period. A purged user's
+//! conversations with active users are kept for them, anonymized, and their
+//! export archives are deleted. Each rule deletes in batches of `batch_size`
+//! rows with a short pause in between, so no single write transaction holds
+//! the SQLite lock for long.
+//!
+//! `admin_cli retention dry-run` reports what the next run would delete, and
+//! `/status` shows how many rows have been purged since the server started.
+
+use crate::db::queries;
+use crate::services::DataExportService;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+/// Pause between batches, letting other writers take the database lock
+const BATCH_PAUSE: Duration = Duration::from_millis(50);
+
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// Maximum ages per kind of data; `None` keeps it forever
+#[derive(Debug, Clone)]
+pub struct RetentionConfig {
+    /// Delete messages older than this many days
+    pub messages_days: Option<u64>,
+    /// Delete auth log entries older than this many days
+    pub auth_logs_days: Option<u64>,
+    /// Purge users this many days after they were soft-deleted
+    pub deleted_users_days: Option<u64>,
+    /// Rows deleted per transaction
+    pub batch_size: u32,
+    /// How often the rules are applied
+    pub interval: Duration,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            messages_days: None,
+            auth_logs_days: Some(90),
+            deleted_users_days: Some(30),
+            batch_size: 500,
+            interval: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// What a retention rule removes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionRule {
+    Messages,
+    AuthLogs,
+    DeletedUsers,
+}
+
+impl RetentionRule {
+    pub const ALL: [RetentionRule; 3] = [
+        RetentionRule::Messages,
+        RetentionRule::AuthLogs,
+        RetentionRule::DeletedUsers,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RetentionRule::Messages => "messages",
+            RetentionRule::AuthLogs => "auth_logs",
+            RetentionRule::DeletedUsers => "deleted_users",
+        }
+    }
+
+    /// The configured maximum age in days, if the rule is enabled
+    fn max_age_days(&self, config: &RetentionConfig) -> Option<u64> {
+        match self {
+            RetentionRule::Messages => config.messages_days,
+            RetentionRule::AuthLogs => config.auth_logs_days,
+            RetentionRule::DeletedUsers => config.deleted_users_days,
+        }
+    }
+}
+
+/// Rows a rule would delete if it ran now
+#[derive(Debug, Clone, Serialize)]
+pub struct RetentionPreview {
+    pub rule: RetentionRule,
+    pub older_than_days: u64,
+    /// Rows created (or, for users, soft-deleted) before this epoch millisecond go
+    pub cutoff: i64,
+    pub rows: i64,
+}
+
+/// Rows purged since the server started, per rule
+#[derive(Debug, Default)]
+pub struct RetentionMetrics {
+    messages: AtomicU64,
+    auth_logs: AtomicU64,
+    deleted_users: AtomicU64,
+}
+
+/// Snapshot of `RetentionMetrics` for `/status`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RetentionStats {
+    pub messages_purged: u64,
+    pub auth_logs_purged: u64,
+    pub users_purged: u64,
+    pub rows_purged: u64,
+}
+
+impl RetentionMetrics {
+    fn record(&self, rule: RetentionRule, rows: u64) {
+        let counter = match rule {
+            RetentionRule::Messages => &self.messages,
+            RetentionRule::AuthLogs => &self.auth_logs,
+            RetentionRule::DeletedUsers => &self.deleted_users,
+        };
+        counter.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> RetentionStats {
+        let messages_purged = self.messages.load(Ordering::Relaxed);
+        let auth_logs_purged = self.auth_logs.load(Ordering::Relaxed);
+        let users_purged = self.deleted_users.load(Ordering::Relaxed);
+        RetentionStats {
+            messages_purged,
+            auth_logs_purged,
+            users_purged,
+            rows_purged: messages_purged + auth_logs_purged + users_purged,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RetentionService {
+    pool: SqlitePool,
+    config: RetentionConfig,
+    metrics: Arc<RetentionMetrics>,
+    /// Deletes the export archives of purged users
+    data_exports: DataExportService,
+}
+
+impl RetentionService {
+    pub fn new(pool: SqlitePool, config: RetentionConfig, data_exports: DataExportService) -> Self {
+        Self {
+            pool,
+            config,
+            metrics: Arc::new(RetentionMetrics::default()),
+            data_exports,
+        }
+    }
+
+    pub fn metrics(&self) -> &RetentionMetrics {
+        &self.metrics
+    }
+
+    /// Enabled rules with their cutoff as of now
+    fn rules(&self) -> Vec<(RetentionRule, u64, i64)> {
+        let now = chrono::Utc::now().timestamp_millis();
+        RetentionRule::ALL
+            .into_iter()
+            .filter_map(|rule| {
+                let days = rule.max_age_days(&self.config)?;
+                let age = i64::try_from(days)
+                    .unwrap_or(i64::MAX)
+                    .saturating_mul(MILLIS_PER_DAY);
+                Some((rule, days, now.saturating_sub(age)))
+            })
+            .collect()
+    }
+
+    /// Count what each enabled rule would delete, without deleting anything
+    pub async fn preview(&self) -> Result<Vec<RetentionPreview>, String> {
+        let mut previews = Vec::new();
+        for (rule, older_than_days, cutoff) in self.rules() {
+            let rows = match rule {
+                RetentionRule::Messages => {
+                    queries::count_messages_before(&self.pool, cutoff).await?
+                }
+                RetentionRule::AuthLogs => {
+                    queries::count_auth_logs_before(&self.pool, cutoff).await?
+                }
+                RetentionRule::DeletedUsers => {
+                    queries::count_users_deleted_before(&self.pool, cutoff).await?
+                }
+            };
+            previews.push(RetentionPreview {
+                rule,
+                older_than_days,
+                cutoff,
+                rows,
+            });
+        }
+        Ok(previews)
+    }
+
+    /// Apply every enabled rule until nothing past its cutoff is left
+    ///
+    /// Returns the rows deleted per rule.
+    pub async fn run_once(&self) -> Result<Vec<(RetentionRule, u64)>, String> {
+        let batch_size = self.config.batch_size.max(1);
+        let mut purged = Vec::new();
+        for (rule, _, cutoff) in self.rules() {
+            let mut total = 0;
+            loop {
+                let deleted = match rule {
+                    RetentionRule::Messages => {
+                        queries::purge_messages_before(&self.pool, cutoff, batch_size).await?
+                    }
+                    RetentionRule::AuthLogs => {
+                        queries::purge_auth_logs_before(&self.pool, cutoff, batch_size).await?
+                    }
+                    RetentionRule::DeletedUsers => {
+                        let (users, export_ids) =
+                            queries::purge_users_deleted_before(&self.pool, cutoff, batch_size)
+                                .await?;
+                        self.data_exports.remove_archive_files(&export_ids).await;
+                        users
+                    }
+                };
+                self.metrics.record(rule, deleted);
+                total += deleted;
+                if deleted < u64::from(batch_size) {
+                    break;
+                }
+                tokio::time::sleep(BATCH_PAUSE).await;
+            }
+            purged.push((rule, total));
+        }
+        Ok(purged)
+    }
+
+    /// Run `run_once` every `interval` in the background
+    ///
+    /// Does nothing when every rule is disabled.
+    pub fn start_scheduler(&self) {
+        if self.rules().is_empty() {
+            info!("Data retention disabled");
+            return;
+        }
+
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(service.config.interval);
+            loop {
+                ticker.tick().await;
+                match service.run_once().await {
+                    Ok(purged) => {
+                        for (rule, rows) in purged.into_iter().filter(|(_, rows)| *rows > 0) {
+                            info!(
+                                target: "retention",
+                                event = "retention.purge",
+                                rule = rule.as_str(),
+                                rows,
+                                "Purged rows past retention"
+                            );
+                        }
+                    }
+                    Err(e) => warn!("Retention run failed: {}", e),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::queries::AuthEventType;
+    use crate::models::{Conversation, DataExport, Message, User};
+    use crate::services::data_export::DataExportConfig;
+    use std::path::{Path, PathBuf};
+
+    async fn setup_test_db() -> SqlitePool {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+
+        crate::db::run_migrations(&pool).await.unwrap();
+
+        pool
+    }
+
+    async fn insert_pair(
+        pool: &SqlitePool,
+        first: &str,
+        second: &str,
+    ) -> (User, User, Conversation) {
+        let a = User::new(first.to_string(), "hash".to_string(), "salt".to_string());
+        let b = User::new(second.to_string(), "hash".to_string(), "salt".to_string());
+        queries::insert_user(pool, &a).await.unwrap();
+        queries::insert_user(pool, &b).await.unwrap();
+        let (user1, user2) = if a.id < b.id {
+            (a.id.clone(), b.id.clone())
+        } else {
+            (b.id.clone(), a.id.clone())
+        };
+        let conversation = queries::insert_conversation(pool, &Conversation::new(user1, user2))
+            .await
+            .unwrap();
+        (a, b, conversation)
+    }
+
+    fn export_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("chat-exports-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn exports(pool: &SqlitePool, dir: &Path) -> DataExportService {
+        DataExportService::new(
+            pool.clone(),
+            DataExportConfig {
+                dir: dir.to_path_buf(),
+                ..DataExportConfig::default()
+            },
+            b"secret",
+        )
+    }
+
+    fn days_ago(days: i64) -> i64 {
+        chrono::Utc::now().timestamp_millis() - days * MILLIS_PER_DAY
+    }
+
+    fn config(batch_size: u32) -> RetentionConfig {
+        RetentionConfig {
+            messages_days: Some(30),
+            batch_size,
+            ..RetentionConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_purges_old_messages_and_auth_logs_in_batches() {
+        let pool = setup_test_db().await;
+        let (alice, bob, conversation) = insert_pair(&pool, "alice", "bob").await;
+        for (i, age) in [40, 35, 31, 10].into_iter().enumerate() {
+            let mut message = Message::new(
+                conversation.id.clone(),
+                alice.id.clone(),
+                bob.id.clone(),
+                format!("message {}", i),
+            );
+            message.created_at = days_ago(age);
+            queries::insert_message(&pool, &message).await.unwrap();
+        }
+        for _ in 0..2 {
+            queries::insert_auth_log(
+                &pool,
+                "127.0.0.1",
+                Some("alice"),
+                AuthEventType::LoginSuccess,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        }
+        sqlx::query("UPDATE auth_logs SET created_at = ? WHERE rowid = 1")
+            .bind(days_ago(91))
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let service = RetentionService::new(pool.clone(), config(2), exports(&pool, &export_dir()));
+        let preview = service.preview().await.unwrap();
+        let rows: Vec<_> = preview.iter().map(|p| (p.rule, p.rows)).collect();
+        assert_eq!(
+            rows,
+            vec![
+                (RetentionRule::Messages, 3),
+                (RetentionRule::AuthLogs, 1),
+                (RetentionRule::DeletedUsers, 0),
+            ]
+        );
+
+        // The preview deleted nothing
+        let messages: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM messages")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(messages, 4);
+
+        let purged = service.run_once().await.unwrap();
+        assert_eq!(
+            purged,
+            vec![
+                (RetentionRule::Messages, 3),
+                (RetentionRule::AuthLogs, 1),
+                (RetentionRule::DeletedUsers, 0),
+            ]
+        );
+        let remaining = queries::get_messages_by_conversation(&pool, &conversation.id, 50, 0)
+            .await
+            .unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].content, "message 3");
+        let conversation = queries::get_conversation_by_id(&pool, &conversation.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(conversation.message_count, 1);
+        assert_eq!(conversation.last_message_at, Some(remaining[0].created_at));
+
+        let stats = service.metrics().snapshot();
+        assert_eq!(stats.messages_purged, 3);
+        assert_eq!(stats.auth_logs_purged, 1);
+        assert_eq!(stats.rows_purged, 4);
+        assert!(service.preview().await.unwrap().iter().all(|p| p.rows == 0));
+    }
+
+    #[tokio::test]
+    async fn test_purges_users_after_grace_period() {
+        let pool = setup_test_db().await;
+        let (alice, bob, conversation) = insert_pair(&pool, "alice", "bob").await;
+        let (carol, dave, kept) = insert_pair(&pool, "carol", "dave").await;
+        let erin = User::new("erin".to_string(), "hash".to_string(), "salt".to_string());
+        queries::insert_user(&pool, &erin).await.unwrap();
+        let (user1, user2) = if alice.id < erin.id {
+            (alice.id.clone(), erin.id.clone())
+        } else {
+            (erin.id.clone(), alice.id.clone())
+        };
+        let abandoned = queries::insert_conversation(&pool, &Conversation::new(user1, user2))
+            .await
+            .unwrap();
+        let message = Message::new(
+            conversation.id.clone(),
+            alice.id.clone(),
+            bob.id.clone(),
+            "hello".to_string(),
+        );
+        queries::insert_message(&pool, &message).await.unwrap();
+
+        for user in [&alice, &carol, &erin] {
+            queries::soft_delete_user(&pool, &user.id).await.unwrap();
+        }
+        sqlx::query("UPDATE users SET deleted_at = ? WHERE id = ?")
+            .bind(days_ago(31))
+            .bind(&alice.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // An archive left from before alice deleted her account
+        let dir = export_dir();
+        let export = DataExport::new(alice.id.clone());
+        queries::insert_data_export(&pool, &export).await.unwrap();
+        let archive = dir.join(format!("{}.tar.gz", export.id));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&archive, b"archive").unwrap();
+
+        let service = RetentionService::new(
+            pool.clone(),
+            RetentionConfig::default(),
+            exports(&pool, &dir),
+        );
+        let purged = service.run_once().await.unwrap();
+        assert!(purged.contains(&(RetentionRule::DeletedUsers, 1)));
+        assert!(!archive.exists());
+        assert!(queries::get_data_export(&pool, &export.id)
+            .await
+            .unwrap()
+            .is_none());
+
+        // Bob keeps the conversation, with alice's side anonymized and her
+        // row reduced to a tombstone
+        let tombstone = queries::find_user_by_id(&pool, &alice.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(tombstone.is_deleted());
+        assert_eq!(tombstone.username, format!("deleted-{}", alice.id));
+        assert_eq!(tombstone.password_hash, "!");
+        let found = queries::find_message_by_id(&pool, &message.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(found.is_anonymized);
+        assert!(queries::get_conversation_by_id(&pool, &conversation.id)
+            .await
+            .unwrap()
+            .is_some());
+
+        // Nobody active was left in the conversation with erin
+        assert!(queries::get_conversation_by_id(&pool, &abandoned.id)
+            .await
+            .unwrap()
+            .is_none());
+
+        // Inside the grace period, and never deleted, users stay
+        for user in [&bob, &carol, &dave, &erin] {
+            let found = queries::find_user_by_id(&pool, &user.id)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(found.username, user.username);
+        }
+        assert!(queries::get_conversation_by_id(&pool, &kept.id)
+            .await
+            .unwrap()
+            .is_some());
+
+        // A tombstone is not purged again
+        assert!(service.preview().await.unwrap().iter().all(|p| p.rows == 0));
+        assert!(service
+            .run_once()
+            .await
+            .unwrap()
+            .contains(&(RetentionRule::DeletedUsers, 0)));
+        assert_eq!(service.metrics().snapshot().users_purged, 1);
+
+        // Once bob goes too, the conversation and the tombstone go with him
+        queries::soft_delete_user(&pool, &bob.id).await.unwrap();
+        sqlx::query("UPDATE users SET deleted_at = ? WHERE id = ?")
+            .bind(days_ago(31))
+            .bind(&bob.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        service.run_once().await.unwrap();
+        for user in [&alice, &bob] {
+            assert!(queries::find_user_by_id(&pool, &user.id)
+                .await
+                .unwrap()
+                .is_none());
+        }
+        assert!(queries::find_message_by_id(&pool, &message.id)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
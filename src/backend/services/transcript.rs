@@ -0,0 +1,753 @@
+//! Conversation transcripts
+//!
+//! A transcript is a whole conversation in chronological order with each
+//! sender's name resolved, ready to be written out for people rather than
+//! clients. Senders who deleted their account are shown as `DELETED_USER`
+//! and lose their ID, as the apps do.
+//!
+//! Messages are never edited, but they can be removed: disappearing messages
+//! expire, retention purges old ones and declining a message request drops
+//! what it held. Each removal is listed in `gaps`, and Markdown and HTML show
+//! a marker such as "3 messages expired" where the messages were.
+//!
+//! Transcripts are written as JSON, Markdown or HTML, either at once with
+//! [`ExportFormat::render`] or a page of messages at a time with
+//! [`stream_transcript`] so a long conversation is never held in memory.
+
+use crate::db::queries;
+use crate::models::{Conversation, Message, MessageGap, User};
+use chrono::{TimeZone, Utc};
+use futures::Stream;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Name shown in place of an anonymized sender
+pub const DELETED_USER: &str = "Deleted user";
+
+/// Messages fetched per query while loading a transcript
+const PAGE_SIZE: u32 = 500;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Transcript {
+    pub conversation_id: String,
+    pub created_at: i64,
+    pub participants: Vec<Participant>,
+    /// Messages removed from the conversation, oldest first
+    pub gaps: Vec<MessageGap>,
+    pub messages: Vec<TranscriptMessage>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Participant {
+    /// `None` once the account is deleted
+    pub user_id: Option<String>,
+    pub username: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TranscriptMessage {
+    pub id: String,
+    pub sender_id: Option<String>,
+    pub sender_username: String,
+    /// End-to-end encrypted messages keep their envelope; only clients can open it
+    pub content: String,
+    pub created_at: i64,
+    pub delivered_at: Option<i64>,
+    pub read_at: Option<i64>,
+    pub status: String,
+    pub encrypted: bool,
+    pub system: bool,
+}
+
+/// How a transcript is written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    Markdown,
+    Html,
+}
+
+impl Participant {
+    fn from_user(user: Option<&User>) -> Self {
+        match user {
+            Some(user) if user.is_active() => Self {
+                user_id: Some(user.id.clone()),
+                username: user.username.clone(),
+            },
+            _ => Self {
+                user_id: None,
+                username: DELETED_USER.to_string(),
+            },
+        }
+    }
+}
+
+/// Both participants of a conversation, keyed by user ID
+async fn load_participants(
+    pool: &SqlitePool,
+    conversation: &Conversation,
+) -> Result<HashMap<String, Participant>, String> {
+    let mut users = HashMap::new();
+    for user_id in [&conversation.user1_id, &conversation.user2_id] {
+        let user = queries::find_user_by_id(pool, user_id).await?;
+        users.insert(user_id.clone(), Participant::from_user(user.as_ref()));
+    }
+    Ok(users)
+}
+
+/// A transcript without its messages, and its participants keyed by user ID
+async fn load_header(
+    pool: &SqlitePool,
+    conversation: &Conversation,
+) -> Result<(Transcript, HashMap<String, Participant>), String> {
+    let users = load_participants(pool, conversation).await?;
+    let transcript = Transcript {
+        conversation_id: conversation.id.clone(),
+        created_at: conversation.created_at,
+        participants: [&conversation.user1_id, &conversation.user2_id]
+            .into_iter()
+            .filter_map(|id| users.get(id).cloned())
+            .collect(),
+        gaps: queries::get_message_gaps(pool, &conversation.id).await?,
+        messages: Vec::new(),
+    };
+    Ok((transcript, users))
+}
+
+/// Load a conversation's messages, oldest first, with senders resolved
+///
+/// Expired disappearing messages are left out like in every other read.
+pub async fn load_transcript(
+    pool: &SqlitePool,
+    conversation: &Conversation,
+) -> Result<Transcript, String> {
+    let (mut transcript, users) = load_header(pool, conversation).await?;
+
+    let mut after: Option<(i64, String)> = None;
+    loop {
+        let page = queries::get_messages_after(
+            pool,
+            &conversation.id,
+            after.as_ref().map(|(at, id)| (*at, id.as_str())),
+            PAGE_SIZE,
+        )
+        .await?;
+        let len = page.len() as u32;
+        if let Some(last) = page.last() {
+            after = Some((last.created_at, last.id.clone()));
+        }
+        transcript.messages.extend(
+            page.into_iter()
+                .map(|message| transcript_message(message, &users)),
+        );
+        if len < PAGE_SIZE {
+            break;
+        }
+    }
+
+    Ok(transcript)
+}
+
+/// Write a conversation in `format` a page of messages at a time
+///
+/// Yields the same text as rendering [`load_transcript`]'s result, in chunks.
+/// A database error ends the stream after yielding it.
+pub fn stream_transcript(
+    pool: SqlitePool,
+    conversation: Conversation,
+    format: ExportFormat,
+    title: String,
+) -> impl Stream<Item = Result<String, String>> + Send + 'static {
+    struct State {
+        pool: SqlitePool,
+        conversation: Conversation,
+        format: ExportFormat,
+        title: String,
+        /// Set once the header is written
+        users: Option<HashMap<String, Participant>>,
+        gaps: Vec<MessageGap>,
+        /// Gaps before this one have been marked
+        next_gap: usize,
+        after: Option<(i64, String)>,
+        written: usize,
+        done: bool,
+    }
+
+    let state = State {
+        pool,
+        conversation,
+        format,
+        title,
+        users: None,
+        gaps: Vec::new(),
+        next_gap: 0,
+        after: None,
+        written: 0,
+        done: false,
+    };
+
+    futures::stream::unfold(state, |mut state| async move {
+        if state.done {
+            return None;
+        }
+
+        let Some(users) = &state.users else {
+            return match load_header(&state.pool, &state.conversation).await {
+                Ok((header, users)) => {
+                    let chunk = state.format.begin(&state.title, &header);
+                    state.users = Some(users);
+                    state.gaps = header.gaps;
+                    Some((Ok(chunk), state))
+                }
+                Err(e) => {
+                    state.done = true;
+                    Some((Err(e), state))
+                }
+            };
+        };
+
+        let page = match queries::get_messages_after(
+            &state.pool,
+            &state.conversation.id,
+            state.after.as_ref().map(|(at, id)| (*at, id.as_str())),
+            PAGE_SIZE,
+        )
+        .await
+        {
+            Ok(page) => page,
+            Err(e) => {
+                state.done = true;
+                return Some((Err(e), state));
+            }
+        };
+
+        let mut chunk = String::new();
+        let len = page.len() as u32;
+        if let Some(last) = page.last() {
+            state.after = Some((last.created_at, last.id.clone()));
+        }
+        for message in page {
+            let message = transcript_message(message, users);
+            chunk.push_str(&state.format.gap_markers(
+                &state.gaps,
+                &mut state.next_gap,
+                Some(message.created_at),
+            ));
+            chunk.push_str(&state.format.message(&message, state.written == 0));
+            state.written += 1;
+        }
+        if len < PAGE_SIZE {
+            chunk.push_str(
+                &state
+                    .format
+                    .gap_markers(&state.gaps, &mut state.next_gap, None),
+            );
+            chunk.push_str(&state.format.end());
+            state.done = true;
+        }
+        Some((Ok(chunk), state))
+    })
+}
+
+fn transcript_message(message: Message, users: &HashMap<String, Participant>) -> TranscriptMessage {
+    let sender = users
+        .get(&message.sender_id)
+        .filter(|_| !message.is_anonymized)
+        .and_then(|p| {
+            p.user_id
+                .as_ref()
+                .map(|id| (id.clone(), p.username.clone()))
+        });
+    let (sender_id, sender_username) = match sender {
+        Some((id, username)) => (Some(id), username),
+        None => (None, DELETED_USER.to_string()),
+    };
+
+    TranscriptMessage {
+        id: message.id,
+        sender_id,
+        sender_username,
+        content: message.content,
+        created_at: message.created_at,
+        delivered_at: message.delivered_at,
+        read_at: message.read_at,
+        status: message.status,
+        encrypted: message.is_encrypted,
+        system: message.is_system,
+    }
+}
+
+impl ExportFormat {
+    /// `json`, `markdown` (or `md`) or `html`
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            "markdown" | "md" => Some(Self::Markdown),
+            "html" => Some(Self::Html),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Markdown => "text/markdown; charset=utf-8",
+            Self::Html => "text/html; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Markdown => "md",
+            Self::Html => "html",
+        }
+    }
+
+    /// Write a whole transcript
+    pub fn render(self, title: &str, transcript: &Transcript) -> String {
+        let mut text = self.begin(title, transcript);
+        let mut next_gap = 0;
+        for (i, message) in transcript.messages.iter().enumerate() {
+            text.push_str(&self.gap_markers(
+                &transcript.gaps,
+                &mut next_gap,
+                Some(message.created_at),
+            ));
+            text.push_str(&self.message(message, i == 0));
+        }
+        text.push_str(&self.gap_markers(&transcript.gaps, &mut next_gap, None));
+        text.push_str(&self.end());
+        text
+    }
+
+    /// Everything before the first message; `transcript.messages` is not written
+    fn begin(self, title: &str, transcript: &Transcript) -> String {
+        match self {
+            // Same shape as `Transcript` serialized, with messages to follow
+            Self::Json => format!(
+                "{{\"conversation_id\":{},\"created_at\":{},\"participants\":{},\"gaps\":{},\"messages\":[",
+                serde_json::Value::from(transcript.conversation_id.as_str()),
+                transcript.created_at,
+                serde_json::to_string(&transcript.participants).unwrap_or_else(|_| "[]".into()),
+                serde_json::to_string(&transcript.gaps).unwrap_or_else(|_| "[]".into())
+            ),
+            Self::Markdown => {
+                let names: Vec<&str> = transcript
+                    .participants
+                    .iter()
+                    .map(|p| p.username.as_str())
+                    .collect();
+                format!(
+                    "# {}\n\n**Participants:** {}  \n**Started:** {}\n\n---\n\n",
+                    title,
+                    names.join(", "),
+                    format_timestamp(transcript.created_at)
+                )
+            }
+            Self::Html => {
+                let mut html = html_page_start(title);
+                html.push_str(&html_section_start(transcript));
+                html
+            }
+        }
+    }
+
+    fn message(self, message: &TranscriptMessage, first: bool) -> String {
+        match self {
+            Self::Json => format!(
+                "{}\n{}",
+                if first { "" } else { "," },
+                serde_json::to_string(message).unwrap_or_else(|_| "null".into())
+            ),
+            Self::Markdown => markdown_message(message),
+            Self::Html => html_message(message),
+        }
+    }
+
+    /// Markers for the gaps from `next` on that come before a message created
+    /// at `before`, or for all of them when `None`
+    ///
+    /// JSON lists gaps in the header instead.
+    fn gap_markers(self, gaps: &[MessageGap], next: &mut usize, before: Option<i64>) -> String {
+        let mut text = String::new();
+        while let Some(gap) = gaps.get(*next) {
+            if before.is_some_and(|at| gap.first_at > at) {
+                break;
+            }
+            *next += 1;
+            match self {
+                Self::Json => {}
+                Self::Markdown => {
+                    let _ = writeln!(
+                        text,
+                        "_[{} \u{b7} {}]_\n",
+                        describe_gap(gap),
+                        gap_period(gap)
+                    );
+                }
+                Self::Html => {
+                    let _ = writeln!(
+                        text,
+                        "<div class=\"message gap\"><span class=\"time\">{}</span> {}</div>",
+                        gap_period(gap),
+                        escape_html(&describe_gap(gap))
+                    );
+                }
+            }
+        }
+        text
+    }
+
+    fn end(self) -> String {
+        match self {
+            Self::Json => "\n]}\n".to_string(),
+            Self::Markdown => String::new(),
+            Self::Html => format!("</section>\n{}", HTML_PAGE_END),
+        }
+    }
+}
+
+/// `2025-12-15 16:31:30 UTC`
+pub fn format_timestamp(millis: i64) -> String {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| millis.to_string())
+}
+
+/// "3 messages expired"
+fn describe_gap(gap: &MessageGap) -> String {
+    let messages = if gap.message_count == 1 {
+        "1 message".to_string()
+    } else {
+        format!("{} messages", gap.message_count)
+    };
+    match gap.reason.as_str() {
+        "expired" => format!("{} expired", messages),
+        "retention" => format!("{} removed by the retention policy", messages),
+        "declined" => format!("{} removed when the message request was declined", messages),
+        _ => format!("{} removed", messages),
+    }
+}
+
+/// When the removed messages were sent
+fn gap_period(gap: &MessageGap) -> String {
+    if gap.first_at == gap.last_at {
+        format_timestamp(gap.first_at)
+    } else {
+        format!(
+            "{} \u{2013} {}",
+            format_timestamp(gap.first_at),
+            format_timestamp(gap.last_at)
+        )
+    }
+}
+
+/// What a reader sees in place of a message's content
+fn display_content(message: &TranscriptMessage) -> &str {
+    if message.encrypted {
+        "[end-to-end encrypted message]"
+    } else {
+        &message.content
+    }
+}
+
+fn markdown_message(message: &TranscriptMessage) -> String {
+    if message.system {
+        return format!(
+            "_{} \u{b7} {}_\n\n",
+            format_timestamp(message.created_at),
+            display_content(message).replace('\n', " ")
+        );
+    }
+    let mut text = format!(
+        "**{}** \u{b7} {}\n",
+        message.sender_username,
+        format_timestamp(message.created_at)
+    );
+    for line in display_content(message).lines() {
+        let _ = writeln!(text, "> {}", line);
+    }
+    text.push('\n');
+    text
+}
+
+/// One self-contained HTML page with every transcript
+pub fn render_html(title: &str, transcripts: &[Transcript]) -> String {
+    let mut html = html_page_start(title);
+    for transcript in transcripts {
+        html.push_str(&html_section_start(transcript));
+        let mut next_gap = 0;
+        for message in &transcript.messages {
+            html.push_str(&ExportFormat::Html.gap_markers(
+                &transcript.gaps,
+                &mut next_gap,
+                Some(message.created_at),
+            ));
+            html.push_str(&html_message(message));
+        }
+        html.push_str(&ExportFormat::Html.gap_markers(&transcript.gaps, &mut next_gap, None));
+        html.push_str("</section>\n");
+    }
+    html.push_str(HTML_PAGE_END);
+    html
+}
+
+fn html_page_start(title: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        escape_html(title),
+        STYLE,
+        escape_html(title)
+    )
+}
+
+const HTML_PAGE_END: &str = "</body>\n</html>\n";
+
+fn html_section_start(transcript: &Transcript) -> String {
+    let names: Vec<&str> = transcript
+        .participants
+        .iter()
+        .map(|p| p.username.as_str())
+        .collect();
+    format!(
+        "<section id=\"{}\">\n<h2>{}</h2>\n<p class=\"meta\">Started {}</p>\n",
+        escape_html(&transcript.conversation_id),
+        escape_html(&names.join(" & ")),
+        format_timestamp(transcript.created_at)
+    )
+}
+
+fn html_message(message: &TranscriptMessage) -> String {
+    let class = if message.system {
+        "message system"
+    } else {
+        "message"
+    };
+    format!(
+        "<div class=\"{}\"><span class=\"time\">{}</span> <span class=\"sender\">{}</span><div class=\"content\">{}</div></div>\n",
+        class,
+        format_timestamp(message.created_at),
+        escape_html(&message.sender_username),
+        escape_html(display_content(message))
+    )
+}
+
+const STYLE: &str =
+    "body { font-family: sans-serif; max-width: 48rem; margin: 2rem auto; color: #222; }
+section { margin-bottom: 3rem; }
+.meta, .time { color: #777; font-size: 0.85rem; }
+.message { margin: 0.5rem 0; }
+.sender { font-weight: bold; }
+.content { white-space: pre-wrap; }
+.system { font-style: italic; color: #555; }
+.gap { font-style: italic; color: #a33; }
+";
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    async fn setup_test_db() -> SqlitePool {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+
+        crate::db::run_migrations(&pool).await.unwrap();
+
+        pool
+    }
+
+    #[tokio::test]
+    async fn test_transcript_is_chronological_and_anonymized() {
+        let pool = setup_test_db().await;
+        let alice = User::new("alice".to_string(), "hash".to_string(), "salt".to_string());
+        let bob = User::new("bob".to_string(), "hash".to_string(), "salt".to_string());
+        queries::insert_user(&pool, &alice).await.unwrap();
+        queries::insert_user(&pool, &bob).await.unwrap();
+        let (user1, user2) = if alice.id < bob.id {
+            (alice.id.clone(), bob.id.clone())
+        } else {
+            (bob.id.clone(), alice.id.clone())
+        };
+        let conversation = queries::insert_conversation(&pool, &Conversation::new(user1, user2))
+            .await
+            .unwrap();
+
+        for (i, (from, to, text)) in [
+            (&alice, &bob, "hi <bob>"),
+            (&bob, &alice, "hello"),
+            (&alice, &bob, "bye"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut message = Message::new(
+                conversation.id.clone(),
+                from.id.clone(),
+                to.id.clone(),
+                text.to_string(),
+            );
+            message.created_at = 1_000 + i as i64;
+            queries::insert_message(&pool, &message).await.unwrap();
+        }
+        queries::soft_delete_user(&pool, &bob.id).await.unwrap();
+
+        let transcript = load_transcript(&pool, &conversation).await.unwrap();
+        let lines: Vec<_> = transcript
+            .messages
+            .iter()
+            .map(|m| (m.sender_username.as_str(), m.content.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                ("alice", "hi <bob>"),
+                (DELETED_USER, "hello"),
+                ("alice", "bye")
+            ]
+        );
+        assert_eq!(transcript.messages[1].sender_id, None);
+        assert!(transcript
+            .participants
+            .iter()
+            .any(|p| p.username == DELETED_USER && p.user_id.is_none()));
+        assert!(!serde_json::to_string(&transcript)
+            .unwrap()
+            .contains(&bob.id));
+
+        let html = render_html("Export", std::slice::from_ref(&transcript));
+        assert!(html.contains("hi &lt;bob&gt;"));
+        assert!(!html.contains("bob>"));
+
+        // Streaming writes exactly what rendering at once does
+        for format in [
+            ExportFormat::Json,
+            ExportFormat::Markdown,
+            ExportFormat::Html,
+        ] {
+            let chunks: Vec<String> =
+                stream_transcript(pool.clone(), conversation.clone(), format, "Export".into())
+                    .map(|chunk| chunk.unwrap())
+                    .collect()
+                    .await;
+            assert_eq!(chunks.concat(), format.render("Export", &transcript));
+        }
+        let json: serde_json::Value =
+            serde_json::from_str(&ExportFormat::Json.render("Export", &transcript)).unwrap();
+        assert_eq!(json, serde_json::to_value(&transcript).unwrap());
+        let markdown = ExportFormat::Markdown.render("Export", &transcript);
+        assert!(markdown.contains(&format!("**{}** \u{b7} ", DELETED_USER)));
+        assert!(markdown.contains("> hi <bob>"));
+    }
+
+    #[tokio::test]
+    async fn test_transcript_marks_removed_messages() {
+        let pool = setup_test_db().await;
+        let alice = User::new("alice".to_string(), "hash".to_string(), "salt".to_string());
+        let bob = User::new("bob".to_string(), "hash".to_string(), "salt".to_string());
+        queries::insert_user(&pool, &alice).await.unwrap();
+        queries::insert_user(&pool, &bob).await.unwrap();
+        let (user1, user2) = if alice.id < bob.id {
+            (alice.id.clone(), bob.id.clone())
+        } else {
+            (bob.id.clone(), alice.id.clone())
+        };
+        let conversation = queries::insert_conversation(&pool, &Conversation::new(user1, user2))
+            .await
+            .unwrap();
+
+        // Sent at 1s to 5s; the third and fifth have expired
+        for i in 1..=5 {
+            let mut message = Message::new(
+                conversation.id.clone(),
+                alice.id.clone(),
+                bob.id.clone(),
+                format!("message {}", i),
+            );
+            message.created_at = i * 1_000;
+            if i == 3 || i == 5 {
+                message.expires_at = Some(1);
+            }
+            queries::insert_message(&pool, &message).await.unwrap();
+        }
+        queries::purge_messages_before(&pool, 1_500, 10)
+            .await
+            .unwrap();
+        queries::delete_expired_messages(&pool).await.unwrap();
+
+        let transcript = load_transcript(&pool, &conversation).await.unwrap();
+        let contents: Vec<_> = transcript
+            .messages
+            .iter()
+            .map(|m| m.content.as_str())
+            .collect();
+        assert_eq!(contents, vec!["message 2", "message 4"]);
+        let gaps: Vec<_> = transcript
+            .gaps
+            .iter()
+            .map(|g| (g.reason.as_str(), g.message_count, g.first_at, g.last_at))
+            .collect();
+        assert_eq!(
+            gaps,
+            vec![("retention", 1, 1_000, 1_000), ("expired", 2, 3_000, 5_000)]
+        );
+
+        // Each marker sits where its messages were
+        let markdown = ExportFormat::Markdown.render("Export", &transcript);
+        let at = |text: &str| markdown.find(text).unwrap();
+        assert!(at("1 message removed by the retention policy") < at("> message 2"));
+        assert!(at("> message 2") < at("2 messages expired"));
+        assert!(at("2 messages expired") < at("> message 4"));
+        let html = render_html("Export", std::slice::from_ref(&transcript));
+        assert!(html.contains("<div class=\"message gap\">"));
+        assert!(html.contains("2 messages expired"));
+        for format in [
+            ExportFormat::Json,
+            ExportFormat::Markdown,
+            ExportFormat::Html,
+        ] {
+            let chunks: Vec<String> =
+                stream_transcript(pool.clone(), conversation.clone(), format, "Export".into())
+                    .map(|chunk| chunk.unwrap())
+                    .collect()
+                    .await;
+            assert_eq!(chunks.concat(), format.render("Export", &transcript));
+        }
+
+        // Later retention runs grow the same marker
+        queries::purge_messages_before(&pool, 2_500, 10)
+            .await
+            .unwrap();
+        let transcript = load_transcript(&pool, &conversation).await.unwrap();
+        let gaps: Vec<_> = transcript
+            .gaps
+            .iter()
+            .map(|g| (g.reason.as_str(), g.message_count, g.first_at, g.last_at))
+            .collect();
+        assert_eq!(
+            gaps,
+            vec![("retention", 2, 1_000, 2_000), ("expired", 2, 3_000, 5_000)]
+        );
+    }
+}
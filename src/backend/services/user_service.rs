@@ -1,10 +1,11 @@
 //! User service with cached search results
 //!
 //! Provides a 60s TTL in-memory cache for user search responses to reduce
-//! repeated database lookups on rapid search queries.
+//! repeated database lookups on rapid search queries, and manages blocks,
+//! which change what search returns.
 
 use crate::db::queries;
-use crate::models::User;
+use crate::models::{BlockedUser, User};
 use sqlx::SqlitePool;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
@@ -108,6 +109,33 @@ impl UserService {
 
         Ok(users)
     }
+
+    /// Block `blocked_id` for `blocker_id`; returns false if already blocked
+    pub async fn block_user(&self, blocker_id: &str, blocked_id: &str) -> Result<bool, String> {
+        let created = queries::insert_block(&self.pool, blocker_id, blocked_id).await?;
+        self.forget_searches(&[blocker_id, blocked_id]).await;
+        Ok(created)
+    }
+
+    /// Remove a block; returns false if there was none
+    pub async fn unblock_user(&self, blocker_id: &str, blocked_id: &str) -> Result<bool, String> {
+        let removed = queries::delete_block(&self.pool, blocker_id, blocked_id).await?;
+        self.forget_searches(&[blocker_id, blocked_id]).await;
+        Ok(removed)
+    }
+
+    /// Users blocked by `blocker_id`
+    pub async fn blocked_users(&self, blocker_id: &str) -> Result<Vec<BlockedUser>, String> {
+        queries::get_blocked_users(&self.pool, blocker_id).await
+    }
+
+    /// Drop cached searches made by these users, whose results may have changed
+    async fn forget_searches(&self, requester_ids: &[&str]) {
+        self.cache
+            .write()
+            .await
+            .retain(|key, _| !requester_ids.contains(&key.requester_id.as_str()));
+    }
 }
 
 #[cfg(test)]
@@ -121,10 +149,7 @@ mod tests {
             .await
             .unwrap();
 
-        let schema_sql = include_str!("../../backend/db/migrations/001_initial_schema.sql");
-        for statement in schema_sql.split(';').filter(|s| !s.trim().is_empty()) {
-            sqlx::query(statement).execute(&pool).await.unwrap();
-        }
+        crate::db::run_migrations(&pool).await.unwrap();
 
         pool
     }
@@ -157,4 +182,31 @@ mod tests {
         let refreshed = service.search_users(&requester.id, "b", 10).await.unwrap();
         assert_eq!(refreshed.len(), 2);
     }
+
+    #[tokio::test]
+    async fn blocked_users_are_hidden_from_each_others_search() {
+        let pool = setup_test_db().await;
+        let service = UserService::new(pool.clone());
+        let alice = User::new("alice".into(), "hash".into(), "salt".into());
+        let bob = User::new("bob".into(), "hash2".into(), "salt2".into());
+        queries::insert_user(&pool, &alice).await.unwrap();
+        queries::insert_user(&pool, &bob).await.unwrap();
+
+        // Cached before the block, and still forgotten by it
+        assert_eq!(service.search_users(&bob.id, "a", 10).await.unwrap().len(), 1);
+
+        assert!(service.block_user(&alice.id, &bob.id).await.unwrap());
+        assert!(!service.block_user(&alice.id, &bob.id).await.unwrap());
+        assert!(service.search_users(&alice.id, "b", 10).await.unwrap().is_empty());
+        assert!(service.search_users(&bob.id, "a", 10).await.unwrap().is_empty());
+
+        let blocked = service.blocked_users(&alice.id).await.unwrap();
+        assert_eq!(blocked.len(), 1);
+        assert_eq!(blocked[0].username, "bob");
+        assert!(service.blocked_users(&bob.id).await.unwrap().is_empty());
+
+        assert!(service.unblock_user(&alice.id, &bob.id).await.unwrap());
+        assert!(!service.unblock_user(&alice.id, &bob.id).await.unwrap());
+        assert_eq!(service.search_users(&bob.id, "a", 10).await.unwrap().len(), 1);
+    }
 }
@@ -0,0 +1,218 @@
+//! Tracing and telemetry setup
+//!
+//! Every binary logs structured JSON through `tracing`. When
+//! `OTEL_EXPORTER_OTLP_ENDPOINT` is set, spans are additionally exported over
+//! OTLP/HTTP so a message's journey (parse, persist, deliver or queue, ack) can
+//! be followed in a tracing backend. Trace context travels between client and
+//! server in the envelope's `traceparent` field.
+
+use chat_shared::protocol::TraceParent;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use tracing::Span;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter};
+
+/// Service name reported to the collector unless `OTEL_SERVICE_NAME` overrides it
+const DEFAULT_SERVICE_NAME: &str = "chat-server";
+
+const TRACEPARENT_KEY: &str = "traceparent";
+
+static TRACER_PROVIDER: OnceLock<Option<SdkTracerProvider>> = OnceLock::new();
+
+/// Global tracing initializer to produce structured JSON logs across all binaries.
+///
+/// Idempotent: calling multiple times is safe and will only initialize once.
+/// OTLP export is enabled only when `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+pub fn init_tracing(default_level: Option<&str>) {
+    let _ = TRACER_PROVIDER.get_or_init(|| {
+        let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+            default_level
+                .map(EnvFilter::new)
+                .unwrap_or_else(|| EnvFilter::new("info"))
+        });
+
+        let provider = build_otlp_provider();
+        let otel = provider.as_ref().map(otel_layer);
+
+        tracing_subscriber::registry()
+            .with(env_filter)
+            .with(
+                fmt::layer()
+                    .json()
+                    .with_target(true)
+                    .with_level(true)
+                    .with_thread_ids(true)
+                    .with_thread_names(true),
+            )
+            .with(otel)
+            .init();
+
+        if provider.is_some() {
+            tracing::info!("OpenTelemetry OTLP trace export enabled");
+        }
+        provider
+    });
+}
+
+/// Flush and stop the OTLP exporter, if one was started
+pub fn shutdown_tracing() {
+    if let Some(Some(provider)) = TRACER_PROVIDER.get() {
+        if let Err(e) = provider.shutdown() {
+            eprintln!("Failed to shut down OpenTelemetry exporter: {}", e);
+        }
+    }
+}
+
+/// Build a `tracing` layer that records spans into the given provider
+pub fn otel_layer<S>(provider: &SdkTracerProvider) -> OpenTelemetryLayer<S, SdkTracer>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer("chat-backend"))
+}
+
+fn build_otlp_provider() -> Option<SdkTracerProvider> {
+    let endpoint = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok()?;
+    if endpoint.trim().is_empty() {
+        return None;
+    }
+
+    let exporter = match opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+    {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            eprintln!("Failed to create OTLP exporter for {}: {}", endpoint, e);
+            return None;
+        }
+    };
+
+    let service_name =
+        std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| DEFAULT_SERVICE_NAME.to_string());
+
+    Some(
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(service_name).build())
+            .build(),
+    )
+}
+
+/// Attach an inbound `traceparent` as the remote parent of `span`
+///
+/// Invalid or missing values are ignored and the span starts a new trace.
+pub fn set_remote_parent(span: &Span, traceparent: Option<&str>) {
+    let Some(value) = traceparent.filter(|v| TraceParent::parse(v).is_some()) else {
+        return;
+    };
+
+    let mut carrier = HashMap::new();
+    carrier.insert(TRACEPARENT_KEY.to_string(), value.to_string());
+    let cx = TraceContextPropagator::new().extract(&carrier);
+    let _ = span.set_parent(cx);
+}
+
+/// `traceparent` to stamp on frames produced while `span` is active
+///
+/// Uses the span's own context when OpenTelemetry is recording. Otherwise the
+/// inbound trace is continued with a fresh span ID so the client can still
+/// correlate server frames with the message it sent.
+pub fn outbound_traceparent(span: &Span, inbound: Option<&str>) -> Option<String> {
+    let mut carrier: HashMap<String, String> = HashMap::new();
+    TraceContextPropagator::new().inject_context(&span.context(), &mut carrier);
+
+    carrier.remove(TRACEPARENT_KEY).or_else(|| {
+        inbound
+            .and_then(TraceParent::parse)
+            .map(|tp| tp.child().to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_sdk::trace::InMemorySpanExporter;
+
+    const INBOUND: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    fn test_provider() -> (SdkTracerProvider, InMemorySpanExporter) {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        (provider, exporter)
+    }
+
+    #[test]
+    fn test_remote_parent_is_continued() {
+        let (provider, exporter) = test_provider();
+        let subscriber = tracing_subscriber::registry().with(otel_layer(&provider));
+
+        let outbound = tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("message");
+            set_remote_parent(&span, Some(INBOUND));
+            let _guard = span.enter();
+            tracing::info_span!("message.persist").in_scope(|| {});
+            outbound_traceparent(&span, Some(INBOUND))
+        });
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 2);
+        for span in &spans {
+            assert_eq!(
+                span.span_context.trace_id().to_string(),
+                "4bf92f3577b34da6a3ce929d0e0e4736"
+            );
+        }
+
+        let parent = spans.iter().find(|s| s.name == "message").unwrap();
+        assert_eq!(parent.parent_span_id.to_string(), "00f067aa0ba902b7");
+        let child = spans.iter().find(|s| s.name == "message.persist").unwrap();
+        assert_eq!(child.parent_span_id, parent.span_context.span_id());
+
+        let outbound = TraceParent::parse(&outbound.unwrap()).unwrap();
+        assert_eq!(outbound.trace_id_hex(), "4bf92f3577b34da6a3ce929d0e0e4736");
+    }
+
+    #[test]
+    fn test_invalid_traceparent_starts_new_trace() {
+        let (provider, exporter) = test_provider();
+        let subscriber = tracing_subscriber::registry().with(otel_layer(&provider));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("message");
+            set_remote_parent(&span, Some("not-a-traceparent"));
+            span.in_scope(|| {});
+        });
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_ne!(
+            spans[0].span_context.trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+    }
+
+    #[test]
+    fn test_outbound_falls_back_to_inbound_without_otel() {
+        let subscriber = tracing_subscriber::Registry::default();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("message");
+            let outbound = outbound_traceparent(&span, Some(INBOUND)).unwrap();
+            let parsed = TraceParent::parse(&outbound).unwrap();
+            assert_eq!(parsed.trace_id_hex(), "4bf92f3577b34da6a3ce929d0e0e4736");
+            assert_ne!(outbound, INBOUND);
+
+            assert!(outbound_traceparent(&span, None).is_none());
+        });
+    }
+}
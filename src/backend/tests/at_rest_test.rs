@@ -0,0 +1,180 @@
+// ============================================================================
+// Encryption At Rest Integration Test
+// ============================================================================
+// The master key is installed process-wide, so this runs in its own test
+// binary rather than next to the unit tests that expect plaintext rows.
+
+use chat_backend::db::at_rest::{self, MasterKey};
+use chat_backend::db::queries::{self, AuthEventType};
+use chat_backend::models::{Conversation, Message, User};
+use sqlx::SqlitePool;
+
+async fn setup_test_db() -> SqlitePool {
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+
+    chat_backend::db::run_migrations(&pool).await.unwrap();
+
+    pool
+}
+
+#[tokio::test]
+async fn test_content_is_sealed_searchable_and_rotatable() {
+    let master = MasterKey::generate();
+    let master_base64 = master.to_base64();
+    at_rest::install(master).unwrap();
+    let current = at_rest::master_key().unwrap();
+
+    let pool = setup_test_db().await;
+    let alice = User::new(
+        "alice".to_string(),
+        "hash1".to_string(),
+        "salt1".to_string(),
+    );
+    let bob = User::new("bob".to_string(), "hash2".to_string(), "salt2".to_string());
+    queries::insert_user(&pool, &alice).await.unwrap();
+    queries::insert_user(&pool, &bob).await.unwrap();
+    let (user1, user2) = if alice.id < bob.id {
+        (alice.id.clone(), bob.id.clone())
+    } else {
+        (bob.id.clone(), alice.id.clone())
+    };
+    let conversation = queries::insert_conversation(&pool, &Conversation::new(user1, user2))
+        .await
+        .unwrap();
+
+    let message = Message::new(
+        conversation.id.clone(),
+        alice.id.clone(),
+        bob.id.clone(),
+        "Lunch at noon tomorrow?".to_string(),
+    );
+    queries::insert_message(&pool, &message).await.unwrap();
+
+    // The row holds ciphertext, reads return plaintext
+    let (raw, is_sealed): (String, bool) =
+        sqlx::query_as("SELECT content, is_sealed FROM messages WHERE id = ?")
+            .bind(&message.id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert!(is_sealed);
+    assert!(!raw.contains("noon"));
+    let found = queries::find_message_by_id(&pool, &message.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.content, "Lunch at noon tomorrow?");
+    let history = queries::get_messages_by_conversation(&pool, &conversation.id, 50, 0)
+        .await
+        .unwrap();
+    assert_eq!(history[0].content, "Lunch at noon tomorrow?");
+
+    // Later messages reuse the conversation's data key instead of writing a new one
+    sqlx::query(
+        "CREATE TRIGGER no_new_data_keys BEFORE INSERT ON data_keys
+         WHEN NEW.scope != 'auth_logs'
+         BEGIN SELECT RAISE(ABORT, 'data key created again'); END",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let reply = Message::new(
+        conversation.id.clone(),
+        bob.id.clone(),
+        alice.id.clone(),
+        "Sounds good".to_string(),
+    );
+    queries::insert_message(&pool, &reply).await.unwrap();
+    assert_eq!(
+        queries::find_message_by_id(&pool, &reply.id)
+            .await
+            .unwrap()
+            .unwrap()
+            .content,
+        "Sounds good"
+    );
+    sqlx::query("DROP TRIGGER no_new_data_keys")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    // Whole words match through the blind index, in any case and order
+    for query in ["noon", "TOMORROW lunch"] {
+        let results = queries::search_messages_in_conversation(&pool, &conversation.id, query, 10)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1, "query {:?}", query);
+        assert_eq!(results[0].content, "Lunch at noon tomorrow?");
+    }
+    for query in ["noo", "noon dinner"] {
+        let results = queries::search_messages_in_conversation(&pool, &conversation.id, query, 10)
+            .await
+            .unwrap();
+        assert!(results.is_empty(), "query {:?}", query);
+    }
+
+    // Auth log details are sealed too
+    queries::insert_auth_log(
+        &pool,
+        "127.0.0.1",
+        Some("alice"),
+        AuthEventType::LoginFailed,
+        None,
+        Some("wrong password"),
+    )
+    .await
+    .unwrap();
+    let (details, is_sealed): (String, bool) =
+        sqlx::query_as("SELECT details, is_sealed FROM auth_logs")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert!(is_sealed);
+    assert!(!details.contains("password"));
+
+    // Rotation re-wraps the data keys and leaves messages untouched
+    let new = MasterKey::generate();
+    assert_eq!(
+        at_rest::rotate_master_key(&pool, current, &new)
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(
+        at_rest::rotate_master_key(&pool, current, &new)
+            .await
+            .unwrap(),
+        0
+    );
+    let (after,): (String,) = sqlx::query_as("SELECT content FROM messages WHERE id = ?")
+        .bind(&message.id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(after, raw);
+    assert_eq!(
+        at_rest::data_key_counts(&pool).await.unwrap(),
+        vec![(new.id().to_string(), 2)]
+    );
+
+    // The old key no longer unwraps anything until the keys are rotated back to it
+    let old = MasterKey::from_base64(&master_base64).unwrap();
+    assert!(
+        at_rest::rotate_master_key(&pool, &old, &MasterKey::generate())
+            .await
+            .is_err()
+    );
+    assert_eq!(
+        at_rest::rotate_master_key(&pool, &new, &old).await.unwrap(),
+        2
+    );
+    let found = queries::find_message_by_id(&pool, &message.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.content, "Lunch at noon tomorrow?");
+}
@@ -3,6 +3,13 @@
 // ============================================================================
 // This file runs the token validation tests through the integration test runner
 
+// Ordering checks deliberately spell out the literal token scale
+#![allow(
+    clippy::assertions_on_constants,
+    clippy::manual_range_contains,
+    clippy::nonminimal_bool
+)]
+
 // ============================================================================
 // Token Unit Tests
 // ============================================================================
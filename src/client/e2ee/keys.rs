@@ -0,0 +1,192 @@
+//! Identity and prekeys
+//!
+//! A user's identity is one Ed25519 key. It signs the prekeys, and its X25519
+//! form (the birationally equivalent Montgomery point) is the identity key
+//! used in key agreement, so a single key is published and verified.
+
+use super::{b64, E2eeError};
+use crate::types::KeyBundle;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Long-term identity key pair
+#[derive(Clone)]
+pub struct IdentityKeyPair {
+    signing: SigningKey,
+}
+
+impl IdentityKeyPair {
+    pub fn generate() -> Self {
+        Self {
+            signing: SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        Self {
+            signing: SigningKey::from_bytes(seed),
+        }
+    }
+
+    pub fn seed(&self) -> [u8; 32] {
+        self.signing.to_bytes()
+    }
+
+    /// Ed25519 public key; what safety numbers are computed from
+    pub fn public_key(&self) -> [u8; 32] {
+        self.signing.verifying_key().to_bytes()
+    }
+
+    /// X25519 secret for key agreement
+    pub fn dh_secret(&self) -> StaticSecret {
+        StaticSecret::from(self.signing.to_scalar_bytes())
+    }
+
+    pub fn dh_public(&self) -> PublicKey {
+        PublicKey::from(self.signing.verifying_key().to_montgomery().to_bytes())
+    }
+
+    pub fn sign(&self, message: &[u8]) -> [u8; 64] {
+        self.signing.sign(message).to_bytes()
+    }
+}
+
+/// X25519 form of an Ed25519 identity key
+pub fn identity_dh_public(identity: &[u8; 32]) -> Result<PublicKey, E2eeError> {
+    let key = VerifyingKey::from_bytes(identity)
+        .map_err(|_| E2eeError::InvalidKey("identity key is not a valid Ed25519 key".into()))?;
+    Ok(PublicKey::from(key.to_montgomery().to_bytes()))
+}
+
+/// Secret half of a signed or one-time prekey
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PrekeySecret {
+    pub key_id: u32,
+    #[serde(with = "b64")]
+    pub secret: [u8; 32],
+}
+
+impl PrekeySecret {
+    pub fn generate(key_id: u32) -> Self {
+        Self {
+            key_id,
+            secret: StaticSecret::random_from_rng(OsRng).to_bytes(),
+        }
+    }
+
+    pub fn dh_secret(&self) -> StaticSecret {
+        StaticSecret::from(self.secret)
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from(&self.dh_secret())
+    }
+}
+
+/// A peer's bundle after its keys and signature have been checked
+pub struct VerifiedBundle {
+    /// Ed25519 identity key
+    pub identity: [u8; 32],
+    pub identity_dh: PublicKey,
+    pub signed_prekey_id: u32,
+    pub signed_prekey: PublicKey,
+    pub one_time_prekey: Option<(u32, PublicKey)>,
+}
+
+/// Check that the bundle's identity key matches its signing key and that the
+/// signed prekey carries a valid signature
+pub fn verify_bundle(bundle: &KeyBundle) -> Result<VerifiedBundle, E2eeError> {
+    let identity: [u8; 32] = b64::decode_array(&bundle.signing_key)?;
+    let identity_dh = identity_dh_public(&identity)?;
+    if b64::decode_array::<32>(&bundle.identity_key)? != identity_dh.to_bytes() {
+        return Err(E2eeError::InvalidKey(
+            "identity key does not belong to the signing key".into(),
+        ));
+    }
+
+    let signed_prekey: [u8; 32] = b64::decode_array(&bundle.signed_prekey.public_key)?;
+    let signature = Signature::from_bytes(&b64::decode_array(&bundle.signed_prekey.signature)?);
+    VerifyingKey::from_bytes(&identity)
+        .and_then(|key| key.verify(&signed_prekey, &signature))
+        .map_err(|_| E2eeError::InvalidKey("signed prekey signature is invalid".into()))?;
+
+    let one_time_prekey = match &bundle.one_time_prekey {
+        Some(prekey) => Some((
+            prekey.key_id,
+            PublicKey::from(b64::decode_array::<32>(&prekey.public_key)?),
+        )),
+        None => None,
+    };
+
+    Ok(VerifiedBundle {
+        identity,
+        identity_dh,
+        signed_prekey_id: bundle.signed_prekey.key_id,
+        signed_prekey: PublicKey::from(signed_prekey),
+        one_time_prekey,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{PublicPrekey, SignedPrekey};
+
+    fn bundle(identity: &IdentityKeyPair, prekey: &PrekeySecret) -> KeyBundle {
+        let public = prekey.public_key().to_bytes();
+        KeyBundle {
+            user_id: "u1".to_string(),
+            identity_key: b64::encode(identity.dh_public().as_bytes()),
+            signing_key: b64::encode(&identity.public_key()),
+            signed_prekey: SignedPrekey {
+                key_id: prekey.key_id,
+                public_key: b64::encode(&public),
+                signature: b64::encode(&identity.sign(&public)),
+            },
+            one_time_prekey: Some(PublicPrekey {
+                key_id: 7,
+                public_key: b64::encode(&public),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_identity_agrees_in_both_forms() {
+        let alice = IdentityKeyPair::generate();
+        let bob = IdentityKeyPair::generate();
+        assert_eq!(
+            identity_dh_public(&alice.public_key()).unwrap(),
+            alice.dh_public()
+        );
+        assert_eq!(
+            alice
+                .dh_secret()
+                .diffie_hellman(&bob.dh_public())
+                .to_bytes(),
+            bob.dh_secret()
+                .diffie_hellman(&alice.dh_public())
+                .to_bytes()
+        );
+    }
+
+    #[test]
+    fn test_bundle_signature_is_checked() {
+        let identity = IdentityKeyPair::generate();
+        let prekey = PrekeySecret::generate(1);
+        let good = bundle(&identity, &prekey);
+        let verified = verify_bundle(&good).unwrap();
+        assert_eq!(verified.identity, identity.public_key());
+        assert_eq!(verified.one_time_prekey.unwrap().0, 7);
+
+        let mut forged = good.clone();
+        forged.signed_prekey.public_key =
+            b64::encode(PrekeySecret::generate(2).public_key().as_bytes());
+        assert!(verify_bundle(&forged).is_err());
+
+        let mut swapped = good;
+        swapped.identity_key = b64::encode(IdentityKeyPair::generate().dh_public().as_bytes());
+        assert!(verify_bundle(&swapped).is_err());
+    }
+}
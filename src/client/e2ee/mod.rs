@@ -0,0 +1,486 @@
+//! End-to-end encryption for one-to-one conversations
+//!
+//! Sessions start with X3DH against the peer's published prekey bundle and
+//! continue with a double ratchet, so the server only stores ciphertext
+//! envelopes. All private state stays in a local key file (see [`E2ee::open`]).
+//!
+//! Message content of an encrypted message is a JSON [`Envelope`]. The first
+//! messages of a session carry a [`PrekeyMessage`] so the recipient can set up
+//! its side without being online when the session starts.
+
+pub mod keys;
+pub mod ratchet;
+pub mod safety;
+mod store;
+pub mod x3dh;
+
+pub use safety::format_safety_number;
+
+use crate::error::ClientError;
+use crate::rest::ChatClient;
+use crate::types::{PublicPrekey, SignedPrekey, UploadKeys};
+use keys::{identity_dh_public, verify_bundle, IdentityKeyPair, PrekeySecret};
+use ratchet::{Header, Ratchet};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use store::{KeyState, Session};
+use thiserror::Error;
+use x25519_dalek::PublicKey;
+
+/// Current [`Envelope`] version
+pub const ENVELOPE_VERSION: u8 = 1;
+
+/// One-time prekeys kept on the server
+const PREKEY_TARGET: i64 = 50;
+
+/// Top up once fewer than this many one-time prekeys are left
+const PREKEY_LOW_WATER: i64 = 10;
+
+/// Why an encryption operation failed
+#[derive(Debug, Error)]
+pub enum E2eeError {
+    #[error(transparent)]
+    Api(#[from] ClientError),
+
+    /// A published key or signature did not check out
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+
+    #[error("Cannot decrypt message: {0}")]
+    Decrypt(String),
+
+    /// Nothing to decrypt with, e.g. the session was lost with the key file
+    #[error("No encrypted session for this conversation")]
+    NoSession,
+
+    #[error("Malformed encrypted message")]
+    Malformed,
+
+    #[error("{0}")]
+    Storage(String),
+}
+
+/// Content of an encrypted message as stored by the server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    pub v: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prekey: Option<PrekeyMessage>,
+    pub header: Header,
+    #[serde(with = "b64")]
+    pub ciphertext: Vec<u8>,
+}
+
+/// What the recipient needs to start its side of a new session
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrekeyMessage {
+    /// Sender's Ed25519 identity key
+    #[serde(with = "b64")]
+    pub identity_key: [u8; 32],
+    #[serde(with = "b64")]
+    pub ephemeral_key: [u8; 32],
+    pub signed_prekey_id: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub one_time_prekey_id: Option<u32>,
+}
+
+/// A user's end-to-end encryption state
+pub struct E2ee {
+    user_id: String,
+    path: PathBuf,
+    state: Mutex<KeyState>,
+}
+
+impl E2ee {
+    /// Load the user's keys from `path`, creating a new identity if there are none
+    pub fn open(path: PathBuf, user_id: &str) -> Result<Self, E2eeError> {
+        let state = match store::load(&path)? {
+            Some(state) => state,
+            None => {
+                let state = KeyState {
+                    identity: IdentityKeyPair::generate().seed(),
+                    signed_prekey: PrekeySecret::generate(1),
+                    one_time_prekeys: Vec::new(),
+                    next_prekey_id: 1,
+                    published: false,
+                    sessions: Default::default(),
+                    verified: Default::default(),
+                    plaintexts: Default::default(),
+                };
+                store::save(&path, &state)?;
+                state
+            }
+        };
+        Ok(Self {
+            user_id: user_id.to_string(),
+            path,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Key file for `user_id`, next to the session file
+    pub fn key_file(session_file: &Path, user_id: &str) -> PathBuf {
+        session_file.with_file_name(format!("keys-{}.json", user_id))
+    }
+
+    pub fn user_id(&self) -> &str {
+        &self.user_id
+    }
+
+    /// Ed25519 identity key
+    pub fn identity_key(&self) -> [u8; 32] {
+        self.identity().public_key()
+    }
+
+    /// Publish the identity and signed prekey if the server lacks them, and
+    /// top up one-time prekeys when they run low
+    pub async fn publish(&self, client: &ChatClient) -> Result<(), E2eeError> {
+        let status = client.key_status().await?;
+        let upload = {
+            let mut state = self.state.lock().unwrap();
+            let published = state.published
+                && status.has_keys
+                && status.signed_prekey_id == Some(state.signed_prekey.key_id);
+            if published && status.one_time_prekeys >= PREKEY_LOW_WATER {
+                return Ok(());
+            }
+
+            // A new identity starts from an empty pool on the server
+            let remaining = if published {
+                status.one_time_prekeys
+            } else {
+                0
+            };
+            let mut fresh = Vec::new();
+            for _ in remaining..PREKEY_TARGET {
+                let prekey = PrekeySecret::generate(state.next_prekey_id);
+                state.next_prekey_id = state.next_prekey_id.wrapping_add(1);
+                fresh.push(prekey);
+            }
+            state.one_time_prekeys.extend(fresh.iter().cloned());
+            store::save(&self.path, &state)?;
+
+            let identity = IdentityKeyPair::from_seed(&state.identity);
+            let signed_public = state.signed_prekey.public_key().to_bytes();
+            UploadKeys {
+                identity_key: b64::encode(identity.dh_public().as_bytes()),
+                signing_key: b64::encode(&identity.public_key()),
+                signed_prekey: SignedPrekey {
+                    key_id: state.signed_prekey.key_id,
+                    public_key: b64::encode(&signed_public),
+                    signature: b64::encode(&identity.sign(&signed_public)),
+                },
+                one_time_prekeys: fresh
+                    .iter()
+                    .map(|prekey| PublicPrekey {
+                        key_id: prekey.key_id,
+                        public_key: b64::encode(prekey.public_key().as_bytes()),
+                    })
+                    .collect(),
+            }
+        };
+
+        client.upload_keys(&upload).await?;
+        let mut state = self.state.lock().unwrap();
+        state.published = true;
+        store::save(&self.path, &state)
+    }
+
+    /// Encrypt `plaintext` for the other participant, starting a session from
+    /// their published keys if there is none yet
+    ///
+    /// Returns the envelope to send as the content of message `message_id`.
+    pub async fn encrypt(
+        &self,
+        client: &ChatClient,
+        conversation_id: &str,
+        peer_id: &str,
+        message_id: &str,
+        plaintext: &str,
+    ) -> Result<String, E2eeError> {
+        let has_session = self
+            .state
+            .lock()
+            .unwrap()
+            .sessions
+            .get(conversation_id)
+            .is_some_and(|s| s.peer_id == peer_id && s.ratchet.can_send());
+        let bundle = if has_session {
+            None
+        } else {
+            Some(verify_bundle(&client.key_bundle(peer_id).await?)?)
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(bundle) = bundle {
+            let identity = self.identity_of(&state);
+            let start = x3dh::initiate(&identity, &bundle);
+            state.sessions.insert(
+                conversation_id.to_string(),
+                Session {
+                    peer_id: peer_id.to_string(),
+                    peer_identity: bundle.identity,
+                    ratchet: Ratchet::initiator(
+                        start.shared_secret,
+                        &bundle.signed_prekey,
+                        start.associated_data,
+                    ),
+                    pending_prekey: Some(PrekeyMessage {
+                        identity_key: identity.public_key(),
+                        ephemeral_key: start.ephemeral_public.to_bytes(),
+                        signed_prekey_id: bundle.signed_prekey_id,
+                        one_time_prekey_id: bundle.one_time_prekey.map(|(id, _)| id),
+                    }),
+                    remote_ephemeral: None,
+                },
+            );
+        }
+
+        let session = state
+            .sessions
+            .get_mut(conversation_id)
+            .ok_or(E2eeError::NoSession)?;
+        let (header, ciphertext) = session.ratchet.encrypt(plaintext.as_bytes())?;
+        let envelope = Envelope {
+            v: ENVELOPE_VERSION,
+            prekey: session.pending_prekey.clone(),
+            header,
+            ciphertext,
+        };
+        state
+            .plaintexts
+            .insert(message_id.to_string(), plaintext.to_string());
+        store::save(&self.path, &state)?;
+
+        serde_json::to_string(&envelope).map_err(|_| E2eeError::Malformed)
+    }
+
+    /// Decrypt the content of message `message_id` from `sender_id`
+    ///
+    /// Messages already decrypted, and the user's own messages sent from this
+    /// device, come from the local copy.
+    pub fn decrypt(
+        &self,
+        conversation_id: &str,
+        sender_id: &str,
+        message_id: &str,
+        content: &str,
+    ) -> Result<String, E2eeError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(plaintext) = state.plaintexts.get(message_id) {
+            return Ok(plaintext.clone());
+        }
+        if sender_id == self.user_id {
+            return Err(E2eeError::Decrypt("sent from another device".into()));
+        }
+
+        let envelope: Envelope = serde_json::from_str(content).map_err(|_| E2eeError::Malformed)?;
+        if envelope.v != ENVELOPE_VERSION {
+            return Err(E2eeError::Malformed);
+        }
+
+        let existing = state.sessions.get(conversation_id).filter(|s| {
+            s.peer_id == sender_id
+                && match &envelope.prekey {
+                    Some(prekey) => s.remote_ephemeral == Some(prekey.ephemeral_key),
+                    None => true,
+                }
+        });
+        let (mut session, used_prekey) = match (existing, &envelope.prekey) {
+            (Some(session), _) => (session.clone(), None),
+            // A new session started by the peer
+            (None, Some(prekey)) => self.accept_session(&state, sender_id, prekey)?,
+            (None, None) => return Err(E2eeError::NoSession),
+        };
+
+        let plaintext = session
+            .ratchet
+            .decrypt(&envelope.header, &envelope.ciphertext)?;
+        let plaintext =
+            String::from_utf8(plaintext).map_err(|_| E2eeError::Decrypt("not UTF-8".into()))?;
+
+        // The peer replied, so it has the session
+        if envelope.prekey.is_none() {
+            session.pending_prekey = None;
+        }
+        if let Some(key_id) = used_prekey {
+            state.one_time_prekeys.retain(|p| p.key_id != key_id);
+        }
+        state.sessions.insert(conversation_id.to_string(), session);
+        state
+            .plaintexts
+            .insert(message_id.to_string(), plaintext.clone());
+        store::save(&self.path, &state)?;
+        Ok(plaintext)
+    }
+
+    /// Decrypted text of a message seen before
+    pub fn plaintext(&self, message_id: &str) -> Option<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .plaintexts
+            .get(message_id)
+            .cloned()
+    }
+
+    pub fn has_session(&self, conversation_id: &str) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .sessions
+            .contains_key(conversation_id)
+    }
+
+    /// 60-digit safety number for the conversation's session, once there is one
+    pub fn safety_number(&self, conversation_id: &str) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        let session = state.sessions.get(conversation_id)?;
+        Some(safety::safety_number(
+            &self.user_id,
+            &self.identity_of(&state).public_key(),
+            &session.peer_id,
+            &session.peer_identity,
+        ))
+    }
+
+    /// Whether the user confirmed the peer's current identity key
+    pub fn is_verified(&self, conversation_id: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        state.sessions.get(conversation_id).is_some_and(|session| {
+            state.verified.get(&session.peer_id) == Some(&b64::encode(&session.peer_identity))
+        })
+    }
+
+    /// Record that the safety number was compared and matched
+    ///
+    /// Lapses if the peer's identity key changes.
+    pub fn mark_verified(&self, conversation_id: &str) -> Result<(), E2eeError> {
+        let mut state = self.state.lock().unwrap();
+        let session = state
+            .sessions
+            .get(conversation_id)
+            .ok_or(E2eeError::NoSession)?;
+        let (peer_id, identity) = (session.peer_id.clone(), b64::encode(&session.peer_identity));
+        state.verified.insert(peer_id, identity);
+        store::save(&self.path, &state)
+    }
+
+    fn identity(&self) -> IdentityKeyPair {
+        self.identity_of(&self.state.lock().unwrap())
+    }
+
+    fn identity_of(&self, state: &KeyState) -> IdentityKeyPair {
+        IdentityKeyPair::from_seed(&state.identity)
+    }
+
+    /// Responder side of a session the peer started; also returns the
+    /// one-time prekey it used up
+    fn accept_session(
+        &self,
+        state: &KeyState,
+        sender_id: &str,
+        prekey: &PrekeyMessage,
+    ) -> Result<(Session, Option<u32>), E2eeError> {
+        if prekey.signed_prekey_id != state.signed_prekey.key_id {
+            return Err(E2eeError::Decrypt("unknown signed prekey".into()));
+        }
+        let one_time = match prekey.one_time_prekey_id {
+            Some(key_id) => Some(
+                state
+                    .one_time_prekeys
+                    .iter()
+                    .find(|p| p.key_id == key_id)
+                    .ok_or_else(|| E2eeError::Decrypt("one-time prekey already used".into()))?
+                    .dh_secret(),
+            ),
+            None => None,
+        };
+
+        let signed_prekey = state.signed_prekey.dh_secret();
+        let (secret, associated_data) = x3dh::respond(
+            &self.identity_of(state),
+            &signed_prekey,
+            one_time.as_ref(),
+            &identity_dh_public(&prekey.identity_key)?,
+            &PublicKey::from(prekey.ephemeral_key),
+        );
+        Ok((
+            Session {
+                peer_id: sender_id.to_string(),
+                peer_identity: prekey.identity_key,
+                ratchet: Ratchet::responder(secret, &signed_prekey, associated_data),
+                pending_prekey: None,
+                remote_ephemeral: Some(prekey.ephemeral_key),
+            },
+            prekey.one_time_prekey_id,
+        ))
+    }
+}
+
+/// Base64 for keys on the wire and in the key file
+pub(crate) mod b64 {
+    use super::E2eeError;
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn encode(bytes: &[u8]) -> String {
+        STANDARD.encode(bytes)
+    }
+
+    pub fn decode_array<const N: usize>(value: &str) -> Result<[u8; N], E2eeError> {
+        STANDARD
+            .decode(value)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| E2eeError::InvalidKey(format!("expected {} bytes of base64", N)))
+    }
+
+    pub fn serialize<S: Serializer, T: AsRef<[u8]>>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&encode(value.as_ref()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: TryFrom<Vec<u8>>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        STANDARD
+            .decode(value)
+            .ok()
+            .and_then(|bytes| T::try_from(bytes).ok())
+            .ok_or_else(|| serde::de::Error::custom("invalid base64 key"))
+    }
+
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer, T: AsRef<[u8]>>(
+            value: &Option<T>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match value {
+                Some(value) => super::serialize(value, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>, T: TryFrom<Vec<u8>>>(
+            deserializer: D,
+        ) -> Result<Option<T>, D::Error> {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(with = "super")] Vec<u8>);
+
+            match Option::<Wrapper>::deserialize(deserializer)? {
+                Some(Wrapper(bytes)) => T::try_from(bytes)
+                    .map(Some)
+                    .map_err(|_| serde::de::Error::custom("invalid key length")),
+                None => Ok(None),
+            }
+        }
+    }
+}
@@ -0,0 +1,333 @@
+//! Double ratchet
+//!
+//! Follows the Signal specification: a Diffie-Hellman ratchet step whenever
+//! the peer's ratchet key changes, and a symmetric chain step per message, so
+//! every message has its own key and old keys cannot be recovered. Message
+//! keys for messages that arrive out of order are kept until those messages
+//! turn up, within `MAX_SKIP` per chain.
+
+use super::{b64, E2eeError};
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// Most message keys skipped in one chain
+pub const MAX_SKIP: u32 = 1000;
+
+/// Most skipped message keys kept per session; the oldest are dropped first
+const MAX_STORED_SKIPPED: usize = 2000;
+
+/// Sent in the clear with each message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    /// Sender's current ratchet public key
+    #[serde(with = "b64")]
+    pub dh: [u8; 32],
+    /// Length of the sender's previous sending chain
+    pub pn: u32,
+    /// Message number in the current sending chain
+    pub n: u32,
+}
+
+impl Header {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.dh.to_vec();
+        bytes.extend_from_slice(&self.pn.to_be_bytes());
+        bytes.extend_from_slice(&self.n.to_be_bytes());
+        bytes
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SkippedKey {
+    #[serde(with = "b64")]
+    dh: [u8; 32],
+    n: u32,
+    #[serde(with = "b64")]
+    key: [u8; 32],
+}
+
+/// Ratchet state of one session
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Ratchet {
+    #[serde(with = "b64")]
+    dh_secret: [u8; 32],
+    #[serde(with = "b64::option")]
+    dh_remote: Option<[u8; 32]>,
+    #[serde(with = "b64")]
+    root_key: [u8; 32],
+    #[serde(with = "b64::option")]
+    sending_chain: Option<[u8; 32]>,
+    #[serde(with = "b64::option")]
+    receiving_chain: Option<[u8; 32]>,
+    sent: u32,
+    received: u32,
+    previous_sent: u32,
+    skipped: Vec<SkippedKey>,
+    #[serde(with = "b64")]
+    associated_data: Vec<u8>,
+}
+
+impl Ratchet {
+    /// Initiator state; `remote` is the responder's signed prekey
+    pub fn initiator(
+        shared_secret: [u8; 32],
+        remote: &PublicKey,
+        associated_data: Vec<u8>,
+    ) -> Self {
+        let dh_secret = StaticSecret::random_from_rng(OsRng);
+        let (root_key, sending_chain) =
+            kdf_root(&shared_secret, dh_secret.diffie_hellman(remote).as_bytes());
+        Self {
+            dh_secret: dh_secret.to_bytes(),
+            dh_remote: Some(remote.to_bytes()),
+            root_key,
+            sending_chain: Some(sending_chain),
+            receiving_chain: None,
+            sent: 0,
+            received: 0,
+            previous_sent: 0,
+            skipped: Vec::new(),
+            associated_data,
+        }
+    }
+
+    /// Responder state; the signed prekey is the first ratchet key
+    pub fn responder(
+        shared_secret: [u8; 32],
+        signed_prekey: &StaticSecret,
+        associated_data: Vec<u8>,
+    ) -> Self {
+        Self {
+            dh_secret: signed_prekey.to_bytes(),
+            dh_remote: None,
+            root_key: shared_secret,
+            sending_chain: None,
+            receiving_chain: None,
+            sent: 0,
+            received: 0,
+            previous_sent: 0,
+            skipped: Vec::new(),
+            associated_data,
+        }
+    }
+
+    /// Whether this side can send before it has received anything
+    pub fn can_send(&self) -> bool {
+        self.sending_chain.is_some()
+    }
+
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<(Header, Vec<u8>), E2eeError> {
+        let chain = self.sending_chain.ok_or(E2eeError::NoSession)?;
+        let (next, message_key) = kdf_chain(&chain);
+        self.sending_chain = Some(next);
+
+        let header = Header {
+            dh: PublicKey::from(&StaticSecret::from(self.dh_secret)).to_bytes(),
+            pn: self.previous_sent,
+            n: self.sent,
+        };
+        self.sent += 1;
+        let ciphertext = seal(&message_key, plaintext, &self.aad(&header))?;
+        Ok((header, ciphertext))
+    }
+
+    /// Decrypt a message; the state is only changed if decryption succeeds
+    pub fn decrypt(&mut self, header: &Header, ciphertext: &[u8]) -> Result<Vec<u8>, E2eeError> {
+        let mut next = self.clone();
+        let plaintext = next.decrypt_in_place(header, ciphertext)?;
+        *self = next;
+        Ok(plaintext)
+    }
+
+    fn decrypt_in_place(
+        &mut self,
+        header: &Header,
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, E2eeError> {
+        let aad = self.aad(header);
+        if let Some(index) = self
+            .skipped
+            .iter()
+            .position(|k| k.dh == header.dh && k.n == header.n)
+        {
+            let skipped = self.skipped.remove(index);
+            return open(&skipped.key, ciphertext, &aad);
+        }
+
+        if self.dh_remote != Some(header.dh) {
+            self.skip_until(header.pn)?;
+            self.dh_step(header);
+        }
+        self.skip_until(header.n)?;
+
+        let chain = self.receiving_chain.ok_or(E2eeError::NoSession)?;
+        let (next, message_key) = kdf_chain(&chain);
+        self.receiving_chain = Some(next);
+        self.received += 1;
+        open(&message_key, ciphertext, &aad)
+    }
+
+    /// Store keys for messages of the receiving chain before number `until`
+    fn skip_until(&mut self, until: u32) -> Result<(), E2eeError> {
+        let (Some(mut chain), Some(dh)) = (self.receiving_chain, self.dh_remote) else {
+            return Ok(());
+        };
+        if until > self.received + MAX_SKIP {
+            return Err(E2eeError::Decrypt("too many skipped messages".into()));
+        }
+        while self.received < until {
+            let (next, key) = kdf_chain(&chain);
+            self.skipped.push(SkippedKey {
+                dh,
+                n: self.received,
+                key,
+            });
+            chain = next;
+            self.received += 1;
+        }
+        self.receiving_chain = Some(chain);
+
+        let excess = self.skipped.len().saturating_sub(MAX_STORED_SKIPPED);
+        self.skipped.drain(..excess);
+        Ok(())
+    }
+
+    fn dh_step(&mut self, header: &Header) {
+        let remote = PublicKey::from(header.dh);
+        self.previous_sent = self.sent;
+        self.sent = 0;
+        self.received = 0;
+        self.dh_remote = Some(header.dh);
+
+        let secret = StaticSecret::from(self.dh_secret);
+        let (root_key, receiving) =
+            kdf_root(&self.root_key, secret.diffie_hellman(&remote).as_bytes());
+
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let (root_key, sending) = kdf_root(&root_key, secret.diffie_hellman(&remote).as_bytes());
+        self.dh_secret = secret.to_bytes();
+        self.root_key = root_key;
+        self.receiving_chain = Some(receiving);
+        self.sending_chain = Some(sending);
+    }
+
+    fn aad(&self, header: &Header) -> Vec<u8> {
+        [self.associated_data.as_slice(), &header.to_bytes()].concat()
+    }
+}
+
+fn kdf_root(root_key: &[u8; 32], dh_output: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let mut output = [0u8; 64];
+    Hkdf::<Sha256>::new(Some(root_key), dh_output)
+        .expand(b"chat-app ratchet", &mut output)
+        .expect("64 bytes is a valid HKDF-SHA256 length");
+    let (root, chain) = output.split_at(32);
+    (root.try_into().unwrap(), chain.try_into().unwrap())
+}
+
+/// Next chain key and this step's message key
+fn kdf_chain(chain_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let step = |constant: u8| {
+        let mut mac =
+            <Hmac<Sha256> as Mac>::new_from_slice(chain_key).expect("HMAC accepts any key length");
+        mac.update(&[constant]);
+        let out: [u8; 32] = mac.finalize().into_bytes().into();
+        out
+    };
+    (step(0x02), step(0x01))
+}
+
+/// Cipher key and nonce for a message key
+fn message_cipher(message_key: &[u8; 32]) -> (ChaCha20Poly1305, [u8; 12]) {
+    let mut output = [0u8; 44];
+    Hkdf::<Sha256>::new(None, message_key)
+        .expand(b"chat-app message", &mut output)
+        .expect("44 bytes is a valid HKDF-SHA256 length");
+    let cipher = ChaCha20Poly1305::new_from_slice(&output[..32]).expect("32-byte key");
+    (cipher, output[32..].try_into().unwrap())
+}
+
+fn seal(message_key: &[u8; 32], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, E2eeError> {
+    let (cipher, nonce) = message_cipher(message_key);
+    cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| E2eeError::Decrypt("encryption failed".into()))
+}
+
+fn open(message_key: &[u8; 32], ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>, E2eeError> {
+    let (cipher, nonce) = message_cipher(message_key);
+    cipher
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| E2eeError::Decrypt("message authentication failed".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair() -> (Ratchet, Ratchet) {
+        let secret = [42u8; 32];
+        let bob_prekey = StaticSecret::random_from_rng(OsRng);
+        let alice = Ratchet::initiator(secret, &PublicKey::from(&bob_prekey), b"ad".to_vec());
+        let bob = Ratchet::responder(secret, &bob_prekey, b"ad".to_vec());
+        (alice, bob)
+    }
+
+    #[test]
+    fn test_conversation_round_trip() {
+        let (mut alice, mut bob) = pair();
+        assert!(!bob.can_send());
+
+        for round in 0..3 {
+            let text = format!("hello {}", round);
+            let (header, ciphertext) = alice.encrypt(text.as_bytes()).unwrap();
+            assert_eq!(bob.decrypt(&header, &ciphertext).unwrap(), text.as_bytes());
+
+            let (header, ciphertext) = bob.encrypt(b"reply").unwrap();
+            assert_eq!(alice.decrypt(&header, &ciphertext).unwrap(), b"reply");
+        }
+
+        // Survives being stored and loaded
+        let mut bob: Ratchet = serde_json::from_str(&serde_json::to_string(&bob).unwrap()).unwrap();
+        let (header, ciphertext) = alice.encrypt(b"stored").unwrap();
+        assert_eq!(bob.decrypt(&header, &ciphertext).unwrap(), b"stored");
+    }
+
+    #[test]
+    fn test_out_of_order_and_tampered_messages() {
+        let (mut alice, mut bob) = pair();
+        let first = alice.encrypt(b"one").unwrap();
+        let second = alice.encrypt(b"two").unwrap();
+        let third = alice.encrypt(b"three").unwrap();
+
+        assert_eq!(bob.decrypt(&third.0, &third.1).unwrap(), b"three");
+        assert_eq!(bob.decrypt(&first.0, &first.1).unwrap(), b"one");
+
+        // A tampered message fails and leaves the state usable
+        let mut tampered = second.1.clone();
+        tampered[0] ^= 1;
+        assert!(bob.decrypt(&second.0, &tampered).is_err());
+        assert_eq!(bob.decrypt(&second.0, &second.1).unwrap(), b"two");
+
+        // Each message key is used once
+        assert!(bob.decrypt(&second.0, &second.1).is_err());
+    }
+}
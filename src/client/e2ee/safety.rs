@@ -0,0 +1,77 @@
+//! Safety numbers
+//!
+//! Two users compare the same 60 digits, read aloud or side by side, to check
+//! that nobody swapped their identity keys in transit. Each half is a slow
+//! hash of one user's identity key and ID, so the number changes when either
+//! of them gets a new key.
+
+use sha2::{Digest, Sha512};
+
+const ITERATIONS: usize = 5200;
+
+/// Safety number for two identities, the same from either side
+pub fn safety_number(
+    local_id: &str,
+    local_identity: &[u8; 32],
+    remote_id: &str,
+    remote_identity: &[u8; 32],
+) -> String {
+    let mut halves = [
+        fingerprint(local_id, local_identity),
+        fingerprint(remote_id, remote_identity),
+    ];
+    halves.sort();
+    halves.concat()
+}
+
+/// Safety number in groups of five digits
+pub fn format_safety_number(number: &str) -> String {
+    number
+        .as_bytes()
+        .chunks(5)
+        .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 30 digits for one identity
+fn fingerprint(user_id: &str, identity: &[u8; 32]) -> String {
+    let mut hash = Sha512::new()
+        .chain_update(0u16.to_be_bytes())
+        .chain_update(identity)
+        .chain_update(user_id.as_bytes())
+        .finalize();
+    for _ in 0..ITERATIONS {
+        hash = Sha512::new()
+            .chain_update(hash)
+            .chain_update(identity)
+            .finalize();
+    }
+
+    hash[..30]
+        .chunks(5)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |acc, b| (acc << 8) | u64::from(*b));
+            format!("{:05}", value % 100_000)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safety_number_is_symmetric() {
+        let alice = [1u8; 32];
+        let bob = [2u8; 32];
+        let number = safety_number("alice", &alice, "bob", &bob);
+        assert_eq!(number.len(), 60);
+        assert!(number.bytes().all(|b| b.is_ascii_digit()));
+        assert_eq!(number, safety_number("bob", &bob, "alice", &alice));
+        assert_ne!(number, safety_number("alice", &alice, "bob", &[3u8; 32]));
+
+        let formatted = format_safety_number(&number);
+        assert_eq!(formatted.split(' ').count(), 12);
+    }
+}
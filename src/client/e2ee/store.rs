@@ -0,0 +1,86 @@
+//! Local key storage
+//!
+//! Private keys, ratchet sessions and decrypted messages never leave the
+//! device. They live in one JSON file per user next to the session file,
+//! readable only by the owner on Unix.
+
+use super::keys::PrekeySecret;
+use super::ratchet::Ratchet;
+use super::{b64, E2eeError, PrekeyMessage};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Everything the client keeps for end-to-end encryption
+#[derive(Serialize, Deserialize)]
+pub(crate) struct KeyState {
+    /// Ed25519 seed of the identity key
+    #[serde(with = "b64")]
+    pub identity: [u8; 32],
+    pub signed_prekey: PrekeySecret,
+    /// One-time prekeys handed to the server and not yet used
+    pub one_time_prekeys: Vec<PrekeySecret>,
+    pub next_prekey_id: u32,
+    /// Whether the server has this identity and signed prekey
+    pub published: bool,
+    /// By conversation ID
+    pub sessions: HashMap<String, Session>,
+    /// Identity key each peer had when it was verified, by user ID
+    pub verified: HashMap<String, String>,
+    /// Plaintext by message ID; message keys are single-use, so history is
+    /// shown from here rather than decrypted again
+    pub plaintexts: HashMap<String, String>,
+}
+
+/// Double-ratchet session with the other participant of a conversation
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Session {
+    pub peer_id: String,
+    #[serde(with = "b64")]
+    pub peer_identity: [u8; 32],
+    pub ratchet: Ratchet,
+    /// Sent along with each message until the peer first replies, so it can
+    /// set up its side of the session from any of them
+    pub pending_prekey: Option<PrekeyMessage>,
+    /// Ephemeral key of the prekey message that started this session, if
+    /// the peer started it
+    #[serde(with = "b64::option")]
+    pub remote_ephemeral: Option<[u8; 32]>,
+}
+
+pub(crate) fn load(path: &Path) -> Result<Option<KeyState>, E2eeError> {
+    match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| E2eeError::Storage(format!("Failed to parse key file: {}", e))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(E2eeError::Storage(format!(
+            "Failed to read key file: {}",
+            e
+        ))),
+    }
+}
+
+/// Write the state atomically, so a crash never leaves half a key file
+pub(crate) fn save(path: &Path, state: &KeyState) -> Result<(), E2eeError> {
+    let storage =
+        |e: std::io::Error| E2eeError::Storage(format!("Failed to write key file: {}", e));
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(storage)?;
+    }
+    let json = serde_json::to_vec(state)
+        .map_err(|e| E2eeError::Storage(format!("Failed to serialize keys: {}", e)))?;
+
+    let temp = path.with_extension("json.tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&temp).map_err(storage)?;
+    std::io::Write::write_all(&mut file, &json).map_err(storage)?;
+    file.sync_all().map_err(storage)?;
+    std::fs::rename(&temp, path).map_err(storage)
+}
@@ -0,0 +1,121 @@
+//! X3DH key agreement
+//!
+//! The initiator combines its identity and a fresh ephemeral key with the
+//! responder's identity, signed prekey and (if one was left) one-time prekey.
+//! Both sides derive the same secret, which seeds the double ratchet.
+
+use super::keys::{IdentityKeyPair, VerifiedBundle};
+use hkdf::Hkdf;
+use rand_core::OsRng;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const INFO: &[u8] = b"chat-app X3DH";
+
+/// Result of starting a session from a peer's bundle
+pub struct Initiation {
+    pub shared_secret: [u8; 32],
+    pub ephemeral_public: PublicKey,
+    /// Both identity keys, bound into every message as associated data
+    pub associated_data: Vec<u8>,
+}
+
+/// Agree on a secret with the owner of `bundle`
+pub fn initiate(identity: &IdentityKeyPair, bundle: &VerifiedBundle) -> Initiation {
+    let ephemeral = StaticSecret::random_from_rng(OsRng);
+    let identity_secret = identity.dh_secret();
+
+    let mut dh = Vec::with_capacity(4 * 32);
+    dh.extend_from_slice(
+        identity_secret
+            .diffie_hellman(&bundle.signed_prekey)
+            .as_bytes(),
+    );
+    dh.extend_from_slice(ephemeral.diffie_hellman(&bundle.identity_dh).as_bytes());
+    dh.extend_from_slice(ephemeral.diffie_hellman(&bundle.signed_prekey).as_bytes());
+    if let Some((_, one_time)) = &bundle.one_time_prekey {
+        dh.extend_from_slice(ephemeral.diffie_hellman(one_time).as_bytes());
+    }
+
+    Initiation {
+        shared_secret: derive(&dh),
+        ephemeral_public: PublicKey::from(&ephemeral),
+        associated_data: associated_data(&identity.dh_public(), &bundle.identity_dh),
+    }
+}
+
+/// Derive the initiator's secret on the responding side
+pub fn respond(
+    identity: &IdentityKeyPair,
+    signed_prekey: &StaticSecret,
+    one_time_prekey: Option<&StaticSecret>,
+    initiator_identity: &PublicKey,
+    initiator_ephemeral: &PublicKey,
+) -> ([u8; 32], Vec<u8>) {
+    let mut dh = Vec::with_capacity(4 * 32);
+    dh.extend_from_slice(signed_prekey.diffie_hellman(initiator_identity).as_bytes());
+    dh.extend_from_slice(
+        identity
+            .dh_secret()
+            .diffie_hellman(initiator_ephemeral)
+            .as_bytes(),
+    );
+    dh.extend_from_slice(signed_prekey.diffie_hellman(initiator_ephemeral).as_bytes());
+    if let Some(one_time) = one_time_prekey {
+        dh.extend_from_slice(one_time.diffie_hellman(initiator_ephemeral).as_bytes());
+    }
+
+    (
+        derive(&dh),
+        associated_data(initiator_identity, &identity.dh_public()),
+    )
+}
+
+fn associated_data(initiator: &PublicKey, responder: &PublicKey) -> Vec<u8> {
+    [initiator.as_bytes().as_slice(), responder.as_bytes()].concat()
+}
+
+fn derive(dh: &[u8]) -> [u8; 32] {
+    // 32 0xFF bytes first, as X3DH specifies for X25519
+    let ikm = [[0xFF; 32].as_slice(), dh].concat();
+    let mut secret = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&[0u8; 32]), &ikm)
+        .expand(INFO, &mut secret)
+        .expect("32 bytes is a valid HKDF-SHA256 length");
+    secret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::e2ee::keys::PrekeySecret;
+
+    #[test]
+    fn test_both_sides_agree() {
+        let alice = IdentityKeyPair::generate();
+        let bob = IdentityKeyPair::generate();
+        let signed = PrekeySecret::generate(1);
+        let one_time = PrekeySecret::generate(2);
+
+        for with_one_time in [true, false] {
+            let bundle = VerifiedBundle {
+                identity: bob.public_key(),
+                identity_dh: bob.dh_public(),
+                signed_prekey_id: 1,
+                signed_prekey: signed.public_key(),
+                one_time_prekey: with_one_time.then(|| (2, one_time.public_key())),
+            };
+            let start = initiate(&alice, &bundle);
+            let one_time_secret = one_time.dh_secret();
+            let (secret, ad) = respond(
+                &bob,
+                &signed.dh_secret(),
+                with_one_time.then_some(&one_time_secret),
+                &alice.dh_public(),
+                &start.ephemeral_public,
+            );
+            assert_eq!(secret, start.shared_secret);
+            assert_eq!(ad, start.associated_data);
+        }
+    }
+}
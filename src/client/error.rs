@@ -0,0 +1,93 @@
+//! Client error type
+
+use thiserror::Error;
+
+/// Why a request to the server failed
+#[derive(Debug, Error)]
+pub enum ClientError {
+    /// The request never got a response
+    #[error("Network error: {0}")]
+    Network(#[from] reqwest::Error),
+
+    /// The server answered with an error status
+    #[error("{message}")]
+    Api {
+        status: u16,
+        /// Machine-readable code, e.g. `VALIDATION_ERROR`
+        code: String,
+        message: String,
+    },
+
+    /// The response body did not match the expected type
+    #[error("Failed to parse response: {0}")]
+    Decode(String),
+
+    /// The route needs a token and none is set
+    #[error("Not logged in")]
+    NotAuthenticated,
+}
+
+impl ClientError {
+    /// HTTP status of an API error
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ClientError::Api { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Whether sending the same request again may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ClientError::Network(_) => true,
+            ClientError::Api { status, .. } => *status >= 500 || *status == 429,
+            ClientError::Decode(_) | ClientError::NotAuthenticated => false,
+        }
+    }
+
+    /// Build an API error from a response status and body
+    ///
+    /// Handlers answer `{"error", "message"}` and typed rejections
+    /// `{"code", "message"}`; anything else keeps the status as its message.
+    pub(crate) fn from_response(status: u16, body: &[u8]) -> Self {
+        let body: serde_json::Value = serde_json::from_slice(body).unwrap_or_default();
+        let code = match body.get("code").or_else(|| body.get("error")) {
+            Some(serde_json::Value::String(code)) => code.clone(),
+            Some(other) if !other.is_null() => other.to_string(),
+            _ => status.to_string(),
+        };
+        let message = body
+            .get("message")
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("Request failed with status {}", status));
+        ClientError::Api {
+            status,
+            code,
+            message,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_bodies_are_parsed() {
+        let err = ClientError::from_response(
+            409,
+            br#"{"error":"USERNAME_TAKEN","message":"Username already exists"}"#,
+        );
+        assert_eq!(err.to_string(), "Username already exists");
+        assert!(matches!(err, ClientError::Api { ref code, .. } if code == "USERNAME_TAKEN"));
+        assert!(!err.is_retryable());
+
+        let err = ClientError::from_response(400, br#"{"code":"INVALID_FRAME","message":"bad"}"#);
+        assert!(matches!(err, ClientError::Api { ref code, .. } if code == "INVALID_FRAME"));
+
+        let err = ClientError::from_response(502, b"<html>Bad Gateway</html>");
+        assert_eq!(err.status(), Some(502));
+        assert!(err.is_retryable());
+    }
+}
@@ -0,0 +1,25 @@
+//! Chat client SDK
+//!
+//! Typed async access to the chat server for the desktop app, integration tests
+//! and bots:
+//! - [`ChatClient`] calls every REST route with the request and response types
+//!   the server documents at `/openapi.json`
+//! - [`WebSocketClient`] keeps a realtime connection open, reconnecting with
+//!   backoff, resuming missed events and falling back to server-sent events
+//!   when WebSocket upgrades are blocked
+//! - [`SessionManager`] stores the logged-in session where every client finds it
+//! - [`e2ee`] encrypts one-to-one conversations end to end
+
+pub mod e2ee;
+pub mod error;
+pub mod realtime;
+pub mod rest;
+pub mod session;
+pub mod sse;
+pub mod types;
+
+pub use error::ClientError;
+pub use realtime::{ConnectionStatus, TokenProvider, WebSocketClient, WebSocketEvent, WireOptions};
+pub use rest::ChatClient;
+pub use session::{SessionData, SessionManager};
+pub use types::*;
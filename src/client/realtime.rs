@@ -0,0 +1,1207 @@
+//! WebSocket client for sending/receiving chat messages and typing indicators.
+//!
+//! Runs as a background Tokio task and communicates with the caller through channels.
+//! Reconnects with backoff, resumes missed events after a reconnect, and falls back
+//! to server-sent events plus REST sends when upgrades keep failing.
+
+use crate::rest::ChatClient;
+use crate::sse;
+use chat_shared::protocol::compression::{
+    self, PayloadKind, COMPRESSION_QUERY_PARAM, DEFAULT_COMPRESSION_THRESHOLD, FRAME_DEFLATE_V1,
+};
+use chat_shared::protocol::{
+    ClientFrame, MessageEnvelope, ResumeData, ServerFrame, TextMessageData, TraceParent,
+    TypingData, WireEncoding, CLOSE_SERVICE_RESTART, CLOSE_UPDATE_REQUIRED, ENCODING_QUERY_PARAM,
+    HEARTBEAT_INTERVAL_HEADER, MIN_PROTOCOL_VERSION, PROTOCOL_QUERY_PARAM, PROTOCOL_VERSION,
+};
+use futures::{SinkExt, StreamExt};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+
+/// Events emitted by the WebSocket client.
+#[derive(Debug, Clone)]
+pub enum WebSocketEvent {
+    /// Connection status changed.
+    ConnectionState(ConnectionStatus),
+    /// A chat message was received from the server.
+    Message {
+        conversation_id: String,
+        message_id: String,
+        sender_id: Option<String>,
+        sender_username: String,
+        content: String,
+        status: String,
+        timestamp: u64,
+        /// `content` is an end-to-end encrypted envelope; see [`crate::e2ee`]
+        encrypted: bool,
+    },
+    /// An acknowledgement for a message we sent.
+    Ack {
+        message_id: Option<String>,
+        status: String,
+        conversation_id: Option<String>,
+    },
+    /// Typing indicator from the remote participant.
+    Typing {
+        sender_id: Option<String>,
+        sender_username: String,
+        recipient_id: String,
+        is_typing: bool,
+    },
+    /// User online status update.
+    Presence {
+        user_id: String,
+        username: String,
+        is_online: bool,
+        last_seen_at: u64,
+    },
+    /// Disappearing messages were deleted by the server; drop them too.
+    MessageExpired {
+        conversation_id: String,
+        message_ids: Vec<String>,
+    },
+    /// Events were missed while disconnected and cannot be replayed; reload state.
+    ResyncRequired,
+    /// A frame could not be handled or a send was refused.
+    Error(String),
+}
+
+/// Connection lifecycle states.
+#[derive(Debug, Clone)]
+pub enum ConnectionStatus {
+    Connecting,
+    Connected,
+    Reconnecting { retry_in_ms: u64 },
+    Disconnected { reason: String },
+    /// The server no longer supports this client; reconnecting will not help.
+    UpdateRequired { message: String },
+}
+
+/// Commands sent from the caller into the WebSocket client.
+enum WebSocketCommand {
+    SendMessage {
+        message_id: String,
+        conversation_id: String,
+        recipient_id: String,
+        content: String,
+        encrypted: bool,
+    },
+    SendTyping {
+        recipient_id: String,
+        is_typing: bool,
+    },
+    Disconnect,
+}
+
+/// Optional wire features requested during the upgrade.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WireOptions {
+    pub encoding: WireEncoding,
+    /// Ask for per-message compression of large frames.
+    pub compression: bool,
+}
+
+/// Supplies the token for each connection attempt, so a refreshed token is used
+/// after a reconnect
+pub type TokenProvider = Arc<dyn Fn() -> String + Send + Sync>;
+
+/// Handle to interact with the WebSocket client.
+#[derive(Clone)]
+pub struct WebSocketClient {
+    command_tx: mpsc::UnboundedSender<WebSocketCommand>,
+}
+
+impl WebSocketClient {
+    /// Connect to the WebSocket server and start background processing.
+    ///
+    /// `websocket_url` is the socket endpoint, e.g. `ws://localhost:8080/socket`.
+    /// `options` are requested during the upgrade; frames are sent as plain JSON
+    /// until the server confirms them.
+    pub fn connect(
+        websocket_url: String,
+        token: TokenProvider,
+        options: WireOptions,
+        event_tx: mpsc::UnboundedSender<WebSocketEvent>,
+        runtime: &tokio::runtime::Handle,
+    ) -> Self {
+        let (command_tx, mut command_rx) = mpsc::unbounded_channel::<WebSocketCommand>();
+
+        // Run the WebSocket loop on the provided runtime.
+        runtime.spawn(async move {
+            let mut pending: VecDeque<WebSocketCommand> = VecDeque::new();
+            let mut attempt: usize = 0;
+            // Reconnect delay requested by the server when it restarts
+            let mut restart_delay: Option<Duration> = None;
+            let mut resume = ResumeState::default();
+            // Consecutive upgrades refused by something between us and the server
+            let mut upgrade_failures: usize = 0;
+            let mut use_event_stream = false;
+            loop {
+                // Capture any queued commands before attempting a connection.
+                while let Ok(cmd) = command_rx.try_recv() {
+                    if matches!(cmd, WebSocketCommand::Disconnect) {
+                        return;
+                    }
+                    pending.push_back(cmd);
+                }
+
+                let token_to_use = token();
+                if use_event_stream {
+                    match run_event_stream(
+                        &websocket_url,
+                        &token_to_use,
+                        &mut pending,
+                        &mut command_rx,
+                        &event_tx,
+                        &mut resume,
+                        &mut restart_delay,
+                    )
+                    .await
+                    {
+                        StreamEnd::Stop => return,
+                        StreamEnd::Closed { connected: true } => attempt = 0,
+                        StreamEnd::Closed { connected: false } => {
+                            // Server unreachable either way; give WebSocket another chance
+                            use_event_stream = false;
+                            upgrade_failures = 0;
+                        }
+                    }
+                    let backoff = restart_delay.take().unwrap_or_else(|| {
+                        attempt += 1;
+                        calculate_backoff(attempt)
+                    });
+                    let _ = event_tx.send(WebSocketEvent::ConnectionState(
+                        ConnectionStatus::Reconnecting {
+                            retry_in_ms: backoff.as_millis() as u64,
+                        },
+                    ));
+                    tokio::time::sleep(backoff).await;
+                    continue;
+                }
+
+                let mut connect_url = format!(
+                    "{}?token={}&{}={}",
+                    websocket_url,
+                    token_to_use,
+                    PROTOCOL_QUERY_PARAM,
+                    supported_protocol_versions()
+                );
+                if options.encoding != WireEncoding::Json {
+                    connect_url.push_str(&format!("&{}={}", ENCODING_QUERY_PARAM, options.encoding));
+                }
+                if options.compression {
+                    connect_url.push_str(&format!("&{}={}", COMPRESSION_QUERY_PARAM, FRAME_DEFLATE_V1));
+                }
+                let _ = event_tx.send(WebSocketEvent::ConnectionState(ConnectionStatus::Connecting));
+
+                match connect_async(&connect_url).await {
+                    Ok((ws_stream, response)) => {
+                        attempt = 0;
+                        upgrade_failures = 0;
+                        let liveness_timeout = liveness_timeout(
+                            response
+                                .headers()
+                                .get(HEARTBEAT_INTERVAL_HEADER)
+                                .and_then(|v| v.to_str().ok()),
+                        );
+                        let mut last_inbound = tokio::time::Instant::now();
+                        let _ = event_tx
+                            .send(WebSocketEvent::ConnectionState(ConnectionStatus::Connected));
+                        let (mut ws_write, mut ws_read) = ws_stream.split();
+
+                        resume.reset();
+                        let mut update_message: Option<String> = None;
+                        // Upgraded once the server confirms the requested options
+                        let mut outbound = OutboundFormat::default();
+
+                        if let Err(e) =
+                            flush_queue(&mut ws_write, &mut pending, &event_tx, outbound).await
+                        {
+                            let _ = event_tx.send(WebSocketEvent::ConnectionState(
+                                ConnectionStatus::Disconnected {
+                                    reason: format!("Send failed: {}", e),
+                                },
+                            ));
+                            continue;
+                        }
+
+                        // Main loop: fan-in reads and commands.
+                        loop {
+                            tokio::select! {
+                                Some(cmd) = command_rx.recv() => {
+                                    if matches!(cmd, WebSocketCommand::Disconnect) {
+                                        let _ = ws_write.send(Message::Close(None)).await;
+                                        return;
+                                    }
+                                    pending.push_back(cmd);
+                                    if let Err(e) = flush_queue(&mut ws_write, &mut pending, &event_tx, outbound).await {
+                                        let _ = event_tx.send(WebSocketEvent::ConnectionState(ConnectionStatus::Disconnected { reason: format!("Send failed: {}", e) }));
+                                        break;
+                                    }
+                                }
+                                _ = tokio::time::sleep_until(last_inbound + liveness_timeout) => {
+                                    // No pings or data from the server: assume a dead link
+                                    let _ = event_tx.send(WebSocketEvent::ConnectionState(ConnectionStatus::Disconnected { reason: "Server not responding".to_string() }));
+                                    break;
+                                }
+                                msg = ws_read.next() => {
+                                    last_inbound = tokio::time::Instant::now();
+                                    match msg.as_ref().and_then(|m| m.as_ref().ok()).and_then(frame_payload) {
+                                        Some(Ok((payload, frame_encoding))) => {
+                                            // The server only answers in MessagePack if it accepted it
+                                            if frame_encoding.is_binary() {
+                                                outbound.encoding = options.encoding;
+                                            }
+                                            match handle_incoming_frame(&payload, frame_encoding, &event_tx, &mut resume) {
+                                                InboundAction::None => {}
+                                                InboundAction::Restart(delay) => restart_delay = Some(delay),
+                                                InboundAction::Welcome { compression, resume: request } => {
+                                                    outbound.compress = compression && options.compression;
+                                                    if let Some(frame) = request.and_then(|r| encode_frame(&r, outbound).ok()) {
+                                                        let _ = ws_write.send(frame).await;
+                                                    }
+                                                }
+                                                InboundAction::UpdateRequired(message) => update_message = Some(message),
+                                            }
+                                            continue;
+                                        }
+                                        Some(Err(e)) => {
+                                            let _ = event_tx.send(WebSocketEvent::Error(e));
+                                            continue;
+                                        }
+                                        None => {}
+                                    }
+                                    match msg {
+                                        Some(Ok(Message::Ping(p))) => {
+                                            let _ = ws_write.send(Message::Pong(p)).await;
+                                        }
+                                        Some(Ok(Message::Close(frame))) => {
+                                            if frame.as_ref().is_some_and(|f| u16::from(f.code) == CLOSE_UPDATE_REQUIRED) {
+                                                let message = update_message.take().unwrap_or_else(|| DEFAULT_UPDATE_MESSAGE.to_string());
+                                                let _ = event_tx.send(WebSocketEvent::ConnectionState(ConnectionStatus::UpdateRequired { message }));
+                                                return;
+                                            }
+                                            let restarting = frame
+                                                .as_ref()
+                                                .is_some_and(|f| u16::from(f.code) == CLOSE_SERVICE_RESTART);
+                                            let reason = if restarting {
+                                                restart_delay.get_or_insert(DEFAULT_RESTART_DELAY);
+                                                "Server restarting".to_string()
+                                            } else {
+                                                "Server closed connection".to_string()
+                                            };
+                                            let _ = event_tx.send(WebSocketEvent::ConnectionState(ConnectionStatus::Disconnected { reason }));
+                                            break;
+                                        }
+                                        Some(Err(e)) => {
+                                            let _ = event_tx.send(WebSocketEvent::ConnectionState(ConnectionStatus::Disconnected { reason: format!("WebSocket error: {}", e) }));
+                                            break;
+                                        }
+                                        None => {
+                                            let _ = event_tx.send(WebSocketEvent::ConnectionState(ConnectionStatus::Disconnected { reason: "Connection dropped".to_string() }));
+                                            break
+                                        },
+                                        _ => {}
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        let _ = event_tx.send(WebSocketEvent::ConnectionState(
+                            ConnectionStatus::Disconnected {
+                                reason: format!("Connect failed: {}", e),
+                            },
+                        ));
+                        if is_upgrade_failure(&e) {
+                            upgrade_failures += 1;
+                            if upgrade_failures >= EVENT_STREAM_FALLBACK_AFTER {
+                                tracing::warn!(
+                                    failures = upgrade_failures,
+                                    "WebSocket upgrades keep failing, falling back to server-sent events"
+                                );
+                                use_event_stream = true;
+                            }
+                        } else {
+                            upgrade_failures = 0;
+                        }
+                    }
+                }
+
+                // Honour the server's restart delay; otherwise back off exponentially.
+                let backoff = match restart_delay.take() {
+                    Some(delay) => {
+                        attempt = 0;
+                        delay
+                    }
+                    None => {
+                        attempt += 1;
+                        calculate_backoff(attempt)
+                    }
+                };
+                let _ = event_tx.send(WebSocketEvent::ConnectionState(
+                    ConnectionStatus::Reconnecting {
+                        retry_in_ms: backoff.as_millis() as u64,
+                    },
+                ));
+                tokio::time::sleep(backoff).await;
+            }
+        });
+
+        Self { command_tx }
+    }
+
+    /// Send a chat message.
+    pub fn send_message(
+        &self,
+        message_id: String,
+        conversation_id: String,
+        recipient_id: String,
+        content: String,
+    ) -> Result<(), String> {
+        self.command_tx
+            .send(WebSocketCommand::SendMessage {
+                message_id,
+                conversation_id,
+                recipient_id,
+                content,
+                encrypted: false,
+            })
+            .map_err(|e| format!("Failed to queue send: {}", e))
+    }
+
+    /// Send an end-to-end encrypted envelope made with [`crate::e2ee::E2ee::encrypt`].
+    pub fn send_encrypted_message(
+        &self,
+        message_id: String,
+        conversation_id: String,
+        recipient_id: String,
+        envelope: String,
+    ) -> Result<(), String> {
+        self.command_tx
+            .send(WebSocketCommand::SendMessage {
+                message_id,
+                conversation_id,
+                recipient_id,
+                content: envelope,
+                encrypted: true,
+            })
+            .map_err(|e| format!("Failed to queue send: {}", e))
+    }
+
+    /// Send typing indicator.
+    pub fn send_typing(&self, recipient_id: String, is_typing: bool) -> Result<(), String> {
+        self.command_tx
+            .send(WebSocketCommand::SendTyping {
+                recipient_id,
+                is_typing,
+            })
+            .map_err(|e| format!("Failed to queue typing: {}", e))
+    }
+
+    /// Disconnect WebSocket
+    pub fn disconnect(&self) -> Result<(), String> {
+        self.command_tx
+            .send(WebSocketCommand::Disconnect)
+            .map_err(|e| format!("Failed to queue disconnect: {}", e))
+    }
+}
+
+async fn flush_queue<S>(
+    ws_write: &mut S,
+    pending: &mut VecDeque<WebSocketCommand>,
+    event_tx: &mpsc::UnboundedSender<WebSocketEvent>,
+    format: OutboundFormat,
+) -> Result<(), tokio_tungstenite::tungstenite::Error>
+where
+    S: SinkExt<Message, Error = tokio_tungstenite::tungstenite::Error> + Unpin,
+{
+    while let Some(cmd) = pending.front() {
+        let frame = match cmd {
+            WebSocketCommand::SendMessage {
+                message_id,
+                conversation_id,
+                recipient_id,
+                content,
+                encrypted,
+            } => match encode_frame(
+                &build_message_envelope(
+                    message_id.clone(),
+                    conversation_id.clone(),
+                    recipient_id.clone(),
+                    content.clone(),
+                    *encrypted,
+                ),
+                format,
+            ) {
+                Ok(p) => p,
+                Err(e) => {
+                    let _ = event_tx.send(WebSocketEvent::Error(format!("Serialize error: {}", e)));
+                    pending.pop_front();
+                    continue;
+                }
+            },
+            WebSocketCommand::SendTyping {
+                recipient_id,
+                is_typing,
+            } => match encode_frame(
+                &build_typing_envelope(recipient_id.clone(), *is_typing),
+                format,
+            ) {
+                Ok(p) => p,
+                Err(e) => {
+                    let _ = event_tx.send(WebSocketEvent::Error(format!("Serialize error: {}", e)));
+                    pending.pop_front();
+                    continue;
+                }
+            },
+            WebSocketCommand::Disconnect => {
+                pending.pop_front();
+                let _ = ws_write.send(Message::Close(None)).await;
+                return Ok(());
+            }
+        };
+
+        ws_write.send(frame).await?;
+        pending.pop_front();
+    }
+
+    Ok(())
+}
+
+/// Encoding and compression in effect for frames sent on the current connection
+#[derive(Debug, Clone, Copy, Default)]
+struct OutboundFormat {
+    encoding: WireEncoding,
+    compress: bool,
+}
+
+/// Serialize an envelope as a text (JSON) or binary (MessagePack) frame,
+/// compressing it if negotiated and large enough
+fn encode_frame(envelope: &MessageEnvelope, format: OutboundFormat) -> Result<Message, String> {
+    let payload = format.encoding.encode(envelope)?;
+    let kind = if format.encoding.is_binary() {
+        PayloadKind::Binary
+    } else {
+        PayloadKind::Text
+    };
+
+    if format.compress && payload.len() >= DEFAULT_COMPRESSION_THRESHOLD {
+        let compressed = compression::deflate(&payload, kind);
+        if compressed.len() < payload.len() {
+            return Ok(Message::Binary(compressed));
+        }
+    }
+
+    match kind {
+        PayloadKind::Binary => Ok(Message::Binary(payload)),
+        PayloadKind::Text => String::from_utf8(payload)
+            .map(Message::Text)
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// Upper bound on a decompressed server frame
+const MAX_INFLATED_FRAME_BYTES: usize = 16 * 1024 * 1024;
+
+/// Payload and encoding of a data frame, inflating compressed frames
+fn frame_payload(msg: &Message) -> Option<Result<(Vec<u8>, WireEncoding), String>> {
+    match msg {
+        Message::Text(text) => Some(Ok((text.as_bytes().to_vec(), WireEncoding::Json))),
+        Message::Binary(bytes) if compression::is_compressed(bytes) => Some(
+            compression::inflate(bytes, MAX_INFLATED_FRAME_BYTES)
+                .map(|(payload, kind)| match kind {
+                    PayloadKind::Text => (payload, WireEncoding::Json),
+                    PayloadKind::Binary => (payload, WireEncoding::MessagePack),
+                })
+                .map_err(|e| e.to_string()),
+        ),
+        Message::Binary(bytes) => Some(Ok((bytes.clone(), WireEncoding::MessagePack))),
+        _ => None,
+    }
+}
+
+fn calculate_backoff(attempt: usize) -> Duration {
+    let ranges = [
+        (0.5f64, 1.5f64),
+        (1.5, 3.5),
+        (3.0, 7.0),
+        (7.0, 15.0),
+        (15.0, 30.0),
+    ];
+
+    let (min, max) = if attempt < ranges.len() {
+        ranges[attempt]
+    } else {
+        (30.0, 60.0)
+    };
+
+    jitter_delay(min, max)
+}
+
+/// Consecutive refused upgrades before switching to the event stream
+const EVENT_STREAM_FALLBACK_AFTER: usize = 3;
+
+/// Whether the server was reached but the upgrade itself was refused or cut off,
+/// as opposed to the server being down or rejecting our credentials
+fn is_upgrade_failure(error: &WsError) -> bool {
+    match error {
+        WsError::Http(response) => !matches!(response.status().as_u16(), 401 | 403 | 503),
+        WsError::Protocol(_) => true,
+        WsError::Io(e) => matches!(
+            e.kind(),
+            std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::UnexpectedEof
+        ),
+        _ => false,
+    }
+}
+
+/// How an event stream session ended
+#[derive(Debug, PartialEq, Eq)]
+enum StreamEnd {
+    /// Disconnect requested, or the client must be updated
+    Stop,
+    /// The stream closed or could not be opened
+    Closed { connected: bool },
+}
+
+/// Receive over `GET /events` and send over REST, for networks that block upgrades
+///
+/// Events go through the same handling as WebSocket frames; missed ones are
+/// replayed by sending the last `seq` as `Last-Event-ID`. Typing indicators have
+/// no REST equivalent and are dropped.
+async fn run_event_stream(
+    websocket_url: &str,
+    token: &str,
+    pending: &mut VecDeque<WebSocketCommand>,
+    command_rx: &mut mpsc::UnboundedReceiver<WebSocketCommand>,
+    event_tx: &mpsc::UnboundedSender<WebSocketEvent>,
+    resume: &mut ResumeState,
+    restart_delay: &mut Option<Duration>,
+) -> StreamEnd {
+    let client = ChatClient::new(sse::http_base_url(websocket_url)).with_token(token);
+    let _ = event_tx.send(WebSocketEvent::ConnectionState(ConnectionStatus::Connecting));
+
+    let mut request = reqwest::Client::new()
+        .get(format!("{}/events", client.base_url()))
+        .bearer_auth(token)
+        .header(reqwest::header::ACCEPT, "text/event-stream");
+    if let Some(last_seq) = resume.last_seq {
+        request = request.header(sse::LAST_EVENT_ID_HEADER, last_seq.to_string());
+    }
+    let mut response = match request.send().await {
+        Ok(response) if response.status().is_success() => response,
+        Ok(response) => {
+            let _ = event_tx.send(WebSocketEvent::ConnectionState(ConnectionStatus::Disconnected {
+                reason: format!("Event stream refused: {}", response.status()),
+            }));
+            return StreamEnd::Closed { connected: false };
+        }
+        Err(e) => {
+            let _ = event_tx.send(WebSocketEvent::ConnectionState(ConnectionStatus::Disconnected {
+                reason: format!("Connect failed: {}", e),
+            }));
+            return StreamEnd::Closed { connected: false };
+        }
+    };
+
+    let liveness_timeout = liveness_timeout(
+        response
+            .headers()
+            .get(HEARTBEAT_INTERVAL_HEADER)
+            .and_then(|v| v.to_str().ok()),
+    );
+    let mut last_inbound = tokio::time::Instant::now();
+    let _ = event_tx.send(WebSocketEvent::ConnectionState(ConnectionStatus::Connected));
+    resume.reset();
+
+    let mut parser = sse::SseParser::default();
+    let mut flush = !pending.is_empty();
+    loop {
+        if flush {
+            flush = false;
+            if let Err(e) = flush_over_rest(&client, pending, event_tx).await {
+                let _ = event_tx.send(WebSocketEvent::ConnectionState(ConnectionStatus::Disconnected {
+                    reason: format!("Send failed: {}", e),
+                }));
+                break;
+            }
+        }
+
+        tokio::select! {
+            Some(cmd) = command_rx.recv() => {
+                if matches!(cmd, WebSocketCommand::Disconnect) {
+                    return StreamEnd::Stop;
+                }
+                pending.push_back(cmd);
+                flush = true;
+            }
+            _ = tokio::time::sleep_until(last_inbound + liveness_timeout) => {
+                let _ = event_tx.send(WebSocketEvent::ConnectionState(ConnectionStatus::Disconnected { reason: "Server not responding".to_string() }));
+                break;
+            }
+            chunk = response.chunk() => match chunk {
+                Ok(Some(bytes)) => {
+                    last_inbound = tokio::time::Instant::now();
+                    for event in parser.push(&bytes) {
+                        match handle_incoming_frame(event.data.as_bytes(), WireEncoding::Json, event_tx, resume) {
+                            InboundAction::Restart(delay) => *restart_delay = Some(delay),
+                            InboundAction::UpdateRequired(message) => {
+                                let _ = event_tx.send(WebSocketEvent::ConnectionState(ConnectionStatus::UpdateRequired { message }));
+                                return StreamEnd::Stop;
+                            }
+                            // Missed events were already requested with Last-Event-ID
+                            InboundAction::Welcome { .. } | InboundAction::None => {}
+                        }
+                    }
+                }
+                Ok(None) => {
+                    let reason = if restart_delay.is_some() {
+                        "Server restarting"
+                    } else {
+                        "Server closed connection"
+                    };
+                    let _ = event_tx.send(WebSocketEvent::ConnectionState(ConnectionStatus::Disconnected { reason: reason.to_string() }));
+                    break;
+                }
+                Err(e) => {
+                    let _ = event_tx.send(WebSocketEvent::ConnectionState(ConnectionStatus::Disconnected { reason: format!("Event stream error: {}", e) }));
+                    break;
+                }
+            }
+        }
+    }
+
+    StreamEnd::Closed { connected: true }
+}
+
+/// Send queued commands over REST, acknowledging each message from the response
+async fn flush_over_rest(
+    client: &ChatClient,
+    pending: &mut VecDeque<WebSocketCommand>,
+    event_tx: &mpsc::UnboundedSender<WebSocketEvent>,
+) -> Result<(), String> {
+    while let Some(cmd) = pending.front() {
+        if let WebSocketCommand::SendMessage {
+            message_id,
+            conversation_id,
+            content,
+            encrypted,
+            ..
+        } = cmd
+        {
+            let sent = if *encrypted {
+                client
+                    .send_encrypted_message(conversation_id, message_id, content)
+                    .await
+            } else {
+                client
+                    .send_message(conversation_id, message_id, content)
+                    .await
+            };
+            match sent {
+                Ok(sent) => {
+                    let _ = event_tx.send(WebSocketEvent::Ack {
+                        message_id: Some(message_id.clone()),
+                        status: sent.status,
+                        conversation_id: Some(conversation_id.clone()),
+                    });
+                }
+                // Left queued; the same ID makes the retry safe
+                Err(e) if e.is_retryable() => return Err(e.to_string()),
+                Err(e) => {
+                    let _ = event_tx.send(WebSocketEvent::Error(e.to_string()));
+                }
+            }
+        }
+        pending.pop_front();
+    }
+
+    Ok(())
+}
+
+/// Ping interval assumed when the server does not advertise one
+const DEFAULT_HEARTBEAT_INTERVAL_SECS: u64 = 25;
+
+/// How long the connection may stay silent before it is considered lost.
+///
+/// The server pings every advertised interval, so two missed intervals mean
+/// the link is gone even if TCP has not noticed.
+fn liveness_timeout(advertised_interval: Option<&str>) -> Duration {
+    let interval = advertised_interval
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DEFAULT_HEARTBEAT_INTERVAL_SECS);
+    Duration::from_secs(interval * 2)
+}
+
+/// Shown when the server refuses this client's protocol without explaining why
+const DEFAULT_UPDATE_MESSAGE: &str = "This version of the app is no longer supported. Please update to continue.";
+
+/// Protocol versions offered during the upgrade, e.g. `1,2`
+fn supported_protocol_versions() -> String {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION)
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Reconnect delay used when the server closes with "service restart" but sent no notice
+const DEFAULT_RESTART_DELAY: Duration = Duration::from_secs(2);
+
+fn jitter_delay(min_secs: f64, max_secs: f64) -> Duration {
+    let span = max_secs - min_secs;
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos() as f64;
+    let ratio = (nanos / 1_000_000_000_f64).clamp(0.0, 1.0);
+    let millis = (min_secs + span * ratio) * 1000.0;
+    Duration::from_millis(millis.round() as u64)
+}
+
+fn build_message_envelope(
+    message_id: String,
+    conversation_id: String,
+    recipient_id: String,
+    content: String,
+    encrypted: bool,
+) -> MessageEnvelope {
+    let data = TextMessageData {
+        sender_id: None,
+        sender_username: None,
+        recipient_id,
+        content,
+        conversation_id: Some(conversation_id),
+        status: None,
+        encrypted,
+        system: false,
+        expires_at: None,
+    };
+
+    // Each outgoing message starts a trace; the server continues it for its
+    // persist/deliver/ack spans and echoes it on the ack
+    let trace = TraceParent::generate();
+    tracing::debug!(
+        message_id = %message_id,
+        trace_id = %trace.trace_id_hex(),
+        "Sending message"
+    );
+
+    ClientFrame::Message(data)
+        .into_envelope_with_id(message_id)
+        .with_traceparent(Some(trace.to_string()))
+}
+
+fn build_typing_envelope(recipient_id: String, is_typing: bool) -> MessageEnvelope {
+    ClientFrame::Typing(TypingData {
+        sender_id: None,
+        sender_username: None,
+        recipient_id,
+        is_typing,
+    })
+    .into_envelope()
+}
+
+/// Event sequence tracking used to resume a session after reconnecting
+#[derive(Default)]
+struct ResumeState {
+    /// Highest `seq` handled so far
+    last_seq: Option<u64>,
+    /// Set while waiting for the reply to a `resume` request
+    resuming: bool,
+    /// Sequenced events that arrived while resuming; handled in order once the replay ends
+    held: Vec<MessageEnvelope>,
+}
+
+impl ResumeState {
+    /// Forget any in-flight resume when a new connection opens
+    fn reset(&mut self) {
+        self.resuming = false;
+        self.held.clear();
+    }
+
+    /// Build the `resume` request for a new connection, if we have seen any events
+    fn start(&mut self) -> Option<MessageEnvelope> {
+        self.held.clear();
+        let last_seq = self.last_seq?;
+        self.resuming = true;
+        Some(ClientFrame::Resume(ResumeData { last_seq }).into_envelope())
+    }
+
+    /// Whether an event with `seq` is new; records it as handled
+    fn accept(&mut self, seq: u64) -> bool {
+        if self.last_seq.is_some_and(|last| seq <= last) {
+            return false;
+        }
+        self.last_seq = Some(seq);
+        true
+    }
+
+    /// End the resume and return held events in order, without duplicates
+    fn finish(&mut self) -> Vec<MessageEnvelope> {
+        self.resuming = false;
+        let mut held = std::mem::take(&mut self.held);
+        held.sort_by_key(|e| e.seq);
+        held.into_iter()
+            .filter(|e| e.seq.is_none_or(|seq| self.accept(seq)))
+            .collect()
+    }
+}
+
+/// What the connection loop should do after an inbound frame
+#[derive(Debug)]
+enum InboundAction {
+    None,
+    /// Server is restarting; reconnect after this delay
+    Restart(Duration),
+    /// Server confirmed the connection; send the resume request, if any
+    Welcome {
+        compression: bool,
+        resume: Option<MessageEnvelope>,
+    },
+    /// Server refused our protocol version; message to show once it closes
+    UpdateRequired(String),
+}
+
+/// Dispatch an incoming frame as events.
+fn handle_incoming_frame(
+    payload: &[u8],
+    encoding: WireEncoding,
+    event_tx: &mpsc::UnboundedSender<WebSocketEvent>,
+    resume: &mut ResumeState,
+) -> InboundAction {
+    let envelope = match encoding.decode(payload) {
+        Ok(v) => v,
+        Err(_) => {
+            let _ = event_tx.send(WebSocketEvent::Error("Invalid message payload".into()));
+            return InboundAction::None;
+        }
+    };
+
+    // Connection-level frames are handled here, outside event sequencing
+    match ServerFrame::from_envelope(&envelope) {
+        Ok(ServerFrame::Welcome(welcome)) => {
+            tracing::info!(
+                protocol_version = welcome.protocol_version,
+                server_version = %welcome.server_version,
+                capabilities = ?welcome.capabilities,
+                encoding = %welcome.encoding,
+                compression = welcome.compression,
+                "Connected to server"
+            );
+            // Ask for anything broadcast while we were away
+            let request = if welcome.capabilities.iter().any(|c| c == "resume") {
+                resume.start()
+            } else {
+                None
+            };
+            return InboundAction::Welcome {
+                compression: welcome.compression,
+                resume: request,
+            };
+        }
+        Ok(ServerFrame::Error(error)) => {
+            if error.code == "UPDATE_REQUIRED" {
+                return InboundAction::UpdateRequired(error.message);
+            }
+            tracing::warn!(code = %error.code, message = %error.message, "Server error");
+            return InboundAction::None;
+        }
+        Ok(ServerFrame::Resumed(resumed)) => {
+            tracing::info!(replayed = resumed.replayed, "Session resumed");
+            for held in resume.finish() {
+                dispatch_envelope(held, event_tx);
+            }
+            return InboundAction::None;
+        }
+        Ok(ServerFrame::ResyncRequired(resync)) => {
+            for held in resume.finish() {
+                dispatch_envelope(held, event_tx);
+            }
+            resume.last_seq = resume.last_seq.max(Some(resync.last_seq));
+            let _ = event_tx.send(WebSocketEvent::ResyncRequired);
+            return InboundAction::None;
+        }
+        _ => {}
+    }
+
+    if let Some(seq) = envelope.seq {
+        if resume.resuming {
+            resume.held.push(envelope);
+            return InboundAction::None;
+        }
+        if !resume.accept(seq) {
+            // Already handled, e.g. delivered live and again in a replay
+            return InboundAction::None;
+        }
+    }
+
+    match dispatch_envelope(envelope, event_tx) {
+        Some(delay) => InboundAction::Restart(delay),
+        None => InboundAction::None,
+    }
+}
+
+fn dispatch_envelope(
+    envelope: MessageEnvelope,
+    event_tx: &mpsc::UnboundedSender<WebSocketEvent>,
+) -> Option<Duration> {
+    let frame = match ServerFrame::from_envelope(&envelope) {
+        Ok(frame) => frame,
+        Err(e) => {
+            // Unknown types come from newer servers; skip them
+            tracing::debug!("Ignoring frame: {}", e);
+            return None;
+        }
+    };
+
+    match frame {
+        ServerFrame::Ack(ack) => {
+            if let Some(trace) = envelope.traceparent.as_deref().and_then(TraceParent::parse) {
+                tracing::debug!(
+                    message_id = ?ack.message_id,
+                    trace_id = %trace.trace_id_hex(),
+                    "Received ack"
+                );
+            }
+            let _ = event_tx.send(WebSocketEvent::Ack {
+                message_id: ack.message_id,
+                status: ack.status,
+                conversation_id: ack.conversation_id,
+            });
+        }
+        ServerFrame::Message(msg) => {
+            let _ = event_tx.send(WebSocketEvent::Message {
+                conversation_id: msg.conversation_id.unwrap_or_else(|| "unknown".to_string()),
+                message_id: envelope.id,
+                sender_id: msg.sender_id,
+                sender_username: msg.sender_username.unwrap_or_else(|| "Unknown".to_string()),
+                content: msg.content,
+                status: msg.status.unwrap_or_else(|| "sent".to_string()),
+                timestamp: envelope.timestamp,
+                encrypted: msg.encrypted,
+            });
+        }
+        ServerFrame::Typing(typing) => {
+            let _ = event_tx.send(WebSocketEvent::Typing {
+                sender_id: typing.sender_id,
+                sender_username: typing
+                    .sender_username
+                    .unwrap_or_else(|| "Unknown".to_string()),
+                recipient_id: typing.recipient_id,
+                is_typing: typing.is_typing,
+            });
+        }
+        ServerFrame::Presence(presence) => {
+            let _ = event_tx.send(WebSocketEvent::Presence {
+                user_id: presence.user_id,
+                username: presence.username,
+                is_online: presence.is_online,
+                last_seen_at: presence.last_seen_at,
+            });
+        }
+        ServerFrame::MessageExpired(expired) => {
+            let _ = event_tx.send(WebSocketEvent::MessageExpired {
+                conversation_id: expired.conversation_id,
+                message_ids: expired.message_ids,
+            });
+        }
+        ServerFrame::Shutdown(notice) => {
+            tracing::info!(
+                reason = %notice.reason,
+                retry_after_ms = notice.retry_after_ms,
+                "Server is restarting"
+            );
+            return Some(Duration::from_millis(notice.retry_after_ms));
+        }
+        // Handled in `handle_incoming_frame` before sequencing
+        ServerFrame::Welcome(_)
+        | ServerFrame::Error(_)
+        | ServerFrame::Resumed(_)
+        | ServerFrame::ResyncRequired(_) => {}
+        // Delivery status sync is not surfaced yet
+        ServerFrame::DeliveryStatusUpdated(_) | ServerFrame::SyncDeliveryStatusCompleted(_) => {}
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(seq: u64) -> String {
+        serde_json::json!({
+            "id": format!("evt-{}", seq),
+            "type": "presence",
+            "timestamp": 0,
+            "seq": seq,
+            "data": {
+                "userId": "u2",
+                "username": "bob",
+                "isOnline": true,
+                "lastSeenAt": 0
+            }
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_replayed_events_are_ordered_and_deduplicated() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut resume = ResumeState::default();
+        handle_incoming_frame(event(10).as_bytes(), WireEncoding::Json, &tx, &mut resume);
+        assert!(rx.try_recv().is_ok());
+
+        resume.reset();
+        let welcome = serde_json::json!({
+            "id": "w", "type": "welcome", "timestamp": 0,
+            "data": {
+                "protocolVersion": 2,
+                "serverVersion": "0.1.0",
+                "capabilities": ["resume"],
+                "heartbeatIntervalSecs": 25
+            }
+        });
+        match handle_incoming_frame(welcome.to_string().as_bytes(), WireEncoding::Json, &tx, &mut resume) {
+            InboundAction::Welcome {
+                resume: Some(request),
+                ..
+            } => {
+                assert_eq!(request.msg_type, "resume");
+                assert_eq!(request.data["lastSeq"], 10);
+            }
+            other => panic!("expected a resume request, got {:?}", other),
+        }
+
+        // A live event overtakes the replay; a stale one is repeated
+        for seq in [13, 11, 10, 12] {
+            handle_incoming_frame(event(seq).as_bytes(), WireEncoding::Json, &tx, &mut resume);
+        }
+        assert!(rx.try_recv().is_err());
+
+        let resumed = serde_json::json!({
+            "id": "r", "type": "resumed", "timestamp": 0,
+            "data": { "replayed": 2, "lastSeq": 12 }
+        });
+        handle_incoming_frame(resumed.to_string().as_bytes(), WireEncoding::Json, &tx, &mut resume);
+
+        let mut delivered = 0;
+        while rx.try_recv().is_ok() {
+            delivered += 1;
+        }
+        assert_eq!(delivered, 3);
+        assert_eq!(resume.last_seq, Some(13));
+
+        handle_incoming_frame(event(12).as_bytes(), WireEncoding::Json, &tx, &mut resume);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_update_required_error_is_surfaced() {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let error = serde_json::json!({
+            "id": "e", "type": "error", "timestamp": 0,
+            "data": { "code": "UPDATE_REQUIRED", "message": "Please update" }
+        });
+        assert!(matches!(
+            handle_incoming_frame(error.to_string().as_bytes(), WireEncoding::Json, &tx, &mut ResumeState::default()),
+            InboundAction::UpdateRequired(ref m) if m == "Please update"
+        ));
+        assert_eq!(supported_protocol_versions(), "1,2");
+    }
+
+    #[test]
+    fn test_msgpack_frames_are_handled_like_json() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut resume = ResumeState::default();
+        let envelope: MessageEnvelope = serde_json::from_str(&event(7)).unwrap();
+        let payload = WireEncoding::MessagePack.encode(&envelope).unwrap();
+
+        handle_incoming_frame(&payload, WireEncoding::MessagePack, &tx, &mut resume);
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(WebSocketEvent::Presence { ref user_id, is_online: true, .. }) if user_id == "u2"
+        ));
+        assert_eq!(resume.last_seq, Some(7));
+
+        let typing = build_typing_envelope("u2".to_string(), true);
+        assert!(matches!(
+            encode_frame(
+                &typing,
+                OutboundFormat {
+                    encoding: WireEncoding::MessagePack,
+                    compress: false
+                }
+            ),
+            Ok(Message::Binary(_))
+        ));
+        assert!(matches!(
+            encode_frame(&typing, OutboundFormat::default()),
+            Ok(Message::Text(_))
+        ));
+    }
+
+    #[test]
+    fn test_compressed_frames_are_inflated() {
+        let compressed = compression::deflate(event(3).as_bytes(), PayloadKind::Text);
+        let (payload, encoding) = frame_payload(&Message::Binary(compressed)).unwrap().unwrap();
+        assert_eq!(payload, event(3).into_bytes());
+        assert_eq!(encoding, WireEncoding::Json);
+
+        // Only large frames are compressed
+        let format = OutboundFormat {
+            encoding: WireEncoding::Json,
+            compress: true,
+        };
+        let typing = build_typing_envelope("u2".to_string(), true);
+        assert!(matches!(encode_frame(&typing, format), Ok(Message::Text(_))));
+        let long = build_message_envelope(
+            "m1".to_string(),
+            "c1".to_string(),
+            "u2".to_string(),
+            "hello ".repeat(200),
+            false,
+        );
+        match encode_frame(&long, format) {
+            Ok(Message::Binary(frame)) => assert!(compression::is_compressed(&frame)),
+            other => panic!("expected a compressed frame, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_first_connection_does_not_resume() {
+        let mut resume = ResumeState::default();
+        assert!(resume.start().is_none());
+        assert!(!resume.resuming);
+    }
+
+    #[test]
+    fn test_only_refused_upgrades_count_towards_fallback() {
+        use std::io::{Error as IoError, ErrorKind};
+        use tokio_tungstenite::tungstenite::http::Response;
+
+        let http = |status: u16| {
+            WsError::Http(Response::builder().status(status).body(None).unwrap())
+        };
+        assert!(is_upgrade_failure(&http(400)));
+        assert!(is_upgrade_failure(&http(502)));
+        assert!(is_upgrade_failure(&WsError::Io(IoError::from(
+            ErrorKind::ConnectionReset
+        ))));
+
+        // Bad credentials, a restarting server or no server at all are not proxy trouble
+        assert!(!is_upgrade_failure(&http(401)));
+        assert!(!is_upgrade_failure(&http(503)));
+        assert!(!is_upgrade_failure(&WsError::Io(IoError::from(
+            ErrorKind::ConnectionRefused
+        ))));
+    }
+}
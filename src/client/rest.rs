@@ -0,0 +1,382 @@
+//! Typed REST client
+//!
+//! One method per route in the server's OpenAPI document. Routes that need
+//! authentication use the token set with [`ChatClient::with_token`] or
+//! [`ChatClient::set_token`]; signup and login store the token they receive.
+
+use crate::error::ClientError;
+use crate::types::{
+    AuthResponse, BlockedUser, Conversation, Credentials, DataExport, EphemeralSettings,
+    HealthResponse, KeyBundle, KeyStatus, MessageDto, MessageRequest, Page, SendMessageResponse,
+    StatusResponse, SuccessResponse, UploadKeys, UserProfile, UserSearchResult,
+};
+use reqwest::{Method, RequestBuilder};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::sync::{Arc, RwLock};
+
+/// Header that makes a REST send safe to retry
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Async client for the chat server's REST API
+///
+/// Cheap to clone; clones share the connection pool and the token.
+#[derive(Clone)]
+pub struct ChatClient {
+    base_url: String,
+    http: reqwest::Client,
+    token: Arc<RwLock<Option<String>>>,
+}
+
+impl ChatClient {
+    /// Client for the server at `base_url`, e.g. `http://localhost:8080`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+            token: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// Use an existing token, e.g. one restored from a saved session
+    pub fn with_token(self, token: impl Into<String>) -> Self {
+        self.set_token(Some(token.into()));
+        self
+    }
+
+    pub fn set_token(&self, token: Option<String>) {
+        *self.token.write().unwrap() = token;
+    }
+
+    pub fn token(&self) -> Option<String> {
+        self.token.read().unwrap().clone()
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// `GET /health`
+    pub async fn health(&self) -> Result<HealthResponse, ClientError> {
+        self.send(self.request(Method::GET, "/health")).await
+    }
+
+    /// `GET /status`
+    pub async fn status(&self) -> Result<StatusResponse, ClientError> {
+        self.send(self.request(Method::GET, "/status")).await
+    }
+
+    /// `GET /protocol/schema` - JSON Schemas of the WebSocket frames
+    pub async fn protocol_schema(&self) -> Result<Value, ClientError> {
+        self.send(self.request(Method::GET, "/protocol/schema")).await
+    }
+
+    /// `GET /openapi.json` - OpenAPI document of the REST API
+    pub async fn openapi(&self) -> Result<Value, ClientError> {
+        self.send(self.request(Method::GET, "/openapi.json")).await
+    }
+
+    /// `POST /auth/signup`; keeps the returned token
+    pub async fn signup(&self, credentials: &Credentials) -> Result<AuthResponse, ClientError> {
+        let auth: AuthResponse = self
+            .send(self.request(Method::POST, "/auth/signup").json(credentials))
+            .await?;
+        self.set_token(Some(auth.token.clone()));
+        Ok(auth)
+    }
+
+    /// `POST /auth/login`; keeps the returned token
+    pub async fn login(&self, credentials: &Credentials) -> Result<AuthResponse, ClientError> {
+        let auth: AuthResponse = self
+            .send(self.request(Method::POST, "/auth/login").json(credentials))
+            .await?;
+        self.set_token(Some(auth.token.clone()));
+        Ok(auth)
+    }
+
+    /// `POST /auth/logout`; forgets the token
+    pub async fn logout(&self) -> Result<SuccessResponse, ClientError> {
+        let response = self.send(self.authed(Method::POST, "/auth/logout")?).await;
+        self.set_token(None);
+        response
+    }
+
+    /// `GET /user/me`
+    pub async fn me(&self) -> Result<UserProfile, ClientError> {
+        self.send(self.authed(Method::GET, "/user/me")?).await
+    }
+
+    /// `DELETE /user/me`; forgets the token
+    pub async fn delete_account(&self, password: &str) -> Result<(), ClientError> {
+        self.send_empty(
+            self.authed(Method::DELETE, "/user/me")?
+                .json(&json!({ "password": password })),
+        )
+        .await?;
+        self.set_token(None);
+        Ok(())
+    }
+
+    /// `POST /user/change-password`
+    pub async fn change_password(
+        &self,
+        current_password: &str,
+        new_password: &str,
+    ) -> Result<SuccessResponse, ClientError> {
+        self.send(self.authed(Method::POST, "/user/change-password")?.json(&json!({
+            "current_password": current_password,
+            "new_password": new_password,
+        })))
+        .await
+    }
+
+    /// `POST /user/export` - start building an archive of the account's data
+    ///
+    /// Returns the export already in progress or still downloadable if there is one.
+    pub async fn request_export(&self) -> Result<DataExport, ClientError> {
+        self.send(self.authed(Method::POST, "/user/export")?).await
+    }
+
+    /// `GET /user/export/{id}`
+    pub async fn export_status(&self, export_id: &str) -> Result<DataExport, ClientError> {
+        let path = format!("/user/export/{}", export_id);
+        self.send(self.authed(Method::GET, &path)?).await
+    }
+
+    /// `GET /exports/{id}/download` - the `.tar.gz` archive of a `ready` export
+    pub async fn download_export(&self, export: &DataExport) -> Result<Vec<u8>, ClientError> {
+        let path = export
+            .download_url
+            .as_deref()
+            .ok_or_else(|| ClientError::Decode("export is not ready".to_string()))?;
+        self.send_empty(self.request(Method::GET, path)).await
+    }
+
+    /// `GET /user/blocks`, most recently blocked first
+    pub async fn blocked_users(&self) -> Result<Vec<BlockedUser>, ClientError> {
+        self.send(self.authed(Method::GET, "/user/blocks")?).await
+    }
+
+    /// `PUT /user/blocks/{id}`; blocking someone already blocked is not an error
+    pub async fn block_user(&self, user_id: &str) -> Result<BlockedUser, ClientError> {
+        let path = format!("/user/blocks/{}", user_id);
+        self.send(self.authed(Method::PUT, &path)?).await
+    }
+
+    /// `DELETE /user/blocks/{id}`
+    pub async fn unblock_user(&self, user_id: &str) -> Result<(), ClientError> {
+        let path = format!("/user/blocks/{}", user_id);
+        self.send_empty(self.authed(Method::DELETE, &path)?).await?;
+        Ok(())
+    }
+
+    /// `GET /users/search` - users whose name starts with `query`
+    pub async fn search_users(
+        &self,
+        query: &str,
+        limit: Option<u32>,
+    ) -> Result<Vec<UserSearchResult>, ClientError> {
+        self.send(
+            self.authed(Method::GET, "/users/search")?
+                .query(&[("q", query)])
+                .query(&Page {
+                    limit,
+                    offset: None,
+                }),
+        )
+        .await
+    }
+
+    /// `GET /conversations`, most recent first
+    pub async fn conversations(&self, page: Page) -> Result<Vec<Conversation>, ClientError> {
+        self.send(self.authed(Method::GET, "/conversations")?.query(&page))
+            .await
+    }
+
+    /// `POST /conversations/start` - the conversation with `other_user_id`, created if needed
+    pub async fn start_conversation(&self, other_user_id: &str) -> Result<Conversation, ClientError> {
+        self.send(
+            self.authed(Method::POST, "/conversations/start")?
+                .json(&json!({ "other_user_id": other_user_id })),
+        )
+        .await
+    }
+
+    /// `GET /conversations/requests` - message requests awaiting this user
+    pub async fn message_requests(&self) -> Result<Vec<MessageRequest>, ClientError> {
+        self.send(self.authed(Method::GET, "/conversations/requests")?)
+            .await
+    }
+
+    /// `POST /conversations/{id}/accept`
+    pub async fn accept_request(&self, conversation_id: &str) -> Result<MessageRequest, ClientError> {
+        let path = format!("/conversations/{}/accept", conversation_id);
+        self.send(self.authed(Method::POST, &path)?).await
+    }
+
+    /// `POST /conversations/{id}/decline`; deletes the held messages
+    pub async fn decline_request(&self, conversation_id: &str) -> Result<MessageRequest, ClientError> {
+        let path = format!("/conversations/{}/decline", conversation_id);
+        self.send(self.authed(Method::POST, &path)?).await
+    }
+
+    /// `GET /conversations/{id}/messages`
+    pub async fn messages(
+        &self,
+        conversation_id: &str,
+        page: Page,
+    ) -> Result<Vec<MessageDto>, ClientError> {
+        let path = format!("/conversations/{}/messages", conversation_id);
+        self.send(self.authed(Method::GET, &path)?.query(&page)).await
+    }
+
+    /// `POST /conversations/{id}/messages`
+    ///
+    /// `message_id` is sent as the `Idempotency-Key` and becomes the message ID,
+    /// so retrying after a network error never sends the message twice.
+    pub async fn send_message(
+        &self,
+        conversation_id: &str,
+        message_id: &str,
+        content: &str,
+    ) -> Result<SendMessageResponse, ClientError> {
+        let path = format!("/conversations/{}/messages", conversation_id);
+        self.send(
+            self.authed(Method::POST, &path)?
+                .header(IDEMPOTENCY_KEY_HEADER, message_id)
+                .json(&json!({ "content": content })),
+        )
+        .await
+    }
+
+    /// `POST /conversations/{id}/messages` with an end-to-end encrypted envelope
+    ///
+    /// The first encrypted message marks the conversation encrypted; the server
+    /// refuses plaintext in it from then on.
+    pub async fn send_encrypted_message(
+        &self,
+        conversation_id: &str,
+        message_id: &str,
+        envelope: &str,
+    ) -> Result<SendMessageResponse, ClientError> {
+        let path = format!("/conversations/{}/messages", conversation_id);
+        self.send(
+            self.authed(Method::POST, &path)?
+                .header(IDEMPOTENCY_KEY_HEADER, message_id)
+                .json(&json!({ "content": envelope, "encrypted": true })),
+        )
+        .await
+    }
+
+    /// `GET /conversations/{id}/search` - messages containing `query`
+    ///
+    /// Encrypted messages never match.
+    pub async fn search_messages(
+        &self,
+        conversation_id: &str,
+        query: &str,
+        limit: Option<u32>,
+    ) -> Result<Vec<MessageDto>, ClientError> {
+        let path = format!("/conversations/{}/search", conversation_id);
+        self.send(
+            self.authed(Method::GET, &path)?
+                .query(&[("q", query)])
+                .query(&Page {
+                    limit,
+                    offset: None,
+                }),
+        )
+        .await
+    }
+
+    /// `PUT /conversations/{id}/ephemeral` - turn disappearing messages on or off
+    ///
+    /// `ttl_secs` of `None` keeps messages; `start` is `sent` or `read`. The
+    /// change is announced to both participants with a system message.
+    pub async fn set_ephemeral(
+        &self,
+        conversation_id: &str,
+        ttl_secs: Option<i64>,
+        start: &str,
+    ) -> Result<EphemeralSettings, ClientError> {
+        let path = format!("/conversations/{}/ephemeral", conversation_id);
+        self.send(
+            self.authed(Method::PUT, &path)?
+                .json(&json!({ "ephemeral_ttl": ttl_secs, "ephemeral_start": start })),
+        )
+        .await
+    }
+
+    /// `GET /conversations/{id}/export` - the whole conversation as a file
+    ///
+    /// `format` is `json`, `markdown` or `html`.
+    pub async fn export_conversation(
+        &self,
+        conversation_id: &str,
+        format: &str,
+    ) -> Result<Vec<u8>, ClientError> {
+        let path = format!("/conversations/{}/export", conversation_id);
+        self.send_empty(self.authed(Method::GET, &path)?.query(&[("format", format)]))
+            .await
+    }
+
+    /// `PUT /keys` - publish identity and prekeys for end-to-end encryption
+    pub async fn upload_keys(&self, keys: &UploadKeys) -> Result<KeyStatus, ClientError> {
+        self.send(self.authed(Method::PUT, "/keys")?.json(keys)).await
+    }
+
+    /// `GET /keys` - how many one-time prekeys the server has left
+    pub async fn key_status(&self) -> Result<KeyStatus, ClientError> {
+        self.send(self.authed(Method::GET, "/keys")?).await
+    }
+
+    /// `GET /keys/{user_id}` - another user's bundle; uses up one of their one-time prekeys
+    pub async fn key_bundle(&self, user_id: &str) -> Result<KeyBundle, ClientError> {
+        let path = format!("/keys/{}", user_id);
+        self.send(self.authed(Method::GET, &path)?).await
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}{}", self.base_url, path))
+    }
+
+    fn authed(&self, method: Method, path: &str) -> Result<RequestBuilder, ClientError> {
+        let token = self.token().ok_or(ClientError::NotAuthenticated)?;
+        Ok(self.request(method, path).bearer_auth(token))
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, ClientError> {
+        let body = self.send_empty(request).await?;
+        serde_json::from_slice(&body).map_err(|e| ClientError::Decode(e.to_string()))
+    }
+
+    /// Send a request and return the body of a successful response
+    async fn send_empty(&self, request: RequestBuilder) -> Result<Vec<u8>, ClientError> {
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.bytes().await?;
+        if status.is_success() {
+            Ok(body.to_vec())
+        } else {
+            Err(ClientError::from_response(status.as_u16(), &body))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_authenticated_routes_need_a_token() {
+        let client = ChatClient::new("http://127.0.0.1:9/");
+        assert_eq!(client.base_url(), "http://127.0.0.1:9");
+        assert!(matches!(client.me().await, Err(ClientError::NotAuthenticated)));
+
+        let client = client.with_token("t1");
+        let clone = client.clone();
+        clone.set_token(Some("t2".to_string()));
+        assert_eq!(client.token().as_deref(), Some("t2"));
+    }
+}
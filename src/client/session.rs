@@ -1,6 +1,8 @@
 //! Session storage and token management
 //!
-//! Handles JWT token storage, retrieval, and automatic refresh
+//! Handles JWT token storage, retrieval, and automatic refresh. Every client
+//! (desktop and terminal) keeps its session in the same file, so logging in
+//! with one signs in the other.
 
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
@@ -22,6 +24,12 @@ pub struct SessionManager {
     current_session: Arc<Mutex<Option<SessionData>>>,
 }
 
+impl Default for SessionManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SessionManager {
     /// Create a new session manager
     ///
@@ -38,14 +46,19 @@ impl SessionManager {
         }
     }
 
-    #[cfg(test)]
-    fn new_with_session_file(session_file: PathBuf) -> Self {
+    /// Session manager backed by a specific file, e.g. for tests
+    pub fn with_session_file(session_file: PathBuf) -> Self {
         Self {
             session_file,
             current_session: Arc::new(Mutex::new(None)),
         }
     }
 
+    /// Where the session is stored; per-user key files live alongside it
+    pub fn session_file(&self) -> &std::path::Path {
+        &self.session_file
+    }
+
     /// Get the session file path based on the OS
     fn get_session_file_path() -> PathBuf {
         #[cfg(target_os = "windows")]
@@ -75,7 +88,6 @@ impl SessionManager {
     }
 
     /// Save session to disk
-    #[allow(dead_code)]
     pub async fn save_session(&self, session: SessionData) -> Result<(), String> {
         // Ensure parent directory exists
         if let Some(parent) = self.session_file.parent() {
@@ -135,7 +147,6 @@ impl SessionManager {
     }
 
     /// Load session from disk
-    #[allow(dead_code)]
     pub async fn load_session(&self) -> Result<Option<SessionData>, String> {
         // Check if file exists
         if !self.session_file.exists() {
@@ -202,7 +213,6 @@ impl SessionManager {
     }
 
     /// Check if token is expired or will expire soon (within 5 minutes)
-    #[allow(dead_code)]
     pub fn should_refresh_token(&self) -> bool {
         if let Some(session) = self.get_current_session() {
             let now = chrono::Utc::now().timestamp();
@@ -216,7 +226,6 @@ impl SessionManager {
     }
 
     /// Check if user is logged in with valid token
-    #[allow(dead_code)]
     pub fn is_logged_in(&self) -> bool {
         if let Some(session) = self.get_current_session() {
             let now = chrono::Utc::now().timestamp();
@@ -242,7 +251,6 @@ pub fn get_token() -> Option<String> {
 }
 
 /// Helper function to check if logged in
-#[allow(dead_code)]
 pub fn is_logged_in() -> bool {
     get_session_manager().is_logged_in()
 }
@@ -280,7 +288,7 @@ mod tests {
         };
         let session_dir = std::env::temp_dir().join(format!("chat-app-test-session-{}", unique));
         let session_file = session_dir.join("session.json");
-        let manager = SessionManager::new_with_session_file(session_file);
+        let manager = SessionManager::with_session_file(session_file);
 
         let session = SessionData {
             user_id: "test_user".to_string(),
@@ -0,0 +1,113 @@
+//! Server-Sent Events fallback transport
+//!
+//! Used when WebSocket upgrades keep failing (e.g. behind proxies that block them):
+//! events arrive over `GET /events` and messages are sent with
+//! `POST /conversations/{id}/messages`.
+
+/// Header used to resume an event stream from the last `seq` seen
+pub const LAST_EVENT_ID_HEADER: &str = "Last-Event-ID";
+
+/// One dispatched SSE event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    pub id: Option<String>,
+    pub data: String,
+}
+
+/// Incremental `text/event-stream` parser
+#[derive(Debug, Default)]
+pub struct SseParser {
+    /// Bytes of an incomplete line
+    partial: Vec<u8>,
+    data: Vec<String>,
+    id: Option<String>,
+}
+
+impl SseParser {
+    /// Feed a chunk of the response body and return the events it completes
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.partial.extend_from_slice(chunk);
+        let mut events = Vec::new();
+
+        while let Some(end) = self.partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(SseEvent {
+                        id: self.id.take(),
+                        data: std::mem::take(&mut self.data).join("\n"),
+                    });
+                }
+                self.id = None;
+                continue;
+            }
+            if line.starts_with(':') {
+                // Comment, used by the server as keep-alive
+                continue;
+            }
+
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "data" => self.data.push(value.to_string()),
+                "id" => self.id = Some(value.to_string()),
+                _ => {}
+            }
+        }
+
+        events
+    }
+}
+
+/// HTTP origin of the server behind a WebSocket URL, e.g. `ws://host:8080/socket` -> `http://host:8080`
+pub fn http_base_url(websocket_url: &str) -> String {
+    let (scheme, rest) = match websocket_url.split_once("://") {
+        Some(("wss", rest)) => ("https", rest),
+        Some(("ws", rest)) => ("http", rest),
+        Some((scheme, rest)) => (scheme, rest),
+        None => ("http", websocket_url),
+    };
+    let authority = rest.split(['/', '?']).next().unwrap_or(rest);
+    format!("{}://{}", scheme, authority)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parser_handles_split_chunks_and_comments() {
+        let mut parser = SseParser::default();
+        assert!(parser.push(b":keep-alive\n\nid: 7\ndata: {\"type\"").is_empty());
+
+        let events = parser.push(b":\"ack\"}\r\n\r\ndata:a\ndata:b\n\n");
+        assert_eq!(
+            events,
+            vec![
+                SseEvent {
+                    id: Some("7".to_string()),
+                    data: "{\"type\":\"ack\"}".to_string(),
+                },
+                SseEvent {
+                    id: None,
+                    data: "a\nb".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_http_base_url() {
+        assert_eq!(
+            http_base_url("ws://localhost:8080/socket"),
+            "http://localhost:8080"
+        );
+        assert_eq!(
+            http_base_url("wss://chat.example.com/socket?x=1"),
+            "https://chat.example.com"
+        );
+    }
+}
@@ -0,0 +1,434 @@
+//! End-to-end tests of the SDK against a real server on an ephemeral port
+
+use chat_backend::server::{create_routes, ServerConfig, ServerState};
+use chat_backend::services::data_export::DataExportConfig;
+use chat_client::e2ee::E2ee;
+use chat_client::{
+    ChatClient, ClientError, ConnectionStatus, Credentials, Page, WebSocketClient, WebSocketEvent,
+    WireOptions,
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+async fn spawn_server() -> SocketAddr {
+    let db_path = std::env::temp_dir().join(format!("chat-client-{}.db", uuid::Uuid::new_v4()));
+    let pool = chat_backend::db::init_db(&db_path).await.unwrap();
+    let config = ServerConfig {
+        data_export: DataExportConfig {
+            dir: std::env::temp_dir().join(format!("chat-client-exports-{}", uuid::Uuid::new_v4())),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let state = ServerState::new(pool, config);
+    let (addr, server) = warp::serve(create_routes(state)).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    addr
+}
+
+fn credentials(username: &str) -> Credentials {
+    Credentials {
+        username: username.to_string(),
+        password: "SecurePass123".to_string(),
+    }
+}
+
+async fn next_event(
+    events: &mut mpsc::UnboundedReceiver<WebSocketEvent>,
+    matches: impl Fn(&WebSocketEvent) -> bool,
+) -> WebSocketEvent {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let event = events.recv().await.expect("client stopped");
+            if matches(&event) {
+                return event;
+            }
+        }
+    })
+    .await
+    .expect("timed out waiting for event")
+}
+
+#[tokio::test]
+async fn test_rest_routes_round_trip() {
+    let addr = spawn_server().await;
+    let base_url = format!("http://{}", addr);
+
+    let alice = ChatClient::new(&base_url);
+    let bob = ChatClient::new(&base_url);
+    assert_eq!(alice.health().await.unwrap().status, "healthy");
+    alice.signup(&credentials("alice")).await.unwrap();
+    let bob_auth = bob.signup(&credentials("bob")).await.unwrap();
+
+    let err = ChatClient::new(&base_url)
+        .signup(&credentials("alice"))
+        .await
+        .unwrap_err();
+    assert_eq!(err.status(), Some(409));
+
+    assert_eq!(alice.me().await.unwrap().username, "alice");
+    let found = alice.search_users("bo", None).await.unwrap();
+    assert_eq!(found[0].user_id, bob_auth.user_id);
+
+    let conversation = alice.start_conversation(&bob_auth.user_id).await.unwrap();
+    let sent = alice
+        .send_message(&conversation.conversation_id, "sdk-msg-1", "Hello Bob")
+        .await
+        .unwrap();
+    assert_eq!(sent.message.id, "sdk-msg-1");
+
+    // A retry with the same key returns the stored message
+    let retried = alice
+        .send_message(&conversation.conversation_id, "sdk-msg-1", "Hello Bob")
+        .await
+        .unwrap();
+    assert_eq!(retried.message.id, "sdk-msg-1");
+
+    let history = bob
+        .messages(&conversation.conversation_id, Page::default())
+        .await
+        .unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].content, "Hello Bob");
+    assert_eq!(
+        bob.search_messages(&conversation.conversation_id, "hello", None)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // Alice is a stranger to Bob, so her conversation waits as a request
+    assert!(alice.conversations(Page::default()).await.unwrap()[0].request_pending);
+    assert!(bob.conversations(Page::new(10, 0)).await.unwrap().is_empty());
+    let requests = bob.message_requests().await.unwrap();
+    assert_eq!(requests[0].requester_username, "alice");
+    assert_eq!(requests[0].message_count, 1);
+    bob.accept_request(&conversation.conversation_id).await.unwrap();
+    assert!(bob.message_requests().await.unwrap().is_empty());
+    assert_eq!(bob.conversations(Page::new(10, 0)).await.unwrap().len(), 1);
+
+    alice.logout().await.unwrap();
+    assert!(matches!(alice.me().await, Err(ClientError::NotAuthenticated)));
+}
+
+#[tokio::test]
+async fn test_realtime_events() {
+    let addr = spawn_server().await;
+    let base_url = format!("http://{}", addr);
+
+    let alice = ChatClient::new(&base_url);
+    let bob = ChatClient::new(&base_url);
+    let alice_auth = alice.signup(&credentials("alice")).await.unwrap();
+    let bob_auth = bob.signup(&credentials("bob")).await.unwrap();
+    let conversation = alice.start_conversation(&bob_auth.user_id).await.unwrap();
+    bob.accept_request(&conversation.conversation_id).await.unwrap();
+
+    let (event_tx, mut events) = mpsc::unbounded_channel();
+    let token = bob_auth.token.clone();
+    let socket = WebSocketClient::connect(
+        format!("ws://{}/socket", addr),
+        Arc::new(move || token.clone()),
+        WireOptions::default(),
+        event_tx,
+        &tokio::runtime::Handle::current(),
+    );
+    next_event(&mut events, |e| {
+        matches!(e, WebSocketEvent::ConnectionState(ConnectionStatus::Connected))
+    })
+    .await;
+
+    alice
+        .send_message(&conversation.conversation_id, "sdk-msg-2", "Are you there?")
+        .await
+        .unwrap();
+    match next_event(&mut events, |e| matches!(e, WebSocketEvent::Message { .. })).await {
+        WebSocketEvent::Message {
+            message_id,
+            sender_id,
+            content,
+            ..
+        } => {
+            assert_eq!(message_id, "sdk-msg-2");
+            assert_eq!(sender_id.as_deref(), Some(alice_auth.user_id.as_str()));
+            assert_eq!(content, "Are you there?");
+        }
+        other => panic!("expected a message, got {:?}", other),
+    }
+
+    socket
+        .send_message(
+            "sdk-msg-3".to_string(),
+            conversation.conversation_id.clone(),
+            alice_auth.user_id.clone(),
+            "Yes".to_string(),
+        )
+        .unwrap();
+    next_event(&mut events, |e| {
+        matches!(e, WebSocketEvent::Ack { message_id: Some(id), .. } if id == "sdk-msg-3")
+    })
+    .await;
+    socket.disconnect().unwrap();
+}
+
+#[tokio::test]
+async fn test_encrypted_exchange() {
+    let addr = spawn_server().await;
+    let base_url = format!("http://{}", addr);
+    let key_dir = std::env::temp_dir().join(format!("chat-keys-{}", uuid::Uuid::new_v4()));
+
+    let alice = ChatClient::new(&base_url);
+    let bob = ChatClient::new(&base_url);
+    let alice_auth = alice.signup(&credentials("alice")).await.unwrap();
+    let bob_auth = bob.signup(&credentials("bob")).await.unwrap();
+    let bob_keys_file = key_dir.join("keys-bob.json");
+    let alice_keys = E2ee::open(key_dir.join("keys-alice.json"), &alice_auth.user_id).unwrap();
+    let bob_keys = E2ee::open(bob_keys_file.clone(), &bob_auth.user_id).unwrap();
+    alice_keys.publish(&alice).await.unwrap();
+    bob_keys.publish(&bob).await.unwrap();
+    assert_eq!(bob.key_status().await.unwrap().one_time_prekeys, 50);
+
+    let conversation = alice.start_conversation(&bob_auth.user_id).await.unwrap();
+    let id = &conversation.conversation_id;
+    bob.accept_request(id).await.unwrap();
+    for (message_id, text) in [("e2ee-1", "first secret"), ("e2ee-2", "second secret")] {
+        let envelope = alice_keys
+            .encrypt(&alice, id, &bob_auth.user_id, message_id, text)
+            .await
+            .unwrap();
+        assert!(!envelope.contains("secret"));
+        alice.send_encrypted_message(id, message_id, &envelope).await.unwrap();
+    }
+    assert!(alice.conversations(Page::default()).await.unwrap()[0].encrypted);
+    assert_eq!(bob.key_status().await.unwrap().one_time_prekeys, 49);
+
+    // Plaintext can no longer be sent into the conversation
+    let err = alice.send_message(id, "e2ee-plain", "oops").await.unwrap_err();
+    assert_eq!(err.status(), Some(400));
+
+    // History comes newest first; decrypt oldest first
+    let mut history = bob.messages(id, Page::default()).await.unwrap();
+    history.reverse();
+    for (message, expected) in history.iter().zip(["first secret", "second secret"]) {
+        assert!(message.encrypted);
+        let text = bob_keys
+            .decrypt(id, &message.sender_id, &message.id, &message.content)
+            .unwrap();
+        assert_eq!(text, expected);
+    }
+
+    // The session survives restarting the app
+    drop(bob_keys);
+    let bob_keys = E2ee::open(bob_keys_file, &bob_auth.user_id).unwrap();
+    let reply = bob_keys
+        .encrypt(&bob, id, &alice_auth.user_id, "e2ee-3", "reply")
+        .await
+        .unwrap();
+    let sent = bob.send_encrypted_message(id, "e2ee-3", &reply).await.unwrap();
+    assert_eq!(
+        alice_keys
+            .decrypt(id, &bob_auth.user_id, &sent.message.id, &sent.message.content)
+            .unwrap(),
+        "reply"
+    );
+
+    // Both sides see the same safety number; verification is local
+    let number = alice_keys.safety_number(id).unwrap();
+    assert_eq!(Some(number), bob_keys.safety_number(id));
+    assert!(!alice_keys.is_verified(id));
+    alice_keys.mark_verified(id).unwrap();
+    assert!(alice_keys.is_verified(id));
+
+    let _ = std::fs::remove_dir_all(key_dir);
+}
+
+#[tokio::test]
+async fn test_disappearing_messages() {
+    let addr = spawn_server().await;
+    let base_url = format!("http://{}", addr);
+
+    let alice = ChatClient::new(&base_url);
+    let bob = ChatClient::new(&base_url);
+    alice.signup(&credentials("alice")).await.unwrap();
+    let bob_auth = bob.signup(&credentials("bob")).await.unwrap();
+    let conversation = alice.start_conversation(&bob_auth.user_id).await.unwrap();
+    let id = &conversation.conversation_id;
+    bob.accept_request(id).await.unwrap();
+
+    let settings = alice.set_ephemeral(id, Some(3600), "sent").await.unwrap();
+    assert_eq!(settings.ephemeral_ttl, Some(3600));
+    assert!(settings.system_message.unwrap().system);
+    assert!(bob.set_ephemeral(id, Some(3600), "sent").await.unwrap().system_message.is_none());
+    let err = bob.set_ephemeral(id, Some(1), "sent").await.unwrap_err();
+    assert_eq!(err.status(), Some(400));
+
+    let sent = alice.send_message(id, "eph-1", "gone soon").await.unwrap();
+    let expires_at = sent.message.expires_at.unwrap();
+    assert!(expires_at >= sent.message.created_at + 3_600_000);
+
+    let conversations = bob.conversations(Page::default()).await.unwrap();
+    assert_eq!(conversations[0].ephemeral_ttl, Some(3600));
+    let history = bob.messages(id, Page::default()).await.unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].expires_at, Some(expires_at));
+    assert!(history[1].system);
+}
+
+#[tokio::test]
+async fn test_conversation_export() {
+    let addr = spawn_server().await;
+    let base_url = format!("http://{}", addr);
+
+    let alice = ChatClient::new(&base_url);
+    let bob = ChatClient::new(&base_url);
+    let eve = ChatClient::new(&base_url);
+    alice.signup(&credentials("alice")).await.unwrap();
+    let bob_auth = bob.signup(&credentials("bob")).await.unwrap();
+    eve.signup(&credentials("eve")).await.unwrap();
+    let conversation = alice.start_conversation(&bob_auth.user_id).await.unwrap();
+    let id = &conversation.conversation_id;
+    bob.accept_request(id).await.unwrap();
+    alice.send_message(id, "exp-1", "first").await.unwrap();
+    bob.send_message(id, "exp-2", "second").await.unwrap();
+    bob.delete_account("SecurePass123").await.unwrap();
+
+    let json = alice.export_conversation(id, "json").await.unwrap();
+    let transcript: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    let messages = transcript["messages"].as_array().unwrap();
+    assert_eq!(messages[0]["content"], "first");
+    assert_eq!(messages[1]["sender_username"], "Deleted user");
+    assert!(messages[1]["sender_id"].is_null());
+
+    let markdown = alice.export_conversation(id, "markdown").await.unwrap();
+    let markdown = String::from_utf8(markdown).unwrap();
+    assert!(markdown.find("> first").unwrap() < markdown.find("> second").unwrap());
+
+    let html = alice.export_conversation(id, "html").await.unwrap();
+    assert!(String::from_utf8(html).unwrap().starts_with("<!DOCTYPE html>"));
+
+    let err = alice.export_conversation(id, "pdf").await.unwrap_err();
+    assert_eq!(err.status(), Some(400));
+    let err = eve.export_conversation(id, "json").await.unwrap_err();
+    assert_eq!(err.status(), Some(403));
+}
+
+#[tokio::test]
+async fn test_personal_data_export() {
+    let addr = spawn_server().await;
+    let base_url = format!("http://{}", addr);
+
+    let alice = ChatClient::new(&base_url);
+    alice.signup(&credentials("alice")).await.unwrap();
+
+    let requested = alice.request_export().await.unwrap();
+    let export = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let export = alice.export_status(&requested.export_id).await.unwrap();
+            if export.status != "pending" {
+                return export;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("export never finished");
+    assert_eq!(export.status, "ready");
+    assert!(export.expires_at.is_some());
+
+    // Anyone holding the link can download it, without logging in
+    let archive = ChatClient::new(&base_url).download_export(&export).await.unwrap();
+    assert_eq!(&archive[..2], &[0x1f, 0x8b]);
+    assert_eq!(export.size_bytes, Some(archive.len() as i64));
+
+    let mut tampered = export.clone();
+    tampered.download_url = export.download_url.map(|url| format!("{}x", url));
+    let err = alice.download_export(&tampered).await.unwrap_err();
+    assert_eq!(err.status(), Some(404));
+}
+
+#[tokio::test]
+async fn test_user_blocking() {
+    let addr = spawn_server().await;
+    let base_url = format!("http://{}", addr);
+
+    let alice = ChatClient::new(&base_url);
+    let bob = ChatClient::new(&base_url);
+    let alice_auth = alice.signup(&credentials("alice")).await.unwrap();
+    let bob_auth = bob.signup(&credentials("bob")).await.unwrap();
+    let conversation = alice.start_conversation(&bob_auth.user_id).await.unwrap();
+    let id = &conversation.conversation_id;
+    bob.accept_request(id).await.unwrap();
+
+    let blocked = alice.block_user(&bob_auth.user_id).await.unwrap();
+    assert_eq!(blocked.username, "bob");
+    alice.block_user(&bob_auth.user_id).await.unwrap();
+    assert_eq!(alice.blocked_users().await.unwrap().len(), 1);
+    let err = alice.block_user(&alice_auth.user_id).await.unwrap_err();
+    assert_eq!(err.status(), Some(400));
+    assert!(bob.search_users("ali", None).await.unwrap().is_empty());
+    assert!(alice.search_users("bo", None).await.unwrap().is_empty());
+
+    // Bob is not told, but his message never reaches Alice
+    let sent = bob.send_message(id, "blk-1", "hello?").await.unwrap();
+    assert_eq!(sent.message.content, "hello?");
+    assert!(alice.messages(id, Page::default()).await.unwrap().is_empty());
+    let err = alice.send_message(id, "blk-2", "hi").await.unwrap_err();
+    assert_eq!(err.status(), Some(400));
+
+    alice.unblock_user(&bob_auth.user_id).await.unwrap();
+    let err = alice.unblock_user(&bob_auth.user_id).await.unwrap_err();
+    assert_eq!(err.status(), Some(404));
+    assert!(alice.blocked_users().await.unwrap().is_empty());
+    assert_eq!(bob.search_users("ali", None).await.unwrap().len(), 1);
+    bob.send_message(id, "blk-3", "again").await.unwrap();
+    let history = alice.messages(id, Page::default()).await.unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].content, "again");
+}
+
+#[tokio::test]
+async fn test_message_requests() {
+    let addr = spawn_server().await;
+    let base_url = format!("http://{}", addr);
+
+    let alice = ChatClient::new(&base_url);
+    let bob = ChatClient::new(&base_url);
+    let carol = ChatClient::new(&base_url);
+    let alice_auth = alice.signup(&credentials("alice")).await.unwrap();
+    let bob_auth = bob.signup(&credentials("bob")).await.unwrap();
+    carol.signup(&credentials("carol")).await.unwrap();
+
+    // Only accepting lets Bob reply: neither replying nor starting a
+    // conversation with Alice himself accepts her request
+    let conversation = alice.start_conversation(&bob_auth.user_id).await.unwrap();
+    let id = &conversation.conversation_id;
+    assert!(conversation.request_pending);
+    alice.send_message(id, "req-1", "hi bob").await.unwrap();
+    let err = bob.send_message(id, "req-2", "hi").await.unwrap_err();
+    assert_eq!(err.status(), Some(400));
+    let err = alice.accept_request(id).await.unwrap_err();
+    assert_eq!(err.status(), Some(404));
+    let started = bob.start_conversation(&alice_auth.user_id).await.unwrap();
+    assert_eq!(&started.conversation_id, id);
+    assert!(started.request_pending);
+    assert_eq!(bob.message_requests().await.unwrap().len(), 1);
+    let err = bob.send_message(id, "req-2", "hi").await.unwrap_err();
+    assert_eq!(err.status(), Some(400));
+    assert!(alice.conversations(Page::default()).await.unwrap()[0].request_pending);
+
+    bob.accept_request(id).await.unwrap();
+    assert!(!alice.conversations(Page::default()).await.unwrap()[0].request_pending);
+    bob.send_message(id, "req-2", "hi").await.unwrap();
+
+    // Declining drops held messages, and later ones, without telling Carol
+    let conversation = carol.start_conversation(&bob_auth.user_id).await.unwrap();
+    let id = &conversation.conversation_id;
+    carol.send_message(id, "req-3", "buy now").await.unwrap();
+    bob.decline_request(id).await.unwrap();
+    carol.send_message(id, "req-4", "last chance").await.unwrap();
+    assert!(bob.message_requests().await.unwrap().is_empty());
+    assert!(bob.messages(id, Page::default()).await.unwrap().is_empty());
+    assert_eq!(bob.conversations(Page::default()).await.unwrap().len(), 1);
+}
@@ -0,0 +1,219 @@
+//! REST request and response types
+//!
+//! Mirrors the server's handler types field for field (REST bodies use
+//! snake_case, unlike WebSocket frames). Messages use the shared `MessageDto`.
+
+use serde::{Deserialize, Serialize};
+
+pub use chat_shared::protocol::MessageDto;
+
+/// Username and password for signup and login
+#[derive(Debug, Clone, Serialize)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Returned by signup and login
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthResponse {
+    pub user_id: String,
+    pub username: String,
+    pub token: String,
+    /// Expiry of `token` as a Unix timestamp in seconds
+    pub expires_in: u64,
+}
+
+/// Confirmation for actions without a resource to return
+#[derive(Debug, Clone, Deserialize)]
+pub struct SuccessResponse {
+    pub message: String,
+}
+
+/// `GET /health`
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthResponse {
+    pub status: String,
+    pub timestamp: i64,
+    pub uptime_seconds: u64,
+}
+
+/// `GET /status`
+#[derive(Debug, Clone, Deserialize)]
+pub struct StatusResponse {
+    pub status: String,
+    pub version: String,
+    pub timestamp: i64,
+    pub uptime_seconds: u64,
+    pub database: DatabaseStatus,
+    pub metrics: StatusMetrics,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DatabaseStatus {
+    pub status: String,
+    pub engine: String,
+}
+
+/// Headline counters from `/status`; queue and compression details are in `/openapi.json`
+#[derive(Debug, Clone, Deserialize)]
+pub struct StatusMetrics {
+    pub total_users: i64,
+    pub total_messages: i64,
+    pub online_connections: usize,
+}
+
+/// The authenticated user's profile
+#[derive(Debug, Clone, Deserialize)]
+pub struct UserProfile {
+    pub user_id: String,
+    pub username: String,
+    pub created_at: i64,
+    pub is_online: bool,
+    pub last_seen_at: Option<i64>,
+}
+
+/// `POST /user/export` and `GET /user/export/{id}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct DataExport {
+    pub export_id: String,
+    /// `pending`, `ready`, `failed` or `expired`
+    pub status: String,
+    pub created_at: i64,
+    pub completed_at: Option<i64>,
+    pub expires_at: Option<i64>,
+    pub size_bytes: Option<i64>,
+    /// Signed path of the archive; set once `ready`
+    pub download_url: Option<String>,
+    pub error: Option<String>,
+}
+
+/// One entry of `GET /user/blocks`
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockedUser {
+    pub user_id: String,
+    pub username: String,
+    pub blocked_at: i64,
+}
+
+/// One match from `GET /users/search`
+#[derive(Debug, Clone, Deserialize)]
+pub struct UserSearchResult {
+    pub user_id: String,
+    pub username: String,
+    pub is_online: bool,
+}
+
+/// A conversation as seen by the current user
+#[derive(Debug, Clone, Deserialize)]
+pub struct Conversation {
+    pub conversation_id: String,
+    pub participant_id: String,
+    pub participant_username: String,
+    pub participant_is_online: bool,
+    pub created_at: i64,
+    pub last_message_at: Option<i64>,
+    pub message_count: i32,
+    /// Messages are end-to-end encrypted; plaintext sends are refused
+    #[serde(default)]
+    pub encrypted: bool,
+    /// Seconds new messages are kept before they disappear, if set
+    #[serde(default)]
+    pub ephemeral_ttl: Option<i64>,
+    /// `sent` or `read`: when the disappearing-message timer starts
+    #[serde(default)]
+    pub ephemeral_start: String,
+    /// This conversation's message request has not been accepted yet
+    #[serde(default)]
+    pub request_pending: bool,
+}
+
+/// One entry of `GET /conversations/requests`
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessageRequest {
+    pub conversation_id: String,
+    pub requester_id: String,
+    pub requester_username: String,
+    pub created_at: i64,
+    /// Messages held until the request is accepted
+    pub message_count: i64,
+    pub last_message_at: Option<i64>,
+}
+
+/// `PUT /conversations/{id}/ephemeral`
+#[derive(Debug, Clone, Deserialize)]
+pub struct EphemeralSettings {
+    pub conversation_id: String,
+    pub ephemeral_ttl: Option<i64>,
+    pub ephemeral_start: String,
+    /// System message announcing the change; absent if nothing changed
+    pub system_message: Option<MessageDto>,
+}
+
+/// `POST /conversations/{id}/messages`
+#[derive(Debug, Clone, Deserialize)]
+pub struct SendMessageResponse {
+    pub message: MessageDto,
+    /// `delivered` if the recipient has it, otherwise `sent`
+    pub status: String,
+}
+
+/// Signed prekey; `signature` is the Ed25519 signature of `public_key`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedPrekey {
+    pub key_id: u32,
+    pub public_key: String,
+    pub signature: String,
+}
+
+/// One-time prekey
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublicPrekey {
+    pub key_id: u32,
+    pub public_key: String,
+}
+
+/// `PUT /keys`; keys and signatures are base64
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadKeys {
+    pub identity_key: String,
+    pub signing_key: String,
+    pub signed_prekey: SignedPrekey,
+    pub one_time_prekeys: Vec<PublicPrekey>,
+}
+
+/// `GET /keys` and the response to `PUT /keys`
+#[derive(Debug, Clone, Deserialize)]
+pub struct KeyStatus {
+    pub has_keys: bool,
+    pub signed_prekey_id: Option<u32>,
+    pub one_time_prekeys: i64,
+}
+
+/// `GET /keys/{user_id}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct KeyBundle {
+    pub user_id: String,
+    pub identity_key: String,
+    pub signing_key: String,
+    pub signed_prekey: SignedPrekey,
+    pub one_time_prekey: Option<PublicPrekey>,
+}
+
+/// `limit`/`offset` query parameters; `None` uses the server default
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Page {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u32>,
+}
+
+impl Page {
+    pub fn new(limit: u32, offset: u32) -> Self {
+        Self {
+            limit: Some(limit),
+            offset: Some(offset),
+        }
+    }
+}
@@ -1,5 +1,7 @@
+use chat_client::e2ee::{format_safety_number, E2ee};
+use chat_client::{MessageDto, Page};
 use crate::services::ConnectionStatus;
-use crate::ui::{ChatScreenComponent, ConversationItem, MessageItem};
+use crate::ui::{ChatScreenComponent, ConversationItem, MessageItem, MessageRequestItem};
 use slint::{ComponentHandle, ModelRc, VecModel};
 use std::collections::HashMap;
 use std::rc::Rc;
@@ -18,6 +20,8 @@ pub struct ConversationData {
     pub last_message: String,
     pub last_message_time: String,
     pub message_count: i32,
+    /// Messages are end-to-end encrypted
+    pub encrypted: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -43,6 +47,7 @@ pub struct ChatScreen {
     _typing_state: Arc<Mutex<bool>>,
     _typing_indicator_token: Arc<Mutex<String>>,
     websocket_client: Option<crate::services::WebSocketClient>,
+    e2ee: Option<Arc<E2ee>>,
     _event_handle: Option<std::thread::JoinHandle<()>>,
 }
 
@@ -67,18 +72,53 @@ impl ChatScreen {
         ui.set_error_dialog_title("Something went wrong".into());
         ui.set_error_dialog_message("".into());
 
+        // End-to-end encryption keys live next to the session file
+        let e2ee = {
+            let session_file = crate::services::session::get_session_manager().session_file();
+            match E2ee::open(E2ee::key_file(session_file, &user_id), &user_id) {
+                Ok(e2ee) => Some(Arc::new(e2ee)),
+                Err(e) => {
+                    tracing::warn!("End-to-end encryption unavailable: {}", e);
+                    None
+                }
+            }
+        };
+        if let Some(e2ee) = e2ee.clone() {
+            runtime.spawn(async move {
+                if let Err(e) = e2ee.publish(&crate::services::api_client()).await {
+                    tracing::warn!("Failed to publish encryption keys: {}", e);
+                }
+            });
+        }
+
         // WebSocket client bootstrap
         let ui_weak = ui.as_weak();
         let session_token =
             crate::services::session::get_token().ok_or("No authentication token found")?;
         let ws_url = std::env::var("SERVER_WS_URL")
             .unwrap_or_else(|_| "ws://localhost:8080/socket".to_string());
+        // Opt in to compact binary frames with CHAT_WIRE_ENCODING=msgpack;
+        // compression is requested unless CHAT_WS_COMPRESSION=off
+        let wire_options = crate::services::WireOptions {
+            encoding: std::env::var("CHAT_WIRE_ENCODING")
+                .ok()
+                .and_then(|v| chat_shared::protocol::WireEncoding::from_name(&v))
+                .unwrap_or_default(),
+            compression: std::env::var("CHAT_WS_COMPRESSION")
+                .map(|v| !matches!(v.trim().to_ascii_lowercase().as_str(), "off" | "false" | "0"))
+                .unwrap_or(true),
+        };
         let (event_tx, event_rx) = mpsc::unbounded_channel::<crate::services::WebSocketEvent>();
+        // Reconnects pick up a refreshed token from the session
+        let token_provider: crate::services::TokenProvider = Arc::new(move || {
+            crate::services::session::get_token().unwrap_or_else(|| session_token.clone())
+        });
         let websocket_client = Some(crate::services::WebSocketClient::connect(
             ws_url,
-            session_token,
+            token_provider,
+            wire_options,
             event_tx,
-            &runtime,
+            runtime.handle(),
         ));
         let event_handle = spawn_event_listener(
             event_rx,
@@ -91,6 +131,7 @@ impl ChatScreen {
             current_user_id.clone(),
             runtime.clone(),
             websocket_client.clone(),
+            e2ee.clone(),
         );
 
         // Logout callback
@@ -153,6 +194,7 @@ impl ChatScreen {
         let messages_for_select = messages.clone();
         let selected_conv_for_select = selected_conversation_id.clone();
         let selected_participant_for_select = selected_participant_id.clone();
+        let e2ee_for_select = e2ee.clone();
         let ui_weak_select = ui.as_weak();
 
         // Set up conversation selection callback
@@ -163,6 +205,7 @@ impl ChatScreen {
             let messages = messages_for_select.clone();
             let selected_conv = selected_conv_for_select.clone();
             let selected_participant = selected_participant_for_select.clone();
+            let e2ee = e2ee_for_select.clone();
             let _user_id = user_id_clone.clone();
 
             let _ui = match ui_weak.upgrade() {
@@ -201,9 +244,11 @@ impl ChatScreen {
                 .ok();
             }
 
+            render_encryption_state(ui_weak.clone(), e2ee.as_deref(), &conversations, &conv_id);
+
             runtime.spawn(async move {
                 // Load messages for selected conversation
-                match load_messages(&conv_id).await {
+                match load_messages(&conv_id, e2ee.as_deref()).await {
                     Ok(msgs) => {
                         {
                             let mut cache = messages.lock().unwrap();
@@ -214,6 +259,13 @@ impl ChatScreen {
                             messages.clone(),
                             conv_id.clone(),
                         );
+                        // Decrypting may have started a session
+                        render_encryption_state(
+                            ui_weak.clone(),
+                            e2ee.as_deref(),
+                            &conversations,
+                            &conv_id,
+                        );
                     }
                     Err(e) => {
                         let err_msg = format!("Failed to load messages: {}", e);
@@ -234,6 +286,9 @@ impl ChatScreen {
         let selected_participant_for_send = selected_participant_id.clone();
         let ws_for_send = websocket_client.clone();
         let typing_state_for_send = typing_state.clone();
+        let conversations_for_send = conversations.clone();
+        let e2ee_for_send = e2ee.clone();
+        let runtime_for_send = runtime.clone();
 
         ui.on_send_message(move |content| {
             let ui_weak = ui_weak_send.clone();
@@ -241,6 +296,7 @@ impl ChatScreen {
             let selected_participant = selected_participant_for_send.clone();
             let ws_client = ws_for_send.clone();
             let typing_state = typing_state_for_send.clone();
+            let conversations = conversations_for_send.clone();
 
             let ui = match ui_weak.upgrade() {
                 Some(ui) => ui,
@@ -284,7 +340,52 @@ impl ChatScreen {
                 conversation_id.clone(),
             );
 
-            if let Some(ws) = ws_client.as_ref() {
+            let encrypted = conversations
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|c| c.conversation_id == conversation_id && c.encrypted);
+            if encrypted {
+                let (Some(ws), Some(e2ee)) = (ws_client.clone(), e2ee_for_send.clone()) else {
+                    ui.set_error_message("End-to-end encryption is unavailable".into());
+                    return;
+                };
+                let participant_id = participant_id.clone();
+                runtime_for_send.spawn(async move {
+                    let sent = match e2ee
+                        .encrypt(
+                            &crate::services::api_client(),
+                            &conversation_id,
+                            &participant_id,
+                            &message_id,
+                            &message_content,
+                        )
+                        .await
+                    {
+                        Ok(envelope) => ws
+                            .send_encrypted_message(
+                                message_id,
+                                conversation_id.clone(),
+                                participant_id,
+                                envelope,
+                            )
+                            .map_err(|e| e.to_string()),
+                        Err(e) => Err(e.to_string()),
+                    };
+                    if let Err(e) = sent {
+                        let err_msg = format!("Failed to send message: {}", e);
+                        let ui_weak = ui_weak.clone();
+                        slint::invoke_from_event_loop(move || {
+                            if let Some(ui) = ui_weak.upgrade() {
+                                ui.set_error_message(err_msg.into());
+                            }
+                        })
+                        .ok();
+                    }
+                    // The first message starts the session and its safety number
+                    render_encryption_state(ui_weak, Some(&e2ee), &conversations, &conversation_id);
+                });
+            } else if let Some(ws) = ws_client.as_ref() {
                 if let Err(e) = ws.send_message(
                     message_id.clone(),
                     conversation_id.clone(),
@@ -433,6 +534,194 @@ impl ChatScreen {
             render_messages_for_conversation(ui_weak.clone(), messages.clone(), conversation_id);
         });
 
+        // End-to-end encryption callbacks
+        let ui_weak_encrypt = ui.as_weak();
+        let conversations_for_encrypt = conversations.clone();
+        let e2ee_for_encrypt = e2ee.clone();
+        ui.on_enable_encryption(move || {
+            let Some(ui) = ui_weak_encrypt.upgrade() else {
+                return;
+            };
+            if e2ee_for_encrypt.is_none() {
+                ui.set_error_message("End-to-end encryption is unavailable".into());
+                return;
+            }
+
+            // The server marks the conversation encrypted with the first
+            // encrypted message, after which plaintext is refused
+            let conversation_id = ui.get_selected_conversation_id().to_string();
+            for conv in conversations_for_encrypt.lock().unwrap().iter_mut() {
+                if conv.conversation_id == conversation_id {
+                    conv.encrypted = true;
+                }
+            }
+            render_encryption_state(
+                ui_weak_encrypt.clone(),
+                e2ee_for_encrypt.as_deref(),
+                &conversations_for_encrypt,
+                &conversation_id,
+            );
+        });
+
+        let ui_weak_verify = ui.as_weak();
+        let conversations_for_verify = conversations.clone();
+        let e2ee_for_verify = e2ee.clone();
+        ui.on_verify_safety_number(move || {
+            let (Some(ui), Some(e2ee)) = (ui_weak_verify.upgrade(), e2ee_for_verify.as_deref())
+            else {
+                return;
+            };
+            let conversation_id = ui.get_selected_conversation_id().to_string();
+            if let Err(e) = e2ee.mark_verified(&conversation_id) {
+                ui.set_error_message(format!("Failed to verify: {}", e).into());
+            }
+            render_encryption_state(
+                ui_weak_verify.clone(),
+                Some(e2ee),
+                &conversations_for_verify,
+                &conversation_id,
+            );
+        });
+
+        // Export… menu: the server writes the transcript, we save it locally
+        let ui_weak_export = ui.as_weak();
+        let runtime_for_export = runtime.clone();
+        ui.on_export_conversation(move |format| {
+            let Some(ui) = ui_weak_export.upgrade() else {
+                return;
+            };
+            let conversation_id = ui.get_selected_conversation_id().to_string();
+            let participant = ui.get_selected_participant_username().to_string();
+            let format = format.to_string();
+            let ui_weak = ui_weak_export.clone();
+
+            runtime_for_export.spawn(async move {
+                let result = save_export(&conversation_id, &participant, &format)
+                    .await
+                    .map_err(|e| e.to_string());
+                slint::invoke_from_event_loop(move || {
+                    if let Some(ui) = ui_weak.upgrade() {
+                        match result {
+                            Ok(path) => {
+                                ui.set_error_dialog_title("Conversation exported".into());
+                                ui.set_error_dialog_message(
+                                    format!("Saved to {}", path.display()).into(),
+                                );
+                                ui.set_show_error_dialog(true);
+                            }
+                            Err(e) => {
+                                ui.set_error_message(format!("Export failed: {}", e).into());
+                            }
+                        }
+                    }
+                })
+                .ok();
+            });
+        });
+
+        // Block user menu: they drop out of search and presence, and can be
+        // unblocked from settings
+        let ui_weak_block = ui.as_weak();
+        let runtime_for_block = runtime.clone();
+        ui.on_block_participant(move || {
+            let Some(ui) = ui_weak_block.upgrade() else {
+                return;
+            };
+            let participant_id = ui.get_selected_participant_id().to_string();
+            let participant = ui.get_selected_participant_username().to_string();
+            let ui_weak = ui_weak_block.clone();
+
+            runtime_for_block.spawn(async move {
+                let result = crate::services::api_client()
+                    .block_user(&participant_id)
+                    .await
+                    .map_err(|e| e.to_string());
+                slint::invoke_from_event_loop(move || {
+                    if let Some(ui) = ui_weak.upgrade() {
+                        match result {
+                            Ok(_) => {
+                                ui.set_selected_participant_is_online(false);
+                                ui.set_error_dialog_title("User blocked".into());
+                                ui.set_error_dialog_message(
+                                    format!(
+                                        "You won't receive messages from {}. Unblock them in Settings.",
+                                        participant
+                                    )
+                                    .into(),
+                                );
+                                ui.set_show_error_dialog(true);
+                            }
+                            Err(e) => {
+                                ui.set_error_message(format!("Block failed: {}", e).into());
+                            }
+                        }
+                    }
+                })
+                .ok();
+            });
+        });
+
+        // Message requests: accepting moves the conversation into the list
+        let ui_weak_requests = ui.as_weak();
+        let runtime_for_requests = runtime.clone();
+        let conversations_for_requests = conversations.clone();
+        let messages_for_requests = messages.clone();
+        let selected_for_requests = selected_conversation_id.clone();
+        let e2ee_for_requests = e2ee.clone();
+        ui.on_accept_request(move |conversation_id| {
+            let conversation_id = conversation_id.to_string();
+            let ui_weak = ui_weak_requests.clone();
+            let conversations = conversations_for_requests.clone();
+            let messages = messages_for_requests.clone();
+            let selected = selected_for_requests.clone();
+            let e2ee = e2ee_for_requests.clone();
+            runtime_for_requests.spawn(async move {
+                let result = crate::services::api_client()
+                    .accept_request(&conversation_id)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string());
+                show_request_error(ui_weak.clone(), result);
+                reload_from_server(ui_weak, conversations, messages, selected, e2ee).await;
+            });
+        });
+
+        let ui_weak_decline = ui.as_weak();
+        let runtime_for_decline = runtime.clone();
+        ui.on_decline_request(move |conversation_id| {
+            let conversation_id = conversation_id.to_string();
+            let ui_weak = ui_weak_decline.clone();
+            runtime_for_decline.spawn(async move {
+                let result = crate::services::api_client()
+                    .decline_request(&conversation_id)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string());
+                show_request_error(ui_weak.clone(), result);
+                load_message_requests(ui_weak).await;
+            });
+        });
+
+        let ui_weak_block_requester = ui.as_weak();
+        let runtime_for_block_requester = runtime.clone();
+        ui.on_block_requester(move |conversation_id, requester_id| {
+            let conversation_id = conversation_id.to_string();
+            let requester_id = requester_id.to_string();
+            let ui_weak = ui_weak_block_requester.clone();
+            runtime_for_block_requester.spawn(async move {
+                let client = crate::services::api_client();
+                let result = match client.block_user(&requester_id).await {
+                    Ok(_) => client.decline_request(&conversation_id).await.map(|_| ()),
+                    Err(e) => Err(e),
+                }
+                .map_err(|e| e.to_string());
+                show_request_error(ui_weak.clone(), result);
+                load_message_requests(ui_weak).await;
+            });
+        });
+
+        runtime.spawn(load_message_requests(ui.as_weak()));
+
         // Load initial conversations
         let ui_weak_init = ui.as_weak();
         let conversations_init = conversations.clone();
@@ -491,6 +780,7 @@ impl ChatScreen {
             _typing_state: typing_state,
             _typing_indicator_token: typing_indicator_token,
             websocket_client,
+            e2ee,
             _event_handle: Some(event_handle),
         })
     }
@@ -517,6 +807,7 @@ fn spawn_event_listener(
     current_user_id: String,
     runtime: Arc<Runtime>,
     websocket_client: Option<crate::services::WebSocketClient>,
+    e2ee: Option<Arc<E2ee>>,
 ) -> std::thread::JoinHandle<()> {
     std::thread::spawn(move || {
         while let Some(event) = event_rx.blocking_recv() {
@@ -565,35 +856,18 @@ fn spawn_event_listener(
                             let selected_conv_refresh = selected_conversation_id.clone();
                             let ui_refresh = ui_weak.clone();
                             let ws_for_resend = websocket_client.clone();
+                            let e2ee_refresh = e2ee.clone();
                             let runtime_refresh = runtime.clone();
 
                             runtime_refresh.spawn(async move {
-                                if let Ok(fresh_conversations) = load_conversations().await {
-                                    {
-                                        let mut cache = conversations_refresh.lock().unwrap();
-                                        *cache = fresh_conversations.clone();
-                                    }
-                                    render_conversations(
-                                        ui_refresh.clone(),
-                                        conversations_refresh.clone(),
-                                    );
-                                }
-
-                                let active_conv = { selected_conv_refresh.lock().unwrap().clone() };
-
-                                if let Some(active_conv) = active_conv {
-                                    if let Ok(fresh_messages) = load_messages(&active_conv).await {
-                                        {
-                                            let mut cache = messages_refresh.lock().unwrap();
-                                            *cache = fresh_messages.clone();
-                                        }
-                                        render_messages_for_conversation(
-                                            ui_refresh.clone(),
-                                            messages_refresh.clone(),
-                                            active_conv,
-                                        );
-                                    }
-                                }
+                                reload_from_server(
+                                    ui_refresh,
+                                    conversations_refresh.clone(),
+                                    messages_refresh.clone(),
+                                    selected_conv_refresh,
+                                    e2ee_refresh,
+                                )
+                                .await;
 
                                 if let Some(ws) = ws_for_resend {
                                     resend_pending_messages(
@@ -622,6 +896,19 @@ fn spawn_event_listener(
                             })
                             .ok();
                         }
+                        ConnectionStatus::UpdateRequired { message } => {
+                            let ui_for_status = ui_weak.clone();
+                            slint::invoke_from_event_loop(move || {
+                                if let Some(ui) = ui_for_status.upgrade() {
+                                    ui.set_connection_status("Update required".into());
+                                    ui.set_connection_online(false);
+                                    ui.set_error_dialog_title("Update required".into());
+                                    ui.set_error_dialog_message(message.clone().into());
+                                    ui.set_show_error_dialog(true);
+                                }
+                            })
+                            .ok();
+                        }
                     }
                 }
                 crate::services::WebSocketEvent::Presence {
@@ -663,18 +950,41 @@ fn spawn_event_listener(
                 crate::services::WebSocketEvent::Message {
                     conversation_id,
                     message_id,
+                    sender_id,
                     sender_username,
                     content,
                     status,
                     timestamp,
+                    encrypted,
                 } => {
+                    if encrypted {
+                        // The other participant turned encryption on
+                        for conv in conversations.lock().unwrap().iter_mut() {
+                            if conv.conversation_id == conversation_id {
+                                conv.encrypted = true;
+                            }
+                        }
+                    }
                     if selected_conversation_id.lock().unwrap().as_deref() != Some(&conversation_id)
                     {
                         continue;
                     }
 
+                    let content = message_text(
+                        e2ee.as_deref(),
+                        &conversation_id,
+                        sender_id.as_deref().unwrap_or_default(),
+                        &message_id,
+                        content,
+                        encrypted,
+                    );
+
                     {
                         let mut cache = messages.lock().unwrap();
+                        if cache.iter().any(|m| m.message_id == message_id) {
+                            // Already shown, e.g. redelivered after a reconnect
+                            continue;
+                        }
                         cache.push(MessageData {
                             message_id,
                             conversation_id: conversation_id.clone(),
@@ -685,6 +995,14 @@ fn spawn_event_listener(
                             status,
                         });
                     }
+                    if encrypted {
+                        render_encryption_state(
+                            ui_weak.clone(),
+                            e2ee.as_deref(),
+                            &conversations,
+                            &conversation_id,
+                        );
+                    }
 
                     let is_searching = ui_weak
                         .upgrade()
@@ -796,6 +1114,41 @@ fn spawn_event_listener(
                         });
                     }
                 }
+                crate::services::WebSocketEvent::MessageExpired {
+                    conversation_id,
+                    message_ids,
+                } => {
+                    messages
+                        .lock()
+                        .unwrap()
+                        .retain(|m| !message_ids.contains(&m.message_id));
+
+                    if selected_conversation_id.lock().unwrap().as_deref() != Some(&conversation_id)
+                    {
+                        continue;
+                    }
+                    let is_searching = ui_weak
+                        .upgrade()
+                        .map(|ui| ui.get_is_search_active())
+                        .unwrap_or(false);
+                    if !is_searching {
+                        render_messages_for_conversation(
+                            ui_weak.clone(),
+                            messages.clone(),
+                            conversation_id,
+                        );
+                    }
+                }
+                crate::services::WebSocketEvent::ResyncRequired => {
+                    // Missed events are gone; refetch conversations and the open thread
+                    runtime.spawn(reload_from_server(
+                        ui_weak.clone(),
+                        conversations.clone(),
+                        messages.clone(),
+                        selected_conversation_id.clone(),
+                        e2ee.clone(),
+                    ));
+                }
                 crate::services::WebSocketEvent::Error(err) => {
                     let ui_weak_err = ui_weak.clone();
                     slint::invoke_from_event_loop(move || {
@@ -844,6 +1197,107 @@ fn render_messages_for_conversation(
     .ok();
 }
 
+/// Replace message requests, cached conversations and the open thread with fresh
+/// copies from the server
+async fn reload_from_server(
+    ui_weak: slint::Weak<ChatScreenComponent>,
+    conversations: Arc<Mutex<Vec<ConversationData>>>,
+    messages: Arc<Mutex<Vec<MessageData>>>,
+    selected_conversation_id: Arc<Mutex<Option<String>>>,
+    e2ee: Option<Arc<E2ee>>,
+) {
+    load_message_requests(ui_weak.clone()).await;
+
+    if let Ok(fresh_conversations) = load_conversations().await {
+        {
+            let mut cache = conversations.lock().unwrap();
+            *cache = fresh_conversations;
+        }
+        render_conversations(ui_weak.clone(), conversations.clone());
+    }
+
+    let active_conv = { selected_conversation_id.lock().unwrap().clone() };
+
+    if let Some(active_conv) = active_conv {
+        if let Ok(fresh_messages) = load_messages(&active_conv, e2ee.as_deref()).await {
+            {
+                let mut cache = messages.lock().unwrap();
+                *cache = fresh_messages;
+            }
+            render_messages_for_conversation(ui_weak, messages, active_conv);
+        }
+    }
+}
+
+/// Fetch the message requests waiting for this user into the sidebar
+async fn load_message_requests(ui_weak: slint::Weak<ChatScreenComponent>) {
+    let requests = match crate::services::api_client().message_requests().await {
+        Ok(requests) => requests,
+        Err(e) => {
+            tracing::warn!("Failed to load message requests: {}", e);
+            return;
+        }
+    };
+    slint::invoke_from_event_loop(move || {
+        if let Some(ui) = ui_weak.upgrade() {
+            let items: Vec<MessageRequestItem> = requests
+                .into_iter()
+                .map(|r| MessageRequestItem {
+                    conversation_id: r.conversation_id.into(),
+                    requester_id: r.requester_id.into(),
+                    requester_username: r.requester_username.into(),
+                    message_count: r.message_count as i32,
+                })
+                .collect();
+            ui.set_message_requests(ModelRc::from(Rc::new(VecModel::from(items))));
+        }
+    })
+    .ok();
+}
+
+fn show_request_error(ui_weak: slint::Weak<ChatScreenComponent>, result: Result<(), String>) {
+    if let Err(e) = result {
+        slint::invoke_from_event_loop(move || {
+            if let Some(ui) = ui_weak.upgrade() {
+                ui.set_error_message(format!("Message request failed: {}", e).into());
+            }
+        })
+        .ok();
+    }
+}
+
+/// Show the lock, safety number and verification state of a conversation
+fn render_encryption_state(
+    ui_weak: slint::Weak<ChatScreenComponent>,
+    e2ee: Option<&E2ee>,
+    conversations: &Arc<Mutex<Vec<ConversationData>>>,
+    conversation_id: &str,
+) {
+    let encrypted = conversations
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|c| c.conversation_id == conversation_id && c.encrypted);
+    let safety_number = e2ee
+        .and_then(|e2ee| e2ee.safety_number(conversation_id))
+        .map(|number| format_safety_number(&number))
+        .unwrap_or_default();
+    let verified = e2ee.is_some_and(|e2ee| e2ee.is_verified(conversation_id));
+
+    let conversation_id = conversation_id.to_string();
+    slint::invoke_from_event_loop(move || {
+        if let Some(ui) = ui_weak.upgrade() {
+            if ui.get_selected_conversation_id() != conversation_id.as_str() {
+                return;
+            }
+            ui.set_selected_is_encrypted(encrypted);
+            ui.set_selected_safety_number(safety_number.into());
+            ui.set_selected_is_verified(verified);
+        }
+    })
+    .ok();
+}
+
 fn render_conversations(
     ui_weak: slint::Weak<ChatScreenComponent>,
     conversations: Arc<Mutex<Vec<ConversationData>>>,
@@ -876,10 +1330,12 @@ fn resend_pending_messages(
     messages: &Arc<Mutex<Vec<MessageData>>>,
     conversations: &Arc<Mutex<Vec<ConversationData>>>,
 ) {
+    // Encrypted sends wait in the realtime client's outbox as envelopes
     let participants: HashMap<String, String> = conversations
         .lock()
         .unwrap()
         .iter()
+        .filter(|c| !c.encrypted)
         .map(|c| (c.conversation_id.clone(), c.participant_id.clone()))
         .collect();
 
@@ -917,56 +1373,23 @@ fn format_timestamp(timestamp: Option<i64>) -> String {
 
 // API call to logout
 async fn api_logout() -> Result<(), Box<dyn std::error::Error>> {
-    let base_url =
-        std::env::var("SERVER_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
-
-    let token = match crate::services::session::get_token() {
-        Some(t) => t,
-        None => return Ok(()), // Already logged out or no token
-    };
+    let client = crate::services::api_client();
+    if client.token().is_none() {
+        return Ok(()); // Already logged out or no token
+    }
 
-    let client = reqwest::Client::new();
-    let _ = client
-        .post(format!("{}/auth/logout", base_url))
-        .header("Authorization", format!("Bearer {}", token))
-        .send()
-        .await?;
+    let _ = client.logout().await;
 
     Ok(())
 }
 
 // API call to load conversations
 async fn load_conversations() -> Result<Vec<ConversationData>, Box<dyn std::error::Error>> {
-    let base_url =
-        std::env::var("SERVER_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
-
-    let token = crate::services::session::get_token().ok_or("No authentication token found")?;
-
-    #[derive(serde::Deserialize)]
-    struct ApiConversation {
-        conversation_id: String,
-        participant_id: String,
-        participant_username: String,
-        participant_is_online: bool,
-        last_message_at: Option<i64>,
-        message_count: i32,
-    }
-
-    let client = reqwest::Client::new();
-    let response = client
-        .get(format!("{}/conversations", base_url))
-        .query(&[("limit", "20"), ("offset", "0")])
-        .header("Authorization", format!("Bearer {}", token))
-        .send()
+    let conversations = crate::services::api_client()
+        .conversations(Page::new(20, 0))
         .await?;
 
-    if !response.status().is_success() {
-        return Err(format!("Failed to load conversations: {}", response.status()).into());
-    }
-
-    let api_conversations: Vec<ApiConversation> = response.json().await?;
-
-    Ok(api_conversations
+    Ok(conversations
         .into_iter()
         .map(|c| ConversationData {
             conversation_id: c.conversation_id,
@@ -976,6 +1399,7 @@ async fn load_conversations() -> Result<Vec<ConversationData>, Box<dyn std::erro
             last_message: "".to_string(), // TODO: Get from messages
             last_message_time: format_timestamp(c.last_message_at),
             message_count: c.message_count,
+            encrypted: c.encrypted,
         })
         .collect())
 }
@@ -983,54 +1407,20 @@ async fn load_conversations() -> Result<Vec<ConversationData>, Box<dyn std::erro
 // API call to load messages for a conversation
 async fn load_messages(
     conversation_id: &str,
+    e2ee: Option<&E2ee>,
 ) -> Result<Vec<MessageData>, Box<dyn std::error::Error>> {
-    let base_url =
-        std::env::var("SERVER_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
-
-    let token = crate::services::session::get_token().ok_or("No authentication token found")?;
-
     let session = crate::services::session::get_session_manager()
         .get_current_session()
         .ok_or("No session found")?;
 
-    #[derive(serde::Deserialize)]
-    struct ApiMessage {
-        id: String,
-        sender_id: String,
-        sender_username: Option<String>,
-        content: String,
-        created_at: i64,
-        status: String,
-    }
-
-    let client = reqwest::Client::new();
-    let response = client
-        .get(format!(
-            "{}/conversations/{}/messages",
-            base_url, conversation_id
-        ))
-        .query(&[("limit", "100"), ("offset", "0")])
-        .header("Authorization", format!("Bearer {}", token))
-        .send()
+    let mut messages = crate::services::api_client()
+        .messages(conversation_id, Page::new(100, 0))
         .await?;
+    decrypt_history(e2ee, conversation_id, &mut messages);
 
-    if !response.status().is_success() {
-        return Err(format!("Failed to load messages: {}", response.status()).into());
-    }
-
-    let api_messages: Vec<ApiMessage> = response.json().await?;
-
-    Ok(api_messages
+    Ok(messages
         .into_iter()
-        .map(|m| MessageData {
-            message_id: m.id.clone(),
-            conversation_id: conversation_id.to_string(),
-            sender_username: m.sender_username.unwrap_or_else(|| "Unknown".to_string()),
-            content: m.content,
-            timestamp: format_timestamp(Some(m.created_at)),
-            is_own_message: m.sender_id == session.user_id,
-            status: m.status,
-        })
+        .map(|m| to_message_data(m, conversation_id, &session.user_id))
         .collect())
 }
 
@@ -1039,52 +1429,110 @@ async fn search_messages(
     conversation_id: &str,
     query: &str,
 ) -> Result<Vec<MessageData>, Box<dyn std::error::Error>> {
-    let base_url =
-        std::env::var("SERVER_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
-
-    let token = crate::services::session::get_token().ok_or("No authentication token found")?;
-
     let session = crate::services::session::get_session_manager()
         .get_current_session()
         .ok_or("No session found")?;
 
-    #[derive(serde::Deserialize)]
-    struct ApiMessage {
-        id: String,
-        sender_id: String,
-        sender_username: Option<String>,
-        content: String,
-        created_at: i64,
-        status: String,
-    }
+    let messages = crate::services::api_client()
+        .search_messages(conversation_id, query, Some(50))
+        .await?;
+
+    Ok(messages
+        .into_iter()
+        .map(|m| to_message_data(m, conversation_id, &session.user_id))
+        .collect())
+}
 
-    let client = reqwest::Client::new();
-    let response = client
-        .get(format!(
-            "{}/conversations/{}/search",
-            base_url, conversation_id
-        ))
-        .query(&[("q", query), ("limit", "50")])
-        .header("Authorization", format!("Bearer {}", token))
-        .send()
+// API call to export a conversation, saved under `export_dir()`
+async fn save_export(
+    conversation_id: &str,
+    participant_username: &str,
+    format: &str,
+) -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
+    let bytes = crate::services::api_client()
+        .export_conversation(conversation_id, format)
         .await?;
 
-    if !response.status().is_success() {
-        return Err(format!("Failed to search messages: {}", response.status()).into());
+    let extension = match format {
+        "markdown" => "md",
+        other => other,
+    };
+    let dir = export_dir();
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!(
+        "conversation-{}-{}.{}",
+        participant_username,
+        chrono::Local::now().format("%Y-%m-%d-%H%M%S"),
+        extension
+    ));
+    std::fs::write(&path, bytes)?;
+    Ok(path)
+}
+
+/// `CHAT_EXPORT_DIR`, else `~/Downloads` if it exists, else the working directory
+fn export_dir() -> std::path::PathBuf {
+    if let Ok(dir) = std::env::var("CHAT_EXPORT_DIR") {
+        return dir.into();
     }
+    std::env::var("HOME")
+        .or_else(|_| std::env::var("USERPROFILE"))
+        .map(|home| std::path::Path::new(&home).join("Downloads"))
+        .ok()
+        .filter(|dir| dir.is_dir())
+        .unwrap_or_else(|| ".".into())
+}
 
-    let api_messages: Vec<ApiMessage> = response.json().await?;
+fn to_message_data(message: MessageDto, conversation_id: &str, current_user_id: &str) -> MessageData {
+    MessageData {
+        message_id: message.id,
+        conversation_id: conversation_id.to_string(),
+        sender_username: message.sender_username,
+        content: message.content,
+        timestamp: format_timestamp(Some(message.created_at as i64)),
+        is_own_message: message.sender_id == current_user_id,
+        status: message.status,
+    }
+}
 
-    Ok(api_messages
-        .into_iter()
-        .map(|m| MessageData {
-            message_id: m.id.clone(),
-            conversation_id: conversation_id.to_string(),
-            sender_username: m.sender_username.unwrap_or_else(|| "Unknown".to_string()),
-            content: m.content,
-            timestamp: format_timestamp(Some(m.created_at)),
-            is_own_message: m.sender_id == session.user_id,
-            status: m.status,
-        })
-        .collect())
+/// Shown in place of an encrypted message this device cannot read
+const UNREADABLE_MESSAGE: &str = "🔒 Encrypted message";
+
+/// Plaintext of a message, decrypting it if it is end-to-end encrypted
+fn message_text(
+    e2ee: Option<&E2ee>,
+    conversation_id: &str,
+    sender_id: &str,
+    message_id: &str,
+    content: String,
+    encrypted: bool,
+) -> String {
+    if !encrypted {
+        return content;
+    }
+    match e2ee.map(|e2ee| e2ee.decrypt(conversation_id, sender_id, message_id, &content)) {
+        Some(Ok(plaintext)) => plaintext,
+        Some(Err(e)) => {
+            tracing::warn!("Cannot decrypt message {}: {}", message_id, e);
+            UNREADABLE_MESSAGE.to_string()
+        }
+        None => UNREADABLE_MESSAGE.to_string(),
+    }
+}
+
+/// Decrypt a page of history oldest first, since each message moves the
+/// ratchet forward
+fn decrypt_history(e2ee: Option<&E2ee>, conversation_id: &str, messages: &mut [MessageDto]) {
+    let mut encrypted: Vec<&mut MessageDto> = messages.iter_mut().filter(|m| m.encrypted).collect();
+    encrypted.sort_by_key(|m| m.created_at);
+    for message in encrypted {
+        let content = std::mem::take(&mut message.content);
+        message.content = message_text(
+            e2ee,
+            conversation_id,
+            &message.sender_id,
+            &message.id,
+            content,
+            true,
+        );
+    }
 }
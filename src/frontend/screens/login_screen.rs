@@ -1,6 +1,7 @@
 //! Login screen UI and logic
 
-use crate::services::{HttpClient, SessionManager};
+use chat_client::Credentials;
+use crate::services::{ChatClient, SessionManager};
 use crate::ui::LoginScreenComponent;
 use slint::ComponentHandle;
 use std::sync::Arc;
@@ -9,7 +10,7 @@ use std::sync::Arc;
 #[allow(dead_code)]
 pub struct LoginScreen {
     ui: LoginScreenComponent,
-    http_client: Arc<HttpClient>,
+    http_client: Arc<ChatClient>,
     session_manager: Arc<SessionManager>,
 }
 
@@ -20,7 +21,7 @@ impl LoginScreen {
         on_navigate_to_signup: Box<dyn Fn() + Send + Sync>,
     ) -> Self {
         let ui = LoginScreenComponent::new().unwrap();
-        let http_client = Arc::new(HttpClient::new(base_url));
+        let http_client = Arc::new(ChatClient::new(base_url));
         let session_manager = Arc::new(SessionManager::new());
 
         let ui_weak = ui.as_weak();
@@ -60,7 +61,10 @@ impl LoginScreen {
 
             std::thread::spawn(move || {
                 let runtime = tokio::runtime::Runtime::new().unwrap();
-                match runtime.block_on(http_client.login(username.clone(), password.clone())) {
+                match runtime.block_on(http_client.login(&Credentials {
+                    username: username.clone(),
+                    password: password.clone(),
+                })) {
                     Ok(response) => {
                         // Save session to disk
                         if let Err(e) = session_manager.save_session_sync(
@@ -85,7 +89,7 @@ impl LoginScreen {
                         slint::invoke_from_event_loop(move || {
                             if let Some(ui) = ui_weak_inner.upgrade() {
                                 ui.set_is_loading(false);
-                                ui.set_error_message(e.into());
+                                ui.set_error_message(e.to_string().into());
                             }
                         })
                         .ok();
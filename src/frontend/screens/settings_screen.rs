@@ -1,7 +1,8 @@
 //! Settings screen logic
 
-use crate::ui::SettingsScreenComponent;
-use slint::ComponentHandle;
+use crate::ui::{BlockedUserItem, SettingsScreenComponent};
+use slint::{ComponentHandle, ModelRc, VecModel};
+use std::rc::Rc;
 use std::sync::Arc;
 use tokio::runtime::Runtime;
 
@@ -105,6 +106,39 @@ impl SettingsScreen {
             });
         });
 
+        let ui_weak = ui.as_weak();
+        let runtime_clone = runtime.clone();
+        ui.on_unblock_user(move |user_id| {
+            let ui = ui_weak.unwrap();
+            let user_id = user_id.to_string();
+
+            ui.set_is_loading(true);
+            ui.set_error_message("".into());
+            ui.set_success_message("".into());
+
+            let ui_weak_inner = ui_weak.clone();
+            runtime_clone.spawn(async move {
+                let result = crate::services::api_client()
+                    .unblock_user(&user_id)
+                    .await
+                    .map_err(|e| e.to_string());
+                let ui_weak_reload = ui_weak_inner.clone();
+                slint::invoke_from_event_loop(move || {
+                    if let Some(ui) = ui_weak_inner.upgrade() {
+                        ui.set_is_loading(false);
+                        match result {
+                            Ok(()) => ui.set_success_message("User unblocked".into()),
+                            Err(e) => ui.set_error_message(format!("Failed: {}", e).into()),
+                        }
+                    }
+                })
+                .ok();
+                load_blocked_users(ui_weak_reload).await;
+            });
+        });
+
+        runtime.spawn(load_blocked_users(ui.as_weak()));
+
         Self { ui, runtime }
     }
 
@@ -113,46 +147,39 @@ impl SettingsScreen {
     }
 }
 
+/// Fetch the blocked users list into the screen
+async fn load_blocked_users(ui_weak: slint::Weak<SettingsScreenComponent>) {
+    let blocked = match crate::services::api_client().blocked_users().await {
+        Ok(blocked) => blocked,
+        Err(e) => {
+            tracing::warn!("Failed to load blocked users: {}", e);
+            return;
+        }
+    };
+    slint::invoke_from_event_loop(move || {
+        if let Some(ui) = ui_weak.upgrade() {
+            let items: Vec<BlockedUserItem> = blocked
+                .into_iter()
+                .map(|b| BlockedUserItem {
+                    user_id: b.user_id.into(),
+                    username: b.username.into(),
+                })
+                .collect();
+            ui.set_blocked_users(ModelRc::from(Rc::new(VecModel::from(items))));
+        }
+    })
+    .ok();
+}
+
 async fn api_change_password(current: &str, new: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let base_url =
-        std::env::var("SERVER_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
-    let token = crate::services::session::get_token().ok_or("No token")?;
-
-    let client = reqwest::Client::new();
-    let res = client
-        .post(format!("{}/user/change-password", base_url))
-        .header("Authorization", format!("Bearer {}", token))
-        .json(&serde_json::json!({
-            "current_password": current,
-            "new_password": new
-        }))
-        .send()
+    crate::services::api_client()
+        .change_password(current, new)
         .await?;
-
-    if !res.status().is_success() {
-        return Err(format!("Error: {}", res.status()).into());
-    }
     Ok(())
 }
 
 async fn api_delete_account(password: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let base_url =
-        std::env::var("SERVER_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
-    let token = crate::services::session::get_token().ok_or("No token")?;
-
-    let client = reqwest::Client::new();
-    let res = client
-        .delete(format!("{}/user/me", base_url))
-        .header("Authorization", format!("Bearer {}", token))
-        .json(&serde_json::json!({
-            "password": password
-        }))
-        .send()
-        .await?;
-
-    if !res.status().is_success() {
-        return Err(format!("Error: {}", res.status()).into());
-    }
+    crate::services::api_client().delete_account(password).await?;
 
     // Also clear session locally
     let _ = crate::services::session::get_session_manager()
@@ -1,6 +1,7 @@
 //! Signup screen UI and logic
 
-use crate::services::HttpClient;
+use chat_client::Credentials;
+use crate::services::ChatClient;
 use crate::services::SessionManager;
 use crate::ui::SignupScreenComponent;
 use slint::ComponentHandle;
@@ -10,7 +11,7 @@ use std::sync::Arc;
 #[allow(dead_code)]
 pub struct SignupScreen {
     ui: SignupScreenComponent,
-    http_client: Arc<HttpClient>,
+    http_client: Arc<ChatClient>,
     session_manager: Arc<SessionManager>,
 }
 
@@ -22,7 +23,7 @@ impl SignupScreen {
         on_navigate_to_login: Box<dyn Fn() + Send + Sync>,
     ) -> Self {
         let ui = SignupScreenComponent::new().unwrap();
-        let http_client = Arc::new(HttpClient::new(base_url));
+        let http_client = Arc::new(ChatClient::new(base_url));
         let session_manager = Arc::new(SessionManager::new());
 
         let ui_weak = ui.as_weak();
@@ -75,7 +76,10 @@ impl SignupScreen {
                 eprintln!("DEBUG: Signup thread started");
                 let runtime = tokio::runtime::Runtime::new().unwrap();
                 eprintln!("DEBUG: Calling signup API for user: {}", username);
-                match runtime.block_on(http_client.signup(username.clone(), password.clone())) {
+                match runtime.block_on(http_client.signup(&Credentials {
+                    username: username.clone(),
+                    password: password.clone(),
+                })) {
                     Ok(response) => {
                         tracing::info!("Signup successful for user: {}", response.username);
                         
@@ -103,7 +107,7 @@ impl SignupScreen {
                         tracing::error!("Signup failed: {}", e);
                         slint::invoke_from_event_loop(move || {
                             if let Some(ui) = ui_weak_inner.upgrade() {
-                                ui.set_error_message(e.into());
+                                ui.set_error_message(e.to_string().into());
                             }
                         })
                         .ok();
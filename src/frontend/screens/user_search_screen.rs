@@ -127,30 +127,9 @@ impl UserSearchScreen {
     }
 }
 
-async fn search_users_api(query: &str) -> Result<Vec<ApiUserResult>, Box<dyn std::error::Error>> {
-    let base_url =
-        std::env::var("SERVER_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
-    let token = crate::services::session::get_token().ok_or("No token")?;
-
-    let client = reqwest::Client::new();
-    let res = client
-        .get(format!("{}/users/search", base_url))
-        .query(&[("q", query), ("limit", "20")])
-        .header("Authorization", format!("Bearer {}", token))
-        .send()
+async fn search_users_api(query: &str) -> Result<Vec<chat_client::UserSearchResult>, Box<dyn std::error::Error>> {
+    let results = crate::services::api_client()
+        .search_users(query, Some(20))
         .await?;
-
-    if !res.status().is_success() {
-        return Err(format!("Error: {}", res.status()).into());
-    }
-
-    let results: Vec<ApiUserResult> = res.json().await?;
     Ok(results)
 }
-
-#[derive(serde::Deserialize)]
-struct ApiUserResult {
-    user_id: String,
-    username: String,
-    is_online: bool,
-}
@@ -1,9 +1,18 @@
 //! Frontend services
+//!
+//! REST, realtime and session storage come from the `chat-client` SDK.
 
-pub mod http_client;
-pub mod session;
-pub mod websocket_client;
+pub use chat_client::session;
+pub use chat_client::{
+    ChatClient, ConnectionStatus, SessionManager, TokenProvider, WebSocketClient, WebSocketEvent,
+    WireOptions,
+};
 
-pub use http_client::HttpClient;
-pub use session::SessionManager;
-pub use websocket_client::{ConnectionStatus, WebSocketClient, WebSocketEvent};
+/// REST client for `SERVER_URL`, authenticated with the saved session's token
+pub fn api_client() -> ChatClient {
+    let base_url =
+        std::env::var("SERVER_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
+    let client = ChatClient::new(base_url);
+    client.set_token(session::get_token());
+    client
+}
@@ -0,0 +1,152 @@
+//! Application-level frame compression (`frame-deflate-v1`)
+//!
+//! This is a custom scheme of this protocol, not the RFC 7692
+//! `permessage-deflate` extension, which warp's WebSocket stack does not
+//! implement. A client offers the schemes it speaks in the upgrade URL, e.g.
+//! `compression=frame-deflate-v1`, and the server confirms the one it picked in
+//! the `welcome` frame. Unknown schemes are ignored, so a client can offer a
+//! future version alongside this one. Once confirmed, either side may send any
+//! frame as a compressed binary frame:
+//!
+//! ```text
+//! 0xC1 | kind (0 = text, 1 = binary) | raw DEFLATE stream of the original payload
+//! ```
+//!
+//! `0xC1` is never used in MessagePack and never starts a text frame, so
+//! compressed frames cannot be mistaken for uncompressed ones. Any change to
+//! this layout gets a new scheme name.
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use std::io::{Read, Write};
+
+/// Upgrade query parameter listing the compression schemes a client offers,
+/// e.g. `compression=frame-deflate-v1`
+pub const COMPRESSION_QUERY_PARAM: &str = "compression";
+
+/// The frame layout described above; also advertised in `welcome` capabilities
+pub const FRAME_DEFLATE_V1: &str = "frame-deflate-v1";
+
+/// First byte of every compressed frame
+pub const COMPRESSED_FRAME_TAG: u8 = 0xC1;
+
+/// Payloads smaller than this are sent uncompressed by default
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 512;
+
+/// Frame type of the original, uncompressed payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadKind {
+    Text,
+    Binary,
+}
+
+/// Why a compressed frame could not be expanded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InflateError {
+    /// The decompressed payload would exceed the limit
+    TooLarge {
+        limit: usize,
+    },
+    Invalid(String),
+}
+
+impl std::fmt::Display for InflateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooLarge { limit } => {
+                write!(f, "Decompressed frame exceeds {} bytes", limit)
+            }
+            Self::Invalid(reason) => write!(f, "Invalid compressed frame: {}", reason),
+        }
+    }
+}
+
+/// Whether a binary frame payload is a compressed frame
+pub fn is_compressed(frame: &[u8]) -> bool {
+    frame.first() == Some(&COMPRESSED_FRAME_TAG)
+}
+
+/// Compress a frame payload into a tagged compressed frame
+pub fn deflate(payload: &[u8], kind: PayloadKind) -> Vec<u8> {
+    let header = [
+        COMPRESSED_FRAME_TAG,
+        match kind {
+            PayloadKind::Text => 0,
+            PayloadKind::Binary => 1,
+        },
+    ];
+    let mut encoder = DeflateEncoder::new(header.to_vec(), flate2::Compression::default());
+    // Writing into a Vec cannot fail
+    let _ = encoder.write_all(payload);
+    encoder.finish().unwrap_or_default()
+}
+
+/// Expand a compressed frame, refusing to produce more than `limit` bytes
+pub fn inflate(frame: &[u8], limit: usize) -> Result<(Vec<u8>, PayloadKind), InflateError> {
+    let (kind, body) = match frame {
+        [COMPRESSED_FRAME_TAG, 0, body @ ..] => (PayloadKind::Text, body),
+        [COMPRESSED_FRAME_TAG, 1, body @ ..] => (PayloadKind::Binary, body),
+        _ => return Err(InflateError::Invalid("missing header".to_string())),
+    };
+
+    // Read one byte past the limit so oversized payloads are detected without
+    // ever holding more than that in memory
+    let mut payload = Vec::new();
+    DeflateDecoder::new(body)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut payload)
+        .map_err(|e| InflateError::Invalid(e.to_string()))?;
+    if payload.len() > limit {
+        return Err(InflateError::TooLarge { limit });
+    }
+    if kind == PayloadKind::Text && std::str::from_utf8(&payload).is_err() {
+        return Err(InflateError::Invalid(
+            "text payload is not UTF-8".to_string(),
+        ));
+    }
+
+    Ok((payload, kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_preserves_kind() {
+        let text = br#"{"type":"presence","data":{"userId":"u1"}}"#.repeat(20);
+        let frame = deflate(&text, PayloadKind::Text);
+        assert!(is_compressed(&frame));
+        assert!(frame.len() < text.len());
+        assert_eq!(
+            inflate(&frame, 10_000).unwrap(),
+            (text.clone(), PayloadKind::Text)
+        );
+
+        let frame = deflate(&[0x81, 0xa1, b'a', 0x01], PayloadKind::Binary);
+        assert_eq!(inflate(&frame, 100).unwrap().1, PayloadKind::Binary);
+    }
+
+    #[test]
+    fn test_inflate_stops_at_limit() {
+        // 1 MiB of zeros compresses to about a kilobyte
+        let bomb = deflate(&vec![b'0'; 1024 * 1024], PayloadKind::Text);
+        assert!(bomb.len() < 4096);
+        assert_eq!(
+            inflate(&bomb, 10 * 1024),
+            Err(InflateError::TooLarge { limit: 10 * 1024 })
+        );
+    }
+
+    #[test]
+    fn test_inflate_rejects_garbage() {
+        assert!(matches!(
+            inflate(&[0x81, 0xa1], 100),
+            Err(InflateError::Invalid(_))
+        ));
+        assert!(matches!(
+            inflate(&[COMPRESSED_FRAME_TAG, 0, 0xff, 0xff, 0xff], 100),
+            Err(InflateError::Invalid(_))
+        ));
+    }
+}
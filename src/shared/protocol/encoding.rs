@@ -0,0 +1,139 @@
+//! Wire encodings for `MessageEnvelope`
+//!
+//! JSON text frames are the default. A client may opt in to MessagePack by
+//! adding `encoding=msgpack` to the upgrade URL; envelopes then travel as
+//! binary frames carrying the same field names, so both encodings decode to
+//! identical envelopes.
+
+use super::MessageEnvelope;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Upgrade query parameter selecting the wire encoding, e.g. `encoding=msgpack`
+pub const ENCODING_QUERY_PARAM: &str = "encoding";
+
+/// Encoding of envelopes on a WebSocket connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum WireEncoding {
+    /// UTF-8 JSON in text frames
+    #[default]
+    #[serde(rename = "json")]
+    Json,
+    /// MessagePack maps in binary frames
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
+
+impl WireEncoding {
+    /// Parse the value of the `encoding` query parameter
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            "msgpack" => Some(Self::MessagePack),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::MessagePack => "msgpack",
+        }
+    }
+
+    /// Whether envelopes travel in binary frames
+    pub fn is_binary(&self) -> bool {
+        matches!(self, Self::MessagePack)
+    }
+
+    /// Serialize an envelope into a frame payload
+    pub fn encode(&self, envelope: &MessageEnvelope) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Json => serde_json::to_vec(envelope).map_err(|e| e.to_string()),
+            // Named fields keep the map keys identical to the JSON encoding
+            Self::MessagePack => rmp_serde::to_vec_named(envelope).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Deserialize a frame payload into an envelope
+    pub fn decode(&self, payload: &[u8]) -> Result<MessageEnvelope, String> {
+        self.decode_as(payload)
+    }
+
+    /// Deserialize a frame payload into any envelope-shaped type
+    pub fn decode_as<T: serde::de::DeserializeOwned>(&self, payload: &[u8]) -> Result<T, String> {
+        match self {
+            Self::Json => serde_json::from_slice(payload).map_err(|e| e.to_string()),
+            Self::MessagePack => rmp_serde::from_slice(payload).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for WireEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sample() -> MessageEnvelope {
+        let mut envelope = MessageEnvelope::new(
+            "message",
+            json!({
+                "recipientId": "bob",
+                "content": "héllo 👋",
+                "nested": { "n": 1, "f": 1.5, "flag": true, "none": null, "list": [1, "two"] }
+            }),
+        )
+        .with_traceparent(Some(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string(),
+        ));
+        envelope.seq = Some(u64::MAX - 1);
+        envelope
+    }
+
+    #[test]
+    fn test_encodings_decode_to_identical_envelopes() {
+        let envelope = sample();
+        let from_json = WireEncoding::Json
+            .decode(&WireEncoding::Json.encode(&envelope).unwrap())
+            .unwrap();
+        let from_msgpack = WireEncoding::MessagePack
+            .decode(&WireEncoding::MessagePack.encode(&envelope).unwrap())
+            .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&from_json).unwrap(),
+            serde_json::to_value(&from_msgpack).unwrap()
+        );
+        assert_eq!(from_msgpack.seq, envelope.seq);
+        assert_eq!(from_msgpack.traceparent, envelope.traceparent);
+    }
+
+    #[test]
+    fn test_msgpack_is_smaller_than_json() {
+        let envelope = sample();
+        let json = WireEncoding::Json.encode(&envelope).unwrap();
+        let msgpack = WireEncoding::MessagePack.encode(&envelope).unwrap();
+        assert!(msgpack.len() < json.len());
+    }
+
+    #[test]
+    fn test_msgpack_rejects_garbage() {
+        assert!(WireEncoding::MessagePack.decode(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(
+            WireEncoding::from_name("msgpack"),
+            Some(WireEncoding::MessagePack)
+        );
+        assert_eq!(WireEncoding::from_name(" JSON "), Some(WireEncoding::Json));
+        assert_eq!(WireEncoding::from_name("cbor"), None);
+    }
+}
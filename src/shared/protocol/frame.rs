@@ -0,0 +1,215 @@
+//! Typed protocol frames
+//!
+//! `ClientFrame` and `ServerFrame` pair each frame `type` with its payload, so
+//! both sides build and read frames through the same definitions instead of
+//! `json!` literals and string matches. They serialize as `{"type", "data"}`,
+//! which is exactly the type/payload half of a `MessageEnvelope`; the envelope
+//! keeps carrying `id`, `timestamp`, `traceparent` and `seq` unchanged.
+
+use super::{
+    AckData, DeliveryStatusUpdatedEvent, ErrorData, MessageEnvelope, MessageExpiredData,
+    PresenceData, ResumeData, ResumedData, ResyncRequiredData, ShutdownData,
+    SyncDeliveryStatusCompletedEvent, TextMessageData, TypingData, WelcomeData,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+/// Frames a client sends to the server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum ClientFrame {
+    #[serde(rename = "message")]
+    Message(TextMessageData),
+    #[serde(rename = "typing")]
+    Typing(TypingData),
+    #[serde(rename = "resume")]
+    Resume(ResumeData),
+    #[serde(rename = "heartbeat")]
+    Heartbeat(Map<String, Value>),
+    #[serde(rename = "ack")]
+    Ack(AckData),
+    #[serde(rename = "presence")]
+    Presence(PresenceData),
+    #[serde(rename = "error")]
+    Error(ErrorData),
+}
+
+impl ClientFrame {
+    /// Every client frame `type`
+    pub const TYPES: &'static [&'static str] = &[
+        "message",
+        "typing",
+        "resume",
+        "heartbeat",
+        "ack",
+        "presence",
+        "error",
+    ];
+
+    /// Read the typed frame out of an envelope
+    pub fn from_envelope(envelope: &MessageEnvelope) -> Result<Self, String> {
+        from_envelope(envelope)
+    }
+
+    /// Wrap the frame in an envelope with a fresh ID
+    pub fn into_envelope(self) -> MessageEnvelope {
+        let (msg_type, data) = split(&self);
+        MessageEnvelope::new(msg_type, data)
+    }
+
+    /// Wrap the frame in an envelope with a caller-supplied ID
+    pub fn into_envelope_with_id(self, id: impl Into<String>) -> MessageEnvelope {
+        let (msg_type, data) = split(&self);
+        MessageEnvelope::with_id(id, msg_type, data)
+    }
+}
+
+/// Frames the server sends to clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum ServerFrame {
+    #[serde(rename = "welcome")]
+    Welcome(WelcomeData),
+    #[serde(rename = "message")]
+    Message(TextMessageData),
+    #[serde(rename = "ack")]
+    Ack(AckData),
+    #[serde(rename = "typing")]
+    Typing(TypingData),
+    #[serde(rename = "presence")]
+    Presence(PresenceData),
+    #[serde(rename = "error")]
+    Error(ErrorData),
+    #[serde(rename = "shutdown")]
+    Shutdown(ShutdownData),
+    #[serde(rename = "resumed")]
+    Resumed(ResumedData),
+    #[serde(rename = "resync_required")]
+    ResyncRequired(ResyncRequiredData),
+    #[serde(rename = "deliveryStatusUpdated")]
+    DeliveryStatusUpdated(DeliveryStatusUpdatedEvent),
+    #[serde(rename = "syncDeliveryStatusCompleted")]
+    SyncDeliveryStatusCompleted(SyncDeliveryStatusCompletedEvent),
+    #[serde(rename = "message_expired")]
+    MessageExpired(MessageExpiredData),
+}
+
+impl ServerFrame {
+    /// Every server frame `type`
+    pub const TYPES: &'static [&'static str] = &[
+        "welcome",
+        "message",
+        "ack",
+        "typing",
+        "presence",
+        "error",
+        "shutdown",
+        "resumed",
+        "resync_required",
+        "deliveryStatusUpdated",
+        "syncDeliveryStatusCompleted",
+        "message_expired",
+    ];
+
+    /// Read the typed frame out of an envelope
+    pub fn from_envelope(envelope: &MessageEnvelope) -> Result<Self, String> {
+        from_envelope(envelope)
+    }
+
+    /// Wrap the frame in an envelope with a fresh ID
+    pub fn into_envelope(self) -> MessageEnvelope {
+        let (msg_type, data) = split(&self);
+        MessageEnvelope::new(msg_type, data)
+    }
+
+    /// Wrap the frame in an envelope with a caller-supplied ID
+    pub fn into_envelope_with_id(self, id: impl Into<String>) -> MessageEnvelope {
+        let (msg_type, data) = split(&self);
+        MessageEnvelope::with_id(id, msg_type, data)
+    }
+}
+
+/// Split a serialized frame into its `type` and `data`
+fn split<F: Serialize>(frame: &F) -> (String, Value) {
+    let mut value = serde_json::to_value(frame).unwrap_or_default();
+    let msg_type = value["type"].as_str().unwrap_or_default().to_string();
+    let data = value
+        .get_mut("data")
+        .map(Value::take)
+        .unwrap_or_else(|| json!({}));
+    (msg_type, data)
+}
+
+fn from_envelope<F: DeserializeOwned>(envelope: &MessageEnvelope) -> Result<F, String> {
+    serde_json::from_value(json!({
+        "type": envelope.msg_type,
+        "data": envelope.data,
+    }))
+    .map_err(|e| format!("Invalid {} frame: {}", envelope.msg_type, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_keep_the_envelope_wire_format() {
+        let frame = ClientFrame::Message(TextMessageData {
+            sender_id: None,
+            sender_username: None,
+            recipient_id: "u2".to_string(),
+            content: "Hi".to_string(),
+            conversation_id: None,
+            status: None,
+            encrypted: false,
+            system: false,
+            expires_at: None,
+        });
+        let envelope = frame.into_envelope_with_id("m1");
+        let wire = serde_json::to_value(&envelope).unwrap();
+        assert_eq!(wire["id"], "m1");
+        assert_eq!(wire["type"], "message");
+        assert_eq!(wire["data"], json!({ "recipientId": "u2", "content": "Hi" }));
+
+        match ClientFrame::from_envelope(&envelope).unwrap() {
+            ClientFrame::Message(data) => assert_eq!(data.content, "Hi"),
+            other => panic!("expected a message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unknown_or_malformed_frames_are_rejected() {
+        let unknown = MessageEnvelope::new("history", json!({}));
+        assert!(ServerFrame::from_envelope(&unknown).is_err());
+
+        let malformed = MessageEnvelope::new("resume", json!({ "lastSeq": "latest" }));
+        assert!(ClientFrame::from_envelope(&malformed).is_err());
+
+        let heartbeat = MessageEnvelope::new("heartbeat", json!({}));
+        assert!(matches!(
+            ClientFrame::from_envelope(&heartbeat),
+            Ok(ClientFrame::Heartbeat(_))
+        ));
+    }
+
+    #[test]
+    fn test_type_lists_match_variants() {
+        let shutdown = ServerFrame::Shutdown(ShutdownData {
+            reason: "restart".to_string(),
+            retry_after_ms: 100,
+        })
+        .into_envelope();
+        assert!(ServerFrame::TYPES.contains(&shutdown.msg_type.as_str()));
+        assert_eq!(shutdown.data["retryAfterMs"], 100);
+
+        let expired = ServerFrame::MessageExpired(MessageExpiredData {
+            conversation_id: "c1".to_string(),
+            message_ids: vec!["m1".to_string()],
+        })
+        .into_envelope();
+        assert_eq!(expired.msg_type, "message_expired");
+        assert!(ServerFrame::TYPES.contains(&expired.msg_type.as_str()));
+        assert_eq!(expired.data["messageIds"], json!(["m1"]));
+    }
+}
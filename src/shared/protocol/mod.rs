@@ -1,7 +1,18 @@
 //! WebSocket protocol message types and schemas
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+pub mod compression;
+pub mod encoding;
+pub mod frame;
+pub mod schema;
+pub mod trace;
+
+pub use encoding::{WireEncoding, ENCODING_QUERY_PARAM};
+pub use frame::{ClientFrame, ServerFrame};
+pub use trace::TraceParent;
+
 /// Message status lifecycle
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -46,9 +57,10 @@ impl std::str::FromStr for MessageStatus {
 }
 
 /// WebSocket message envelope
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MessageEnvelope {
     /// Unique message ID (UUID v4)
+    #[schemars(length(min = 1))]
     pub id: String,
     /// Message type
     #[serde(rename = "type")]
@@ -57,48 +69,109 @@ pub struct MessageEnvelope {
     pub timestamp: u64,
     /// Type-specific payload
     pub data: serde_json::Value,
+    /// W3C trace context (`traceparent`) linking client-side and server-side spans
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub traceparent: Option<String>,
+    /// Per-user sequence number on server-sent events, used to resume after reconnect
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+
+impl MessageEnvelope {
+    /// Build an envelope with a fresh UUID and the current timestamp
+    pub fn new(msg_type: impl Into<String>, data: serde_json::Value) -> Self {
+        Self::with_id(uuid::Uuid::new_v4().to_string(), msg_type, data)
+    }
+
+    /// Build an envelope with a caller-supplied ID and the current timestamp
+    pub fn with_id(id: impl Into<String>, msg_type: impl Into<String>, data: serde_json::Value) -> Self {
+        Self {
+            id: id.into(),
+            msg_type: msg_type.into(),
+            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+            data,
+            traceparent: None,
+            seq: None,
+        }
+    }
+
+    /// Attach a W3C `traceparent` header value
+    pub fn with_traceparent(mut self, traceparent: Option<String>) -> Self {
+        self.traceparent = traceparent;
+        self
+    }
 }
 
 /// Text message data
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct TextMessageData {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sender_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sender_username: Option<String>,
+    #[schemars(length(min = 1))]
     pub recipient_id: String,
+    /// Plaintext is limited to `MAX_CONTENT_LENGTH`; the larger bound admits ciphertext
+    #[schemars(length(min = 1, max = 16384))]
     pub content: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub conversation_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub status: Option<String>,
+    /// `content` is an end-to-end encrypted envelope only the participants can read
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypted: bool,
+    /// Written by the server to announce a conversation change, e.g. its
+    /// disappearing-message timer
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub system: bool,
+    /// When the message disappears (milliseconds), for disappearing messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
 }
 
+/// Longest plaintext message content, in bytes
+pub const MAX_CONTENT_LENGTH: usize = 5000;
+
+/// Longest end-to-end encrypted message content, in bytes
+///
+/// Leaves room for the base64 ciphertext and ratchet header of a
+/// `MAX_CONTENT_LENGTH` plaintext.
+pub const MAX_ENCRYPTED_CONTENT_LENGTH: usize = 16384;
+
 /// Message acknowledgement data
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct AckData {
     pub status: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub conversation_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub message_id: Option<String>,
+    /// Envelope ID of the frame being acknowledged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_message_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub server_timestamp: Option<u64>,
 }
 
 /// Typing indicator data
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct TypingData {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sender_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sender_username: Option<String>,
+    #[schemars(length(min = 1))]
     pub recipient_id: String,
     pub is_typing: bool,
 }
 
 /// Presence status data
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct PresenceData {
     pub user_id: String,
     pub username: String,
@@ -107,7 +180,7 @@ pub struct PresenceData {
 }
 
 /// Error message data
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ErrorData {
     pub code: String,
     pub message: String,
@@ -172,6 +245,12 @@ pub struct MessageDto {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub delivered_at: Option<u64>,
     pub status: String,
+    #[serde(default)]
+    pub encrypted: bool,
+    #[serde(default)]
+    pub system: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
 }
 
 /// Delivery status update from client
@@ -190,7 +269,7 @@ pub struct SyncDeliveryStatusCommand {
 }
 
 /// Delivery status updated event from backend
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct DeliveryStatusUpdatedEvent {
     pub message_id: String,
@@ -208,13 +287,21 @@ pub struct DeliveryStatusBatchUpdatedEvent {
 }
 
 /// Sync delivery status completed event
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SyncDeliveryStatusCompletedEvent {
     pub synced_count: u32,
     pub timestamp: i64,
 }
 
+/// Disappearing messages that were deleted; clients drop them too
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageExpiredData {
+    pub conversation_id: String,
+    pub message_ids: Vec<String>,
+}
+
 /// Sync delivery status failed event
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -223,3 +310,80 @@ pub struct DeliveryStatusSyncFailedEvent {
     pub retriable: bool,
 }
 
+
+/// WebSocket close code sent when the server is restarting (RFC 6455 "Service Restart")
+pub const CLOSE_SERVICE_RESTART: u16 = 1012;
+
+/// Shutdown notice sent just before the server closes a connection for a restart
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ShutdownData {
+    pub reason: String,
+    /// Suggested delay before the client reconnects
+    pub retry_after_ms: u64,
+}
+
+/// WebSocket close code sent when a connection stops answering heartbeats
+pub const CLOSE_HEARTBEAT_TIMEOUT: u16 = 4000;
+
+/// WebSocket close code sent when a client reads too slowly and its send queue overflows
+pub const CLOSE_SLOW_CONSUMER: u16 = 4008;
+
+/// Upgrade response header advertising the server's ping interval in seconds
+pub const HEARTBEAT_INTERVAL_HEADER: &str = "x-heartbeat-interval";
+
+/// Resume request sent by the client after reconnecting
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResumeData {
+    /// Highest `seq` the client has processed
+    pub last_seq: u64,
+}
+
+/// Sent after missed events have been replayed
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResumedData {
+    pub replayed: usize,
+    pub last_seq: u64,
+}
+
+/// Sent when missed events are no longer available; the client must refetch its state
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResyncRequiredData {
+    /// Current `seq`; events after it will be delivered live
+    pub last_seq: u64,
+}
+
+/// Current WebSocket protocol version
+///
+/// Version 1 is the original protocol, assumed for clients that do not declare
+/// any versions. Version 2 adds the `welcome` frame and event sequence numbers.
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// Oldest protocol version this build can speak
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Upgrade query parameter listing the versions a client supports, e.g. `protocol=1,2`
+pub const PROTOCOL_QUERY_PARAM: &str = "protocol";
+
+/// WebSocket close code for clients whose protocol version is no longer supported
+pub const CLOSE_UPDATE_REQUIRED: u16 = 4426;
+
+/// First frame on every connection, confirming the negotiated protocol
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WelcomeData {
+    pub protocol_version: u16,
+    pub server_version: String,
+    /// Optional features this server supports, e.g. `presence`, `receipts`
+    pub capabilities: Vec<String>,
+    pub heartbeat_interval_secs: u64,
+    /// Encoding used for every frame after the upgrade
+    #[serde(default)]
+    pub encoding: WireEncoding,
+    /// Whether `frame-deflate-v1` compression was negotiated
+    #[serde(default)]
+    pub compression: bool,
+}
@@ -0,0 +1,127 @@
+//! JSON Schemas for the WebSocket protocol
+//!
+//! Schemas are generated from the protocol types rather than written by hand, so
+//! they cannot drift from what the server and client actually serialize. Each
+//! frame schema is the `MessageEnvelope` schema with `type` pinned to one value
+//! and `data` replaced by that type's payload schema.
+
+use super::{
+    AckData, ClientFrame, DeliveryStatusUpdatedEvent, ErrorData, MessageEnvelope,
+    MessageExpiredData, PresenceData, ResumeData, ResumedData, ResyncRequiredData, ServerFrame, ShutdownData,
+    SyncDeliveryStatusCompletedEvent, TextMessageData, TypingData, WelcomeData, PROTOCOL_VERSION,
+};
+use schemars::gen::SchemaSettings;
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+/// Frame types a client may send
+pub const INBOUND_TYPES: &[&str] = ClientFrame::TYPES;
+
+/// Frame types the server sends
+pub const OUTBOUND_TYPES: &[&str] = ServerFrame::TYPES;
+
+/// Schema for a frame a client sends, or `None` for an unknown type
+pub fn inbound_frame_schema(msg_type: &str) -> Option<Value> {
+    let schema = match msg_type {
+        "message" => frame_schema::<TextMessageData>(msg_type),
+        "typing" => frame_schema::<TypingData>(msg_type),
+        "resume" => frame_schema::<ResumeData>(msg_type),
+        "heartbeat" => frame_schema::<Map<String, Value>>(msg_type),
+        "ack" => frame_schema::<AckData>(msg_type),
+        "presence" => frame_schema::<PresenceData>(msg_type),
+        "error" => frame_schema::<ErrorData>(msg_type),
+        _ => return None,
+    };
+    Some(schema)
+}
+
+/// Schema for a frame the server sends, or `None` for an unknown type
+pub fn outbound_frame_schema(msg_type: &str) -> Option<Value> {
+    let schema = match msg_type {
+        "welcome" => frame_schema::<WelcomeData>(msg_type),
+        "message" => frame_schema::<TextMessageData>(msg_type),
+        "ack" => frame_schema::<AckData>(msg_type),
+        "typing" => frame_schema::<TypingData>(msg_type),
+        "presence" => frame_schema::<PresenceData>(msg_type),
+        "error" => frame_schema::<ErrorData>(msg_type),
+        "shutdown" => frame_schema::<ShutdownData>(msg_type),
+        "resumed" => frame_schema::<ResumedData>(msg_type),
+        "resync_required" => frame_schema::<ResyncRequiredData>(msg_type),
+        "deliveryStatusUpdated" => frame_schema::<DeliveryStatusUpdatedEvent>(msg_type),
+        "syncDeliveryStatusCompleted" => {
+            frame_schema::<SyncDeliveryStatusCompletedEvent>(msg_type)
+        }
+        "message_expired" => frame_schema::<MessageExpiredData>(msg_type),
+        _ => return None,
+    };
+    Some(schema)
+}
+
+/// The full protocol description served at `/protocol/schema`
+pub fn protocol_schema() -> Value {
+    let collect = |types: &[&str], schema: fn(&str) -> Option<Value>| {
+        types
+            .iter()
+            .filter_map(|t| schema(t).map(|s| (t.to_string(), s)))
+            .collect::<Map<_, _>>()
+    };
+
+    json!({
+        "title": "Chat WebSocket Protocol",
+        "protocolVersion": PROTOCOL_VERSION,
+        "inbound": collect(INBOUND_TYPES, inbound_frame_schema),
+        "outbound": collect(OUTBOUND_TYPES, outbound_frame_schema),
+    })
+}
+
+/// Envelope schema with `type` fixed to `msg_type` and `data` described by `T`
+fn frame_schema<T: JsonSchema>(msg_type: &str) -> Value {
+    let mut generator = SchemaSettings::draft07().into_generator();
+    let data = generator.subschema_for::<T>();
+    let root = generator.into_root_schema_for::<MessageEnvelope>();
+
+    let mut schema = serde_json::to_value(root).unwrap_or_default();
+    schema["title"] = json!(format!("{} frame", msg_type));
+    schema["properties"]["type"] = json!({ "const": msg_type });
+    schema["properties"]["data"] = serde_json::to_value(data).unwrap_or_default();
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_schema_pins_type_and_payload() {
+        let schema = inbound_frame_schema("message").unwrap();
+        assert_eq!(schema["$schema"], "http://json-schema.org/draft-07/schema#");
+        assert_eq!(schema["properties"]["type"]["const"], "message");
+
+        let data = &schema["definitions"]["TextMessageData"];
+        let required: Vec<&str> = data["required"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(Value::as_str)
+            .collect();
+        assert_eq!(required, ["content", "recipientId"]);
+        assert_eq!(data["properties"]["content"]["maxLength"], 16384);
+
+        assert!(inbound_frame_schema("welcome").is_none());
+        assert!(outbound_frame_schema("welcome").is_some());
+    }
+
+    #[test]
+    fn test_protocol_schema_lists_every_type() {
+        let schema = protocol_schema();
+        assert_eq!(schema["protocolVersion"], PROTOCOL_VERSION);
+        assert_eq!(
+            schema["inbound"].as_object().unwrap().len(),
+            INBOUND_TYPES.len()
+        );
+        assert_eq!(
+            schema["outbound"].as_object().unwrap().len(),
+            OUTBOUND_TYPES.len()
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! W3C trace context helpers
+//!
+//! Envelopes carry a `traceparent` value (https://www.w3.org/TR/trace-context/) so that a
+//! message's client-side span and the server-side spans for parse, persist, deliver and ack
+//! share one trace ID. Neither side needs an OpenTelemetry SDK to produce or parse it.
+
+use std::fmt;
+
+/// Parsed `traceparent` header value (version 00)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: [u8; 16],
+    pub parent_id: [u8; 8],
+    pub flags: u8,
+}
+
+impl TraceParent {
+    /// Sampled flag as defined by the W3C spec
+    pub const FLAG_SAMPLED: u8 = 0x01;
+
+    /// Start a new sampled trace with random trace and span IDs
+    pub fn generate() -> Self {
+        let trace_id = *uuid::Uuid::new_v4().as_bytes();
+        Self {
+            trace_id,
+            parent_id: random_span_id(),
+            flags: Self::FLAG_SAMPLED,
+        }
+    }
+
+    /// Derive a child context: same trace, new span ID
+    pub fn child(&self) -> Self {
+        Self {
+            trace_id: self.trace_id,
+            parent_id: random_span_id(),
+            flags: self.flags,
+        }
+    }
+
+    /// Parse a `traceparent` header value, rejecting malformed or all-zero IDs
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let parent_id = parts.next()?;
+        let flags = parts.next()?;
+
+        if version != "00" || parts.next().is_some() {
+            return None;
+        }
+
+        let trace_id: [u8; 16] = decode_hex(trace_id)?.try_into().ok()?;
+        let parent_id: [u8; 8] = decode_hex(parent_id)?.try_into().ok()?;
+        let flags: [u8; 1] = decode_hex(flags)?.try_into().ok()?;
+
+        if trace_id.iter().all(|b| *b == 0) || parent_id.iter().all(|b| *b == 0) {
+            return None;
+        }
+
+        Some(Self {
+            trace_id,
+            parent_id,
+            flags: flags[0],
+        })
+    }
+
+    /// Lowercase hex trace ID, as used in log fields and tracing backends
+    pub fn trace_id_hex(&self) -> String {
+        encode_hex(&self.trace_id)
+    }
+
+    /// Whether the sampled flag is set
+    pub fn is_sampled(&self) -> bool {
+        self.flags & Self::FLAG_SAMPLED != 0
+    }
+}
+
+impl fmt::Display for TraceParent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "00-{}-{}-{:02x}",
+            encode_hex(&self.trace_id),
+            encode_hex(&self.parent_id),
+            self.flags
+        )
+    }
+}
+
+fn random_span_id() -> [u8; 8] {
+    let bytes = uuid::Uuid::new_v4().into_bytes();
+    let mut span_id = [0u8; 8];
+    span_id.copy_from_slice(&bytes[..8]);
+    if span_id.iter().all(|b| *b == 0) {
+        span_id[7] = 1;
+    }
+    span_id
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    if value.bytes().any(|b| b.is_ascii_uppercase()) {
+        return None;
+    }
+
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_round_trips_through_parse() {
+        let tp = TraceParent::generate();
+        let parsed = TraceParent::parse(&tp.to_string()).unwrap();
+        assert_eq!(parsed, tp);
+        assert!(parsed.is_sampled());
+    }
+
+    #[test]
+    fn test_child_keeps_trace_id() {
+        let tp = TraceParent::generate();
+        let child = tp.child();
+        assert_eq!(child.trace_id, tp.trace_id);
+        assert_ne!(child.parent_id, tp.parent_id);
+    }
+
+    #[test]
+    fn test_parse_spec_example() {
+        let tp =
+            TraceParent::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(tp.trace_id_hex(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(tp.flags, 1);
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_values() {
+        assert!(TraceParent::parse("").is_none());
+        assert!(
+            TraceParent::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").is_none()
+        );
+        assert!(
+            TraceParent::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none()
+        );
+        assert!(
+            TraceParent::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01").is_none()
+        );
+        assert!(
+            TraceParent::parse("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01").is_none()
+        );
+        assert!(TraceParent::parse("00-4bf92f35-00f067aa0ba902b7-01").is_none());
+    }
+}
@@ -0,0 +1,980 @@
+//! Terminal client state
+//!
+//! `App` holds everything on screen and never does I/O: keys and server
+//! updates go in, [`Command`]s for the runner come out. That keeps the whole
+//! keyboard flow testable without a terminal or a server.
+
+use crate::keys::Key;
+use chat_client::{
+    ConnectionStatus, Conversation, Credentials, MessageDto, MessageRequest, SessionData,
+    UserSearchResult, WebSocketEvent,
+};
+
+/// Work for the runner to carry out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Login(CredentialsInput),
+    Signup(CredentialsInput),
+    LoadConversations,
+    LoadMessages(String),
+    SendMessage {
+        message_id: String,
+        conversation_id: String,
+        recipient_id: String,
+        content: String,
+    },
+    Typing {
+        recipient_id: String,
+        is_typing: bool,
+    },
+    SearchUsers(String),
+    StartConversation(String),
+    LoadRequests,
+    AcceptRequest(String),
+    DeclineRequest(String),
+    Logout,
+    Quit,
+}
+
+/// Username and password typed into the login form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CredentialsInput {
+    pub username: String,
+    pub password: String,
+}
+
+impl From<CredentialsInput> for Credentials {
+    fn from(input: CredentialsInput) -> Self {
+        Credentials {
+            username: input.username,
+            password: input.password,
+        }
+    }
+}
+
+/// Results of commands and realtime events
+#[derive(Debug, Clone)]
+pub enum Update {
+    LoggedIn(SessionData),
+    Conversations(Vec<Conversation>),
+    Messages {
+        conversation_id: String,
+        messages: Vec<MessageDto>,
+    },
+    SearchResults {
+        query: String,
+        results: Vec<UserSearchResult>,
+    },
+    ConversationStarted(Conversation),
+    Requests(Vec<MessageRequest>),
+    RequestAnswered {
+        conversation_id: String,
+        accepted: bool,
+    },
+    Realtime(WebSocketEvent),
+    Failed(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Screen {
+    Login,
+    Chat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginField {
+    Username,
+    Password,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Focus {
+    Conversations,
+    Composer,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoginForm {
+    pub username: String,
+    pub password: String,
+    pub field: LoginField,
+    /// Create an account instead of signing in
+    pub signup: bool,
+}
+
+/// A conversation in the left pane
+#[derive(Debug, Clone)]
+pub struct ConversationItem {
+    pub conversation: Conversation,
+    pub unread: usize,
+    pub typing: bool,
+}
+
+/// A message in the message pane
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatMessage {
+    pub id: String,
+    pub sender_username: String,
+    pub content: String,
+    /// Unix timestamp in milliseconds
+    pub timestamp: u64,
+    pub mine: bool,
+    pub status: String,
+}
+
+/// The Ctrl-F user search overlay
+#[derive(Debug, Clone, Default)]
+pub struct UserSearch {
+    pub query: String,
+    pub results: Vec<UserSearchResult>,
+    pub selected: usize,
+}
+
+/// The Ctrl-R list of message requests from people you have not talked to
+#[derive(Debug, Clone, Default)]
+pub struct RequestList {
+    pub requests: Vec<MessageRequest>,
+    pub selected: usize,
+    /// Until the first load finishes
+    pub loading: bool,
+}
+
+pub struct App {
+    pub screen: Screen,
+    pub login: LoginForm,
+    pub session: Option<SessionData>,
+    pub conversations: Vec<ConversationItem>,
+    pub selected: usize,
+    pub messages: Vec<ChatMessage>,
+    /// Lines scrolled up from the newest message
+    pub scroll: usize,
+    pub composer: String,
+    pub focus: Focus,
+    pub search: Option<UserSearch>,
+    pub requests: Option<RequestList>,
+    pub connection: ConnectionStatus,
+    pub status: String,
+    pub should_quit: bool,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl App {
+    pub fn new() -> Self {
+        Self {
+            screen: Screen::Login,
+            login: LoginForm {
+                username: String::new(),
+                password: String::new(),
+                field: LoginField::Username,
+                signup: false,
+            },
+            session: None,
+            conversations: Vec::new(),
+            selected: 0,
+            messages: Vec::new(),
+            scroll: 0,
+            composer: String::new(),
+            focus: Focus::Conversations,
+            search: None,
+            requests: None,
+            connection: ConnectionStatus::Disconnected {
+                reason: "not connected".to_string(),
+            },
+            status: String::new(),
+            should_quit: false,
+        }
+    }
+
+    pub fn selected_conversation(&self) -> Option<&Conversation> {
+        self.conversations
+            .get(self.selected)
+            .map(|item| &item.conversation)
+    }
+
+    /// Handle one key press
+    pub fn handle_key(&mut self, key: Key) -> Vec<Command> {
+        if matches!(key, Key::Ctrl('c') | Key::Ctrl('q')) {
+            self.should_quit = true;
+            return vec![Command::Quit];
+        }
+
+        match self.screen {
+            Screen::Login => self.handle_login_key(key),
+            Screen::Chat if self.search.is_some() => self.handle_search_key(key),
+            Screen::Chat if self.requests.is_some() => self.handle_requests_key(key),
+            Screen::Chat => self.handle_chat_key(key),
+        }
+    }
+
+    fn handle_login_key(&mut self, key: Key) -> Vec<Command> {
+        let form = &mut self.login;
+        let field = match form.field {
+            LoginField::Username => &mut form.username,
+            LoginField::Password => &mut form.password,
+        };
+
+        match key {
+            Key::Char(c) => field.push(c),
+            Key::Backspace => {
+                field.pop();
+            }
+            Key::Tab | Key::BackTab | Key::Up | Key::Down => {
+                form.field = match form.field {
+                    LoginField::Username => LoginField::Password,
+                    LoginField::Password => LoginField::Username,
+                };
+            }
+            Key::Ctrl('n') => form.signup = !form.signup,
+            Key::Enter if form.field == LoginField::Username => form.field = LoginField::Password,
+            Key::Enter => {
+                if form.username.is_empty() || form.password.is_empty() {
+                    self.status = "Enter a username and password".to_string();
+                    return Vec::new();
+                }
+
+                let input = CredentialsInput {
+                    username: form.username.trim().to_string(),
+                    password: form.password.clone(),
+                };
+                return if form.signup {
+                    self.status = "Creating account...".to_string();
+                    vec![Command::Signup(input)]
+                } else {
+                    self.status = "Signing in...".to_string();
+                    vec![Command::Login(input)]
+                };
+            }
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    fn handle_search_key(&mut self, key: Key) -> Vec<Command> {
+        let Some(search) = self.search.as_mut() else {
+            return Vec::new();
+        };
+
+        match key {
+            Key::Esc => self.search = None,
+            Key::Char(c) => {
+                search.query.push(c);
+                return vec![Command::SearchUsers(search.query.clone())];
+            }
+            Key::Backspace => {
+                search.query.pop();
+                if search.query.is_empty() {
+                    search.results.clear();
+                } else {
+                    return vec![Command::SearchUsers(search.query.clone())];
+                }
+            }
+            Key::Up => search.selected = search.selected.saturating_sub(1),
+            Key::Down if search.selected + 1 < search.results.len() => search.selected += 1,
+            Key::Enter => {
+                if let Some(user) = search.results.get(search.selected) {
+                    let user_id = user.user_id.clone();
+                    self.status = format!("Opening conversation with {}...", user.username);
+                    self.search = None;
+                    return vec![Command::StartConversation(user_id)];
+                }
+            }
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    fn handle_requests_key(&mut self, key: Key) -> Vec<Command> {
+        let Some(list) = self.requests.as_mut() else {
+            return Vec::new();
+        };
+
+        match key {
+            Key::Esc => self.requests = None,
+            Key::Up => list.selected = list.selected.saturating_sub(1),
+            Key::Down if list.selected + 1 < list.requests.len() => list.selected += 1,
+            Key::Enter | Key::Char('d') => {
+                if let Some(request) = list.requests.get(list.selected) {
+                    let conversation_id = request.conversation_id.clone();
+                    return if key == Key::Enter {
+                        self.status = format!("Accepting {}...", request.requester_username);
+                        vec![Command::AcceptRequest(conversation_id)]
+                    } else {
+                        self.status = format!("Declining {}...", request.requester_username);
+                        vec![Command::DeclineRequest(conversation_id)]
+                    };
+                }
+            }
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    fn handle_chat_key(&mut self, key: Key) -> Vec<Command> {
+        match key {
+            Key::Ctrl('f') => {
+                self.search = Some(UserSearch::default());
+                return Vec::new();
+            }
+            Key::Ctrl('r') => {
+                self.requests = Some(RequestList {
+                    loading: true,
+                    ..RequestList::default()
+                });
+                return vec![Command::LoadRequests];
+            }
+            Key::Ctrl('x') => return self.logout(),
+            Key::Tab | Key::BackTab => {
+                self.focus = match self.focus {
+                    Focus::Conversations => Focus::Composer,
+                    Focus::Composer => Focus::Conversations,
+                };
+                return Vec::new();
+            }
+            Key::PageUp => {
+                self.scroll = (self.scroll + 10).min(self.messages.len());
+                return Vec::new();
+            }
+            Key::PageDown => {
+                self.scroll = self.scroll.saturating_sub(10);
+                return Vec::new();
+            }
+            _ => {}
+        }
+
+        match self.focus {
+            Focus::Conversations => match key {
+                Key::Up if self.selected > 0 => self.select(self.selected - 1),
+                Key::Down if self.selected + 1 < self.conversations.len() => {
+                    self.select(self.selected + 1)
+                }
+                Key::Enter | Key::Right if !self.conversations.is_empty() => {
+                    self.focus = Focus::Composer;
+                    Vec::new()
+                }
+                _ => Vec::new(),
+            },
+            Focus::Composer => match key {
+                Key::Esc | Key::Left if self.composer.is_empty() => {
+                    self.focus = Focus::Conversations;
+                    Vec::new()
+                }
+                Key::Char(c) => {
+                    let was_empty = self.composer.is_empty();
+                    self.composer.push(c);
+                    if was_empty {
+                        self.typing(true)
+                    } else {
+                        Vec::new()
+                    }
+                }
+                Key::Backspace => {
+                    self.composer.pop();
+                    if self.composer.is_empty() {
+                        self.typing(false)
+                    } else {
+                        Vec::new()
+                    }
+                }
+                Key::Enter => self.send(),
+                _ => Vec::new(),
+            },
+        }
+    }
+
+    /// Select a conversation and load its history
+    fn select(&mut self, index: usize) -> Vec<Command> {
+        let mut commands = if self.composer.is_empty() {
+            Vec::new()
+        } else {
+            self.typing(false)
+        };
+        self.composer.clear();
+        self.selected = index;
+        self.messages.clear();
+        self.scroll = 0;
+
+        let item = &mut self.conversations[index];
+        item.unread = 0;
+        commands.push(Command::LoadMessages(
+            item.conversation.conversation_id.clone(),
+        ));
+        commands
+    }
+
+    fn typing(&self, is_typing: bool) -> Vec<Command> {
+        self.selected_conversation()
+            .map(|conversation| Command::Typing {
+                recipient_id: conversation.participant_id.clone(),
+                is_typing,
+            })
+            .into_iter()
+            .collect()
+    }
+
+    fn send(&mut self) -> Vec<Command> {
+        let content = self.composer.trim().to_string();
+        let (Some(conversation), Some(session)) = (self.selected_conversation(), &self.session)
+        else {
+            return Vec::new();
+        };
+        if content.is_empty() {
+            return Vec::new();
+        }
+
+        let message_id = uuid::Uuid::new_v4().to_string();
+        let command = Command::SendMessage {
+            message_id: message_id.clone(),
+            conversation_id: conversation.conversation_id.clone(),
+            recipient_id: conversation.participant_id.clone(),
+            content: content.clone(),
+        };
+        self.messages.push(ChatMessage {
+            id: message_id,
+            sender_username: session.username.clone(),
+            content,
+            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+            mine: true,
+            status: "sending".to_string(),
+        });
+        self.composer.clear();
+        self.scroll = 0;
+        self.bump(self.selected);
+        vec![command]
+    }
+
+    fn logout(&mut self) -> Vec<Command> {
+        let username = self
+            .session
+            .take()
+            .map(|session| session.username)
+            .unwrap_or_default();
+        *self = Self::new();
+        self.login.username = username;
+        self.login.field = LoginField::Password;
+        self.status = "Signed out".to_string();
+        vec![Command::Logout]
+    }
+
+    /// Move a conversation to the top of the list, keeping the selection on it
+    /// if it was selected
+    fn bump(&mut self, index: usize) {
+        let item = self.conversations.remove(index);
+        self.conversations.insert(0, item);
+        if self.selected == index {
+            self.selected = 0;
+        } else if self.selected < index {
+            self.selected += 1;
+        }
+    }
+
+    /// Apply the result of a command or a realtime event
+    pub fn apply(&mut self, update: Update) -> Vec<Command> {
+        match update {
+            Update::LoggedIn(session) => {
+                self.status = format!("Signed in as {}", session.username);
+                self.login.password.clear();
+                self.session = Some(session);
+                self.screen = Screen::Chat;
+                vec![Command::LoadConversations]
+            }
+            Update::Conversations(conversations) => self.set_conversations(conversations),
+            Update::Messages {
+                conversation_id,
+                mut messages,
+            } => {
+                let is_selected = self
+                    .selected_conversation()
+                    .is_some_and(|c| c.conversation_id == conversation_id);
+                if is_selected {
+                    let user_id = self.session.as_ref().map(|s| s.user_id.as_str());
+                    // History arrives newest first
+                    messages.sort_by_key(|m| m.created_at);
+                    // Keep our sends the history request started too early to include
+                    let newest = messages.last().map_or(0, |m| m.created_at);
+                    let pending: Vec<ChatMessage> = self
+                        .messages
+                        .drain(..)
+                        .filter(|m| {
+                            m.mine
+                                && (m.status == "sending" || m.timestamp >= newest)
+                                && !messages.iter().any(|d| d.id == m.id)
+                        })
+                        .collect();
+                    self.messages = messages
+                        .into_iter()
+                        .map(|m| ChatMessage {
+                            mine: Some(m.sender_id.as_str()) == user_id,
+                            id: m.id,
+                            sender_username: m.sender_username,
+                            content: readable(m.content, m.encrypted),
+                            timestamp: m.created_at,
+                            status: m.status,
+                        })
+                        .chain(pending)
+                        .collect();
+                }
+                Vec::new()
+            }
+            Update::SearchResults { query, results } => {
+                if let Some(search) = self.search.as_mut().filter(|s| s.query == query) {
+                    search.selected = search.selected.min(results.len().saturating_sub(1));
+                    search.results = results;
+                }
+                Vec::new()
+            }
+            Update::ConversationStarted(conversation) => {
+                let existing = self
+                    .conversations
+                    .iter()
+                    .position(|c| c.conversation.conversation_id == conversation.conversation_id);
+                let index = match existing {
+                    Some(index) => index,
+                    None => {
+                        self.conversations.push(ConversationItem {
+                            conversation,
+                            unread: 0,
+                            typing: false,
+                        });
+                        self.conversations.len() - 1
+                    }
+                };
+                self.bump(index);
+                self.focus = Focus::Composer;
+                self.status.clear();
+                self.select(0)
+            }
+            Update::Requests(requests) => {
+                if let Some(list) = self.requests.as_mut() {
+                    list.selected = list.selected.min(requests.len().saturating_sub(1));
+                    list.requests = requests;
+                    list.loading = false;
+                }
+                Vec::new()
+            }
+            Update::RequestAnswered {
+                conversation_id,
+                accepted,
+            } => {
+                self.status.clear();
+                if let Some(list) = self.requests.as_mut() {
+                    list.requests
+                        .retain(|r| r.conversation_id != conversation_id);
+                    list.selected = list.selected.min(list.requests.len().saturating_sub(1));
+                }
+                if accepted {
+                    self.requests = None;
+                    vec![Command::LoadConversations]
+                } else {
+                    Vec::new()
+                }
+            }
+            Update::Realtime(event) => self.apply_event(event),
+            Update::Failed(error) => {
+                self.status = error;
+                Vec::new()
+            }
+        }
+    }
+
+    fn set_conversations(&mut self, conversations: Vec<Conversation>) -> Vec<Command> {
+        let previous = self
+            .selected_conversation()
+            .map(|c| c.conversation_id.clone());
+        let old = std::mem::take(&mut self.conversations);
+
+        self.conversations = conversations
+            .into_iter()
+            .map(|conversation| {
+                let kept = old
+                    .iter()
+                    .find(|item| item.conversation.conversation_id == conversation.conversation_id);
+                ConversationItem {
+                    unread: kept.map_or(0, |item| item.unread),
+                    typing: kept.is_some_and(|item| item.typing),
+                    conversation,
+                }
+            })
+            .collect();
+
+        match previous.and_then(|id| {
+            self.conversations
+                .iter()
+                .position(|item| item.conversation.conversation_id == id)
+        }) {
+            Some(index) => {
+                self.selected = index;
+                Vec::new()
+            }
+            None if !self.conversations.is_empty() => self.select(0),
+            None => {
+                self.selected = 0;
+                self.messages.clear();
+                Vec::new()
+            }
+        }
+    }
+
+    fn apply_event(&mut self, event: WebSocketEvent) -> Vec<Command> {
+        match event {
+            WebSocketEvent::ConnectionState(status) => self.connection = status,
+            WebSocketEvent::Message {
+                conversation_id,
+                message_id,
+                sender_id,
+                sender_username,
+                content,
+                status,
+                timestamp,
+                encrypted,
+            } => {
+                let Some(index) = self
+                    .conversations
+                    .iter()
+                    .position(|item| item.conversation.conversation_id == conversation_id)
+                else {
+                    // A conversation someone else just started
+                    return vec![Command::LoadConversations];
+                };
+
+                let item = &mut self.conversations[index];
+                item.typing = false;
+                if index == self.selected {
+                    if !self.messages.iter().any(|m| m.id == message_id) {
+                        let user_id = self.session.as_ref().map(|s| s.user_id.as_str());
+                        self.messages.push(ChatMessage {
+                            id: message_id,
+                            sender_username,
+                            content: readable(content, encrypted),
+                            timestamp,
+                            mine: sender_id.is_some() && sender_id.as_deref() == user_id,
+                            status,
+                        });
+                    }
+                } else {
+                    item.unread += 1;
+                }
+                self.bump(index);
+            }
+            WebSocketEvent::Ack {
+                message_id: Some(message_id),
+                status,
+                ..
+            } => {
+                if let Some(message) = self.messages.iter_mut().find(|m| m.id == message_id) {
+                    message.status = status;
+                }
+            }
+            WebSocketEvent::Ack { .. } => {}
+            WebSocketEvent::Typing {
+                sender_id: Some(sender_id),
+                is_typing,
+                ..
+            } => {
+                for item in &mut self.conversations {
+                    if item.conversation.participant_id == sender_id {
+                        item.typing = is_typing;
+                    }
+                }
+            }
+            WebSocketEvent::Typing { .. } => {}
+            WebSocketEvent::Presence {
+                user_id, is_online, ..
+            } => {
+                for item in &mut self.conversations {
+                    if item.conversation.participant_id == user_id {
+                        item.conversation.participant_is_online = is_online;
+                    }
+                }
+                if let Some(search) = self.search.as_mut() {
+                    for user in &mut search.results {
+                        if user.user_id == user_id {
+                            user.is_online = is_online;
+                        }
+                    }
+                }
+            }
+            WebSocketEvent::MessageExpired {
+                conversation_id,
+                message_ids,
+            } => {
+                if self
+                    .selected_conversation()
+                    .is_some_and(|c| c.conversation_id == conversation_id)
+                {
+                    self.messages.retain(|m| !message_ids.contains(&m.id));
+                }
+            }
+            WebSocketEvent::ResyncRequired => {
+                let mut commands = vec![Command::LoadConversations];
+                if let Some(conversation) = self.selected_conversation() {
+                    commands.push(Command::LoadMessages(conversation.conversation_id.clone()));
+                }
+                return commands;
+            }
+            WebSocketEvent::Error(error) => self.status = error,
+        }
+        Vec::new()
+    }
+}
+
+/// End-to-end encrypted messages can only be read in the desktop app, which
+/// holds the keys
+fn readable(content: String, encrypted: bool) -> String {
+    if encrypted {
+        "[encrypted message]".to_string()
+    } else {
+        content
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> SessionData {
+        SessionData {
+            user_id: "u-alice".to_string(),
+            username: "alice".to_string(),
+            token: "t".to_string(),
+            expires_at: i64::MAX,
+        }
+    }
+
+    fn conversation(id: &str, participant: &str) -> Conversation {
+        Conversation {
+            conversation_id: id.to_string(),
+            participant_id: format!("u-{}", participant),
+            participant_username: participant.to_string(),
+            participant_is_online: false,
+            created_at: 0,
+            last_message_at: None,
+            message_count: 0,
+            encrypted: false,
+            ephemeral_ttl: None,
+            ephemeral_start: "sent".to_string(),
+            request_pending: false,
+        }
+    }
+
+    fn type_text(app: &mut App, text: &str) -> Vec<Command> {
+        text.chars()
+            .flat_map(|c| app.handle_key(Key::Char(c)))
+            .collect()
+    }
+
+    fn chat_app() -> App {
+        let mut app = App::new();
+        app.apply(Update::LoggedIn(session()));
+        app.apply(Update::Conversations(vec![
+            conversation("c-bob", "bob"),
+            conversation("c-carol", "carol"),
+        ]));
+        app
+    }
+
+    #[test]
+    fn test_login_form_submits_credentials() {
+        let mut app = App::new();
+        type_text(&mut app, "alice");
+        app.handle_key(Key::Tab);
+        type_text(&mut app, "secret");
+
+        assert_eq!(
+            app.handle_key(Key::Enter),
+            vec![Command::Login(CredentialsInput {
+                username: "alice".to_string(),
+                password: "secret".to_string(),
+            })]
+        );
+
+        app.handle_key(Key::Ctrl('n'));
+        assert!(matches!(
+            app.handle_key(Key::Enter)[..],
+            [Command::Signup(_)]
+        ));
+
+        assert_eq!(
+            app.apply(Update::LoggedIn(session())),
+            vec![Command::LoadConversations]
+        );
+        assert_eq!(app.screen, Screen::Chat);
+        assert!(app.login.password.is_empty());
+    }
+
+    #[test]
+    fn test_navigation_and_sending() {
+        let mut app = chat_app();
+        assert_eq!(app.selected, 0);
+
+        assert_eq!(
+            app.handle_key(Key::Down),
+            vec![Command::LoadMessages("c-carol".to_string())]
+        );
+        app.handle_key(Key::Enter);
+        assert_eq!(app.focus, Focus::Composer);
+
+        assert_eq!(
+            type_text(&mut app, "hi"),
+            vec![Command::Typing {
+                recipient_id: "u-carol".to_string(),
+                is_typing: true,
+            }]
+        );
+        match &app.handle_key(Key::Enter)[..] {
+            [Command::SendMessage {
+                conversation_id,
+                content,
+                ..
+            }] => {
+                assert_eq!(conversation_id, "c-carol");
+                assert_eq!(content, "hi");
+            }
+            other => panic!("expected a send, got {:?}", other),
+        }
+
+        // The conversation moves to the top and stays selected
+        assert_eq!(app.conversations[0].conversation.conversation_id, "c-carol");
+        assert_eq!(app.selected, 0);
+        assert_eq!(app.messages[0].status, "sending");
+        assert!(app.composer.is_empty());
+    }
+
+    #[test]
+    fn test_realtime_events_update_the_list() {
+        let mut app = chat_app();
+        let message = |conversation_id: &str, id: &str| {
+            Update::Realtime(WebSocketEvent::Message {
+                conversation_id: conversation_id.to_string(),
+                message_id: id.to_string(),
+                sender_id: Some("u-carol".to_string()),
+                sender_username: "carol".to_string(),
+                content: "hey".to_string(),
+                status: "delivered".to_string(),
+                timestamp: 1_700_000_000_000,
+                encrypted: false,
+            })
+        };
+
+        app.apply(Update::Realtime(WebSocketEvent::Typing {
+            sender_id: Some("u-carol".to_string()),
+            sender_username: "carol".to_string(),
+            recipient_id: "u-alice".to_string(),
+            is_typing: true,
+        }));
+        assert!(app.conversations[1].typing);
+
+        app.apply(message("c-carol", "m1"));
+        assert_eq!(app.conversations[0].conversation.conversation_id, "c-carol");
+        assert_eq!(app.conversations[0].unread, 1);
+        assert!(!app.conversations[0].typing);
+        // Selection follows bob's conversation
+        assert_eq!(app.selected, 1);
+
+        app.apply(Update::Realtime(WebSocketEvent::Presence {
+            user_id: "u-bob".to_string(),
+            username: "bob".to_string(),
+            is_online: true,
+            last_seen_at: 0,
+        }));
+        assert!(app.conversations[1].conversation.participant_is_online);
+
+        assert_eq!(
+            app.apply(message("c-new", "m2")),
+            vec![Command::LoadConversations]
+        );
+    }
+
+    #[test]
+    fn test_user_search_starts_conversation() {
+        let mut app = chat_app();
+        app.handle_key(Key::Ctrl('f'));
+        assert_eq!(
+            type_text(&mut app, "da"),
+            vec![
+                Command::SearchUsers("d".to_string()),
+                Command::SearchUsers("da".to_string()),
+            ]
+        );
+
+        // Results for an outdated query are ignored
+        let dave = UserSearchResult {
+            user_id: "u-dave".to_string(),
+            username: "dave".to_string(),
+            is_online: true,
+        };
+        app.apply(Update::SearchResults {
+            query: "d".to_string(),
+            results: vec![dave.clone()],
+        });
+        assert!(app.search.as_ref().unwrap().results.is_empty());
+        app.apply(Update::SearchResults {
+            query: "da".to_string(),
+            results: vec![dave],
+        });
+
+        assert_eq!(
+            app.handle_key(Key::Enter),
+            vec![Command::StartConversation("u-dave".to_string())]
+        );
+        assert!(app.search.is_none());
+        assert_eq!(
+            app.apply(Update::ConversationStarted(conversation("c-dave", "dave"))),
+            vec![Command::LoadMessages("c-dave".to_string())]
+        );
+        assert_eq!(
+            app.selected_conversation().unwrap().participant_username,
+            "dave"
+        );
+        assert_eq!(app.focus, Focus::Composer);
+    }
+
+    #[test]
+    fn test_message_requests_are_answered() {
+        let mut app = chat_app();
+        assert_eq!(app.handle_key(Key::Ctrl('r')), vec![Command::LoadRequests]);
+        assert!(app.requests.as_ref().unwrap().loading);
+
+        let request = |id: &str, from: &str| MessageRequest {
+            conversation_id: id.to_string(),
+            requester_id: format!("u-{}", from),
+            requester_username: from.to_string(),
+            created_at: 0,
+            message_count: 1,
+            last_message_at: Some(0),
+        };
+        app.apply(Update::Requests(vec![
+            request("c-dave", "dave"),
+            request("c-erin", "erin"),
+        ]));
+
+        assert_eq!(
+            app.handle_key(Key::Char('d')),
+            vec![Command::DeclineRequest("c-dave".to_string())]
+        );
+        assert!(app
+            .apply(Update::RequestAnswered {
+                conversation_id: "c-dave".to_string(),
+                accepted: false,
+            })
+            .is_empty());
+        assert_eq!(app.requests.as_ref().unwrap().requests.len(), 1);
+
+        assert_eq!(
+            app.handle_key(Key::Enter),
+            vec![Command::AcceptRequest("c-erin".to_string())]
+        );
+        assert_eq!(
+            app.apply(Update::RequestAnswered {
+                conversation_id: "c-erin".to_string(),
+                accepted: true,
+            }),
+            vec![Command::LoadConversations]
+        );
+        assert!(app.requests.is_none());
+    }
+}
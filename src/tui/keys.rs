@@ -0,0 +1,144 @@
+//! Keyboard input decoding
+//!
+//! Turns the raw bytes a terminal sends in raw mode into keys. The same decoder
+//! reads scripted input in headless mode, so tests can type `\t` or `\x1b[B`.
+
+/// One key press
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    /// Control plus a lowercase letter
+    Ctrl(char),
+    Enter,
+    Tab,
+    BackTab,
+    Backspace,
+    Esc,
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+}
+
+/// Incremental decoder; keeps partial escape sequences and UTF-8 between reads
+#[derive(Debug, Default)]
+pub struct KeyDecoder {
+    pending: Vec<u8>,
+}
+
+impl KeyDecoder {
+    /// Feed bytes and return the keys they complete
+    ///
+    /// A lone `ESC` at the end of a read is taken as the Escape key, since
+    /// terminals send escape sequences in a single write.
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<Key> {
+        self.pending.extend_from_slice(bytes);
+        let mut keys = Vec::new();
+        let mut i = 0;
+
+        while i < self.pending.len() {
+            let rest = &self.pending[i..];
+            let (key, used) = match rest[0] {
+                0x1b => match decode_escape(rest) {
+                    Some(decoded) => decoded,
+                    // Incomplete sequence; wait for more bytes
+                    None if rest.len() > 1 => break,
+                    None => (Some(Key::Esc), 1),
+                },
+                b'\r' | b'\n' => (Some(Key::Enter), 1),
+                b'\t' => (Some(Key::Tab), 1),
+                0x08 | 0x7f => (Some(Key::Backspace), 1),
+                b @ 0x01..=0x1a => (Some(Key::Ctrl((b'a' + b - 1) as char)), 1),
+                b if b < 0x20 => (None, 1),
+                _ => match decode_utf8(rest) {
+                    Some((c, used)) => (Some(Key::Char(c)), used),
+                    None if rest.len() < 4
+                        && std::str::from_utf8(rest).is_err_and(|e| e.error_len().is_none()) =>
+                    {
+                        break
+                    }
+                    None => (None, 1),
+                },
+            };
+            keys.extend(key);
+            i += used;
+        }
+
+        self.pending.drain(..i);
+        keys
+    }
+}
+
+/// Decode an escape sequence at the start of `bytes`; `None` if incomplete
+fn decode_escape(bytes: &[u8]) -> Option<(Option<Key>, usize)> {
+    match bytes.get(1)? {
+        b'[' | b'O' => {
+            let end = bytes[2..].iter().position(|b| (0x40..=0x7e).contains(b))? + 2;
+            let key = match &bytes[2..=end] {
+                b"A" => Some(Key::Up),
+                b"B" => Some(Key::Down),
+                b"C" => Some(Key::Right),
+                b"D" => Some(Key::Left),
+                b"Z" => Some(Key::BackTab),
+                b"5~" => Some(Key::PageUp),
+                b"6~" => Some(Key::PageDown),
+                _ => None,
+            };
+            Some((key, end + 1))
+        }
+        // Alt+key or ESC typed before another key
+        _ => Some((Some(Key::Esc), 1)),
+    }
+}
+
+fn decode_utf8(bytes: &[u8]) -> Option<(char, usize)> {
+    let len = match bytes[0] {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        _ => 4,
+    };
+    let c = std::str::from_utf8(bytes.get(..len)?)
+        .ok()?
+        .chars()
+        .next()?;
+    Some((c, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decodes_keys_and_sequences() {
+        let mut decoder = KeyDecoder::default();
+        assert_eq!(
+            decoder.feed(b"hi\t\x1b[B\x1b[Z\r\x7f\x06\x1b[5~"),
+            vec![
+                Key::Char('h'),
+                Key::Char('i'),
+                Key::Tab,
+                Key::Down,
+                Key::BackTab,
+                Key::Enter,
+                Key::Backspace,
+                Key::Ctrl('f'),
+                Key::PageUp,
+            ]
+        );
+        assert_eq!(decoder.feed(b"\x1b"), vec![Key::Esc]);
+    }
+
+    #[test]
+    fn test_split_reads_are_joined() {
+        let mut decoder = KeyDecoder::default();
+        assert!(decoder.feed(b"\x1b[").is_empty());
+        assert_eq!(decoder.feed(b"A"), vec![Key::Up]);
+
+        let e_acute = "é".as_bytes();
+        assert!(decoder.feed(&e_acute[..1]).is_empty());
+        assert_eq!(decoder.feed(&e_acute[1..]), vec![Key::Char('é')]);
+    }
+}
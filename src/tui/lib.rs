@@ -0,0 +1,19 @@
+//! Terminal chat client
+//!
+//! A keyboard-driven client for the chat server built on the `chat-client` SDK:
+//! conversation list, message pane, composer, user search, and live presence
+//! and typing. It signs in with the same session file as the desktop app.
+//!
+//! The [`app`] state is pure and rendered to plain text, so [`runner::Runner`]
+//! can be driven without a terminal, both by tests and by `chat-tui --headless`.
+
+pub mod app;
+pub mod keys;
+pub mod render;
+pub mod runner;
+pub mod terminal;
+
+pub use app::{App, Command, Update};
+pub use keys::{Key, KeyDecoder};
+pub use render::{render, Frame};
+pub use runner::Runner;
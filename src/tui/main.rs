@@ -0,0 +1,143 @@
+//! chat-tui - terminal chat client
+//!
+//! Interactive by default. With `--headless` keys are read from stdin (e.g.
+//! `printf 'alice\tpassword\r'`) and each changed screen is printed to stdout,
+//! separated by form feeds, until input ends and the client goes idle.
+
+use chat_client::{ChatClient, SessionManager};
+use chat_tui::terminal::Terminal;
+use chat_tui::{Frame, KeyDecoder, Runner};
+use clap::Parser;
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// How long headless mode waits for further events once input has ended and
+/// no request is outstanding
+const HEADLESS_SETTLE: Duration = Duration::from_millis(500);
+
+#[derive(Parser, Debug)]
+#[command(name = "chat-tui")]
+#[command(about = "Terminal client for the chat server", long_about = None)]
+struct Args {
+    /// Server base URL
+    #[arg(long, env = "SERVER_URL", default_value = "http://localhost:8080")]
+    server: String,
+
+    /// WebSocket endpoint; defaults to /socket on the server
+    #[arg(long, env = "SERVER_WS_URL")]
+    ws_url: Option<String>,
+
+    /// Read keys from stdin and print screens instead of using the terminal
+    #[arg(long)]
+    headless: bool,
+
+    /// Screen size in headless mode
+    #[arg(long, default_value = "80")]
+    width: usize,
+
+    #[arg(long, default_value = "24")]
+    height: usize,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let ws_url = args
+        .ws_url
+        .clone()
+        .unwrap_or_else(|| websocket_url(&args.server));
+
+    let mut runner = Runner::new(
+        ChatClient::new(&args.server),
+        ws_url,
+        Arc::new(SessionManager::new()),
+    );
+    let terminal = if args.headless {
+        None
+    } else {
+        Some(Terminal::enter()?)
+    };
+
+    let mut input = spawn_stdin_reader();
+    let mut decoder = KeyDecoder::default();
+    let mut last_frame: Option<Frame> = None;
+
+    loop {
+        let frame = match &terminal {
+            Some(_) => {
+                let (width, height) = Terminal::size();
+                runner.screen(width, height)
+            }
+            None => runner.screen(args.width, args.height),
+        };
+        if last_frame.as_ref() != Some(&frame) {
+            match &terminal {
+                Some(terminal) => terminal.draw(&frame)?,
+                None => print_frame(&frame)?,
+            }
+            last_frame = Some(frame);
+        }
+        if runner.app().should_quit {
+            break;
+        }
+
+        tokio::select! {
+            bytes = input.recv() => match bytes {
+                Some(bytes) => {
+                    for key in decoder.feed(&bytes) {
+                        runner.press(key);
+                    }
+                }
+                None => {
+                    while runner.next_update(HEADLESS_SETTLE).await.is_some() || runner.is_busy() {}
+                    let frame = runner.screen(args.width, args.height);
+                    if terminal.is_none() && last_frame.as_ref() != Some(&frame) {
+                        print_frame(&frame)?;
+                    }
+                    break;
+                }
+            },
+            // Also wakes periodically so a resized terminal is redrawn
+            _ = runner.next_update(Duration::from_millis(250)) => {}
+        }
+    }
+
+    runner.shutdown();
+    Ok(())
+}
+
+/// `http://host:port` -> `ws://host:port/socket`
+fn websocket_url(server: &str) -> String {
+    let server = server.trim_end_matches('/');
+    let base = match server.strip_prefix("https://") {
+        Some(rest) => format!("wss://{}", rest),
+        None => format!("ws://{}", server.strip_prefix("http://").unwrap_or(server)),
+    };
+    format!("{}/socket", base)
+}
+
+fn print_frame(frame: &Frame) -> std::io::Result<()> {
+    let mut stdout = std::io::stdout();
+    writeln!(stdout, "\x0c")?;
+    for line in &frame.lines {
+        writeln!(stdout, "{}", line.trim_end())?;
+    }
+    stdout.flush()
+}
+
+/// Stdin is read on a thread since reads block until a key is pressed
+fn spawn_stdin_reader() -> mpsc::UnboundedReceiver<Vec<u8>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin();
+        let mut buffer = [0u8; 1024];
+        while let Ok(n @ 1..) = stdin.read(&mut buffer) {
+            if tx.send(buffer[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
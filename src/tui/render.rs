@@ -0,0 +1,413 @@
+//! Plain-text rendering
+//!
+//! Lays the app out as rows of exactly `width` characters. The terminal draws
+//! them with ANSI positioning; headless mode prints them as they are.
+
+use crate::app::{App, Focus, LoginField, Screen};
+use chat_client::ConnectionStatus;
+
+/// Width of the conversation list, separator included
+const LIST_WIDTH: usize = 28;
+
+/// One rendered screen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub lines: Vec<String>,
+    /// Column and row of the text cursor, if an input has focus
+    pub cursor: Option<(usize, usize)>,
+}
+
+impl Frame {
+    pub fn text(&self) -> String {
+        self.lines.join("\n")
+    }
+}
+
+pub fn render(app: &App, width: usize, height: usize) -> Frame {
+    let width = width.max(20);
+    let height = height.max(6);
+    let mut frame = match app.screen {
+        Screen::Login => render_login(app, width),
+        Screen::Chat => render_chat(app, width, height),
+    };
+
+    frame.lines.resize(height, String::new());
+    frame.lines.truncate(height);
+    if !app.status.is_empty() || app.screen == Screen::Chat {
+        frame.lines[height - 1] = status_bar(app);
+    }
+    for line in &mut frame.lines {
+        *line = fit(line, width);
+    }
+    frame
+}
+
+fn render_login(app: &App, width: usize) -> Frame {
+    let form = &app.login;
+    let title = if form.signup {
+        "Create account"
+    } else {
+        "Sign in"
+    };
+    let marker = |field| if form.field == field { "> " } else { "  " };
+    let username = format!(
+        "{}Username: {}",
+        marker(LoginField::Username),
+        form.username
+    );
+    let password = format!(
+        "{}Password: {}",
+        marker(LoginField::Password),
+        "*".repeat(form.password.chars().count())
+    );
+    let cursor = match form.field {
+        LoginField::Username => (username.chars().count().min(width - 1), 2),
+        LoginField::Password => (password.chars().count().min(width - 1), 3),
+    };
+
+    let switch = if form.signup {
+        "Ctrl-N: sign in instead"
+    } else {
+        "Ctrl-N: create an account"
+    };
+    Frame {
+        lines: vec![
+            format!("chat-tui - {}", title),
+            String::new(),
+            username,
+            password,
+            String::new(),
+            format!("Enter: submit  Tab: next field  {}  Ctrl-Q: quit", switch),
+        ],
+        cursor: Some(cursor),
+    }
+}
+
+fn render_chat(app: &App, width: usize, height: usize) -> Frame {
+    let list_width = LIST_WIDTH.min(width / 3);
+    let pane_width = width - list_width;
+    // Header, composer and status bar take three rows
+    let body_height = height - 3;
+
+    let username = app
+        .session
+        .as_ref()
+        .map(|s| s.username.as_str())
+        .unwrap_or_default();
+    let mut lines = vec![format!(
+        "chat-tui - {} [{}]",
+        username,
+        connection_label(&app.connection)
+    )];
+
+    let list = conversation_list(app, list_width - 1, body_height);
+    let pane = if app.search.is_some() {
+        search_pane(app, pane_width, body_height)
+    } else if app.requests.is_some() {
+        requests_pane(app, pane_width, body_height)
+    } else {
+        message_pane(app, pane_width, body_height)
+    };
+    for (left, right) in list.iter().zip(&pane) {
+        lines.push(format!("{}|{}", fit(left, list_width - 1), right));
+    }
+
+    let prompt = format!("> {}", app.composer);
+    let mut cursor = None;
+    if let Some(search) = &app.search {
+        cursor = Some((list_width + 12 + search.query.chars().count(), 1));
+    } else if app.focus == Focus::Composer {
+        cursor = Some((prompt.chars().count().min(width - 1), height - 2));
+    }
+    lines.push(prompt);
+
+    Frame {
+        lines,
+        cursor: cursor.map(|(x, y)| (x.min(width - 1), y)),
+    }
+}
+
+fn conversation_list(app: &App, width: usize, height: usize) -> Vec<String> {
+    let mut rows: Vec<String> = app
+        .conversations
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let marker = if i == app.selected {
+                if app.focus == Focus::Conversations {
+                    "> "
+                } else {
+                    "* "
+                }
+            } else {
+                "  "
+            };
+            let presence = if item.conversation.participant_is_online {
+                "+"
+            } else {
+                "-"
+            };
+            let badge = if item.unread > 0 {
+                format!(" ({})", item.unread)
+            } else {
+                String::new()
+            };
+            format!(
+                "{}{} {}{}",
+                marker, presence, item.conversation.participant_username, badge
+            )
+        })
+        .collect();
+
+    if rows.is_empty() {
+        rows.push("  No conversations".to_string());
+        rows.push("  Ctrl-F to find".to_string());
+        rows.push("  someone".to_string());
+    }
+
+    // Keep the selected row visible
+    let skip = (app.selected + 1).saturating_sub(height);
+    let mut rows: Vec<String> = rows
+        .into_iter()
+        .skip(skip)
+        .map(|row| fit(&row, width))
+        .collect();
+    rows.resize(height, String::new());
+    rows
+}
+
+fn message_pane(app: &App, width: usize, height: usize) -> Vec<String> {
+    let inner = width.saturating_sub(1).max(1);
+    let mut rows = Vec::new();
+    for message in &app.messages {
+        let time = chrono::DateTime::from_timestamp_millis(message.timestamp as i64)
+            .map(|t| t.with_timezone(&chrono::Local).format("%H:%M").to_string())
+            .unwrap_or_default();
+        let status = if message.mine && message.status != "delivered" && message.status != "read" {
+            format!(" ({})", message.status)
+        } else {
+            String::new()
+        };
+        let text = format!(
+            "{} {}: {}{}",
+            time, message.sender_username, message.content, status
+        );
+        rows.extend(wrap(&text, inner));
+    }
+
+    let typing = app
+        .conversations
+        .get(app.selected)
+        .filter(|item| item.typing);
+    if let Some(item) = typing {
+        rows.push(format!(
+            "{} is typing...",
+            item.conversation.participant_username
+        ));
+    }
+
+    let end = rows.len().saturating_sub(app.scroll.min(rows.len()));
+    let start = end.saturating_sub(height);
+    let mut visible: Vec<String> = rows[start..end]
+        .iter()
+        .map(|row| format!(" {}", row))
+        .collect();
+    // Bottom-align like a chat log
+    let mut pane = vec![String::new(); height - visible.len()];
+    pane.append(&mut visible);
+    pane
+}
+
+fn search_pane(app: &App, width: usize, height: usize) -> Vec<String> {
+    let Some(search) = &app.search else {
+        return vec![String::new(); height];
+    };
+    let mut rows = vec![format!(" Find user: {}", search.query), String::new()];
+    if search.query.is_empty() {
+        rows.push(" Type a username".to_string());
+    } else if search.results.is_empty() {
+        rows.push(" No matches".to_string());
+    }
+    for (i, user) in search.results.iter().enumerate() {
+        let marker = if i == search.selected { "> " } else { "  " };
+        let presence = if user.is_online { "online" } else { "offline" };
+        rows.push(format!(" {}{} ({})", marker, user.username, presence));
+    }
+    rows.truncate(height);
+    rows.resize(height, String::new());
+    rows.into_iter().map(|row| fit(&row, width)).collect()
+}
+
+fn requests_pane(app: &App, width: usize, height: usize) -> Vec<String> {
+    let Some(list) = &app.requests else {
+        return vec![String::new(); height];
+    };
+    let mut rows = vec![" Message requests".to_string(), String::new()];
+    if list.loading {
+        rows.push(" Loading...".to_string());
+    } else if list.requests.is_empty() {
+        rows.push(" No message requests".to_string());
+    }
+    for (i, request) in list.requests.iter().enumerate() {
+        let marker = if i == list.selected { "> " } else { "  " };
+        let count = match request.message_count {
+            1 => "1 message".to_string(),
+            n => format!("{} messages", n),
+        };
+        rows.push(format!(
+            " {}{} ({})",
+            marker, request.requester_username, count
+        ));
+    }
+    rows.truncate(height);
+    rows.resize(height, String::new());
+    rows.into_iter().map(|row| fit(&row, width)).collect()
+}
+
+fn status_bar(app: &App) -> String {
+    if !app.status.is_empty() {
+        return app.status.clone();
+    }
+    if app.requests.is_some() && app.search.is_none() {
+        return "Enter: accept  d: decline  Up/Down: choose  Esc: close".to_string();
+    }
+    match (app.search.is_some(), app.focus) {
+        (true, _) => "Enter: open  Up/Down: choose  Esc: close".to_string(),
+        (false, Focus::Conversations) => {
+            "Up/Down: choose  Enter: write  Ctrl-F: find user  Ctrl-R: requests  Ctrl-X: sign out  Ctrl-Q: quit"
+                .to_string()
+        }
+        (false, Focus::Composer) => {
+            "Enter: send  Esc: conversations  PgUp/PgDn: scroll  Ctrl-Q: quit".to_string()
+        }
+    }
+}
+
+fn connection_label(status: &ConnectionStatus) -> String {
+    match status {
+        ConnectionStatus::Connecting => "connecting".to_string(),
+        ConnectionStatus::Connected => "online".to_string(),
+        ConnectionStatus::Reconnecting { retry_in_ms } => {
+            format!("reconnecting in {}s", retry_in_ms.div_ceil(1000))
+        }
+        ConnectionStatus::Disconnected { .. } => "offline".to_string(),
+        ConnectionStatus::UpdateRequired { .. } => "update required".to_string(),
+    }
+}
+
+/// Truncate or pad to exactly `width` characters
+fn fit(text: &str, width: usize) -> String {
+    let mut out: String = text.chars().take(width).collect();
+    let len = out.chars().count();
+    out.extend(std::iter::repeat_n(' ', width - len));
+    out
+}
+
+/// Break `text` into rows of at most `width` characters, preferring spaces
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut rows = Vec::new();
+    let mut row = String::new();
+    for word in text.split(' ') {
+        let word_len = word.chars().count();
+        let row_len = row.chars().count();
+        if row_len > 0 && row_len + 1 + word_len > width {
+            rows.push(std::mem::take(&mut row));
+        } else if row_len > 0 {
+            row.push(' ');
+        }
+
+        let mut chars = word.chars().peekable();
+        while chars.peek().is_some() {
+            let room = width - row.chars().count();
+            if room == 0 {
+                rows.push(std::mem::take(&mut row));
+                continue;
+            }
+            row.extend(chars.by_ref().take(room));
+        }
+    }
+    rows.push(row);
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{ChatMessage, Update};
+    use crate::keys::Key;
+    use chat_client::{Conversation, SessionData};
+
+    fn chat_app() -> App {
+        let mut app = App::new();
+        app.apply(Update::LoggedIn(SessionData {
+            user_id: "u-alice".to_string(),
+            username: "alice".to_string(),
+            token: "t".to_string(),
+            expires_at: i64::MAX,
+        }));
+        app.apply(Update::Conversations(vec![Conversation {
+            conversation_id: "c-bob".to_string(),
+            participant_id: "u-bob".to_string(),
+            participant_username: "bob".to_string(),
+            participant_is_online: true,
+            created_at: 0,
+            last_message_at: None,
+            message_count: 1,
+            encrypted: false,
+            ephemeral_ttl: None,
+            ephemeral_start: "sent".to_string(),
+            request_pending: false,
+        }]));
+        app.messages.push(ChatMessage {
+            id: "m1".to_string(),
+            sender_username: "bob".to_string(),
+            content: "hello there".to_string(),
+            timestamp: 0,
+            mine: false,
+            status: "delivered".to_string(),
+        });
+        app.status.clear();
+        app
+    }
+
+    #[test]
+    fn test_login_masks_password() {
+        let mut app = App::new();
+        for key in [Key::Char('a'), Key::Tab, Key::Char('p'), Key::Char('w')] {
+            app.handle_key(key);
+        }
+        let frame = render(&app, 60, 10);
+        assert_eq!(frame.lines.len(), 10);
+        assert!(frame.lines.iter().all(|l| l.chars().count() == 60));
+        assert!(frame.text().contains("  Username: a"));
+        assert!(frame.text().contains("> Password: **"));
+        assert!(!frame.text().contains("pw"));
+        assert_eq!(frame.cursor, Some((14, 3)));
+    }
+
+    #[test]
+    fn test_chat_layout() {
+        let mut app = chat_app();
+        app.conversations[0].typing = true;
+        let frame = render(&app, 80, 8);
+
+        assert!(frame.lines[0].starts_with("chat-tui - alice [offline]"));
+        assert!(frame.lines[1].starts_with("> + bob"));
+        assert!(frame.lines[4].contains(" bob: hello there"));
+        assert!(frame.lines[5].contains("bob is typing..."));
+        assert!(frame.lines[6].starts_with("> "));
+        assert!(frame.lines[7].starts_with("Up/Down: choose"));
+        assert_eq!(frame.cursor, None);
+
+        app.handle_key(Key::Enter);
+        app.handle_key(Key::Char('x'));
+        assert_eq!(render(&app, 80, 8).cursor, Some((3, 6)));
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("ab cd ef", 5), vec!["ab cd", "ef"]);
+        assert_eq!(wrap("abcdefgh", 3), vec!["abc", "def", "gh"]);
+        assert_eq!(fit("abc", 5), "abc  ");
+    }
+}
@@ -0,0 +1,309 @@
+//! Runs commands against the server
+//!
+//! Owns the [`App`] together with the REST client, realtime socket and session
+//! file. Commands run as background tasks that report back as [`Update`]s, so
+//! the UI never blocks on the network. Tests drive a `Runner` directly.
+
+use crate::app::{App, Command, Update};
+use crate::keys::Key;
+use crate::render::{render, Frame};
+use chat_client::{
+    AuthResponse, ChatClient, ClientError, Page, SessionData, SessionManager, WebSocketClient,
+    WireOptions,
+};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Results shown per user search
+const SEARCH_LIMIT: u32 = 20;
+const HISTORY_LIMIT: u32 = 100;
+
+pub struct Runner {
+    app: App,
+    client: ChatClient,
+    websocket_url: String,
+    session: Arc<SessionManager>,
+    socket: Option<WebSocketClient>,
+    update_tx: mpsc::UnboundedSender<Update>,
+    update_rx: mpsc::UnboundedReceiver<Update>,
+    /// Requests that have not reported back yet
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Runner {
+    /// Create a runner, signing in with the saved session if it is still valid
+    ///
+    /// Must be called within a Tokio runtime.
+    pub fn new(client: ChatClient, websocket_url: String, session: Arc<SessionManager>) -> Self {
+        let (update_tx, update_rx) = mpsc::unbounded_channel();
+        let mut runner = Self {
+            app: App::new(),
+            client,
+            websocket_url,
+            session,
+            socket: None,
+            update_tx,
+            update_rx,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        };
+
+        if let Ok(Some(saved)) = runner.session.get_session() {
+            if runner.session.is_logged_in() {
+                runner.client.set_token(Some(saved.token.clone()));
+                runner.apply(Update::LoggedIn(saved));
+            } else {
+                runner.app.login.username = saved.username;
+            }
+        }
+        runner
+    }
+
+    pub fn app(&self) -> &App {
+        &self.app
+    }
+
+    pub fn screen(&self, width: usize, height: usize) -> Frame {
+        render(&self.app, width, height)
+    }
+
+    /// Handle a key press
+    pub fn press(&mut self, key: Key) {
+        let commands = self.app.handle_key(key);
+        self.execute(commands);
+    }
+
+    /// Wait for the next update and apply it; `None` once the runner is idle
+    /// for `idle`
+    pub async fn next_update(&mut self, idle: Duration) -> Option<()> {
+        let update = tokio::time::timeout(idle, self.update_rx.recv())
+            .await
+            .ok()
+            .flatten()?;
+        self.apply(update);
+        Some(())
+    }
+
+    /// Whether any request is still waiting for the server
+    pub fn is_busy(&self) -> bool {
+        self.in_flight.load(Ordering::SeqCst) > 0
+    }
+
+    /// Apply updates until `done` holds; false on timeout
+    pub async fn wait_for(&mut self, timeout: Duration, done: impl Fn(&App) -> bool) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        while !done(&self.app) {
+            let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+            if left.is_zero() || self.next_update(left).await.is_none() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Close the realtime connection
+    pub fn shutdown(&mut self) {
+        if let Some(socket) = self.socket.take() {
+            let _ = socket.disconnect();
+        }
+    }
+
+    fn apply(&mut self, update: Update) {
+        if let Update::LoggedIn(session) = &update {
+            self.connect(session.token.clone());
+        }
+        let commands = self.app.apply(update);
+        self.execute(commands);
+    }
+
+    fn connect(&mut self, token: String) {
+        self.shutdown();
+
+        // The global session may be refreshed by another client; fall back to
+        // the token we signed in with
+        let session = self.session.clone();
+        let token_provider = Arc::new(move || {
+            session
+                .get_current_session()
+                .map(|s| s.token)
+                .unwrap_or_else(|| token.clone())
+        });
+        let (event_tx, mut events) = mpsc::unbounded_channel();
+        self.socket = Some(WebSocketClient::connect(
+            self.websocket_url.clone(),
+            token_provider,
+            WireOptions::default(),
+            event_tx,
+            &tokio::runtime::Handle::current(),
+        ));
+
+        let update_tx = self.update_tx.clone();
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                if update_tx.send(Update::Realtime(event)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    fn execute(&mut self, commands: Vec<Command>) {
+        for command in commands {
+            match command {
+                Command::Login(input) => {
+                    let client = self.client.clone();
+                    let session = self.session.clone();
+                    self.spawn(async move {
+                        Ok(save_session(&session, client.login(&input.into()).await?))
+                    });
+                }
+                Command::Signup(input) => {
+                    let client = self.client.clone();
+                    let session = self.session.clone();
+                    self.spawn(async move {
+                        Ok(save_session(&session, client.signup(&input.into()).await?))
+                    });
+                }
+                Command::LoadConversations => {
+                    let client = self.client.clone();
+                    self.spawn(async move {
+                        let conversations = client.conversations(Page::default()).await?;
+                        Ok(Update::Conversations(conversations))
+                    });
+                }
+                Command::LoadMessages(conversation_id) => {
+                    let client = self.client.clone();
+                    self.spawn(async move {
+                        let messages = client
+                            .messages(&conversation_id, Page::new(HISTORY_LIMIT, 0))
+                            .await?;
+                        Ok(Update::Messages {
+                            conversation_id,
+                            messages,
+                        })
+                    });
+                }
+                Command::SendMessage {
+                    message_id,
+                    conversation_id,
+                    recipient_id,
+                    content,
+                } => match &self.socket {
+                    // The socket queues while reconnecting and falls back to REST
+                    Some(socket) => {
+                        if let Err(e) =
+                            socket.send_message(message_id, conversation_id, recipient_id, content)
+                        {
+                            self.app.status = e;
+                        }
+                    }
+                    None => {
+                        let client = self.client.clone();
+                        self.spawn(async move {
+                            let sent = client
+                                .send_message(&conversation_id, &message_id, &content)
+                                .await?;
+                            Ok(Update::Realtime(chat_client::WebSocketEvent::Ack {
+                                message_id: Some(message_id),
+                                status: sent.status,
+                                conversation_id: Some(conversation_id),
+                            }))
+                        });
+                    }
+                },
+                Command::Typing {
+                    recipient_id,
+                    is_typing,
+                } => {
+                    if let Some(socket) = &self.socket {
+                        let _ = socket.send_typing(recipient_id, is_typing);
+                    }
+                }
+                Command::SearchUsers(query) => {
+                    let client = self.client.clone();
+                    self.spawn(async move {
+                        let results = client.search_users(&query, Some(SEARCH_LIMIT)).await?;
+                        Ok(Update::SearchResults { query, results })
+                    });
+                }
+                Command::StartConversation(user_id) => {
+                    let client = self.client.clone();
+                    self.spawn(async move {
+                        Ok(Update::ConversationStarted(
+                            client.start_conversation(&user_id).await?,
+                        ))
+                    });
+                }
+                Command::LoadRequests => {
+                    let client = self.client.clone();
+                    self.spawn(
+                        async move { Ok(Update::Requests(client.message_requests().await?)) },
+                    );
+                }
+                Command::AcceptRequest(conversation_id) => {
+                    let client = self.client.clone();
+                    self.spawn(async move {
+                        client.accept_request(&conversation_id).await?;
+                        Ok(Update::RequestAnswered {
+                            conversation_id,
+                            accepted: true,
+                        })
+                    });
+                }
+                Command::DeclineRequest(conversation_id) => {
+                    let client = self.client.clone();
+                    self.spawn(async move {
+                        client.decline_request(&conversation_id).await?;
+                        Ok(Update::RequestAnswered {
+                            conversation_id,
+                            accepted: false,
+                        })
+                    });
+                }
+                Command::Logout => {
+                    self.shutdown();
+                    let client = self.client.clone();
+                    let session = self.session.clone();
+                    tokio::spawn(async move {
+                        // The token is dropped locally even if the server is unreachable
+                        let _ = client.logout().await;
+                        let _ = session.clear_session().await;
+                    });
+                }
+                Command::Quit => self.shutdown(),
+            }
+        }
+    }
+
+    /// Run a request in the background and report its result
+    fn spawn(&self, task: impl Future<Output = Result<Update, ClientError>> + Send + 'static) {
+        let update_tx = self.update_tx.clone();
+        let in_flight = self.in_flight.clone();
+        in_flight.fetch_add(1, Ordering::SeqCst);
+        tokio::spawn(async move {
+            let update = task.await.unwrap_or_else(|e| Update::Failed(e.to_string()));
+            let _ = update_tx.send(update);
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Store a fresh login where the desktop client also finds it
+///
+/// A session that cannot be written still works until the client exits.
+fn save_session(session: &SessionManager, auth: AuthResponse) -> Update {
+    let _ = session.save_session_sync(
+        &auth.user_id,
+        &auth.token,
+        &auth.username,
+        auth.expires_in as i64,
+    );
+    Update::LoggedIn(SessionData {
+        user_id: auth.user_id,
+        username: auth.username,
+        token: auth.token,
+        expires_at: auth.expires_in as i64,
+    })
+}
@@ -0,0 +1,94 @@
+//! Raw-mode terminal I/O
+//!
+//! Just enough of a terminal layer for a full-screen app: raw input, the
+//! alternate screen, window size and redrawing a [`Frame`] with ANSI escapes.
+
+use crate::render::Frame;
+use std::io::{self, Write};
+
+/// Puts the terminal in raw mode on the alternate screen until dropped
+pub struct Terminal {
+    #[cfg(unix)]
+    original: libc::termios,
+}
+
+#[cfg(unix)]
+impl Terminal {
+    pub fn enter() -> io::Result<Self> {
+        // SAFETY: termios is plain data filled in by tcgetattr before use
+        let original = unsafe {
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut raw = termios;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            termios
+        };
+
+        let mut stdout = io::stdout();
+        write!(stdout, "\x1b[?1049h\x1b[2J")?;
+        stdout.flush()?;
+        Ok(Self { original })
+    }
+
+    /// Columns and rows, or 80x24 if stdout is not a terminal
+    pub fn size() -> (usize, usize) {
+        // SAFETY: TIOCGWINSZ only writes into the winsize we pass
+        let mut size = unsafe { std::mem::zeroed::<libc::winsize>() };
+        let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+        if ok && size.ws_col > 0 && size.ws_row > 0 {
+            (size.ws_col as usize, size.ws_row as usize)
+        } else {
+            (80, 24)
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl Terminal {
+    pub fn enter() -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "interactive mode needs a Unix terminal; use --headless",
+        ))
+    }
+
+    pub fn size() -> (usize, usize) {
+        (80, 24)
+    }
+}
+
+impl Terminal {
+    /// Redraw the whole screen
+    pub fn draw(&self, frame: &Frame) -> io::Result<()> {
+        let mut out = String::from("\x1b[?25l");
+        for (row, line) in frame.lines.iter().enumerate() {
+            out.push_str(&format!("\x1b[{};1H{}", row + 1, line));
+        }
+        if let Some((column, row)) = frame.cursor {
+            out.push_str(&format!("\x1b[{};{}H\x1b[?25h", row + 1, column + 1));
+        }
+
+        let mut stdout = io::stdout();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "\x1b[?25h\x1b[?1049l");
+        let _ = stdout.flush();
+
+        #[cfg(unix)]
+        // SAFETY: restores the settings read in `enter`
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Drives the terminal client headlessly against a real server
+
+use chat_backend::server::{create_routes, ServerConfig, ServerState};
+use chat_client::{ChatClient, SessionManager};
+use chat_tui::app::{Focus, Screen};
+use chat_tui::{App, Key, KeyDecoder, Runner};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn spawn_server() -> SocketAddr {
+    let db_path = std::env::temp_dir().join(format!("chat-tui-{}.db", uuid::Uuid::new_v4()));
+    let pool = chat_backend::db::init_db(&db_path).await.unwrap();
+    let state = ServerState::new(pool, ServerConfig::default());
+    let (addr, server) = warp::serve(create_routes(state)).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    addr
+}
+
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("chat-tui-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn runner(addr: SocketAddr, session_file: PathBuf) -> Runner {
+    Runner::new(
+        ChatClient::new(format!("http://{}", addr)),
+        format!("ws://{}/socket", addr),
+        Arc::new(SessionManager::with_session_file(session_file)),
+    )
+}
+
+fn type_keys(runner: &mut Runner, input: &str) {
+    for key in KeyDecoder::default().feed(input.as_bytes()) {
+        runner.press(key);
+    }
+}
+
+async fn wait(runner: &mut Runner, what: &str, done: impl Fn(&App) -> bool) {
+    if !runner.wait_for(TIMEOUT, done).await {
+        panic!(
+            "timed out waiting for {}:\n{}",
+            what,
+            runner.screen(80, 24).text()
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_two_clients_chat() {
+    let addr = spawn_server().await;
+    let dir = temp_dir();
+
+    let mut alice = runner(addr, dir.join("alice.json"));
+    let mut bob = runner(addr, dir.join("bob.json"));
+    for (runner, name) in [(&mut alice, "alice"), (&mut bob, "bob")] {
+        type_keys(runner, &format!("{}\tSecurePass123\x0e\r", name));
+        wait(runner, "signup", |app| app.screen == Screen::Chat).await;
+    }
+
+    // Find bob and open a conversation
+    alice.press(Key::Ctrl('f'));
+    type_keys(&mut alice, "bo");
+    wait(&mut alice, "search results", |app| {
+        app.search.as_ref().is_some_and(|s| !s.results.is_empty())
+    })
+    .await;
+    alice.press(Key::Enter);
+    wait(&mut alice, "conversation", |app| {
+        app.focus == Focus::Composer
+    })
+    .await;
+
+    type_keys(&mut alice, "Hello Bob\r");
+    wait(&mut alice, "ack", |app| {
+        app.messages.iter().any(|m| m.status != "sending")
+    })
+    .await;
+
+    // Alice's first message waits in bob's message requests until he accepts
+    bob.press(Key::Ctrl('r'));
+    wait(&mut bob, "message request", |app| {
+        app.requests.as_ref().is_some_and(|r| r.requests.len() == 1)
+    })
+    .await;
+    assert!(bob.app().messages.is_empty());
+    assert!(bob.screen(80, 24).text().contains("alice (1 message)"));
+    bob.press(Key::Enter);
+
+    // The conversation appears for bob with the message and alice online
+    wait(&mut bob, "message", |app| {
+        app.messages.iter().any(|m| m.content == "Hello Bob")
+    })
+    .await;
+    let screen = bob.screen(80, 24).text();
+    assert!(screen.contains("> + alice"), "{}", screen);
+    assert!(screen.contains("alice: Hello Bob"), "{}", screen);
+
+    bob.press(Key::Enter);
+    type_keys(&mut bob, "H");
+    wait(&mut alice, "typing", |app| app.conversations[0].typing).await;
+    assert!(alice.screen(80, 24).text().contains("bob is typing..."));
+
+    type_keys(&mut bob, "i alice\r");
+    wait(&mut alice, "reply", |app| {
+        app.messages
+            .iter()
+            .any(|m| m.content == "Hi alice" && !m.mine)
+    })
+    .await;
+
+    // Signing out on bob's side shows him offline to alice
+    bob.press(Key::Ctrl('x'));
+    assert_eq!(bob.app().screen, Screen::Login);
+    wait(&mut alice, "presence", |app| {
+        !app.conversations[0].conversation.participant_is_online
+    })
+    .await;
+
+    // A new client picks up alice's saved session
+    let mut restored = runner(addr, dir.join("alice.json"));
+    assert_eq!(restored.app().screen, Screen::Chat);
+    wait(&mut restored, "history", |app| app.messages.len() == 2).await;
+
+    alice.shutdown();
+    restored.shutdown();
+}
+
+#[tokio::test]
+async fn test_headless_binary() {
+    let addr = spawn_server().await;
+    let home = temp_dir();
+
+    let output = tokio::task::spawn_blocking({
+        let home = home.clone();
+        move || {
+            let mut child = Command::new(env!("CARGO_BIN_EXE_chat-tui"))
+                .args(["--headless", "--server", &format!("http://{}", addr)])
+                .env("HOME", &home)
+                .env("APPDATA", &home)
+                .env_remove("SERVER_WS_URL")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(b"carol\tSecurePass123\x0e\r")
+                .unwrap();
+            child.wait_with_output().unwrap()
+        }
+    })
+    .await
+    .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let last_screen = stdout.rsplit('\x0c').next().unwrap();
+    assert!(last_screen.contains("chat-tui - carol"), "{}", stdout);
+    assert!(last_screen.contains("No conversations"), "{}", stdout);
+
+    // The session is saved where the desktop app looks for it
+    let session = SessionManager::with_session_file(if cfg!(target_os = "macos") {
+        home.join("Library/Application Support/chat-app/session.json")
+    } else if cfg!(windows) {
+        home.join("chat-app/session.json")
+    } else {
+        home.join(".config/chat-app/session.json")
+    });
+    assert_eq!(session.get_session().unwrap().unwrap().username, "carol");
+}
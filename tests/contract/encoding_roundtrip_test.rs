@@ -0,0 +1,103 @@
+//! Contract tests for the negotiated wire encodings
+//!
+//! Every envelope must mean the same thing whether it travels as a JSON text
+//! frame or a MessagePack binary frame: decoding either encoding yields the
+//! same envelope, and both satisfy message-envelope-schema.json.
+
+use chat_shared::protocol::{MessageEnvelope, WireEncoding};
+use jsonschema::{Draft, JSONSchema};
+use serde_json::json;
+
+fn envelope_validator() -> JSONSchema {
+    let schema: serde_json::Value = serde_json::from_str(include_str!(
+        "../../specs/001-private-chat/contracts/message-envelope-schema.json"
+    ))
+    .expect("invalid JSON schema file");
+    let envelope = schema["definitions"]["messageEnvelope"].clone();
+
+    JSONSchema::options()
+        .with_draft(Draft::Draft7)
+        .compile(&envelope)
+        .expect("schema should compile")
+}
+
+fn samples() -> Vec<MessageEnvelope> {
+    let mut replayed = MessageEnvelope::new(
+        "presence",
+        json!({ "user_id": "u2", "username": "bob", "is_online": true, "last_seen_at": 0 }),
+    );
+    replayed.seq = Some(1_702_657_890_001);
+
+    vec![
+        MessageEnvelope::new(
+            "message",
+            json!({
+                "recipient_id": "550e8400-e29b-41d4-a716-446655440000",
+                "content": "Hello Bob! 👋 — ünïcödé",
+                "status": "pending"
+            }),
+        )
+        .with_traceparent(Some(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string(),
+        )),
+        MessageEnvelope::new(
+            "typing",
+            json!({ "recipient_id": "550e8400-e29b-41d4-a716-446655440000", "is_typing": false }),
+        ),
+        MessageEnvelope::new(
+            "ack",
+            json!({ "status": "delivered", "message_id": "m1", "server_timestamp": u64::MAX }),
+        ),
+        MessageEnvelope::new(
+            "error",
+            json!({ "code": "INVALID_JSON", "message": "bad", "details": null }),
+        ),
+        MessageEnvelope::new("heartbeat", json!({})),
+        replayed,
+    ]
+}
+
+fn round_trip(encoding: WireEncoding, envelope: &MessageEnvelope) -> serde_json::Value {
+    let bytes = encoding.encode(envelope).expect("encode");
+    let decoded = encoding.decode(&bytes).expect("decode");
+    serde_json::to_value(decoded).unwrap()
+}
+
+#[test]
+fn both_encodings_carry_identical_envelopes() {
+    for envelope in samples() {
+        let original = serde_json::to_value(&envelope).unwrap();
+        assert_eq!(round_trip(WireEncoding::Json, &envelope), original);
+        assert_eq!(round_trip(WireEncoding::MessagePack, &envelope), original);
+    }
+}
+
+#[test]
+fn msgpack_envelopes_satisfy_the_json_schema() {
+    let validator = envelope_validator();
+    for envelope in samples() {
+        let decoded = round_trip(WireEncoding::MessagePack, &envelope);
+        assert!(validator.is_valid(&decoded), "{}", decoded);
+    }
+}
+
+#[test]
+fn msgpack_uses_json_field_names() {
+    let envelope = samples().remove(0);
+    let bytes = WireEncoding::MessagePack.encode(&envelope).unwrap();
+
+    // Decoding as a plain map proves the keys are names, not positional fields
+    let map: serde_json::Map<String, serde_json::Value> =
+        WireEncoding::MessagePack.decode_as(&bytes).unwrap();
+    for key in ["id", "type", "timestamp", "data", "traceparent"] {
+        assert!(map.contains_key(key), "missing {}", key);
+    }
+}
+
+#[test]
+fn malformed_frames_are_rejected_by_both_encodings() {
+    assert!(WireEncoding::Json.decode(b"{\"id\":1}").is_err());
+    assert!(WireEncoding::MessagePack
+        .decode(&[0x81, 0xa2, b'i', b'd'])
+        .is_err());
+}
@@ -6,7 +6,7 @@ use sqlx::SqlitePool;
 
 /// Sets up an in-memory SQLite database with the full schema.
 ///
-/// Creates a new in-memory database and runs all migrations from the initial schema file.
+/// Creates a new in-memory database and runs all migrations in order.
 /// This function is designed to be called at the start of each test to ensure a clean state.
 ///
 /// # Errors
@@ -31,10 +31,7 @@ pub async fn setup_test_db() -> SqlitePool {
         .await
         .unwrap();
 
-    let schema_sql = include_str!("../../src/backend/db/migrations/001_initial_schema.sql");
-    for statement in schema_sql.split(';').filter(|s| !s.trim().is_empty()) {
-        sqlx::query(statement).execute(&pool).await.unwrap();
-    }
+    chat_backend::db::run_migrations(&pool).await.unwrap();
 
     pool
 }
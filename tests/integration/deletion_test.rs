@@ -5,8 +5,14 @@ use chat_backend::db::queries;
 use chat_backend::models::{User, Message, Conversation};
 use chat_backend::handlers::user::{delete_account, DeleteAccountRequest};
 use chat_backend::services::AuthService;
+use chat_backend::services::DataExportService;
+use chat_backend::services::data_export::DataExportConfig;
 use crate::fixtures::setup_test_db;
 
+fn exports(pool: &sqlx::SqlitePool) -> DataExportService {
+    DataExportService::new(pool.clone(), DataExportConfig::default(), b"secret")
+}
+
 /// Test ID: T004-001
 /// Given: A user account with correct password
 /// When: The account deletion request is made with correct password
@@ -26,7 +32,7 @@ async fn test_account_deletion_success() {
     };
     
     // Call handler
-    let result = delete_account(user.id.clone(), req, pool.clone()).await;
+    let result = delete_account(user.id.clone(), req, pool.clone(), exports(&pool)).await;
     assert!(result.is_ok());
     
     // Verify user is deleted
@@ -50,7 +56,7 @@ async fn test_account_deletion_success() {
     };
     
     // Call handler
-    let result = delete_account(user.id.clone(), req, pool.clone()).await;
+    let result = delete_account(user.id.clone(), req, pool.clone(), exports(&pool)).await;
     // Handler returns Ok(Reply) even for errors (wrapped in JSON response with status code)
     // To verify failure, we check DB
     
@@ -80,7 +86,7 @@ async fn test_account_deletion_success() {
     
     // Delete account
     let req = DeleteAccountRequest { password: "Password123".to_string() };
-    delete_account(user.id.clone(), req, pool.clone()).await.unwrap();
+    delete_account(user.id.clone(), req, pool.clone(), exports(&pool)).await.unwrap();
     
     // Check message
     let msgs = queries::get_messages_by_conversation(&pool, &conv.id, 10, 0).await.unwrap();